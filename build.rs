@@ -4,5 +4,33 @@ fn main() {
         const QUEUE_LEN: usize = 4096;
         /// 数组长度，决定同时可用的队列数量
         const ARRAY_LEN: usize = 64;
+        /// 优先通道的队列长度，见`push_priority`
+        const PRIORITY_QUEUE_LEN: usize = 256;
     }
+    static_array();
+}
+
+/// `static-array`默认放置队列数组的段。以`.bss.`开头，链接器将其视为未初始化数据合并入`.bss`，不占用文件空间
+const DEFAULT_ARRAY_SECTION: &str = ".bss.vqueue_array";
+
+/// 启用`static-array`时，生成声明队列数组静态变量的代码，由`lib.rs`通过`include!`引入
+///
+/// `#[link_section]`只接受字面量，因此段名须在此处写入生成的代码：
+/// 取环境变量`VQUEUE_ARRAY_SECTION`，未设置时使用`DEFAULT_ARRAY_SECTION`。
+fn static_array() {
+    if std::env::var_os("CARGO_FEATURE_STATIC_ARRAY").is_none() {
+        return;
+    }
+    println!("cargo:rerun-if-env-changed=VQUEUE_ARRAY_SECTION");
+    let section =
+        std::env::var("VQUEUE_ARRAY_SECTION").unwrap_or_else(|_| DEFAULT_ARRAY_SECTION.into());
+    let out = std::path::Path::new(&std::env::var("OUT_DIR").unwrap()).join("static_array.rs");
+    std::fs::write(
+        out,
+        format!(
+            "#[unsafe(link_section = {section:?})]\n\
+             static QUEUE_ARRAY: StaticQueueArray = StaticQueueArray(UnsafeCell::new(MaybeUninit::uninit()));\n"
+        ),
+    )
+    .unwrap();
 }
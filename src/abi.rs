@@ -0,0 +1,122 @@
+//! 检测进程间ABI不一致的版本号。
+//!
+//! 在vDSO模型中，生产者与消费者进程各自链接本crate，却共享同一块全局数据结构。若双方以不同的`QUEUE_LEN`、`ARRAY_LEN`
+//! 或数据结构布局构建，它们对同一块内存的索引计算会不一致，后果无法预料。
+//! 因此每个进程应在启动时比较`vqueue_abi_version()`（由共享的vDSO实现返回）与本进程编译得到的`ABI_VERSION`，不一致时拒绝继续运行。
+
+use core::mem::{align_of, size_of};
+
+use crate::{
+    ARRAY_LEN, IPCItem, PerProcess, QUEUE_CAPACITY, QUEUE_LEN, QueuedItem, deque::LockFreeDeque,
+    slot_array::SlotArray,
+};
+
+/// 本进程编译得到的ABI版本号，见模块文档
+pub const ABI_VERSION: u64 = abi_version::<QUEUE_CAPACITY, ARRAY_LEN>();
+
+/// 返回共享的vDSO实现所使用的ABI版本号，进程启动时应与`ABI_VERSION`比较，不一致时拒绝继续运行。
+#[unsafe(no_mangle)]
+pub extern "C" fn vqueue_abi_version() -> u64 {
+    ABI_VERSION
+}
+
+/// 本crate构建时确定的常量与大小，由`vqueue_constants`导出
+///
+/// 映射vvar区域的程序（如`map.rs`）应从编译得到的`.so`中读取这些值来确定区域大小，而不是自行以相同参数重新推导。
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct VqueueConstants {
+    /// `ARRAY_LEN`，即同时可用的队列数量
+    pub array_len: usize,
+    /// `QUEUE_LEN`，即单条队列长度
+    pub queue_len: usize,
+    /// `QUEUE_CAPACITY`，即队列占用的槽位数
+    pub queue_capacity: usize,
+    /// `size_of::<IPCItem>()`
+    pub ipc_item_size: usize,
+    /// 全局数据结构的大小：启用`vdso`时为`size_of::<VvarData>()`，否则为队列数组的大小`QUEUE_ARRAY_SIZE`
+    pub vvar_data_size: usize,
+}
+
+/// 本进程编译得到的常量，见`VqueueConstants`
+const CONSTANTS: VqueueConstants = VqueueConstants {
+    array_len: ARRAY_LEN,
+    queue_len: QUEUE_LEN,
+    queue_capacity: QUEUE_CAPACITY,
+    ipc_item_size: size_of::<IPCItem>(),
+    #[cfg(feature = "vdso")]
+    vvar_data_size: size_of::<crate::VvarData>(),
+    #[cfg(not(feature = "vdso"))]
+    vvar_data_size: crate::QUEUE_ARRAY_SIZE,
+};
+
+/// 将构建时确定的常量写入`out`，供映射vvar区域的程序在dlopen或解析ELF后读取，见`VqueueConstants`。
+///
+/// # Safety
+///
+/// `out`须指向可写的`VqueueConstants`。
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn vqueue_constants(out: *mut VqueueConstants) {
+    unsafe { out.write(CONSTANTS) };
+}
+
+/// 以给定的队列容量与数组长度计算ABI版本号：对容量、数组长度以及各共享结构的大小与对齐做FNV-1a哈希。
+///
+/// 只依赖布局，因此字段重排但大小不变的改动无法检测。
+const fn abi_version<const CAPACITY: usize, const N: usize>() -> u64 {
+    let values = [
+        CAPACITY,
+        N,
+        size_of::<IPCItem>(),
+        align_of::<IPCItem>(),
+        size_of::<QueuedItem>(),
+        size_of::<LockFreeDeque<QueuedItem, CAPACITY>>(),
+        align_of::<LockFreeDeque<QueuedItem, CAPACITY>>(),
+        size_of::<PerProcess>(),
+        align_of::<PerProcess>(),
+        size_of::<SlotArray<PerProcess, N>>(),
+    ];
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    let mut i = 0;
+    while i < values.len() {
+        let bytes = (values[i] as u64).to_le_bytes();
+        let mut j = 0;
+        while j < bytes.len() {
+            hash ^= bytes[j] as u64;
+            hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+            j += 1;
+        }
+        i += 1;
+    }
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ABI_VERSION, VqueueConstants, abi_version, vqueue_abi_version, vqueue_constants};
+    use crate::{ARRAY_LEN, IPCItem, QUEUE_CAPACITY, QUEUE_LEN};
+    use core::mem::size_of;
+
+    #[test]
+    fn test_abi_version() {
+        assert_eq!(vqueue_abi_version(), ABI_VERSION);
+        assert_eq!(abi_version::<QUEUE_CAPACITY, ARRAY_LEN>(), ABI_VERSION);
+        // A producer and a consumer built with different parameters
+        assert_ne!(abi_version::<5, 4>(), abi_version::<9, 4>());
+        assert_ne!(abi_version::<5, 4>(), abi_version::<5, 8>());
+    }
+
+    #[test]
+    fn test_constants() {
+        let mut constants = VqueueConstants::default();
+        unsafe { vqueue_constants(&mut constants) };
+        assert_eq!(constants.array_len, ARRAY_LEN);
+        assert_eq!(constants.queue_len, QUEUE_LEN);
+        assert_eq!(constants.queue_capacity, QUEUE_CAPACITY);
+        assert_eq!(constants.ipc_item_size, size_of::<IPCItem>());
+        #[cfg(feature = "vdso")]
+        assert_eq!(constants.vvar_data_size, size_of::<crate::VvarData>());
+        #[cfg(not(feature = "vdso"))]
+        assert_eq!(constants.vvar_data_size, size_of::<crate::QueueRegion>());
+    }
+}
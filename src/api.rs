@@ -1,35 +1,685 @@
 //! 通过vDSO访问的API，可以操作全局数据结构
 use core::mem;
-use core::sync::atomic::{AtomicUsize, Ordering};
+use core::ops::Deref;
+use core::sync::atomic::{AtomicUsize, Ordering, fence};
 
-use crate::{ARRAY_LEN, IPCItem, LockFreeDeque, PerProcess, QUEUE_CAPACITY, SlotGuard, SlotRef};
+#[cfg(feature = "deque-slot")]
+use crate::SlotGuard;
+use crate::{
+    ARRAY_LEN, Discipline, IPCItem, LargeError, LockFreeDeque, NonemptyHook, PerProcess, PushError,
+    QUEUE_CAPACITY, QueueConfig, QueuedItem, SlotRef,
+};
 
 use crate::get_queue_array;
+use crate::slot_array::{PushFailure, SlotArray};
+#[cfg(feature = "trace")]
+use crate::{TraceCallback, TraceOp};
+
+/// FFI函数在一次操作期间借用id对应队列的RAII守卫。
+///
+/// 本守卫在创建时通过`SlotRef::pin`取得自己的一份引用计数，在drop时归还，因此无论正常返回、提前返回还是panic展开，
+/// 计数都保持平衡；操作期间即使其他进程注销该队列，队列也会推迟到守卫drop时才释放。
+pub(crate) struct BorrowedQueue<'a, const N: usize = ARRAY_LEN>(SlotRef<'a, PerProcess, N>);
+
+impl BorrowedQueue<'static> {
+    /// 借用全局队列数组中id对应的队列，id无效或已失效时panic，见`SlotRef::pin`。
+    pub(crate) fn borrow(id: usize) -> Self {
+        Self(SlotRef::pin(id))
+    }
+}
+
+#[cfg(test)]
+impl<'a, const N: usize> BorrowedQueue<'a, N> {
+    /// 借用给定队列数组中id对应的队列，id无效或已失效时panic，见`SlotArray::pin_id`。
+    pub(crate) fn borrow_in(array: &'a SlotArray<PerProcess, N>, id: usize) -> Self {
+        Self(array.pin_id(id))
+    }
+}
+
+impl<const N: usize> Deref for BorrowedQueue<'_, N> {
+    type Target = PerProcess;
+
+    fn deref(&self) -> &PerProcess {
+        &self.0
+    }
+}
 
 /// 注册当前进程，返回一个`SlotRef`，其中包含了当前进程的IPC数据结构。
 #[unsafe(no_mangle)]
 pub extern "C" fn register_process() -> Result<SlotRef<'static, PerProcess, ARRAY_LEN>, ()> {
-    get_queue_array().push(PerProcess::default())
+    let res = get_queue_array().push(PerProcess::default());
+    #[cfg(feature = "trace")]
+    crate::trace::emit(
+        TraceOp::Register,
+        res.as_ref().map_or(usize::MAX, |slot_ref| slot_ref.id()),
+        res.is_ok(),
+    );
+    res
+}
+
+/// 以名字注册一个队列，返回其id，供按字符串名字分配队列、又不想另外维护注册表的调用者使用。
+///
+/// 名字的哈希值决定起始槽位，从那里线性探测第一个空闲的槽位注册，因此无冲突时同一名字总是得到同一槽位，
+/// 冲突（槽位已被占用）时依次使用后面的槽位。若该名字已注册，则失败。`lookup_named`以同样的方式查找。
+///
+/// 这只是便利接口，不是完整的哈希表：只比较名字的63位哈希值，哈希值相同的两个名字视为同一名字；
+/// 注销会在探测序列中留下空位，因此查找不能在空闲槽位处停止，最坏情况下扫描整个数组。
+///
+/// 名字分两步占用：先在取得的槽位中预留（写入带`NAME_RESERVED`位的哈希值），再扫描其他槽位，
+/// 没有其他槽位预留或注册了同一名字时才发布。预留与扫描之间有`SeqCst`屏障，因此并发注册同一名字时，
+/// 后扫描的一方一定能看到先预留的一方：至多一个成功，也可能都失败，此时可以重试。
+///
+/// # Safety
+///
+/// `name`须指向`len`个可读的字节；`len`为0时可以为空指针。
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn register_queue_named(name: *const u8, len: usize) -> Result<usize, ()> {
+    let name = match len {
+        0 => &[],
+        _ => unsafe { core::slice::from_raw_parts(name, len) },
+    };
+    let res = register_named_in(get_queue_array(), name);
+    #[cfg(feature = "trace")]
+    crate::trace::emit(
+        TraceOp::Register,
+        res.as_ref().map_or(usize::MAX, |slot_ref| slot_ref.id()),
+        res.is_ok(),
+    );
+    res.map(SlotRef::into_id)
+}
+
+/// 查找以`register_queue_named`注册的名字`name`（`len`个字节），返回其id，未注册时返回`None`，见`register_queue_named`。
+///
+/// 仍在预留中、尚未发布的名字视为未注册。
+///
+/// # Safety
+///
+/// `name`须指向`len`个可读的字节；`len`为0时可以为空指针。
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn lookup_named(name: *const u8, len: usize) -> Option<usize> {
+    let name = match len {
+        0 => &[],
+        _ => unsafe { core::slice::from_raw_parts(name, len) },
+    };
+    lookup_named_in(get_queue_array(), name).map(|slot_ref| slot_ref.id())
+}
+
+/// `PerProcess::name`中表示名字仍在预留中的位
+const NAME_RESERVED: u64 = 1 << 63;
+
+/// `register_queue_named`的实现，数组作为参数以便测试
+fn register_named_in<'a, const N: usize>(
+    array: &'a SlotArray<PerProcess, N>,
+    name: &[u8],
+) -> Result<SlotRef<'a, PerProcess, N>, ()> {
+    let hash = name_hash(name);
+    if lookup_named_in(array, name).is_some() {
+        return Err(());
+    }
+    let slot_ref = array.push_probing(PerProcess::default(), hash as usize)?;
+    let queue = slot_ref.get();
+    // Reserve the name in our own slot, then look for anyone else holding or reserving it.
+    // The fences order each reservation before the other side's scan, so two racing
+    // registrations can't both miss each other.
+    queue.name.store(hash | NAME_RESERVED, Ordering::Relaxed);
+    fence(Ordering::SeqCst);
+    let taken = array
+        .find_probing(hash as usize, |other| {
+            !core::ptr::eq(other, queue)
+                && other.name.load(Ordering::Relaxed) & !NAME_RESERVED == hash
+        })
+        .is_some();
+    if taken {
+        return Err(());
+    }
+    queue.name.store(hash, Ordering::Release);
+    Ok(slot_ref)
+}
+
+/// `lookup_named`的实现，数组作为参数以便测试
+fn lookup_named_in<'a, const N: usize>(
+    array: &'a SlotArray<PerProcess, N>,
+    name: &[u8],
+) -> Option<SlotRef<'a, PerProcess, N>> {
+    let hash = name_hash(name);
+    array.find_probing(hash as usize, |queue| {
+        queue.name.load(Ordering::Acquire) == hash
+    })
+}
+
+/// 名字的FNV-1a哈希值，去掉`NAME_RESERVED`位，且不为0（0表示未命名）
+fn name_hash(name: &[u8]) -> u64 {
+    let hash = name.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x0000_0100_0000_01b3)
+    });
+    (hash & !NAME_RESERVED).max(1)
+}
+
+/// `register_process_bounded`等注册函数失败的原因
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RegisterError {
+    /// 已扫描整个数组，没有空闲的槽位
+    Full = 0,
+    /// 在扫描窗口内没有找到空闲的槽位，但窗口外可能还有
+    WouldBlock = 1,
+    /// 多次扫描整个数组都没有找到空闲的槽位，但每次都看到其他线程正在注册或注销，稍后重试可能成功，见`register_process_passes`
+    TooContended = 2,
+    /// 要关联的队列（`register_tee_queue`的监视队列或`register_with_overflow`的溢出队列）的id无效：未注册或已失效
+    InvalidQueue = 3,
+}
+
+/// 注册当前进程，但最多扫描`max_scan`个槽位，返回一个`SlotRef`，其中包含了当前进程的IPC数据结构。
+///
+/// `register_process`在数组（接近）满时会扫描全部`ARRAY_LEN`个槽位，对实时线程而言延迟不可接受。
+/// 本函数的工作量有上界：扫描窗口内没有空闲槽位时返回`RegisterError::WouldBlock`，调用者可推迟到非实时上下文中注册。
+/// 注意，即使窗口外还有空闲槽位，也可能返回`WouldBlock`。`max_scan`不小于`ARRAY_LEN`时与`register_process`相同，
+/// 失败时返回`RegisterError::Full`。
+#[unsafe(no_mangle)]
+pub extern "C" fn register_process_bounded(
+    max_scan: usize,
+) -> Result<SlotRef<'static, PerProcess, ARRAY_LEN>, RegisterError> {
+    let res = get_queue_array().push_bounded(PerProcess::default(), max_scan);
+    #[cfg(feature = "trace")]
+    crate::trace::emit(
+        TraceOp::Register,
+        res.as_ref().map_or(usize::MAX, |slot_ref| slot_ref.id()),
+        res.is_ok(),
+    );
+    res.map_err(|()| match max_scan >= ARRAY_LEN {
+        true => RegisterError::Full,
+        false => RegisterError::WouldBlock,
+    })
+}
+
+/// 注册当前进程，但最多扫描整个数组`max_passes`次（至少一次），返回一个`SlotRef`，其中包含了当前进程的IPC数据结构。
+///
+/// 大量线程同时注册或注销时，一次扫描可能恰好错过正在被释放的槽位，因此只要扫描中看到其他线程正在占用或释放槽位，
+/// 就会再扫描一次。`register_process`最多扫描4次（`PUSH_PASSES`）；本函数由调用者指定次数，以限制启动时大量线程同时注册的最坏耗时。
+///
+/// 次数用尽时返回`RegisterError::TooContended`，调用者可以退避后重试；某次扫描只看到已被占用的槽位时返回`RegisterError::Full`。
+#[unsafe(no_mangle)]
+pub extern "C" fn register_process_passes(
+    max_passes: usize,
+) -> Result<SlotRef<'static, PerProcess, ARRAY_LEN>, RegisterError> {
+    let res = get_queue_array().push_passes(PerProcess::default(), max_passes);
+    #[cfg(feature = "trace")]
+    crate::trace::emit(
+        TraceOp::Register,
+        res.as_ref().map_or(usize::MAX, |slot_ref| slot_ref.id()),
+        res.is_ok(),
+    );
+    res.map_err(|failure| match failure {
+        PushFailure::Full => RegisterError::Full,
+        PushFailure::Contended => RegisterError::TooContended,
+    })
+}
+
+/// 以给定的配置注册当前进程，返回一个`SlotRef`，其中包含了当前进程的IPC数据结构。
+///
+/// 此后`deque_push`、`deque_pop`将按照该配置操作队列。`QueueConfig::default()`与`register_process`的行为一致。
+///
+/// 后进先出（`Discipline::Lifo`）的队列需要从前端弹出，未启用`deque-front` feature时注册失败。
+#[unsafe(no_mangle)]
+pub extern "C" fn register_process_configured(
+    config: QueueConfig,
+) -> Result<SlotRef<'static, PerProcess, ARRAY_LEN>, ()> {
+    if cfg!(not(feature = "deque-front")) && config.discipline == Discipline::Lifo {
+        return Err(());
+    }
+    let res = get_queue_array().push(PerProcess::with_config(config));
+    #[cfg(feature = "trace")]
+    crate::trace::emit(
+        TraceOp::Register,
+        res.as_ref().map_or(usize::MAX, |slot_ref| slot_ref.id()),
+        res.is_ok(),
+    );
+    res
+}
+
+/// 注册当前进程，并将`monitor_id`对应的队列作为其监视队列，返回一个`SlotRef`，其中包含了当前进程的IPC数据结构。
+///
+/// 此后每条成功推入该队列的消息（`deque_push`、`try_push`等）都会复制一份推入监视队列（与`deque_push`推入的一端相同），用于监控或旁路记录。
+/// 复制是尽力而为的：监视队列满或已注销时，复制的消息被静默丢弃，不影响原队列的推入。
+///
+/// `monitor_id`无效（未注册或已失效）时返回`RegisterError::InvalidQueue`，没有空闲的槽位时返回`RegisterError::Full`。
+/// 等价于以`monitor`字段为`monitor_id`的默认配置调用`register_process_configured`。
+#[unsafe(no_mangle)]
+pub extern "C" fn register_tee_queue(
+    monitor_id: usize,
+) -> Result<SlotRef<'static, PerProcess, ARRAY_LEN>, RegisterError> {
+    if SlotRef::try_from_id(monitor_id).is_none() {
+        return Err(RegisterError::InvalidQueue);
+    }
+    register_process_configured(QueueConfig {
+        monitor: monitor_id,
+        ..Default::default()
+    })
+    .map_err(|()| RegisterError::Full)
+}
+
+/// 注册当前进程，并将`overflow_id`对应的队列作为其溢出队列，返回一个`SlotRef`，其中包含了当前进程的IPC数据结构。
+///
+/// 此后推入该队列的消息（`deque_push`、`try_push`等）在队列满时不再失败，而是改为推入溢出队列；
+/// 只有溢出队列也满（或已注销）时才失败。`deque_pop`等先弹出本队列中的消息，本队列为空时再从溢出队列弹出。
+///
+/// 因此溢出的消息总是在本队列弹空之后送达：即使是先进先出的队列，它们也晚于此后推入本队列的消息，
+/// 消费者需要全局顺序时可以使用`pop_with_seq`（注意两个队列的序号各自独立）。溢出队列不会再溢出到它自己的溢出队列。
+///
+/// `overflow_id`无效（未注册或已失效）时返回`RegisterError::InvalidQueue`，没有空闲的槽位时返回`RegisterError::Full`。
+/// 等价于以`overflow_queue`字段为`overflow_id`的默认配置调用`register_process_configured`。
+#[unsafe(no_mangle)]
+pub extern "C" fn register_with_overflow(
+    overflow_id: usize,
+) -> Result<SlotRef<'static, PerProcess, ARRAY_LEN>, RegisterError> {
+    if SlotRef::try_from_id(overflow_id).is_none() {
+        return Err(RegisterError::InvalidQueue);
+    }
+    register_process_configured(QueueConfig {
+        overflow_queue: overflow_id,
+        ..Default::default()
+    })
+    .map_err(|()| RegisterError::Full)
 }
 
 /// 向当前进程的IPC队列（`deque`）中推入一条消息。
+///
+/// 默认推入队列前端（`deque_pop`从后端弹出），启用`push-back-default` feature后推入后端（`deque_pop`从前端弹出）。
+/// 两种构建下`deque_push`/`deque_pop`的顺序相同，但与`deque_push_front`等指定方向的函数混用时顺序不同，
+/// 因此同一队列的所有使用者必须以相同的设置构建。
+///
+/// 队列满时的行为由注册时的`QueueConfig::overflow`决定。
 #[unsafe(no_mangle)]
 pub extern "C" fn deque_push(process_id: usize, item: IPCItem) -> Result<(), IPCItem> {
-    let slot_ref: SlotRef<'_, PerProcess, ARRAY_LEN> = unsafe { SlotRef::from_id(process_id) };
-    let res = slot_ref.deque.push_front(item);
-    slot_ref.into_id(); // prevent drop
+    let queue = BorrowedQueue::borrow(process_id);
+    let res = queue.push(item);
+    #[cfg(feature = "trace")]
+    crate::trace::emit(TraceOp::Push, process_id, res.is_ok());
+    res
+}
+
+/// 向当前进程IPC队列的优先通道推入一条消息。
+///
+/// `deque_pop`等弹出函数总是先取完优先通道中的消息，再取普通通道（`deque`）中的消息。
+/// 每个通道内部的顺序与`deque_push`相同，但两个通道之间不保持推入的顺序：优先通道中的消息总是先于普通通道中的消息弹出。
+///
+/// 优先通道满时直接失败并返回该消息，不受`QueueConfig::overflow`与溢出队列影响。
+#[unsafe(no_mangle)]
+pub extern "C" fn push_priority(process_id: usize, item: IPCItem) -> Result<(), IPCItem> {
+    let queue = BorrowedQueue::borrow(process_id);
+    let res = queue.push_priority(item);
+    #[cfg(feature = "trace")]
+    crate::trace::emit(TraceOp::Push, process_id, res.is_ok());
+    res
+}
+
+/// 向当前进程的IPC队列（`deque`）的前端推入一条消息，不受`push-back-default` feature影响。
+///
+/// 队列满时的行为由注册时的`QueueConfig::overflow`决定，`Overwrite`时丢弃后端的消息。
+#[unsafe(no_mangle)]
+pub extern "C" fn deque_push_front(process_id: usize, item: IPCItem) -> Result<(), IPCItem> {
+    let queue = BorrowedQueue::borrow(process_id);
+    let res = queue.push_at(item, true);
+    #[cfg(feature = "trace")]
+    crate::trace::emit(TraceOp::Push, process_id, res.is_ok());
+    res
+}
+
+/// 向当前进程的IPC队列（`deque`）的后端推入一条消息，不受`push-back-default` feature影响。
+///
+/// 队列满时的行为由注册时的`QueueConfig::overflow`决定，`Overwrite`时丢弃前端的消息。
+///
+/// 需要启用`deque-back`与`deque-front`（用于丢弃前端的消息）feature。
+#[cfg(all(feature = "deque-back", feature = "deque-front"))]
+#[unsafe(no_mangle)]
+pub extern "C" fn deque_push_back(process_id: usize, item: IPCItem) -> Result<(), IPCItem> {
+    let queue = BorrowedQueue::borrow(process_id);
+    let res = queue.push_at(item, false);
+    #[cfg(feature = "trace")]
+    crate::trace::emit(TraceOp::Push, process_id, res.is_ok());
+    res
+}
+
+/// `push_large`的返回值：推入成功
+pub const PUSH_LARGE_OK: i32 = 0;
+/// `push_large`的返回值：队列空间不足，或队列不是先进先出，未推入任何分片
+pub const PUSH_LARGE_FAILED: i32 = -1;
+
+/// 向当前进程的IPC队列（`deque`）中推入一条长消息，消息为`data`指向的`len`个字，可以超出单个`IPCItem`的负载。
+///
+/// 消息被拆分为多个连续的`IPCItem`分片，分片格式见`MSG_CONTINUED`。所有分片预留连续的槽位后再发布，
+/// 因此并发推入时也不会与其他消息交错。
+///
+/// 返回`PUSH_LARGE_OK`或`PUSH_LARGE_FAILED`：队列空间不足时失败（不受`OverflowPolicy`影响），只支持`Discipline::Fifo`的队列。
+///
+/// # Safety
+///
+/// `data`须指向`len`个可读的`u64`；`len`为0时可以为空指针。
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn push_large(process_id: usize, data: *const u64, len: usize) -> i32 {
+    unsafe { push_large_in(&BorrowedQueue::borrow(process_id), data, len) }
+}
+
+/// `push_large`的实现，队列作为参数以便测试
+///
+/// # Safety
+///
+/// 同`push_large`。
+unsafe fn push_large_in(queue: &PerProcess, data: *const u64, len: usize) -> i32 {
+    let data = match len {
+        0 => &[],
+        _ => unsafe { core::slice::from_raw_parts(data, len) },
+    };
+    match queue.push_large(data) {
+        Ok(()) => PUSH_LARGE_OK,
+        Err(()) => PUSH_LARGE_FAILED,
+    }
+}
+
+/// `pop_large`的返回值：弹出了一条完整的长消息，其总字数已写入`out_len`
+pub const POP_LARGE_OK: i32 = 0;
+/// `pop_large`的返回值：队列为空，未写入`out`与`out_len`
+pub const POP_LARGE_EMPTY: i32 = 1;
+/// `pop_large`的返回值：弹出的消息不是长消息的首个分片，所属消息的开头已被其他消费者取走。
+/// 该分片已从队列中移除并被丢弃，未写入`out`与`out_len`
+pub const POP_LARGE_ORPHAN: i32 = -1;
+/// `pop_large`的返回值：长消息在中途中断，剩余的分片已被其他消费者取走。
+/// 已收到的部分写入了`out`，其字数写入`out_len`；下一条消息留在队列中
+pub const POP_LARGE_TRUNCATED: i32 = -2;
+
+/// 从当前进程的IPC队列（`deque`）中弹出一条由`push_large`推入的长消息，写入`out`指向的`cap`个字。
+///
+/// 返回`POP_LARGE_OK`、`POP_LARGE_EMPTY`、`POP_LARGE_ORPHAN`或`POP_LARGE_TRUNCATED`，各自写入的内容见这些常量。
+/// 弹出成功时`out_len`为消息的总字数：`cap`不足时，超出的部分被丢弃，此时总字数大于`cap`。
+/// 后续分片尚未到达时等待。`out_len`为空指针时不写入。
+///
+/// 只适用于单消费者：多个消费者并发弹出时，同一条消息的分片可能被不同消费者取走。
+///
+/// # Safety
+///
+/// `out`须指向`cap`个可写的`u64`，`cap`为0时可以为空指针；`out_len`须为空指针，或指向可写的`usize`。
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pop_large(
+    process_id: usize,
+    out: *mut u64,
+    cap: usize,
+    out_len: *mut usize,
+) -> i32 {
+    unsafe { pop_large_in(&BorrowedQueue::borrow(process_id), out, cap, out_len) }
+}
+
+/// `pop_large`的实现，队列作为参数以便测试
+///
+/// # Safety
+///
+/// 同`pop_large`。
+unsafe fn pop_large_in(queue: &PerProcess, out: *mut u64, cap: usize, out_len: *mut usize) -> i32 {
+    let out = match cap {
+        0 => &mut [],
+        _ => unsafe { core::slice::from_raw_parts_mut(out, cap) },
+    };
+    let (res, len) = match queue.pop_large(out) {
+        Ok(Some(total)) => (POP_LARGE_OK, Some(total)),
+        Ok(None) => (POP_LARGE_EMPTY, None),
+        Err(LargeError::Orphan) => (POP_LARGE_ORPHAN, None),
+        Err(LargeError::Truncated { received }) => (POP_LARGE_TRUNCATED, Some(received)),
+    };
+    if let Some(len) = len
+        && !out_len.is_null()
+    {
+        unsafe { out_len.write(len) };
+    }
     res
 }
 
-/// 检查当前进程的IPC队列（`deque`）是否为空。
+/// 检查当前进程的IPC队列（包括优先通道，见`push_priority`）是否为空。
 #[unsafe(no_mangle)]
 pub extern "C" fn deque_is_empty(process_id: usize) -> bool {
-    let slot_ref: SlotRef<'_, PerProcess, ARRAY_LEN> = unsafe { SlotRef::from_id(process_id) };
-    let res = slot_ref.deque.is_empty();
-    slot_ref.into_id(); // prevent drop
+    let queue = BorrowedQueue::borrow(process_id);
+    queue.priority.is_empty() && queue.deque.is_empty()
+}
+
+/// 清空进程的IPC队列（`deque`与优先通道），丢弃其中所有消息，返回丢弃的消息数量。
+///
+/// 只需要进程id，不需要持有`SlotRef`，供调试工具清理卡住的队列。
+/// 使用带检查的访问：若id无效或已失效（进程已注销），则不做任何操作并返回0。
+///
+/// 若同时有生产者在推入，清空只是尽力而为，清空期间推入的消息可能保留。
+#[unsafe(no_mangle)]
+pub extern "C" fn queue_clear(process_id: usize) -> usize {
+    match SlotRef::try_from_id(process_id) {
+        Some(slot_ref) => slot_ref.priority.clear() + slot_ref.deque.clear(),
+        None => 0,
+    }
+}
+
+/// 移除进程IPC队列（`deque`与优先通道）中`sender`为给定值的所有消息，其余消息保持原有顺序，返回移除的消息数量。
+///
+/// 供消费者在发送者断开后清理其遗留的消息。不需要独占队列，可以与推入、弹出并发，见`LockFreeDeque::retain`；
+/// 但扫描期间推入的该发送者的消息可能保留。被移除的消息在弹出到达其位置之前仍计入队列长度。
+/// 与`queue_clear`相同，使用带检查的访问：若id无效或已失效，则不做任何操作并返回0。
+#[unsafe(no_mangle)]
+pub extern "C" fn drop_by_sender(process_id: usize, sender: u64) -> usize {
+    let Some(slot_ref) = SlotRef::try_from_id(process_id) else {
+        return 0;
+    };
+    let pred = |queued: &QueuedItem| queued.item.sender != sender;
+    slot_ref.priority.retain(pred) + slot_ref.deque.retain(pred)
+}
+
+/// 统计进程IPC队列（`deque`与优先通道）中`msg_type`为给定值的消息数量，不取出消息。
+///
+/// 供调度器判断是否值得唤醒处理该类消息的专门消费者。并发推入或弹出时只是尽力而为的近似值，见`LockFreeDeque::count_where`。
+#[unsafe(no_mangle)]
+pub extern "C" fn count_by_type(process_id: usize, msg_type: u64) -> usize {
+    let queue = BorrowedQueue::borrow(process_id);
+    let pred = |queued: &QueuedItem| queued.item.msg_type == msg_type;
+    queue.priority.count_where(pred) + queue.deque.count_where(pred)
+}
+
+/// 将进程IPC队列（`deque`）每个槽位最后一次写入其状态的操作依次写入`out`，返回写入的数量，供调试工具诊断卡住的队列。
+///
+/// 每个字节为`SlotOp`的值，0表示该槽位从未使用。最多写入`len`与`QUEUE_CAPACITY`中较小者个字节，顺序为槽位在环形缓冲区中的顺序。
+/// 与`queue_clear`相同，使用带检查的访问：若id无效或已失效，则不写入并返回0。
+///
+/// 需要启用`debug-history` feature。
+///
+/// # Safety
+///
+/// `out`须指向`len`个可写的字节；`len`为0时可以为空指针。
+#[cfg(feature = "debug-history")]
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn queue_history(process_id: usize, out: *mut u8, len: usize) -> usize {
+    let Some(slot_ref) = SlotRef::try_from_id(process_id) else {
+        return 0;
+    };
+    let mut count = 0;
+    for op in slot_ref.deque.history().take(len) {
+        unsafe { out.add(count).write(op.map_or(0, |op| op as u8)) };
+        count += 1;
+    }
+    count
+}
+
+/// `try_push`的返回值：推入成功
+pub const TRY_PUSH_OK: i32 = 0;
+/// `try_push`的返回值：id无效或对应的进程未注册（或已注销，包括槽位已被其他进程重新注册）
+pub const TRY_PUSH_NOT_REGISTERED: i32 = -1;
+/// `try_push`的返回值：队列已满
+pub const TRY_PUSH_FULL: i32 = -2;
+/// `try_push`的返回值：队列的数据结构已被破坏，所有操作都会失败，见`LockFreeDeque::is_poisoned`
+pub const TRY_PUSH_POISONED: i32 = -3;
+/// `try_push_bounded`的返回值：因竞争重试次数用尽，队列未满，稍后重试可能成功
+pub const TRY_PUSH_CONTENDED: i32 = -4;
+
+/// 向进程的IPC队列（`deque`）中推入一条消息，适用于由调用者提供、可能已失效的id。
+///
+/// 与先调用检查函数再调用`deque_push`不同，注册检查与推入在同一次带引用计数的槽位获取下完成，
+/// 推入期间进程即使注销，其数据结构也不会被释放，因此不存在检查与使用之间的竞争。
+///
+/// 返回`TRY_PUSH_OK`、`TRY_PUSH_NOT_REGISTERED`、`TRY_PUSH_FULL`或`TRY_PUSH_POISONED`。队列满时的行为由注册时的`QueueConfig::overflow`决定。
+#[unsafe(no_mangle)]
+pub extern "C" fn try_push(process_id: usize, item: IPCItem) -> i32 {
+    let res = try_push_in(get_queue_array(), process_id, item);
+    #[cfg(feature = "trace")]
+    crate::trace::emit(TraceOp::Push, process_id, res == TRY_PUSH_OK);
     res
 }
 
+/// `try_push`的实现，数组作为参数以便测试
+fn try_push_in<const N: usize>(
+    array: &SlotArray<PerProcess, N>,
+    process_id: usize,
+    item: IPCItem,
+) -> i32 {
+    match array.try_ref_id(process_id) {
+        Some(slot_ref) => match slot_ref.push(item) {
+            Ok(()) => TRY_PUSH_OK,
+            Err(_) if slot_ref.deque.is_poisoned() => TRY_PUSH_POISONED,
+            Err(_) => TRY_PUSH_FULL,
+        },
+        None => TRY_PUSH_NOT_REGISTERED,
+    }
+}
+
+/// 向进程的IPC队列中推入一条消息，因竞争重试`max_retries`次后放弃，适用于不能无限自旋的调用者。
+///
+/// 与`try_push`相同，注册检查与推入在同一次带引用计数的槽位获取下完成。各失败原因（见`PushError`）对应不同的返回值：
+/// `Closed`对应`TRY_PUSH_NOT_REGISTERED`，`Full`对应`TRY_PUSH_FULL`，`Contended`对应`TRY_PUSH_CONTENDED`，
+/// `Poisoned`对应`TRY_PUSH_POISONED`，成功时返回`TRY_PUSH_OK`。
+///
+/// 与`try_push`不同，不应用`QueueConfig::overflow`、溢出队列与监视队列。
+#[unsafe(no_mangle)]
+pub extern "C" fn try_push_bounded(process_id: usize, item: IPCItem, max_retries: usize) -> i32 {
+    let res = match push_bounded_in(get_queue_array(), process_id, item, max_retries) {
+        Ok(()) => TRY_PUSH_OK,
+        Err(PushError::Closed(_)) => TRY_PUSH_NOT_REGISTERED,
+        Err(PushError::Full(_)) => TRY_PUSH_FULL,
+        Err(PushError::Contended(_)) => TRY_PUSH_CONTENDED,
+        Err(PushError::Poisoned(_)) => TRY_PUSH_POISONED,
+    };
+    #[cfg(feature = "trace")]
+    crate::trace::emit(TraceOp::Push, process_id, res == TRY_PUSH_OK);
+    res
+}
+
+/// `try_push_bounded`的实现，数组作为参数以便测试；id无效或未注册时返回`PushError::Closed`
+fn push_bounded_in<const N: usize>(
+    array: &SlotArray<PerProcess, N>,
+    process_id: usize,
+    item: IPCItem,
+    max_retries: usize,
+) -> Result<(), PushError<IPCItem>> {
+    match array.try_ref_id(process_id) {
+        Some(slot_ref) => slot_ref.push_bounded(item, max_retries),
+        None => Err(PushError::Closed(item)),
+    }
+}
+
+/// `push_fields`返回值中的标志位：`data_len`超过`IPCItem::data`的8个字，只推入了前8个字
+///
+/// 只在推入成功时置位，与`TRY_PUSH_OK`按位或；推入失败时返回值为`TRY_PUSH_NOT_REGISTERED`等错误码，不带此标志。
+pub const PUSH_TRUNCATED: i32 = 1 << 30;
+
+/// 以各字段构造一条消息，推入进程的IPC队列（`deque`），供不便在C侧构造`IPCItem`的调用者使用。
+///
+/// `data_ptr`指向的`data_len`个字写入`IPCItem::data`，不足8个字的部分填0。`data_len`超过8时，只推入前8个字，
+/// 并在返回值中置位`PUSH_TRUNCATED`，调用者据此得知数据没有完整发送（需要更长的消息时使用`push_large`）。
+///
+/// 推入方式与`try_push`相同，返回值为`TRY_PUSH_OK`（可能带`PUSH_TRUNCATED`）或`try_push`的其他返回值。
+///
+/// # Safety
+///
+/// `data_ptr`须指向`data_len`个可读的`u64`；`data_len`为0时可以为空指针。
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn push_fields(
+    process_id: usize,
+    sender: u64,
+    msg_type: u64,
+    rep_type: u64,
+    data_ptr: *const u64,
+    data_len: usize,
+) -> i32 {
+    let data: &[u64] = match data_len {
+        0 => &[],
+        _ => unsafe { core::slice::from_raw_parts(data_ptr, data_len) },
+    };
+    let res = push_fields_in(
+        get_queue_array(),
+        process_id,
+        sender,
+        msg_type,
+        rep_type,
+        data,
+    );
+    #[cfg(feature = "trace")]
+    crate::trace::emit(
+        TraceOp::Push,
+        process_id,
+        res & !PUSH_TRUNCATED == TRY_PUSH_OK,
+    );
+    res
+}
+
+/// `push_fields`的实现，数组作为参数以便测试
+fn push_fields_in<const N: usize>(
+    array: &SlotArray<PerProcess, N>,
+    process_id: usize,
+    sender: u64,
+    msg_type: u64,
+    rep_type: u64,
+    data: &[u64],
+) -> i32 {
+    let mut item = IPCItem {
+        sender,
+        msg_type,
+        rep_type,
+        data: [0; 8],
+    };
+    let truncated = data.len() > item.data.len();
+    item.data
+        .iter_mut()
+        .zip(data)
+        .for_each(|(dst, src)| *dst = *src);
+    match try_push_in(array, process_id, item) {
+        TRY_PUSH_OK if truncated => TRY_PUSH_OK | PUSH_TRUNCATED,
+        res => res,
+    }
+}
+
+/// 向`ids_ptr`指向的`len`个进程的IPC队列（`deque`）各推入一条`item`的副本，返回推入成功的队列数量。
+///
+/// 每个队列的推入与`try_push`相同：id无效、未注册或队列已满（由`QueueConfig::overflow`决定）时跳过该队列。
+/// 各队列的推入互相独立，整体不是原子的：部分队列收到消息、其他队列未收到是可能的，并发的消费者也可能先于其他队列看到消息。
+///
+/// # Safety
+///
+/// `ids_ptr`须指向`len`个可读的`usize`；`len`为0时可以为空指针。
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn broadcast(ids_ptr: *const usize, len: usize, item: IPCItem) -> usize {
+    if len == 0 {
+        return 0;
+    }
+    let ids = unsafe { core::slice::from_raw_parts(ids_ptr, len) };
+    broadcast_in(get_queue_array(), ids, item)
+}
+
+/// `broadcast`的实现，数组作为参数以便测试
+fn broadcast_in<const N: usize>(
+    array: &SlotArray<PerProcess, N>,
+    ids: &[usize],
+    item: IPCItem,
+) -> usize {
+    ids.iter()
+        .filter(|&&process_id| {
+            let res = try_push_in(array, process_id, item);
+            #[cfg(feature = "trace")]
+            crate::trace::emit(TraceOp::Push, process_id, res == TRY_PUSH_OK);
+            res == TRY_PUSH_OK
+        })
+        .count()
+}
+
 // // Don't work because of lifetime issue
 // #[unsafe(no_mangle)]
 // pub extern "C" fn push_slot(queue_id: usize) -> Result<SlotGuard<'static, IPCItem>, ()> {
@@ -41,14 +691,243 @@ pub extern "C" fn deque_is_empty(process_id: usize) -> bool {
 // }
 
 /// 从当前进程的IPC队列（`deque`）中弹出一条消息。
+///
+/// 弹出的顺序由注册时的`QueueConfig::discipline`决定：先进先出时从`deque_push`推入的另一端弹出，
+/// 后进先出时从同一端弹出（见`deque_push`关于`push-back-default` feature的说明）。
+///
+/// 下一条消息所在的槽位被持有时（生产者持有未提交的`SlotGuard`或`Batch`，或另一消费者持有`PeekGuard`），
+/// 本函数一直等待到它被释放，持有者泄漏该槽位时永不返回。其他弹出函数（`pop_with_seq`、`pop_into`等）同样等待，
+/// 不能阻塞在生产者上的消费者应使用`pop_into_bounded`。
 #[unsafe(no_mangle)]
 pub extern "C" fn deque_pop(process_id: usize) -> Option<IPCItem> {
-    let slot_ref: SlotRef<'_, PerProcess, ARRAY_LEN> = unsafe { SlotRef::from_id(process_id) };
-    let res = slot_ref.deque.pop_back();
-    slot_ref.into_id(); // prevent drop
+    let queue = BorrowedQueue::borrow(process_id);
+    let res = queue.pop();
+    #[cfg(feature = "trace")]
+    crate::trace::emit(TraceOp::Pop, process_id, res.is_some());
     res
 }
 
+/// 与`deque_pop`相同地弹出一条消息，并校验推入时计算的校验和。
+///
+/// 队列为空时返回`Ok(None)`；校验和不一致时，说明消息在共享内存中被破坏（例如另一侧的程序错误），
+/// 该消息仍被移除，并以`Err(Corrupted)`返回其破坏后的内容。`deque_pop`等其他弹出函数不做校验。
+///
+/// 需要启用`checksum` feature，见`Corrupted`。
+#[cfg(feature = "checksum")]
+#[unsafe(no_mangle)]
+pub extern "C" fn deque_pop_checked(
+    process_id: usize,
+) -> Result<Option<IPCItem>, crate::Corrupted> {
+    let queue = BorrowedQueue::borrow(process_id);
+    let res = queue.pop_checked();
+    #[cfg(feature = "trace")]
+    crate::trace::emit(TraceOp::Pop, process_id, !matches!(res, Ok(None)));
+    res
+}
+
+/// 从当前进程的IPC队列（`deque`）的前端弹出一条消息，不受`QueueConfig::discipline`与`push-back-default` feature影响。
+///
+/// 需要启用`deque-front` feature。
+#[cfg(feature = "deque-front")]
+#[unsafe(no_mangle)]
+pub extern "C" fn deque_pop_front(process_id: usize) -> Option<IPCItem> {
+    let queue = BorrowedQueue::borrow(process_id);
+    let res = queue.pop_at(true);
+    #[cfg(feature = "trace")]
+    crate::trace::emit(TraceOp::Pop, process_id, res.is_some());
+    res
+}
+
+/// 从当前进程的IPC队列（`deque`）的后端弹出一条消息，不受`QueueConfig::discipline`与`push-back-default` feature影响。
+#[unsafe(no_mangle)]
+pub extern "C" fn deque_pop_back(process_id: usize) -> Option<IPCItem> {
+    let queue = BorrowedQueue::borrow(process_id);
+    let res = queue.pop_at(false);
+    #[cfg(feature = "trace")]
+    crate::trace::emit(TraceOp::Pop, process_id, res.is_some());
+    res
+}
+
+/// 从当前进程的IPC队列（`deque`）中弹出一条消息，并将弹出后队列中剩余的消息数量写入`out_remaining`。
+///
+/// 消费者可据此调整轮询频率：队列将空时放缓，积压时加快，而不需要另外读取队列长度（那样会与弹出竞争）。
+/// 剩余数量与弹出基于同一次读取的队列状态，但只是近似值：其他进程可能同时推入或弹出。
+///
+/// `out_remaining`为空指针时不写入。队列为空时返回`None`，不写入`out_remaining`。弹出的顺序与`deque_pop`相同。
+///
+/// # Safety
+///
+/// `out_remaining`须为空指针，或指向可写的`usize`。
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pop_with_remaining(
+    process_id: usize,
+    out_remaining: *mut usize,
+) -> Option<IPCItem> {
+    let queue = BorrowedQueue::borrow(process_id);
+    let res = queue.pop_remaining();
+    #[cfg(feature = "trace")]
+    crate::trace::emit(TraceOp::Pop, process_id, res.is_some());
+    res.map(|(item, remaining)| {
+        if !out_remaining.is_null() {
+            unsafe { out_remaining.write(remaining) };
+        }
+        item
+    })
+}
+
+/// 从当前进程的IPC队列（`deque`）中弹出一条消息，并将其推入时分配的序号写入`out_seq`。
+///
+/// 序号由每个队列的计数器在推入时分配，消费者可据此检测重复处理的消息（例如重试之后）。
+/// 同一队列中的序号互不相同，且同一生产者推入的消息序号递增；多个生产者并发推入时，出队顺序不一定与序号顺序一致。
+/// 序号在`u64::MAX`后回绕到0，实际上不会发生。
+///
+/// `out_seq`为空指针时不写入序号。队列为空时返回`None`，不写入`out_seq`。
+///
+/// # Safety
+///
+/// `out_seq`须为空指针，或指向可写的`u64`。
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pop_with_seq(process_id: usize, out_seq: *mut u64) -> Option<IPCItem> {
+    let queue = BorrowedQueue::borrow(process_id);
+    let res = queue.pop_with_seq();
+    #[cfg(feature = "trace")]
+    crate::trace::emit(TraceOp::Pop, process_id, res.is_some());
+    res.map(|(item, seq)| {
+        if !out_seq.is_null() {
+            unsafe { out_seq.write(seq) };
+        }
+        item
+    })
+}
+
+/// 从当前进程的IPC队列（`deque`）中弹出一条消息，并将其推入时分配的全局序号写入`out_seq`。
+///
+/// 与`pop_with_seq`的序号不同，全局序号由所有队列共享的一个计数器分配：若一次推入先于另一次发生（例如同一线程先后推入两个队列），
+/// 前者的全局序号更小，因此从多个队列接收消息的消费者可以按全局序号重建推入的先后顺序。并发的推入之间的顺序则是任意的。
+/// 计数器只对同一地址空间中的推入者共享，且所有推入都争用它，见`global-seq` feature。
+///
+/// `out_seq`为空指针时不写入序号。队列为空时返回`None`，不写入`out_seq`。弹出的顺序与`deque_pop`相同。
+///
+/// # Safety
+///
+/// `out_seq`须为空指针，或指向可写的`u64`。
+#[cfg(feature = "global-seq")]
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pop_with_global_seq(
+    process_id: usize,
+    out_seq: *mut u64,
+) -> Option<IPCItem> {
+    let queue = BorrowedQueue::borrow(process_id);
+    let res = queue.pop_with_global_seq();
+    #[cfg(feature = "trace")]
+    crate::trace::emit(TraceOp::Pop, process_id, res.is_some());
+    res.map(|(item, seq)| {
+        if !out_seq.is_null() {
+            unsafe { out_seq.write(seq) };
+        }
+        item
+    })
+}
+
+/// `pop_into`的返回值：弹出成功，消息已写入`out`
+pub const POP_INTO_OK: i32 = 0;
+/// `pop_into`的返回值：队列为空，未写入`out`
+pub const POP_INTO_EMPTY: i32 = 1;
+/// `pop_into`的返回值：id无效或对应的队列未注册（或已注销）
+pub const POP_INTO_NOT_REGISTERED: i32 = -1;
+/// `pop_into`的返回值：`out`为空指针，未弹出消息
+pub const POP_INTO_NULL: i32 = -2;
+/// `pop_into_bounded`的返回值：下一条消息所在的槽位被持有，未弹出消息，见`pop_into_bounded`
+pub const POP_INTO_CONTENDED: i32 = 2;
+
+/// 从队列中弹出一条消息，直接写入调用者提供的`out`，供在自己的内存中预先分配了`IPCItem`的C侧消费者使用。
+///
+/// 与`deque_pop`相比，消息不经过作为返回值的`Option<IPCItem>`再复制一次。弹出的顺序与`deque_pop`相同，
+/// 也同样等待被持有的槽位；与`try_push`相同，id无效或未注册时返回错误码而不是panic。
+///
+/// 返回`POP_INTO_OK`、`POP_INTO_EMPTY`、`POP_INTO_NOT_REGISTERED`或`POP_INTO_NULL`，只有返回`POP_INTO_OK`时写入`out`。
+///
+/// # Safety
+///
+/// `out`须为空指针，或指向可写的`IPCItem`（可以未初始化）。
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pop_into(queue_id: usize, out: *mut IPCItem) -> i32 {
+    let res = unsafe { pop_into_in(get_queue_array(), queue_id, out) };
+    #[cfg(feature = "trace")]
+    crate::trace::emit(TraceOp::Pop, queue_id, res == POP_INTO_OK);
+    res
+}
+
+/// `pop_into`的实现，数组作为参数以便测试
+///
+/// # Safety
+///
+/// 同`pop_into`。
+unsafe fn pop_into_in<const N: usize>(
+    array: &SlotArray<PerProcess, N>,
+    queue_id: usize,
+    out: *mut IPCItem,
+) -> i32 {
+    if out.is_null() {
+        return POP_INTO_NULL;
+    }
+    let Some(slot_ref) = array.try_ref_id(queue_id) else {
+        return POP_INTO_NOT_REGISTERED;
+    };
+    match slot_ref.pop() {
+        Some(item) => {
+            unsafe { out.write(item) };
+            POP_INTO_OK
+        }
+        None => POP_INTO_EMPTY,
+    }
+}
+
+/// 与`pop_into`相同地弹出一条消息，但不等待被持有的槽位。
+///
+/// 下一条消息所在的槽位被持有时（见`deque_pop`），连续观察到`WRITE_PATIENCE`次后放弃并返回`POP_INTO_CONTENDED`，
+/// 与队列为空（`POP_INTO_EMPTY`）相区别：该槽位之后的消息仍在队列中，调用者可以稍后重试。与其他弹出者的竞争则总是重试。
+///
+/// 返回`POP_INTO_OK`、`POP_INTO_EMPTY`、`POP_INTO_CONTENDED`、`POP_INTO_NOT_REGISTERED`或`POP_INTO_NULL`，
+/// 只有返回`POP_INTO_OK`时写入`out`。
+///
+/// # Safety
+///
+/// 同`pop_into`。
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pop_into_bounded(queue_id: usize, out: *mut IPCItem) -> i32 {
+    let res = unsafe { pop_into_bounded_in(get_queue_array(), queue_id, out) };
+    #[cfg(feature = "trace")]
+    crate::trace::emit(TraceOp::Pop, queue_id, res == POP_INTO_OK);
+    res
+}
+
+/// `pop_into_bounded`的实现，数组作为参数以便测试
+///
+/// # Safety
+///
+/// 同`pop_into`。
+unsafe fn pop_into_bounded_in<const N: usize>(
+    array: &SlotArray<PerProcess, N>,
+    queue_id: usize,
+    out: *mut IPCItem,
+) -> i32 {
+    if out.is_null() {
+        return POP_INTO_NULL;
+    }
+    let Some(slot_ref) = array.try_ref_id(queue_id) else {
+        return POP_INTO_NOT_REGISTERED;
+    };
+    match slot_ref.pop_bounded() {
+        Ok(Some(item)) => {
+            unsafe { out.write(item) };
+            POP_INTO_OK
+        }
+        Ok(None) => POP_INTO_EMPTY,
+        Err(_) => POP_INTO_CONTENDED,
+    }
+}
+
 /// 从进程id获取对应的`SlotRef`，以操作`SlotRef`。
 ///
 /// 当前，该接口只用于clone。
@@ -63,16 +942,103 @@ pub extern "C" fn slotref_from_id(process_id: usize) -> SlotRef<'static, PerProc
     unsafe { SlotRef::from_id(process_id) }
 }
 
+/// 获取进程IPC队列（`deque`）的指针，供需要缓存队列地址、避免每次操作都以id查找槽位的调度器使用。
+///
+/// 返回前引用计数加1，因此在调用`queue_ptr_release`之前，即使进程已注销，队列也不会被释放，指针一直有效；
+/// 每次成功的调用都必须与一次`queue_ptr_release`配对，否则队列永不释放。释放后不能再使用该指针，
+/// 若其他引用也都已drop，队列所在的槽位可能已被释放并重新注册给其他进程。见`SlotRef::as_ptr`。
+///
+/// 指针指向`LockFreeDeque<QueuedItem, QUEUE_CAPACITY>`，其布局不是`#[repr(C)]`的，只能传回本crate使用。
+/// 与`queue_clear`相同，使用带检查的访问：若id无效或已失效，返回空指针，且不需要释放。
+#[unsafe(no_mangle)]
+pub extern "C" fn queue_ptr(process_id: usize) -> *const core::ffi::c_void {
+    let Some(slot_ref) = SlotRef::try_from_id(process_id) else {
+        return core::ptr::null();
+    };
+    let ptr = &slot_ref.deque as *const _ as *const core::ffi::c_void;
+    // The count taken by `try_from_id` is given back by `queue_ptr_release`
+    mem::forget(slot_ref);
+    ptr
+}
+
+/// 释放`queue_ptr`获取的引用计数，此后不能再使用其返回的指针。
+///
+/// # Safety
+///
+/// 每次`queue_ptr`成功返回非空指针后，必须以相同的`process_id`调用且只调用一次。
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn queue_ptr_release(process_id: usize) {
+    // The slot can't have been freed, so the id is still live
+    drop(unsafe { SlotRef::from_id(process_id) });
+}
+
+/// `queue_pin`的返回值：成功，之后须调用`queue_unpin`
+pub const QUEUE_PIN_OK: i32 = 0;
+/// `queue_pin`的返回值：id无效或对应的队列未注册（或已注销），不需要调用`queue_unpin`
+pub const QUEUE_PIN_NOT_REGISTERED: i32 = -1;
+
+/// 使队列在调用`queue_unpin`之前一直有效，供只持有队列id的C侧使用者包围一段由多步操作组成的临界区。
+///
+/// 相当于对Rust侧的`SlotRef`调用`clone`：引用计数加1，此后即使队列被注销（最后一个`SlotRef`被drop），
+/// 队列也只在`queue_unpin`时才被释放，期间id一直有效，以该id进行的操作不会因队列被释放而panic。
+/// 与`queue_ptr`相同，使用带检查的访问：id无效或已失效时返回`QUEUE_PIN_NOT_REGISTERED`，引用计数不变。
+///
+/// 每次返回`QUEUE_PIN_OK`的调用都必须与一次`queue_unpin`配对。多调用`queue_unpin`会提前释放仍被他人使用的队列，
+/// 少调用则队列永不释放，两者都会破坏引用计数。引用计数只有8位，同一队列同时被钉住的次数也受其限制。
+#[unsafe(no_mangle)]
+pub extern "C" fn queue_pin(queue_id: usize) -> i32 {
+    queue_pin_in(get_queue_array(), queue_id)
+}
+
+/// `queue_pin`的实现，数组作为参数以便测试
+fn queue_pin_in<const N: usize>(array: &SlotArray<PerProcess, N>, queue_id: usize) -> i32 {
+    match array.try_ref_id(queue_id) {
+        Some(slot_ref) => {
+            // The count taken by `try_ref_id` is given back by `queue_unpin`
+            mem::forget(slot_ref);
+            QUEUE_PIN_OK
+        }
+        None => QUEUE_PIN_NOT_REGISTERED,
+    }
+}
+
+/// 释放`queue_pin`增加的引用计数，结束临界区。若队列已被注销且这是最后一个引用，队列在此时被释放。
+///
+/// # Safety
+///
+/// 每次`queue_pin`返回`QUEUE_PIN_OK`后，必须以相同的`queue_id`调用且只调用一次，见`queue_pin`。
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn queue_unpin(queue_id: usize) {
+    unsafe { get_queue_array().unpin_id(queue_id) };
+}
+
 /// 获取先前使用`set_pid`设置的`pid`。
 ///
 /// - `process_id`：使用`register_process`分配的pid
 /// - `pid`：调度模块中的进程id，用于通知机制
 #[unsafe(no_mangle)]
 pub extern "C" fn get_pid(process_id: usize) -> usize {
-    let slot_ref: SlotRef<'_, PerProcess, ARRAY_LEN> = unsafe { SlotRef::from_id(process_id) };
-    let res = slot_ref.pid.load(Ordering::Acquire);
-    slot_ref.into_id(); // prevent drop
-    res
+    BorrowedQueue::borrow(process_id)
+        .pid
+        .load(Ordering::Acquire)
+}
+
+/// 消费者发布自己的滞后程度，供生产者通过`lag_level`读取，作为协作式的背压信号。
+///
+/// 与队列满、空这些硬性信号不同，该值只是建议：队列本身不读取它，推入不会因此失败。
+/// 取值的含义由使用者约定，例如0表示跟得上，`u8::MAX`表示严重滞后；消费者可以在每次轮询时按队列的填充程度更新它。
+/// 新注册的队列为0。
+#[unsafe(no_mangle)]
+pub extern "C" fn set_lag_level(process_id: usize, level: u8) {
+    BorrowedQueue::borrow(process_id).set_lag_level(level);
+}
+
+/// 读取消费者最近以`set_lag_level`发布的滞后程度，生产者可据此在推入前自行决定是否减速、丢弃或改用其他队列。
+///
+/// 该值是建议性的，可能已过时，见`set_lag_level`。
+#[unsafe(no_mangle)]
+pub extern "C" fn lag_level(process_id: usize) -> u8 {
+    BorrowedQueue::borrow(process_id).lag_level()
 }
 
 /// 记录当前进程的`pid`。
@@ -83,9 +1049,8 @@ pub extern "C" fn get_pid(process_id: usize) -> usize {
 /// - `pid`：调度模块中的进程id，用于通知机制
 #[unsafe(no_mangle)]
 pub extern "C" fn set_pid(process_id: usize, pid: usize) {
-    let slot_ref: SlotRef<'_, PerProcess, ARRAY_LEN> = unsafe { SlotRef::from_id(process_id) };
-    slot_ref.pid.store(pid, Ordering::Release);
-    slot_ref.into_id(); // prevent drop
+    let queue = BorrowedQueue::borrow(process_id);
+    queue.pid.store(pid, Ordering::Release);
 }
 
 /// 添加从msg_type（调度器协程id）到ntf_id（通知源id）的映射
@@ -95,47 +1060,590 @@ pub extern "C" fn map_add_entry(
     msg_type: usize,
     ntf_id: usize,
 ) -> Result<(), ()> {
-    let slot_ref: SlotRef<'_, PerProcess, ARRAY_LEN> = unsafe { SlotRef::from_id(process_id) };
-    let res = slot_ref.map.push((msg_type, ntf_id));
-    let res = res.map(|sref| {
+    let queue = BorrowedQueue::borrow(process_id);
+    let res = queue.map.push((msg_type, ntf_id));
+    res.map(|sref| {
         mem::forget(sref); // 保持引用计数
-    });
-    slot_ref.into_id(); // prevent drop
-    res
+    })
 }
 
 /// 根据msg_type（调度器协程id）查找ntf_id（通知源id）
 #[unsafe(no_mangle)]
 pub extern "C" fn map_get_ntf_id(process_id: usize, msg_type: usize) -> Option<usize> {
-    let slot_ref: SlotRef<'_, PerProcess, ARRAY_LEN> = unsafe { SlotRef::from_id(process_id) };
+    let queue = BorrowedQueue::borrow(process_id);
     for i in 0..ARRAY_LEN {
-        if let Some(&(this_msg_type, this_ntf_id)) = slot_ref.map.get(i) {
+        if let Some((this_msg_type, this_ntf_id)) = queue.map.get(i).map(|entry| *entry) {
             if this_msg_type == msg_type || this_msg_type == usize::MAX {
-                slot_ref.into_id(); // prevent drop
                 return Some(this_ntf_id);
             }
         }
     }
-    slot_ref.into_id(); // prevent drop
     None
 }
 
 /// 删除从msg_type（调度器协程id）到ntf_id（通知源id）的映射
 #[unsafe(no_mangle)]
 pub extern "C" fn map_pop_ntf_id(process_id: usize, msg_type: usize) -> Option<usize> {
-    let slot_ref: SlotRef<'_, PerProcess, ARRAY_LEN> = unsafe { SlotRef::from_id(process_id) };
+    let queue = BorrowedQueue::borrow(process_id);
     for i in 0..ARRAY_LEN {
-        if let Some(&(this_msg_type, this_ntf_id)) = slot_ref.map.get(i) {
+        // 复制出映射后立即释放`get`返回的引用，使`drop_slot`时引用计数恢复为1
+        if let Some((this_msg_type, this_ntf_id)) = queue.map.get(i).map(|entry| *entry) {
             if this_msg_type == msg_type {
                 // 删除slot
                 unsafe {
-                    slot_ref.map.drop_slot(i);
+                    queue.map.drop_slot(i);
                 }
-                slot_ref.into_id(); // prevent drop
                 return Some(this_ntf_id);
             }
         }
     }
-    slot_ref.into_id(); // prevent drop
     None
 }
+
+/// 设置跟踪回调，此后`register_process`、`deque_push`、`deque_pop`每次调用都会以一个`TraceEvent`调用该回调。
+///
+/// 传入`None`（C侧为空指针）则取消跟踪。回调只在设置它的进程内生效。
+#[cfg(feature = "trace")]
+#[unsafe(no_mangle)]
+pub extern "C" fn set_trace_callback(callback: Option<TraceCallback>) {
+    crate::trace::set_callback(callback);
+}
+
+/// 设置进程的IPC队列由空变为非空时调用的函数，调用时传入`process_id`，供事件循环以边沿触发的方式设置就绪标志。
+///
+/// 推入消息（包括`push_priority`）前若观察到队列的两个通道都为空，则推入成功后调用该函数，此后的推入不再调用，
+/// 直到队列再次被弹空。推入溢出队列或监视队列不会调用本队列的函数。
+///
+/// 判断与推入不是原子的，因此并发时可能多次调用（例如多个生产者同时观察到队列为空，或推入前消费者恰好弹空了队列），
+/// 调用者应能容忍重复的通知；函数在推入者的上下文中同步调用，应尽快返回。
+///
+/// 函数指针只在设置它的进程的地址空间内有效，因此只应在所有推入者都与设置者处于同一地址空间时使用。
+/// 传入`None`（C侧为空指针）则取消。
+#[unsafe(no_mangle)]
+pub extern "C" fn set_nonempty_hook(process_id: usize, hook: Option<NonemptyHook>) {
+    BorrowedQueue::borrow(process_id).set_nonempty_hook(process_id, hook);
+}
+
+/// 当前进程的IPC队列（`deque`）是否已满，即其中的消息数是否达到`QUEUE_LEN`（队列的容量，见`LockFreeDeque::capacity`）。
+///
+/// 只反映本队列：配置了溢出队列时，本队列满后推入仍可能成功。并发推入或弹出时只是近似值。
+#[unsafe(no_mangle)]
+pub extern "C" fn queue_is_full(process_id: usize) -> bool {
+    BorrowedQueue::borrow(process_id).deque.is_full()
+}
+
+/// 估计当前进程IPC队列的压力（0-255），综合了队列的填充程度与最近的重试次数，可供调度器做路由决策。
+///
+/// 重试次数只在注册时指定了`QueueConfig::metrics`的队列上统计，否则只反映填充程度。
+#[cfg(feature = "metrics")]
+#[unsafe(no_mangle)]
+pub extern "C" fn queue_pressure(process_id: usize) -> u8 {
+    BorrowedQueue::borrow(process_id).pressure()
+}
+
+/// 返回成功推入当前进程IPC队列的消息总数，作为`queue_rate_since`的快照，见`PerProcess::push_count`。
+///
+/// 只在注册时指定了`QueueConfig::metrics`的队列上统计，否则总是0。
+#[cfg(feature = "metrics")]
+#[unsafe(no_mangle)]
+pub extern "C" fn queue_push_count(process_id: usize) -> u64 {
+    BorrowedQueue::borrow(process_id).push_count()
+}
+
+/// 估计当前进程IPC队列自快照以来每个时间单位推入的消息数，可供调度器据此增减消费者，见`PerProcess::rate_since`。
+///
+/// - `last_count`：之前某一时刻`queue_push_count`的返回值
+/// - `elapsed_ticks`：自那一时刻经过的时间，单位由调用者决定
+#[cfg(feature = "metrics")]
+#[unsafe(no_mangle)]
+pub extern "C" fn queue_rate_since(process_id: usize, last_count: u64, elapsed_ticks: u64) -> u64 {
+    BorrowedQueue::borrow(process_id).rate_since(last_count, elapsed_ticks)
+}
+
+/// 返回进程IPC数据结构（包括其队列）在全局队列数组中的存储位置：起始地址与字节数。
+///
+/// 全局队列数组是一块连续内存，此函数只计算地址，不做绑定：集成者可据此调用`mbind`/`move_pages`等平台相关接口，
+/// 将队列的内存绑定到其主要消费者所在的NUMA节点上。计算方式见`SlotArray::value_range`。
+/// 返回的范围一般不是页对齐的，两端的页可能与相邻进程的数据共享。
+///
+/// `process_id`越界时panic；id未注册或已失效时同样返回其槽位的存储位置。
+#[cfg(feature = "std")]
+#[unsafe(no_mangle)]
+pub extern "C" fn queue_memory_range(process_id: usize) -> (usize, usize) {
+    get_queue_array().value_range(process_id & ((1 << crate::ID_INDEX_BITS) - 1))
+}
+
+/// 释放全局队列数组中所有仍在使用的进程IPC数据结构，运行其析构函数（包括清空其中的队列），返回释放的数量。
+///
+/// 全局数组被原地写入vvar区域（或调用者提供的地址），其析构函数不会自动运行，因此需要在关闭时手动调用本函数。
+///
+/// # Safety
+///
+/// 调用后，任何进程都不得再使用此前注册得到的id或`SlotRef`，且调用期间不得有其他并发操作。
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn vqueue_shutdown() -> usize {
+    unsafe { get_queue_array().drop_in_place_all() }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use super::{
+        BorrowedQueue, POP_INTO_CONTENDED, POP_INTO_EMPTY, POP_INTO_NOT_REGISTERED, POP_INTO_NULL,
+        POP_INTO_OK, POP_LARGE_EMPTY, POP_LARGE_OK, POP_LARGE_ORPHAN, POP_LARGE_TRUNCATED,
+        PUSH_LARGE_FAILED, PUSH_LARGE_OK, PUSH_TRUNCATED, QUEUE_PIN_NOT_REGISTERED, QUEUE_PIN_OK,
+        TRY_PUSH_FULL, TRY_PUSH_NOT_REGISTERED, TRY_PUSH_OK, broadcast_in, lookup_named_in,
+        name_hash, pop_into_bounded_in, pop_into_in, pop_large_in, push_bounded_in, push_fields_in,
+        push_large_in, queue_pin_in, register_named_in, try_push_in,
+    };
+    use crate::{IPCItem, PerProcess, PushError, slot_array::SlotArray};
+
+    const ITEM: IPCItem = IPCItem {
+        sender: 0,
+        msg_type: 0,
+        rep_type: 0,
+        data: [0; 8],
+    };
+
+    #[test]
+    fn test_borrowed_queue_panic() {
+        static ARRAY: SlotArray<PerProcess, 4> = SlotArray::new();
+        std::thread::Builder::new()
+            .stack_size(16 << 20)
+            .spawn(|| {
+                let slot_ref = ARRAY.push(PerProcess::default()).unwrap();
+                let id = slot_ref.id();
+                let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+                    let queue = BorrowedQueue::borrow_in(&ARRAY, id);
+                    queue.push(ITEM).unwrap();
+                    panic!("operation failed midway");
+                }));
+                assert!(res.is_err());
+
+                // The unwinding gave back the guard's count and nothing else: the queue is still registered,
+                // and dropping the original reference is enough to free it
+                let queue = BorrowedQueue::borrow_in(&ARRAY, id);
+                assert_eq!(queue.deque.len(), 1);
+                drop(queue);
+                drop(slot_ref);
+                assert!(ARRAY.try_ref_id(id).is_none());
+            })
+            .unwrap()
+            .join()
+            .unwrap();
+    }
+
+    #[test]
+    fn test_named() {
+        static ARRAY: SlotArray<PerProcess, 4> = SlotArray::new();
+        // `PerProcess` is too large for the default test thread stack
+        std::thread::Builder::new()
+            .stack_size(16 << 20)
+            .spawn(|| {
+                assert!(lookup_named_in(&ARRAY, b"log").is_none());
+                let log = register_named_in(&ARRAY, b"log").unwrap();
+                let net = register_named_in(&ARRAY, b"net").unwrap();
+                // Without collision, a name gets the slot its hash points to
+                assert_eq!(log.id(), name_hash(b"log") as usize % 4);
+                assert_eq!(lookup_named_in(&ARRAY, b"log").unwrap().id(), log.id());
+                assert_eq!(lookup_named_in(&ARRAY, b"net").unwrap().id(), net.id());
+                assert!(register_named_in(&ARRAY, b"log").is_err());
+                assert!(lookup_named_in(&ARRAY, b"fs").is_none());
+
+                // Dropping the last reference unregisters the name
+                let id = log.id();
+                drop(log);
+                assert!(lookup_named_in(&ARRAY, b"log").is_none());
+                let log = register_named_in(&ARRAY, b"log").unwrap();
+                assert_ne!(log.id(), id);
+                assert_eq!(lookup_named_in(&ARRAY, b"log").unwrap().id(), log.id());
+            })
+            .unwrap()
+            .join()
+            .unwrap();
+    }
+
+    #[cfg(not(feature = "single-thread"))]
+    #[test]
+    fn test_named_race() {
+        use std::sync::Barrier;
+
+        const THREADS: usize = 4;
+        static ARRAY: SlotArray<PerProcess, 8> = SlotArray::new();
+        let barrier = Barrier::new(THREADS);
+        for _ in 0..50 {
+            let winners = std::thread::scope(|s| {
+                let handles: std::vec::Vec<_> = (0..THREADS)
+                    .map(|_| {
+                        std::thread::Builder::new()
+                            .stack_size(16 << 20)
+                            .spawn_scoped(s, || {
+                                barrier.wait();
+                                register_named_in(&ARRAY, b"log").ok()
+                            })
+                            .unwrap()
+                    })
+                    .collect();
+                handles
+                    .into_iter()
+                    .filter_map(|handle| handle.join().unwrap())
+                    .collect::<std::vec::Vec<_>>()
+            });
+            // Racing registrations of one name never both succeed
+            assert!(winners.len() <= 1);
+            match winners.first() {
+                Some(winner) => {
+                    assert_eq!(lookup_named_in(&ARRAY, b"log").unwrap().id(), winner.id())
+                }
+                None => assert!(lookup_named_in(&ARRAY, b"log").is_none()),
+            }
+        }
+    }
+
+    #[cfg(not(feature = "single-thread"))]
+    #[test]
+    fn test_try_push_unregistering() {
+        use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+        use std::thread;
+
+        const PRODUCERS: usize = 4;
+        static ARRAY: SlotArray<PerProcess, 1> = SlotArray::new();
+        static DONE: AtomicBool = AtomicBool::new(false);
+        static ID: AtomicUsize = AtomicUsize::new(0);
+
+        assert_eq!(try_push_in(&ARRAY, 0, ITEM), TRY_PUSH_NOT_REGISTERED);
+        assert_eq!(try_push_in(&ARRAY, 1, ITEM), TRY_PUSH_NOT_REGISTERED);
+
+        // Register and unregister the only queue over and over
+        let churn = thread::Builder::new()
+            .stack_size(16 << 20)
+            .spawn(|| {
+                for _ in 0..200 {
+                    let slot_ref = ARRAY.push(PerProcess::default()).unwrap();
+                    ID.store(slot_ref.id(), Ordering::Release);
+                    thread::yield_now();
+                    drop(slot_ref);
+                }
+                DONE.store(true, Ordering::Release);
+            })
+            .unwrap();
+        let producers: std::vec::Vec<_> = (0..PRODUCERS)
+            .map(|_| {
+                thread::spawn(|| {
+                    while !DONE.load(Ordering::Acquire) {
+                        let code = try_push_in(&ARRAY, ID.load(Ordering::Acquire), ITEM);
+                        assert!(code == TRY_PUSH_OK || code == TRY_PUSH_NOT_REGISTERED);
+                        thread::yield_now();
+                    }
+                })
+            })
+            .collect();
+        churn.join().unwrap();
+        for producer in producers {
+            producer.join().unwrap();
+        }
+        assert_eq!(
+            try_push_in(&ARRAY, ID.load(Ordering::Acquire), ITEM),
+            TRY_PUSH_NOT_REGISTERED
+        );
+    }
+
+    #[test]
+    fn test_try_push_full() {
+        static ARRAY: SlotArray<PerProcess, 1> = SlotArray::new();
+        // `PerProcess` is too large for the default test thread stack
+        let slot_ref = std::thread::Builder::new()
+            .stack_size(16 << 20)
+            .spawn(|| ARRAY.push(PerProcess::default()).unwrap())
+            .unwrap()
+            .join()
+            .unwrap();
+        for _ in 0..slot_ref.deque.capacity() {
+            assert_eq!(try_push_in(&ARRAY, 0, ITEM), TRY_PUSH_OK);
+        }
+        assert_eq!(try_push_in(&ARRAY, 0, ITEM), TRY_PUSH_FULL);
+    }
+
+    #[test]
+    fn test_push_bounded() {
+        static ARRAY: SlotArray<PerProcess, 1> = SlotArray::new();
+        assert_eq!(
+            push_bounded_in(&ARRAY, 0, ITEM, 10).map_err(|err| err.map(|_| ())),
+            Err(PushError::Closed(()))
+        );
+        let slot_ref = std::thread::Builder::new()
+            .stack_size(16 << 20)
+            .spawn(|| ARRAY.push(PerProcess::default()).unwrap())
+            .unwrap()
+            .join()
+            .unwrap();
+        for _ in 0..slot_ref.deque.capacity() {
+            assert!(push_bounded_in(&ARRAY, 0, ITEM, 10).is_ok());
+        }
+        assert!(matches!(
+            push_bounded_in(&ARRAY, 0, ITEM, 10),
+            Err(PushError::Full(_))
+        ));
+        assert_eq!(slot_ref.deque.len(), slot_ref.deque.capacity());
+    }
+
+    #[test]
+    fn test_pop_into() {
+        use core::mem::MaybeUninit;
+
+        static ARRAY: SlotArray<PerProcess, 1> = SlotArray::new();
+        // The C caller's pre-allocated item
+        let mut out = MaybeUninit::<IPCItem>::uninit();
+        let out_ptr = out.as_mut_ptr();
+        assert_eq!(
+            unsafe { pop_into_in(&ARRAY, 0, out_ptr) },
+            POP_INTO_NOT_REGISTERED
+        );
+
+        let slot_ref = std::thread::Builder::new()
+            .stack_size(16 << 20)
+            .spawn(|| ARRAY.push(PerProcess::default()).unwrap())
+            .unwrap()
+            .join()
+            .unwrap();
+        let id = slot_ref.id();
+        assert_eq!(unsafe { pop_into_in(&ARRAY, id, out_ptr) }, POP_INTO_EMPTY);
+        for i in 0..2 {
+            let item = IPCItem {
+                msg_type: i,
+                ..ITEM
+            };
+            assert_eq!(try_push_in(&ARRAY, id, item), TRY_PUSH_OK);
+        }
+        for i in 0..2 {
+            assert_eq!(unsafe { pop_into_in(&ARRAY, id, out_ptr) }, POP_INTO_OK);
+            assert_eq!(unsafe { (*out_ptr).msg_type }, i);
+        }
+        assert_eq!(unsafe { pop_into_in(&ARRAY, id, out_ptr) }, POP_INTO_EMPTY);
+        assert_eq!(
+            unsafe { pop_into_in(&ARRAY, id, core::ptr::null_mut()) },
+            POP_INTO_NULL
+        );
+    }
+
+    #[test]
+    #[cfg(all(feature = "deque-slot", feature = "deque-back"))]
+    fn test_pop_into_bounded_held_slot() {
+        use crate::QueuedItem;
+        use core::mem::MaybeUninit;
+
+        static ARRAY: SlotArray<PerProcess, 1> = SlotArray::new();
+        let slot_ref = std::thread::Builder::new()
+            .stack_size(16 << 20)
+            .spawn(|| ARRAY.push(PerProcess::default()).unwrap())
+            .unwrap()
+            .join()
+            .unwrap();
+        let id = slot_ref.id();
+        let mut out = MaybeUninit::<IPCItem>::uninit();
+        let out_ptr = out.as_mut_ptr();
+        let push = |i| {
+            let item = IPCItem {
+                msg_type: i,
+                ..ITEM
+            };
+            assert_eq!(try_push_in(&ARRAY, id, item), TRY_PUSH_OK);
+        };
+
+        push(0);
+        push(1);
+        // A producer holding a slot between the two halves of the queue, pushed at the same end
+        let mut guard = match crate::config::PUSH_FRONT {
+            true => slot_ref.deque.push_slot_front(),
+            false => slot_ref.deque.push_slot_back(),
+        }
+        .unwrap();
+        push(2);
+
+        // The drain stops at the held slot instead of waiting for it
+        for i in 0..2 {
+            assert_eq!(
+                unsafe { pop_into_bounded_in(&ARRAY, id, out_ptr) },
+                POP_INTO_OK
+            );
+            assert_eq!(unsafe { (*out_ptr).msg_type }, i);
+        }
+        assert_eq!(
+            unsafe { pop_into_bounded_in(&ARRAY, id, out_ptr) },
+            POP_INTO_CONTENDED
+        );
+
+        guard.write(QueuedItem::new(
+            IPCItem {
+                msg_type: 3,
+                ..ITEM
+            },
+            0,
+        ));
+        drop(guard);
+        for i in [3, 2] {
+            assert_eq!(
+                unsafe { pop_into_bounded_in(&ARRAY, id, out_ptr) },
+                POP_INTO_OK
+            );
+            assert_eq!(unsafe { (*out_ptr).msg_type }, i);
+        }
+        assert_eq!(
+            unsafe { pop_into_bounded_in(&ARRAY, id, out_ptr) },
+            POP_INTO_EMPTY
+        );
+    }
+
+    #[test]
+    fn test_large_status() {
+        use crate::{Discipline, MSG_CONTINUED, QueueConfig};
+
+        // `PerProcess` is too large for the default test thread stack
+        std::thread::Builder::new()
+            .stack_size(16 << 20)
+            .spawn(|| {
+                let queue = PerProcess::default();
+                let data: [u64; 20] = core::array::from_fn(|i| i as u64);
+                let mut out = [0u64; 20];
+                let mut len = 0;
+                let pop = |out: &mut [u64], len: &mut usize| unsafe {
+                    pop_large_in(&queue, out.as_mut_ptr(), out.len(), len)
+                };
+
+                assert_eq!(pop(&mut out, &mut len), POP_LARGE_EMPTY);
+                assert_eq!(
+                    unsafe { push_large_in(&queue, data.as_ptr(), data.len()) },
+                    PUSH_LARGE_OK
+                );
+                assert_eq!(pop(&mut out, &mut len), POP_LARGE_OK);
+                assert_eq!((out, len), (data, 20));
+                // A short buffer still reports the total length
+                assert_eq!(
+                    unsafe { push_large_in(&queue, data.as_ptr(), data.len()) },
+                    PUSH_LARGE_OK
+                );
+                assert_eq!(pop(&mut out[..4], &mut len), POP_LARGE_OK);
+                assert_eq!(len, 20);
+                assert_eq!(
+                    unsafe { pop_large_in(&queue, core::ptr::null_mut(), 0, &mut len) },
+                    POP_LARGE_EMPTY
+                );
+
+                let fragment = |index, last| IPCItem {
+                    sender: 20,
+                    msg_type: if last { 0 } else { MSG_CONTINUED },
+                    rep_type: index,
+                    ..ITEM
+                };
+                // A fragment whose first one was taken is dropped
+                assert!(queue.push(fragment(1, false)).is_ok());
+                len = usize::MAX;
+                assert_eq!(pop(&mut out, &mut len), POP_LARGE_ORPHAN);
+                assert_eq!(len, usize::MAX);
+                // A message whose remaining fragments were taken reports what was received
+                assert!(queue.push(fragment(0, false)).is_ok());
+                assert!(queue.push(ITEM).is_ok());
+                assert_eq!(pop(&mut out, &mut len), POP_LARGE_TRUNCATED);
+                assert_eq!(len, 8);
+                assert!(queue.pop().is_some());
+
+                let lifo = PerProcess::with_config(QueueConfig {
+                    discipline: Discipline::Lifo,
+                    ..Default::default()
+                });
+                assert_eq!(
+                    unsafe { push_large_in(&lifo, data.as_ptr(), data.len()) },
+                    PUSH_LARGE_FAILED
+                );
+            })
+            .unwrap()
+            .join()
+            .unwrap();
+    }
+
+    #[test]
+    fn test_queue_pin() {
+        static ARRAY: SlotArray<PerProcess, 1> = SlotArray::new();
+        assert_eq!(queue_pin_in(&ARRAY, 0), QUEUE_PIN_NOT_REGISTERED);
+
+        let slot_ref = std::thread::Builder::new()
+            .stack_size(16 << 20)
+            .spawn(|| ARRAY.push(PerProcess::default()).unwrap())
+            .unwrap()
+            .join()
+            .unwrap();
+        let id = slot_ref.id();
+        assert_eq!(queue_pin_in(&ARRAY, id), QUEUE_PIN_OK);
+
+        // Unregistered by another thread in the middle of the critical section
+        std::thread::spawn(move || drop(slot_ref)).join().unwrap();
+        assert_eq!(try_push_in(&ARRAY, id, ITEM), TRY_PUSH_OK);
+        assert_eq!(
+            unsafe { pop_into_in(&ARRAY, id, &mut { ITEM }) },
+            POP_INTO_OK
+        );
+
+        unsafe { ARRAY.unpin_id(id) };
+        assert_eq!(try_push_in(&ARRAY, id, ITEM), TRY_PUSH_NOT_REGISTERED);
+        assert_eq!(queue_pin_in(&ARRAY, id), QUEUE_PIN_NOT_REGISTERED);
+    }
+
+    #[test]
+    fn test_push_fields_truncated() {
+        static ARRAY: SlotArray<PerProcess, 1> = SlotArray::new();
+        let slot_ref = std::thread::Builder::new()
+            .stack_size(16 << 20)
+            .spawn(|| ARRAY.push(PerProcess::default()).unwrap())
+            .unwrap()
+            .join()
+            .unwrap();
+        let data: [u64; 12] = core::array::from_fn(|i| i as u64 + 1);
+
+        let res = push_fields_in(&ARRAY, 0, 1, 2, 3, &data);
+        assert_eq!(res, TRY_PUSH_OK | PUSH_TRUNCATED);
+        assert_ne!(res & PUSH_TRUNCATED, 0);
+        let item = slot_ref.pop().unwrap();
+        assert_eq!((item.sender, item.msg_type, item.rep_type), (1, 2, 3));
+        assert_eq!(item.data, data[..8]);
+
+        // Fits: no flag, the rest is zero-filled
+        assert_eq!(push_fields_in(&ARRAY, 0, 1, 2, 3, &data[..3]), TRY_PUSH_OK);
+        assert_eq!(slot_ref.pop().unwrap().data, [1, 2, 3, 0, 0, 0, 0, 0]);
+        // Failures are reported without the flag
+        assert_eq!(
+            push_fields_in(&ARRAY, 1, 1, 2, 3, &data),
+            TRY_PUSH_NOT_REGISTERED
+        );
+    }
+
+    #[test]
+    fn test_broadcast() {
+        static ARRAY: SlotArray<PerProcess, 3> = SlotArray::new();
+        let slot_refs: std::vec::Vec<_> = std::thread::Builder::new()
+            .stack_size(16 << 20)
+            .spawn(|| {
+                (0..3)
+                    .map(|_| ARRAY.push(PerProcess::default()).unwrap())
+                    .collect()
+            })
+            .unwrap()
+            .join()
+            .unwrap();
+        let ids: std::vec::Vec<_> = slot_refs.iter().map(|slot_ref| slot_ref.id()).collect();
+        // Fill the second queue
+        for _ in 0..slot_refs[1].deque.capacity() {
+            assert_eq!(try_push_in(&ARRAY, ids[1], ITEM), TRY_PUSH_OK);
+        }
+
+        let item = IPCItem { sender: 7, ..ITEM };
+        assert_eq!(broadcast_in(&ARRAY, &ids, item), 2);
+        assert_eq!(slot_refs[0].pop().unwrap().sender, 7);
+        assert_eq!(slot_refs[2].pop().unwrap().sender, 7);
+        assert_eq!(slot_refs[1].deque.len(), slot_refs[1].deque.capacity());
+        assert!(slot_refs[1].deque.is_full());
+
+        // Unregistered ids are skipped as well
+        assert_eq!(broadcast_in(&ARRAY, &[ids[0], 3, ids[2]], item), 2);
+        assert_eq!(broadcast_in(&ARRAY, &[], item), 0);
+    }
+}
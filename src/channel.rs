@@ -0,0 +1,129 @@
+//! 通道形式的Rust接口：将注册、`deque_push`与`deque_pop`包装为类似`std::sync::mpsc`的发送端与接收端。
+
+use crate::{ARRAY_LEN, IPCItem, PerProcess, SlotRef};
+
+/// 通道的发送端，持有队列的`SlotRef`
+///
+/// 可以clone，多个发送端可以并发发送。
+pub struct Sender<const N: usize = ARRAY_LEN> {
+    slot_ref: SlotRef<'static, PerProcess, N>,
+}
+
+/// 通道的接收端，持有队列的`SlotRef`
+///
+/// 可以clone，多个接收端可以并发接收，每条消息只被其中一个接收。
+pub struct Receiver<const N: usize = ARRAY_LEN> {
+    slot_ref: SlotRef<'static, PerProcess, N>,
+}
+
+/// 注册一个新的队列，返回其发送端与接收端。
+///
+/// 底层仍是共享内存中的无锁双端队列，与`register_process`得到的队列相同，收发顺序与`deque_push`、`deque_pop`一致。
+/// 发送端与接收端都可以clone，因此通道是多生产者多消费者（MPMC）的。
+/// 所有发送端与接收端（以及由它们得到的其他`SlotRef`）都drop后，队列被释放，其中剩余的消息一并丢弃。
+///
+/// 全局队列数组已满时失败。
+pub fn channel() -> Result<(Sender, Receiver), ()> {
+    crate::register_process().map(split)
+}
+
+/// 由同一队列的`SlotRef`构造发送端与接收端
+fn split<const N: usize>(slot_ref: SlotRef<'static, PerProcess, N>) -> (Sender<N>, Receiver<N>) {
+    (
+        Sender {
+            slot_ref: slot_ref.clone(),
+        },
+        Receiver { slot_ref },
+    )
+}
+
+impl<const N: usize> Sender<N> {
+    /// 发送一条消息，与`deque_push`相同。
+    ///
+    /// 队列满时的行为由`QueueConfig::overflow`决定（`channel`使用默认配置，即失败并返回`Err(item)`）。
+    pub fn send(&self, item: IPCItem) -> Result<(), IPCItem> {
+        self.slot_ref.push(item)
+    }
+}
+
+impl<const N: usize> Receiver<N> {
+    /// 接收一条消息，与`deque_pop`相同。队列为空时立即返回`None`，不等待。
+    pub fn recv(&self) -> Option<IPCItem> {
+        self.slot_ref.pop()
+    }
+}
+
+impl<const N: usize> Clone for Sender<N> {
+    fn clone(&self) -> Self {
+        Self {
+            slot_ref: self.slot_ref.clone(),
+        }
+    }
+}
+
+impl<const N: usize> Clone for Receiver<N> {
+    fn clone(&self) -> Self {
+        Self {
+            slot_ref: self.slot_ref.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use super::split;
+    use crate::{IPCItem, PerProcess, slot_array::SlotArray};
+
+    #[cfg(not(feature = "single-thread"))]
+    #[test]
+    fn test_channel() {
+        static ARRAY: SlotArray<PerProcess, 1> = SlotArray::new();
+        // `PerProcess` is too large for the default test thread stack
+        let (sender, receiver) = std::thread::Builder::new()
+            .stack_size(16 << 20)
+            .spawn(|| split(ARRAY.push(PerProcess::default()).unwrap()))
+            .unwrap()
+            .join()
+            .unwrap();
+        assert!(receiver.recv().is_none());
+
+        let senders: std::vec::Vec<_> = (0..2u64)
+            .map(|id| {
+                let sender = sender.clone();
+                std::thread::spawn(move || {
+                    for i in 0..10 {
+                        let item = IPCItem {
+                            sender: id,
+                            msg_type: i,
+                            rep_type: 0,
+                            data: [0; 8],
+                        };
+                        while sender.send(item).is_err() {
+                            std::thread::yield_now();
+                        }
+                    }
+                })
+            })
+            .collect();
+        drop(sender);
+        for sender in senders {
+            sender.join().unwrap();
+        }
+
+        // FIFO per sender
+        let mut next = [0; 2];
+        while let Some(item) = receiver.recv() {
+            assert_eq!(item.msg_type, next[item.sender as usize]);
+            next[item.sender as usize] += 1;
+        }
+        assert_eq!(next, [10, 10]);
+
+        // The queue is freed once every end is dropped
+        let other = receiver.clone();
+        drop(receiver);
+        assert!(ARRAY.try_ref(0).is_some());
+        drop(other);
+        assert!(ARRAY.try_ref(0).is_none());
+    }
+}
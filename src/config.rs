@@ -0,0 +1,1014 @@
+//! 队列在注册时的配置。
+
+use core::sync::atomic::Ordering;
+
+#[cfg(feature = "checksum")]
+use crate::Corrupted;
+use crate::{
+    Contended, IPCItem, LockFreeDeque, PerProcess, PushError, QueuedItem, SlotRef, get_queue_array,
+    slot_array::SlotArray,
+};
+
+/// `deque_push`推入的一端：为true时推入前端，为false时推入后端。
+///
+/// 默认推入前端，先进先出的队列从后端弹出；启用`push-back-default` feature后推入后端，从前端弹出。
+/// 两种方式对使用`deque_push`/`deque_pop`的进程而言顺序相同，但直接操作队列两端的代码（例如`deque_push_front`
+/// 与`deque_pop`混用）会看到不同的顺序，因此同一队列的所有使用者必须以相同的设置构建。
+pub(crate) const PUSH_FRONT: bool = !cfg!(feature = "push-back-default");
+
+#[cfg(all(
+    feature = "push-back-default",
+    not(all(feature = "deque-front", feature = "deque-back"))
+))]
+compile_error!(
+    "the `push-back-default` feature requires the `deque-front` and `deque-back` features"
+);
+
+/// 队列的出队顺序
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Discipline {
+    /// 先进先出（默认）
+    #[default]
+    Fifo = 0,
+    /// 后进先出
+    Lifo = 1,
+}
+
+/// 队列满时`deque_push`的行为
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// 推入失败，返回该消息（默认）
+    #[default]
+    Fail = 0,
+    /// 丢弃最早推入的消息，为新消息腾出空间
+    Overwrite = 1,
+}
+
+/// 队列由空变为非空时调用的函数，参数为设置时传入的队列id，见`set_nonempty_hook`
+pub type NonemptyHook = extern "C" fn(queue_id: usize);
+
+/// 消费者登记的唤醒句柄：队列由空变为非空时，推入者调用`wake(data)`，见`SlotRef::set_waker`
+///
+/// 与`NonemptyHook`不同，句柄属于拥有队列的某一个消费者，只唤醒它，而不是通知所有关心该队列的一方。
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct WakeToken {
+    /// 唤醒函数，在推入者的上下文中同步调用，应尽快返回
+    pub wake: extern "C" fn(data: *const ()),
+    /// 传给`wake`的不透明数据，例如消费者的线程或协程句柄
+    pub data: *const (),
+}
+
+/// `QueueConfig::monitor`的取值，表示不复制推入的消息
+pub const NO_MONITOR: usize = usize::MAX;
+
+/// `QueueConfig::overflow_queue`的取值，表示没有溢出队列
+pub const NO_OVERFLOW_QUEUE: usize = usize::MAX;
+
+/// 队列的配置，在注册时通过`register_process_configured`指定，注册后不可修改。
+///
+/// 默认值与`register_process`的行为一致：先进先出，队列满时推入失败，不统计指标，不复制消息，没有溢出队列。
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct QueueConfig {
+    /// 出队顺序
+    pub discipline: Discipline,
+    /// 队列满时的行为
+    pub overflow: OverflowPolicy,
+    /// 是否为该队列统计指标（需要启用`metrics` feature），见`queue_pressure`
+    pub metrics: bool,
+    /// 监视队列的id，每条成功推入的消息都会复制一份推入其中，见`register_tee_queue`。`NO_MONITOR`表示不复制
+    pub monitor: usize,
+    /// 溢出队列的id，队列满而推入失败时改为推入其中，见`register_with_overflow`。`NO_OVERFLOW_QUEUE`表示没有溢出队列
+    pub overflow_queue: usize,
+}
+
+impl Default for QueueConfig {
+    fn default() -> Self {
+        Self {
+            discipline: Discipline::default(),
+            overflow: OverflowPolicy::default(),
+            metrics: false,
+            monitor: NO_MONITOR,
+            overflow_queue: NO_OVERFLOW_QUEUE,
+        }
+    }
+}
+
+impl PerProcess {
+    /// 按照配置向IPC队列中推入一条消息，并为其分配序号。
+    ///
+    /// 消息推入`PUSH_FRONT`决定的一端，默认为前端，因此最早推入的消息位于后端。
+    pub(crate) fn push(&self, item: IPCItem) -> Result<(), IPCItem> {
+        self.push_at(item, PUSH_FRONT)
+    }
+
+    /// 向IPC队列的前端或后端推入一条消息，并为其分配序号。
+    ///
+    /// 队列满时的行为由`QueueConfig::overflow`决定，`Overwrite`时丢弃另一端的消息。
+    /// 仍然失败且配置了溢出队列时，改为推入溢出队列，见`spill`。
+    /// 推入成功且配置了监视队列时，将消息复制一份推入监视队列，见`tee`。
+    pub(crate) fn push_at(&self, item: IPCItem, front: bool) -> Result<(), IPCItem> {
+        let was_empty = self.is_drained();
+        match self.push_at_(item, front) {
+            Ok(()) if was_empty => self.notify_nonempty(),
+            Ok(()) => {}
+            Err(item) => {
+                if self.config.overflow_queue == NO_OVERFLOW_QUEUE {
+                    return Err(item);
+                }
+                self.spill(get_queue_array(), item)?;
+            }
+        }
+        if self.config.monitor != NO_MONITOR {
+            self.tee(get_queue_array(), item);
+        }
+        Ok(())
+    }
+
+    /// 向IPC队列推入一条消息并为其分配序号，因竞争重试`max_retries`次后放弃，失败时给出原因，见`PushError`。
+    ///
+    /// 推入`PUSH_FRONT`决定的一端。与`push`不同，不应用`QueueConfig::overflow`、溢出队列与监视队列，
+    /// 由调用者根据失败原因决定是否重试。
+    pub(crate) fn push_bounded(
+        &self,
+        item: IPCItem,
+        max_retries: usize,
+    ) -> Result<(), PushError<IPCItem>> {
+        let was_empty = self.is_drained();
+        let item = QueuedItem::new(item, self.seq.fetch_add(1, Ordering::Relaxed));
+        let res = match PUSH_FRONT {
+            true => self.deque.push_front_bounded(item, max_retries),
+            #[cfg(feature = "deque-back")]
+            false => self.deque.push_back_bounded(item, max_retries),
+            #[cfg(not(feature = "deque-back"))]
+            false => Err(PushError::Full(item)),
+        };
+        match res {
+            Ok(()) => {
+                #[cfg(feature = "metrics")]
+                if self.config.metrics {
+                    self.metrics.record_push();
+                }
+                if was_empty {
+                    self.notify_nonempty();
+                }
+                Ok(())
+            }
+            Err(err) => Err(err.map(|rejected| rejected.item)),
+        }
+    }
+
+    /// 将消息复制一份推入`array`中的监视队列，推入的一端与`deque_push`相同，因此监视者用`deque_pop`按推入顺序读取。
+    ///
+    /// 复制是尽力而为的：监视队列不存在（id无效或已注销）或推入失败（例如队列满）时，复制的消息被静默丢弃，
+    /// 不影响本队列的推入结果。复制的消息不会再被复制到监视队列自己的监视队列中。
+    pub(crate) fn tee<const N: usize>(&self, array: &SlotArray<PerProcess, N>, item: IPCItem) {
+        if let Some(monitor) = array.try_ref_id(self.config.monitor) {
+            let _ = monitor.push_at_(item, PUSH_FRONT);
+        }
+    }
+
+    /// 将本队列放不下的消息推入`array`中的溢出队列，推入的一端与`deque_push`相同，序号由溢出队列分配。
+    ///
+    /// 溢出队列不存在（id无效或已注销）或也已满时失败，返回该消息。溢出队列自己的溢出队列不会再被使用。
+    pub(crate) fn spill<const N: usize>(
+        &self,
+        array: &SlotArray<PerProcess, N>,
+        item: IPCItem,
+    ) -> Result<(), IPCItem> {
+        match array.try_ref_id(self.config.overflow_queue) {
+            Some(overflow) => overflow.push_at_(item, PUSH_FRONT),
+            None => Err(item),
+        }
+    }
+
+    /// 向IPC队列的前端或后端推入一条消息，并为其分配序号，不复制到监视队列
+    fn push_at_(&self, item: IPCItem, front: bool) -> Result<(), IPCItem> {
+        let item = QueuedItem::new(item, self.seq.fetch_add(1, Ordering::Relaxed));
+        match self.config.overflow {
+            OverflowPolicy::Fail => self.push_(item, front).map_err(|rejected| rejected.item),
+            OverflowPolicy::Overwrite => {
+                let mut item = item;
+                loop {
+                    match self.push_(item, front) {
+                        Ok(()) => return Ok(()),
+                        Err(rejected) => {
+                            // 丢弃最早的消息后重试
+                            item = rejected;
+                            let _ = self.pop_(!front);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// 按照配置从IPC队列中弹出一条消息。
+    pub(crate) fn pop(&self) -> Option<IPCItem> {
+        self.pop_with_seq().map(|(item, _)| item)
+    }
+
+    /// 按照配置从IPC队列中弹出一条消息，同时返回其推入时分配的序号。
+    ///
+    /// 同一队列中的序号互不相同，且同一生产者推入的消息序号递增；
+    /// 但多个生产者并发推入时，消息在队列中的顺序不一定与序号顺序一致。
+    /// 序号在`u64::MAX`后回绕到0，实际上不会发生。
+    ///
+    /// 本队列为空且配置了溢出队列时，从溢出队列弹出，此时序号由溢出队列分配，与本队列的序号无关。
+    pub(crate) fn pop_with_seq(&self) -> Option<(IPCItem, u64)> {
+        self.pop_queued().map(|queued| (queued.item, queued.seq))
+    }
+
+    /// 按照配置从IPC队列中弹出一条消息，同时返回其推入时分配的全局序号，见`pop_with_global_seq`。
+    #[cfg(feature = "global-seq")]
+    pub(crate) fn pop_with_global_seq(&self) -> Option<(IPCItem, u64)> {
+        self.pop_queued()
+            .map(|queued| (queued.item, queued.global_seq))
+    }
+
+    /// 按照配置从IPC队列中弹出一条消息，并校验其校验和，见`deque_pop_checked`。
+    ///
+    /// 与`pop`相同，本队列为空且配置了溢出队列时，从溢出队列弹出。
+    #[cfg(feature = "checksum")]
+    pub(crate) fn pop_checked(&self) -> Result<Option<IPCItem>, Corrupted> {
+        match self.pop_queued() {
+            Some(queued) if !queued.is_intact() => Err(Corrupted(queued.item)),
+            queued => Ok(queued.map(|queued| queued.item)),
+        }
+    }
+
+    /// 按照配置从IPC队列中弹出一条消息，本队列为空且配置了溢出队列时，从溢出队列弹出
+    fn pop_queued(&self) -> Option<QueuedItem> {
+        self.pop_local().or_else(|| {
+            if self.config.overflow_queue == NO_OVERFLOW_QUEUE {
+                return None;
+            }
+            self.pop_overflow(get_queue_array())
+        })
+    }
+
+    /// 与`pop`相同地弹出一条消息，但下一条消息所在的槽位被持有时不等待，见`pop_into_bounded`。
+    ///
+    /// 该槽位被持有了`WRITE_PATIENCE`次观察后返回`Err(Contended)`，不再尝试溢出队列。
+    pub(crate) fn pop_bounded(&self) -> Result<Option<IPCItem>, Contended> {
+        let queued = match self.pop_local_bounded()? {
+            Some(queued) => Some(queued),
+            None if self.config.overflow_queue == NO_OVERFLOW_QUEUE => None,
+            None => match get_queue_array().try_ref_id(self.config.overflow_queue) {
+                Some(overflow) => overflow.pop_local_bounded()?,
+                None => None,
+            },
+        };
+        Ok(queued.map(|queued| queued.item))
+    }
+
+    /// 从`array`中的溢出队列按照其配置弹出一条消息
+    ///
+    /// 溢出的消息总是在本队列弹空之后才被取出，因此即使是先进先出的队列，溢出的消息也晚于此后推入本队列的消息送达。
+    pub(crate) fn pop_overflow<const N: usize>(
+        &self,
+        array: &SlotArray<PerProcess, N>,
+    ) -> Option<QueuedItem> {
+        array.try_ref_id(self.config.overflow_queue)?.pop_local()
+    }
+
+    /// 按照配置从IPC队列中弹出一条消息，不使用溢出队列
+    ///
+    /// 先弹出优先通道中的消息，优先通道为空时才从`deque`弹出。
+    fn pop_local(&self) -> Option<QueuedItem> {
+        let front = self.pop_end();
+        self.pop_in(&self.priority, front)
+            .or_else(|| self.pop_(front))
+    }
+
+    /// 与`pop_local`相同，但下一条消息所在的槽位被持有时返回`Err(Contended)`，见`pop_in_bounded`
+    fn pop_local_bounded(&self) -> Result<Option<QueuedItem>, Contended> {
+        let front = self.pop_end();
+        match self.pop_in_bounded(&self.priority, front)? {
+            Some(queued) => Ok(Some(queued)),
+            None => self.pop_in_bounded(&self.deque, front),
+        }
+    }
+
+    /// 按照`QueueConfig::discipline`弹出的一端：为true时从前端弹出
+    fn pop_end(&self) -> bool {
+        match self.config.discipline {
+            Discipline::Fifo => !PUSH_FRONT,
+            Discipline::Lifo => PUSH_FRONT,
+        }
+    }
+
+    /// 对按照配置下一条将被弹出的消息调用`f`，不弹出，见`SlotRef::with_front`
+    pub(crate) fn peek_with<R>(&self, f: impl FnOnce(&IPCItem) -> R) -> Option<R> {
+        let front = self.pop_end();
+        if !self.priority.is_empty() {
+            let f = |queued: &QueuedItem| f(&queued.item);
+            // The priority lane may have been drained meanwhile, then peeking it returns None
+            return match front {
+                true => self.priority.peek_front_with(f),
+                false => self.priority.peek_back_with(f),
+            };
+        }
+        let f = |queued: &QueuedItem| f(&queued.item);
+        match front {
+            true => self.deque.peek_front_with(f),
+            false => self.deque.peek_back_with(f),
+        }
+    }
+
+    /// 按照配置从IPC队列中弹出一条消息，同时返回弹出后队列中剩余消息数量的近似值。
+    ///
+    /// 本队列为空且配置了溢出队列时，从溢出队列弹出，剩余数量为溢出队列中的数量。
+    pub(crate) fn pop_remaining(&self) -> Option<(IPCItem, usize)> {
+        self.pop_remaining_local().or_else(|| {
+            if self.config.overflow_queue == NO_OVERFLOW_QUEUE {
+                return None;
+            }
+            get_queue_array()
+                .try_ref_id(self.config.overflow_queue)?
+                .pop_remaining_local()
+        })
+    }
+
+    /// 按照配置从IPC队列中弹出一条消息并返回剩余消息数量（包括两个通道），不使用溢出队列
+    fn pop_remaining_local(&self) -> Option<(IPCItem, usize)> {
+        let front = self.pop_end();
+        let res = match Self::pop_remaining_in(&self.priority, front) {
+            Some((queued, remaining)) => Some((queued, remaining + self.deque.len())),
+            None => Self::pop_remaining_in(&self.deque, front),
+        };
+        res.map(|(queued, remaining)| (queued.item, remaining))
+    }
+
+    /// 从`deque`的前端或后端弹出一条消息并返回剩余消息数量
+    fn pop_remaining_in<const C: usize>(
+        deque: &LockFreeDeque<QueuedItem, C>,
+        front: bool,
+    ) -> Option<(QueuedItem, usize)> {
+        match front {
+            #[cfg(feature = "deque-front")]
+            true => deque.pop_front_remaining(),
+            // Unreachable: see `pop_in`
+            #[cfg(not(feature = "deque-front"))]
+            true => None,
+            false => deque.pop_back_remaining(),
+        }
+    }
+
+    /// 从IPC队列的前端或后端弹出一条消息，不受`QueueConfig::discipline`影响。
+    pub(crate) fn pop_at(&self, front: bool) -> Option<IPCItem> {
+        self.pop_(front).map(|queued| queued.item)
+    }
+
+    /// 向优先通道推入一条消息，并为其分配序号，见`push_priority`。
+    ///
+    /// 推入的一端与`push`相同，因此通道内的顺序与`deque`相同。优先通道满时失败，不受`QueueConfig::overflow`
+    /// 与溢出队列影响；推入成功且配置了监视队列时，与`push_at`一样复制到监视队列。
+    pub(crate) fn push_priority(&self, item: IPCItem) -> Result<(), IPCItem> {
+        let queued = QueuedItem::new(item, self.seq.fetch_add(1, Ordering::Relaxed));
+        let was_empty = self.is_drained();
+        self.push_in(&self.priority, queued, PUSH_FRONT)
+            .map_err(|rejected| rejected.item)?;
+        if was_empty {
+            self.notify_nonempty();
+        }
+        if self.config.monitor != NO_MONITOR {
+            self.tee(get_queue_array(), item);
+        }
+        Ok(())
+    }
+
+    /// 设置队列由空变为非空时调用的函数，调用时传入`queue_id`，见`set_nonempty_hook`。传入`None`则取消。
+    pub(crate) fn set_nonempty_hook(&self, queue_id: usize, hook: Option<NonemptyHook>) {
+        let ptr = match hook {
+            Some(hook) => hook as *mut (),
+            None => core::ptr::null_mut(),
+        };
+        // The id is published by the release store of the hook
+        self.nonempty_hook_id.store(queue_id, Ordering::Relaxed);
+        self.nonempty_hook.store(ptr, Ordering::Release);
+    }
+
+    /// 发布消费者的滞后程度，见`set_lag_level`
+    pub(crate) fn set_lag_level(&self, level: u8) {
+        self.lag_level.store(level, Ordering::Release);
+    }
+
+    /// 读取消费者最近发布的滞后程度，见`lag_level`
+    pub(crate) fn lag_level(&self) -> u8 {
+        self.lag_level.load(Ordering::Acquire)
+    }
+
+    /// 两个通道是否都为空
+    fn is_drained(&self) -> bool {
+        self.priority.is_empty() && self.deque.is_empty()
+    }
+
+    /// 若设置了`NonemptyHook`，则调用它；若登记了`WakeToken`，则唤醒其消费者
+    fn notify_nonempty(&self) {
+        let ptr = self.nonempty_hook.load(Ordering::Acquire);
+        if !ptr.is_null() {
+            // Safe because the pointer is only ever set from a `NonemptyHook` in `set_nonempty_hook`
+            let hook: NonemptyHook = unsafe { core::mem::transmute(ptr) };
+            hook(self.nonempty_hook_id.load(Ordering::Relaxed));
+        }
+        if let Some(token) = self.waker() {
+            (token.wake)(token.data);
+        }
+    }
+
+    /// 读取登记的`WakeToken`，见`set_waker`
+    ///
+    /// 以`waker_version`为序列锁：版本为奇数时登记者正在修改，读到的两个字段可能不属于同一个句柄，需要重读。
+    fn waker(&self) -> Option<WakeToken> {
+        loop {
+            let version = self.waker_version.load(Ordering::Acquire);
+            if version % 2 == 1 {
+                core::hint::spin_loop();
+                continue;
+            }
+            let wake = self.waker.load(Ordering::Acquire);
+            let data = self.waker_data.load(Ordering::Acquire);
+            if self.waker_version.load(Ordering::Acquire) != version {
+                continue;
+            }
+            if wake.is_null() {
+                return None;
+            }
+            // Safe because the pointer is only ever set from a `WakeToken` in `set_waker`
+            let wake = unsafe { core::mem::transmute::<*mut (), extern "C" fn(*const ())>(wake) };
+            return Some(WakeToken {
+                wake,
+                data: data.cast_const(),
+            });
+        }
+    }
+
+    /// 登记或（传入`None`时）取消`WakeToken`，见`SlotRef::set_waker`
+    fn set_waker_(&self, token: Option<WakeToken>) {
+        let (wake, data) = match token {
+            Some(token) => (token.wake as *mut (), token.data.cast_mut()),
+            None => (core::ptr::null_mut(), core::ptr::null_mut()),
+        };
+        // Only the owning consumer writes, so an odd version is never observed here
+        let version = self.waker_version.fetch_add(1, Ordering::Acquire);
+        self.waker.store(wake, Ordering::Release);
+        self.waker_data.store(data, Ordering::Release);
+        self.waker_version.store(version + 2, Ordering::Release);
+    }
+
+    /// 推入`deque`的前端或后端，见`push_in`
+    fn push_(&self, item: QueuedItem, front: bool) -> Result<(), QueuedItem> {
+        self.push_in(&self.deque, item, front)
+    }
+
+    /// 推入`deque`（本队列的`deque`或优先通道）的前端或后端，启用指标时统计重试次数与推入次数
+    ///
+    /// 未启用`deque-back` feature时，推入后端的操作没有编译，直接失败。
+    /// 只有`deque_push_back`与`push-back-default` feature会推入后端，它们都要求启用该feature。
+    fn push_in<const C: usize>(
+        &self,
+        deque: &LockFreeDeque<QueuedItem, C>,
+        item: QueuedItem,
+        front: bool,
+    ) -> Result<(), QueuedItem> {
+        #[cfg(feature = "metrics")]
+        if self.config.metrics {
+            self.metrics.record_op();
+            let res = match front {
+                true => deque.push_front_backoff(item, &self.metrics),
+                #[cfg(feature = "deque-back")]
+                false => deque.push_back_backoff(item, &self.metrics),
+                #[cfg(not(feature = "deque-back"))]
+                false => Err(item),
+            };
+            if res.is_ok() {
+                self.metrics.record_push();
+            }
+            return res;
+        }
+        match front {
+            true => deque.push_front(item),
+            #[cfg(feature = "deque-back")]
+            false => deque.push_back(item),
+            #[cfg(not(feature = "deque-back"))]
+            false => Err(item),
+        }
+    }
+
+    /// 从`deque`的前端或后端弹出，见`pop_in`
+    pub(crate) fn pop_(&self, front: bool) -> Option<QueuedItem> {
+        self.pop_in(&self.deque, front)
+    }
+
+    /// 从`deque`（本队列的`deque`或优先通道）的前端或后端弹出，启用指标时统计重试次数
+    ///
+    /// 未启用`deque-front` feature时，从前端弹出的操作没有编译，总是返回`None`。
+    /// 从前端弹出的情况（后进先出的队列、`deque_pop_front`、`push-back-default` feature，以及`deque_push_back`在
+    /// `Overwrite`时丢弃前端的消息）都要求启用该feature，`register_process_configured`也会拒绝后进先出的配置。
+    fn pop_in<const C: usize>(
+        &self,
+        deque: &LockFreeDeque<QueuedItem, C>,
+        front: bool,
+    ) -> Option<QueuedItem> {
+        #[cfg(feature = "metrics")]
+        if self.config.metrics {
+            self.metrics.record_op();
+            return match front {
+                #[cfg(feature = "deque-front")]
+                true => deque.pop_front_backoff(&self.metrics),
+                #[cfg(not(feature = "deque-front"))]
+                true => None,
+                false => deque.pop_back_backoff(&self.metrics),
+            };
+        }
+        match front {
+            #[cfg(feature = "deque-front")]
+            true => deque.pop_front(),
+            #[cfg(not(feature = "deque-front"))]
+            true => None,
+            false => deque.pop_back(),
+        }
+    }
+
+    /// 与`pop_in`相同，但在下一条消息所在的槽位被持有（正被写入或查看）`WRITE_PATIENCE`次观察后放弃，
+    /// 返回`Err(Contended)`，见`LockFreeDeque::pop_front_bounded`
+    ///
+    /// 与其他弹出者的竞争不受限制地重试，因此`Err(Contended)`只表示槽位被持有。
+    fn pop_in_bounded<const C: usize>(
+        &self,
+        deque: &LockFreeDeque<QueuedItem, C>,
+        front: bool,
+    ) -> Result<Option<QueuedItem>, Contended> {
+        #[cfg(feature = "metrics")]
+        if self.config.metrics {
+            self.metrics.record_op();
+        }
+        match front {
+            #[cfg(feature = "deque-front")]
+            true => deque.pop_front_bounded(usize::MAX),
+            #[cfg(not(feature = "deque-front"))]
+            true => Ok(None),
+            false => deque.pop_back_bounded(usize::MAX),
+        }
+    }
+}
+
+impl<const N: usize> SlotRef<'_, PerProcess, N> {
+    /// 登记拥有本队列的消费者的唤醒句柄：此后推入（包括`push_priority`）观察到队列由空变为非空时，调用`token.wake(token.data)`。
+    ///
+    /// 触发条件与`set_nonempty_hook`相同，并发时同样可能重复唤醒，消费者应能容忍。两者可以同时设置，推入者先调用钩子再唤醒。
+    ///
+    /// 假定队列只有一个拥有者：只有该消费者登记或取消句柄，再次登记会替换之前的句柄。
+    /// 多个消费者各自登记时只有最后一个会被唤醒，这种情况应改用`set_nonempty_hook`。
+    /// 与钩子相同，函数与数据指针只在登记者的地址空间内有效。
+    pub fn set_waker(&self, token: WakeToken) {
+        self.set_waker_(Some(token));
+    }
+
+    /// 取消登记的唤醒句柄，见`set_waker`
+    pub fn clear_waker(&self) {
+        self.set_waker_(None);
+    }
+
+    /// 对队列中下一条将被弹出的消息（即`pop`、`deque_pop`将返回的消息）调用`f`并返回其结果，消息留在队列中。
+    ///
+    /// 队列为空时返回`None`。只查看本队列，不查看溢出队列（见`QueueConfig::overflow_queue`）。
+    /// `f`执行期间该消息的槽位被占用，并发弹出它的操作会等待，因此`f`应尽量短，且不能panic，见`LockFreeDeque::peek_front_with`。
+    pub fn with_front<R>(&self, f: impl FnOnce(&IPCItem) -> R) -> Option<R> {
+        self.peek_with(f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use super::{NO_OVERFLOW_QUEUE, PUSH_FRONT, QueueConfig, WakeToken};
+    use crate::{IPCItem, PerProcess, QUEUE_LEN, slot_array::SlotArray};
+
+    fn item(data: u64) -> IPCItem {
+        IPCItem {
+            sender: 0,
+            msg_type: 0,
+            rep_type: 0,
+            data: [data; 8],
+        }
+    }
+
+    #[test]
+    fn test_default_config() {
+        // `PerProcess` is too large for the default test thread stack
+        std::thread::Builder::new()
+            .stack_size(16 << 20)
+            .spawn(|| {
+                let queue = PerProcess::default();
+                assert_eq!(queue.config, QueueConfig::default());
+                for i in 0..QUEUE_LEN as u64 {
+                    assert!(queue.push(item(i)).is_ok());
+                }
+                assert!(queue.push(item(0)).is_err());
+                assert_eq!(queue.pop().unwrap().data[0], 0);
+                assert_eq!(queue.pop().unwrap().data[0], 1);
+            })
+            .unwrap()
+            .join()
+            .unwrap();
+    }
+
+    #[test]
+    fn test_with_front() {
+        static ARRAY: SlotArray<PerProcess, 2> = SlotArray::new();
+        // `PerProcess` is too large for the default test thread stack
+        std::thread::Builder::new()
+            .stack_size(16 << 20)
+            .spawn(|| {
+                let fifo = ARRAY.push(PerProcess::default()).unwrap();
+                assert_eq!(fifo.with_front(|item| item.data[0]), None);
+                for i in 0..3 {
+                    assert!(fifo.push(item(i)).is_ok());
+                }
+                // Peeking doesn't pop
+                assert_eq!(fifo.with_front(|item| item.data[0]), Some(0));
+                assert_eq!(fifo.with_front(|item| item.data[0]), Some(0));
+                assert_eq!(fifo.pop().unwrap().data[0], 0);
+                assert_eq!(fifo.with_front(|item| item.data[0]), Some(1));
+
+                #[cfg(feature = "deque-front")]
+                {
+                    let lifo = ARRAY
+                        .push(PerProcess::with_config(QueueConfig {
+                            discipline: super::Discipline::Lifo,
+                            ..Default::default()
+                        }))
+                        .unwrap();
+                    for i in 0..3 {
+                        assert!(lifo.push(item(i)).is_ok());
+                    }
+                    assert_eq!(lifo.with_front(|item| item.data[0]), Some(2));
+                    assert_eq!(lifo.pop().unwrap().data[0], 2);
+                }
+            })
+            .unwrap()
+            .join()
+            .unwrap();
+    }
+
+    #[test]
+    fn test_seq() {
+        let queue = PerProcess::default();
+        for i in 0..3 {
+            assert!(queue.push(item(i)).is_ok());
+        }
+        for i in 0..3 {
+            let (item, seq) = queue.pop_with_seq().unwrap();
+            assert_eq!((item.data[0], seq), (i, i));
+        }
+        assert!(queue.push(item(3)).is_ok());
+        assert_eq!(queue.pop_with_seq().unwrap().1, 3);
+    }
+
+    #[cfg(feature = "global-seq")]
+    #[test]
+    fn test_global_seq() {
+        static ARRAY: SlotArray<PerProcess, 2> = SlotArray::new();
+        // `PerProcess` is too large for the default test thread stack
+        std::thread::Builder::new()
+            .stack_size(16 << 20)
+            .spawn(|| {
+                let queues = [
+                    ARRAY.push(PerProcess::default()).unwrap(),
+                    ARRAY.push(PerProcess::default()).unwrap(),
+                ];
+                for i in 0..6 {
+                    assert!(queues[i % 2].push(item(i as u64)).is_ok());
+                }
+                // Merging both queues by stamp gives back the push order, whatever other tests push meanwhile
+                let mut popped: std::vec::Vec<_> = queues
+                    .iter()
+                    .flat_map(|queue| core::iter::from_fn(|| queue.pop_with_global_seq()))
+                    .map(|(item, stamp)| (stamp, item.data[0]))
+                    .collect();
+                popped.sort();
+                let order: std::vec::Vec<_> = popped.iter().map(|&(_, data)| data).collect();
+                assert_eq!(order, [0, 1, 2, 3, 4, 5]);
+                assert!(popped.windows(2).all(|pair| pair[0].0 < pair[1].0));
+            })
+            .unwrap()
+            .join()
+            .unwrap();
+    }
+
+    #[test]
+    fn test_priority() {
+        static ARRAY: SlotArray<PerProcess, 1> = SlotArray::new();
+        // `PerProcess` is too large for the default test thread stack
+        std::thread::Builder::new()
+            .stack_size(16 << 20)
+            .spawn(|| {
+                let queue = ARRAY.push(PerProcess::default()).unwrap();
+                for i in 0..6 {
+                    let res = match i % 2 {
+                        0 => queue.push(item(i)),
+                        _ => queue.push_priority(item(i)),
+                    };
+                    assert!(res.is_ok());
+                }
+                // The priority lane drains first, each lane keeps its own order
+                assert_eq!(queue.with_front(|item| item.data[0]), Some(1));
+                assert_eq!(
+                    queue
+                        .pop_remaining()
+                        .map(|(item, remaining)| (item.data[0], remaining)),
+                    Some((1, 5))
+                );
+                for i in [3, 5, 0, 2, 4] {
+                    assert_eq!(queue.pop().unwrap().data[0], i);
+                }
+                assert!(queue.pop().is_none());
+            })
+            .unwrap()
+            .join()
+            .unwrap();
+    }
+
+    #[test]
+    fn test_nonempty_hook() {
+        use core::sync::atomic::{AtomicUsize, Ordering};
+
+        static ARRAY: SlotArray<PerProcess, 1> = SlotArray::new();
+        static CALLS: AtomicUsize = AtomicUsize::new(0);
+        static LAST_ID: AtomicUsize = AtomicUsize::new(0);
+        extern "C" fn hook(queue_id: usize) {
+            LAST_ID.store(queue_id, Ordering::Relaxed);
+            CALLS.fetch_add(1, Ordering::Relaxed);
+        }
+        // `PerProcess` is too large for the default test thread stack
+        std::thread::Builder::new()
+            .stack_size(16 << 20)
+            .spawn(|| {
+                let queue = ARRAY.push(PerProcess::default()).unwrap();
+                queue.set_nonempty_hook(42, Some(hook));
+                for i in 0..3 {
+                    assert!(queue.push(item(i)).is_ok());
+                }
+                // Only the first push found the queue empty
+                assert_eq!(CALLS.load(Ordering::Relaxed), 1);
+                assert_eq!(LAST_ID.load(Ordering::Relaxed), 42);
+
+                while queue.pop().is_some() {}
+                assert!(queue.push_priority(item(3)).is_ok());
+                assert_eq!(CALLS.load(Ordering::Relaxed), 2);
+
+                queue.set_nonempty_hook(42, None);
+                while queue.pop().is_some() {}
+                assert!(queue.push(item(4)).is_ok());
+                assert_eq!(CALLS.load(Ordering::Relaxed), 2);
+            })
+            .unwrap()
+            .join()
+            .unwrap();
+    }
+
+    #[test]
+    fn test_waker() {
+        use core::sync::atomic::{AtomicUsize, Ordering};
+
+        static ARRAY: SlotArray<PerProcess, 1> = SlotArray::new();
+        static WOKEN: AtomicUsize = AtomicUsize::new(0);
+        static CONSUMER: AtomicUsize = AtomicUsize::new(0);
+        extern "C" fn wake(data: *const ()) {
+            assert_eq!(data, (&raw const CONSUMER).cast());
+            WOKEN.fetch_add(1, Ordering::Relaxed);
+        }
+        // `PerProcess` is too large for the default test thread stack
+        std::thread::Builder::new()
+            .stack_size(16 << 20)
+            .spawn(|| {
+                let queue = ARRAY.push(PerProcess::default()).unwrap();
+                queue.set_waker(WakeToken {
+                    wake,
+                    data: (&raw const CONSUMER).cast(),
+                });
+                for i in 0..3 {
+                    assert!(queue.push(item(i)).is_ok());
+                }
+                // Only the first push found the queue empty
+                assert_eq!(WOKEN.load(Ordering::Relaxed), 1);
+
+                while queue.pop().is_some() {}
+                assert!(queue.push(item(3)).is_ok());
+                assert_eq!(WOKEN.load(Ordering::Relaxed), 2);
+
+                queue.clear_waker();
+                while queue.pop().is_some() {}
+                assert!(queue.push(item(4)).is_ok());
+                assert_eq!(WOKEN.load(Ordering::Relaxed), 2);
+            })
+            .unwrap()
+            .join()
+            .unwrap();
+    }
+
+    #[cfg(not(feature = "single-thread"))]
+    #[test]
+    fn test_lag_level() {
+        static ARRAY: SlotArray<PerProcess, 1> = SlotArray::new();
+        // `PerProcess` is too large for the default test thread stack
+        std::thread::Builder::new()
+            .stack_size(16 << 20)
+            .spawn(|| {
+                let queue = ARRAY.push(PerProcess::default()).unwrap();
+                assert_eq!(queue.lag_level(), 0);
+                std::thread::scope(|s| {
+                    // The consumer falls behind and says so
+                    s.spawn(|| queue.set_lag_level(200)).join().unwrap();
+                    // A producer sees it and backs off
+                    let producer = s.spawn(|| queue.lag_level());
+                    assert_eq!(producer.join().unwrap(), 200);
+                });
+                // Advisory only: pushes still succeed
+                assert!(queue.push(item(0)).is_ok());
+                queue.set_lag_level(0);
+                assert_eq!(queue.lag_level(), 0);
+            })
+            .unwrap()
+            .join()
+            .unwrap();
+    }
+
+    #[cfg(feature = "checksum")]
+    #[test]
+    fn test_checksum() {
+        static ARRAY: SlotArray<PerProcess, 1> = SlotArray::new();
+        // `PerProcess` is too large for the default test thread stack
+        std::thread::Builder::new()
+            .stack_size(16 << 20)
+            .spawn(|| {
+                let queue = ARRAY.push(PerProcess::default()).unwrap();
+                assert!(queue.push(item(1)).is_ok());
+                assert_eq!(queue.pop_checked().unwrap().unwrap().data[0], 1);
+                assert!(matches!(queue.pop_checked(), Ok(None)));
+
+                // Flip a bit of the only item, where a buggy peer would write it
+                assert!(queue.push(item(2)).is_ok());
+                let (head, _) = queue.deque.raw_indices();
+                unsafe { (*queue.deque.slot_ptr(head)).item.data[5] ^= 1 << 7 };
+                let Err(crate::Corrupted(corrupted)) = queue.pop_checked() else {
+                    panic!("corruption not detected");
+                };
+                assert_eq!(corrupted.data[5], 2 ^ (1 << 7));
+                // The corrupted item is removed
+                assert!(matches!(queue.pop_checked(), Ok(None)));
+            })
+            .unwrap()
+            .join()
+            .unwrap();
+    }
+
+    #[cfg(all(feature = "deque-front", feature = "deque-back"))]
+    #[test]
+    fn test_push_at() {
+        let queue = PerProcess::default();
+        assert!(queue.push_at(item(1), true).is_ok());
+        assert!(queue.push_at(item(2), false).is_ok());
+        assert!(queue.push_at(item(0), true).is_ok());
+        for i in 0..3 {
+            assert_eq!(queue.pop_at(true).unwrap().data[0], i);
+        }
+        assert!(queue.pop_at(false).is_none());
+    }
+
+    #[test]
+    fn test_tee() {
+        static ARRAY: SlotArray<PerProcess, 2> = SlotArray::new();
+        // `PerProcess` is too large for the default test thread stack
+        let (primary, monitor) = std::thread::Builder::new()
+            .stack_size(16 << 20)
+            .spawn(|| {
+                let monitor = ARRAY.push(PerProcess::default()).unwrap();
+                let primary = ARRAY
+                    .push(PerProcess::with_config(QueueConfig {
+                        monitor: monitor.id(),
+                        ..Default::default()
+                    }))
+                    .unwrap();
+                (primary, monitor)
+            })
+            .unwrap()
+            .join()
+            .unwrap();
+
+        // Like `push`, with the monitor looked up in the local array
+        let push = |i| {
+            primary.push_at_(item(i), PUSH_FRONT)?;
+            primary.tee(&ARRAY, item(i));
+            Ok::<(), IPCItem>(())
+        };
+        for i in 0..3 {
+            assert!(push(i).is_ok());
+        }
+        for i in 0..3 {
+            assert_eq!(primary.pop().unwrap().data[0], i);
+            assert_eq!(monitor.pop().unwrap().data[0], i);
+        }
+
+        // A full monitor drops the copies silently
+        while monitor.push(item(9)).is_ok() {}
+        assert!(push(3).is_ok());
+        assert_eq!(primary.pop().unwrap().data[0], 3);
+        assert_eq!(monitor.deque.len(), QUEUE_LEN);
+    }
+
+    #[test]
+    fn test_overflow_queue() {
+        static ARRAY: SlotArray<PerProcess, 2> = SlotArray::new();
+        // `PerProcess` is too large for the default test thread stack
+        let (primary, overflow) = std::thread::Builder::new()
+            .stack_size(16 << 20)
+            .spawn(|| {
+                let overflow = ARRAY.push(PerProcess::default()).unwrap();
+                let primary = ARRAY
+                    .push(PerProcess::with_config(QueueConfig {
+                        overflow_queue: overflow.id(),
+                        ..Default::default()
+                    }))
+                    .unwrap();
+                (primary, overflow)
+            })
+            .unwrap()
+            .join()
+            .unwrap();
+        assert_eq!(QueueConfig::default().overflow_queue, NO_OVERFLOW_QUEUE);
+
+        // Like `push` and `pop`, with the overflow queue looked up in the local array
+        let push = |i| match primary.push_at_(item(i), PUSH_FRONT) {
+            Ok(()) => Ok(()),
+            Err(item) => primary.spill(&ARRAY, item),
+        };
+        let pop = || {
+            primary
+                .pop_local()
+                .or_else(|| primary.pop_overflow(&ARRAY))
+                .map(|queued| queued.item.data[0])
+        };
+
+        let total = QUEUE_LEN as u64 + 2;
+        for i in 0..total {
+            assert!(push(i).is_ok());
+        }
+        assert_eq!(primary.deque.len(), QUEUE_LEN);
+        // The excess went to the overflow queue
+        assert_eq!(overflow.deque.len(), 2);
+
+        // The primary is drained first, then the overflow
+        assert_eq!(pop(), Some(0));
+        assert!(push(total).is_ok());
+        for i in 1..QUEUE_LEN as u64 {
+            assert_eq!(pop(), Some(i));
+        }
+        // Pushed after the excess, but delivered before it
+        assert_eq!(pop(), Some(total));
+        assert_eq!(pop(), Some(QUEUE_LEN as u64));
+        assert_eq!(pop(), Some(QUEUE_LEN as u64 + 1));
+        assert_eq!(pop(), None);
+
+        // Both full: the push fails
+        while overflow.push(item(9)).is_ok() {}
+        while primary.push_at_(item(9), PUSH_FRONT).is_ok() {}
+        assert!(push(0).is_err());
+    }
+
+    #[cfg(feature = "deque-front")]
+    #[test]
+    fn test_lifo_overwrite() {
+        use super::{Discipline, OverflowPolicy};
+
+        // `PerProcess` is too large for the default test thread stack
+        std::thread::Builder::new()
+            .stack_size(16 << 20)
+            .spawn(|| {
+                let queue = PerProcess::with_config(QueueConfig {
+                    discipline: Discipline::Lifo,
+                    overflow: OverflowPolicy::Overwrite,
+                    ..Default::default()
+                });
+                for i in 0..QUEUE_LEN as u64 + 2 {
+                    assert!(queue.push(item(i)).is_ok());
+                }
+                assert_eq!(queue.deque.len(), QUEUE_LEN);
+                // The newest item comes first, the two oldest ones are overwritten
+                assert_eq!(queue.pop().unwrap().data[0], QUEUE_LEN as u64 + 1);
+                let mut last = None;
+                while let Some(item) = queue.pop() {
+                    last = Some(item.data[0]);
+                }
+                assert_eq!(last, Some(2));
+            })
+            .unwrap()
+            .join()
+            .unwrap();
+    }
+}
@@ -1,40 +1,218 @@
 //! 用于实现IPC队列的无锁双端队列（deque）数据结构，支持多生产者多消费者（MPMC）场景。
 //!
-//! Satety:
-//!     Work when the queue is full in the MPMC situation will cause error.
-//!
 //! Copied and modified from [https://github.com/AsyncModules/vsched/blob/main/utils/src/deque.rs](https://github.com/AsyncModules/vsched/blob/main/utils/src/deque.rs).
 
 use core::cell::UnsafeCell;
 use core::mem::MaybeUninit;
-use core::ops::{Deref, DerefMut};
-use core::sync::atomic::{AtomicU8, AtomicUsize, Ordering};
+use core::ops::Deref;
+#[cfg(feature = "deque-slot")]
+use core::ops::DerefMut;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use crate::backoff::{Backoff, SpinBackoff, WriterLimit};
+use crate::sync::{AtomicU8, AtomicUsize, OrderingProfile, Profile, const_fn};
 
 // Slot states for tracking initialization
 const SLOT_EMPTY: u8 = 0;
 const SLOT_WRITING: u8 = 1;
 const SLOT_READY: u8 = 2;
 const SLOT_READING: u8 = 3;
+const SLOT_CANCELLED: u8 = 4;
+/// Held by a `PeekGuard`: like READING, but for as long as the guard lives
+const SLOT_PEEKING: u8 = 5;
+
+/// Number of bits of `LockFreeDeque::ends` holding the tail, the head taking the bits above
+pub(crate) const END_BITS: u32 = usize::BITS / 2;
+
+/// Pack head and tail into a value of `LockFreeDeque::ends`
+pub(crate) const fn pack(head: usize, tail: usize) -> usize {
+    (head << END_BITS) | tail
+}
+
+/// Unpack a value of `LockFreeDeque::ends` into head and tail
+pub(crate) const fn unpack(ends: usize) -> (usize, usize) {
+    (ends >> END_BITS, ends & ((1 << END_BITS) - 1))
+}
+
+/// Number of consecutive times a bounded pop, or `LockFreeDeque::peek_front`, finds the slot it needs in WRITING
+/// state before giving up
+///
+/// A push keeps its slot in that state only briefly, but a held `SlotGuard` or an uncommitted `Batch` keeps it
+/// for as long as the producer likes, see `LockFreeDeque::pop_front_bounded`. The same goes for a slot held by
+/// a `PeekGuard`. The unbounded pops have no such limit: they wait for the slot, so that None means empty.
+pub const WRITE_PATIENCE: usize = 64;
+
+/// The operation that last wrote the state of a slot, recorded with the `debug-history` feature
+///
+/// See `LockFreeDeque::history`.
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SlotOp {
+    /// Published an item pushed to the front (including by a `SlotGuard` or a committed batch)
+    PushFront = 1,
+    /// Published an item pushed to the back (including by a `SlotGuard` or a committed batch)
+    PushBack = 2,
+    /// Emptied the slot after popping its item from the front (or discarding a cancelled slot there)
+    PopFront = 3,
+    /// Emptied the slot after popping its item from the back (or discarding a cancelled slot there)
+    PopBack = 4,
+    /// Claimed the slot for writing or reading; the operation has not completed yet
+    Claim = 5,
+    /// Gave the slot up: restored it after losing the head/tail CAS, or cancelled it in a batch
+    Release = 6,
+}
+
+#[cfg(feature = "debug-history")]
+impl SlotOp {
+    /// Decode a recorded op code, 0 meaning that the slot was never touched
+    pub const fn from_u8(code: u8) -> Option<Self> {
+        match code {
+            1 => Some(Self::PushFront),
+            2 => Some(Self::PushBack),
+            3 => Some(Self::PopFront),
+            4 => Some(Self::PopBack),
+            5 => Some(Self::Claim),
+            6 => Some(Self::Release),
+            _ => None,
+        }
+    }
+}
 
 struct Slot<T> {
     data: UnsafeCell<MaybeUninit<T>>,
     state: AtomicU8,
+    #[cfg(feature = "debug-history")]
+    last_op: AtomicU8,
+    /// Time at which the slot was claimed for writing or published, see `Slot::time`
+    #[cfg(feature = "timing")]
+    since: core::sync::atomic::AtomicU64,
 }
 
 impl<T> Slot<T> {
-    const fn new() -> Self {
-        Self {
-            data: UnsafeCell::new(MaybeUninit::uninit()),
-            state: AtomicU8::new(SLOT_EMPTY),
+    const_fn! {
+        const fn new() -> Self {
+            Self {
+                data: UnsafeCell::new(MaybeUninit::uninit()),
+                state: AtomicU8::new(SLOT_EMPTY),
+                #[cfg(feature = "debug-history")]
+                last_op: AtomicU8::new(0),
+                #[cfg(feature = "timing")]
+                since: core::sync::atomic::AtomicU64::new(crate::timing::NO_STAMP),
+            }
+        }
+    }
+
+    /// Record the operation that just wrote the state, a no-op without the `debug-history` feature
+    #[inline(always)]
+    fn record(&self, _op: SlotOp) {
+        #[cfg(feature = "debug-history")]
+        self.last_op.store(_op as u8, Ordering::Relaxed);
+    }
+
+    /// Record the time at which the slot enters `_state`, a no-op without the `timing` feature
+    ///
+    /// Called with SLOT_WRITING once a push claimed the slot, with SLOT_READY right before publishing it,
+    /// and with SLOT_EMPTY right before a pop hands it back, i.e. always while the slot is owned by the caller.
+    /// Publishing records the time spent in WRITING state, and the pop the time spent in READY state
+    /// (see `timing`). Peeks, restored claims and discarded cancelled slots don't call it.
+    #[inline(always)]
+    fn time(&self, _state: u8) {
+        #[cfg(feature = "timing")]
+        if let Some(now) = crate::timing::now() {
+            match _state {
+                SLOT_WRITING => self.since.store(now, Ordering::Relaxed),
+                SLOT_READY => {
+                    let since = self.since.swap(now, Ordering::Relaxed);
+                    crate::timing::record_dwell(true, since, now);
+                }
+                _ => {
+                    let since = self.since.swap(crate::timing::NO_STAMP, Ordering::Relaxed);
+                    crate::timing::record_dwell(false, since, now);
+                }
+            }
         }
     }
 }
 
+#[cfg(feature = "deque-slot")]
 /// A guard that holds a slot for writing. The slot will be marked as ready when the guard is dropped.
+///
+/// The guard must not be forgotten (`mem::forget`, a leaked `Box`, a reference cycle...): the slot would stay
+/// in WRITING state forever, permanently taking up capacity, and pops reaching it would wait for it forever.
+/// `LockFreeDeque::scan_leaked_writing` finds such slots, and in debug builds with the `std` feature,
+/// `SlotGuard::outstanding` lets tests check that no guard was leaked.
 pub struct SlotGuard<'a, T> {
     slot: &'a Slot<T>,
 }
 
+/// A hook run by `yield_hook`, with the operation in progress
+#[cfg(test)]
+type YieldHook = std::boxed::Box<dyn FnMut(SlotOp)>;
+
+#[cfg(test)]
+std::thread_local! {
+    /// The hook run by `yield_hook` on this thread, see `set_yield_hook`
+    static YIELD_HOOK: core::cell::RefCell<Option<YieldHook>> = const { core::cell::RefCell::new(None) };
+}
+
+/// Test-only injection point, called by pushes and pops right after claiming their slot,
+/// before moving head or tail, with the operation in progress
+///
+/// A test sets a hook with `set_yield_hook` to run other operations exactly there, which turns a race
+/// between threads into a deterministic sequence on one thread. The hook is taken out while it runs,
+/// so the operations it performs don't call it again. Compiled to nothing outside tests.
+#[cfg(test)]
+fn yield_hook(op: SlotOp) {
+    if let Some(mut hook) = YIELD_HOOK.with_borrow_mut(Option::take) {
+        hook(op);
+        YIELD_HOOK.with_borrow_mut(|current| {
+            current.get_or_insert(hook);
+        });
+    }
+}
+
+#[cfg(not(test))]
+#[inline(always)]
+fn yield_hook(_: SlotOp) {}
+
+/// Set (or with None, clear) the hook run by `yield_hook` on the current thread
+#[cfg(all(
+    test,
+    feature = "deque-front",
+    feature = "deque-back",
+    feature = "deque-slot"
+))]
+fn set_yield_hook(hook: Option<YieldHook>) {
+    YIELD_HOOK.set(hook);
+}
+
+#[cfg(all(feature = "deque-slot", feature = "std", debug_assertions))]
+std::thread_local! {
+    /// Number of `SlotGuard`s created minus dropped on this thread, see `SlotGuard::outstanding`
+    static OUTSTANDING_GUARDS: core::cell::Cell<isize> = const { core::cell::Cell::new(0) };
+}
+
+#[cfg(feature = "deque-slot")]
+impl<'a, T> SlotGuard<'a, T> {
+    fn new(slot: &'a Slot<T>) -> Self {
+        #[cfg(all(feature = "std", debug_assertions))]
+        OUTSTANDING_GUARDS.set(OUTSTANDING_GUARDS.get() + 1);
+        Self { slot }
+    }
+
+    #[cfg(all(feature = "std", debug_assertions))]
+    /// Get the number of guards created on the current thread minus the number dropped on it,
+    /// across all deques (debug builds with the `std` feature only)
+    ///
+    /// Lets a test assert that it leaked no guard: the value must be back to what it was before the test.
+    /// It is per thread so that tests running in parallel don't disturb each other; a guard sent to and
+    /// dropped on another thread is counted as outstanding here and as negative there.
+    pub fn outstanding() -> isize {
+        OUTSTANDING_GUARDS.get()
+    }
+}
+
+#[cfg(feature = "deque-slot")]
 impl<'a, T> Deref for SlotGuard<'a, T> {
     type Target = MaybeUninit<T>;
 
@@ -44,6 +222,7 @@ impl<'a, T> Deref for SlotGuard<'a, T> {
     }
 }
 
+#[cfg(feature = "deque-slot")]
 impl<'a, T> DerefMut for SlotGuard<'a, T> {
     fn deref_mut(&mut self) -> &mut Self::Target {
         // Safe because the slot is guaranteed to be in WRITING state
@@ -51,862 +230,2446 @@ impl<'a, T> DerefMut for SlotGuard<'a, T> {
     }
 }
 
+#[cfg(feature = "deque-slot")]
 impl<'a, T> Drop for SlotGuard<'a, T> {
     fn drop(&mut self) {
         // Mark the slot as ready after writing
-        self.slot.state.store(SLOT_READY, Ordering::Release);
+        self.slot.time(SLOT_READY);
+        self.slot.state.store(SLOT_READY, Profile::RELEASE);
+        #[cfg(all(feature = "std", debug_assertions))]
+        OUTSTANDING_GUARDS.set(OUTSTANDING_GUARDS.get() - 1);
     }
 }
 
-/// A lock-free deque implementation with fixed capacity, supporting multiple producers and multiple consumers.
-pub struct LockFreeDeque<T, const CAPACITY: usize> {
-    buffer: [Slot<T>; CAPACITY],
-    head: AtomicUsize, // Points to the first element
-    tail: AtomicUsize, // Points to one past the last element
+/// A transactional batch of slots reserved at one end of a deque,
+/// returned by `LockFreeDeque::begin_batch` or `LockFreeDeque::begin_batch_front`.
+///
+/// Items pushed into the batch become visible to consumers only when the batch is committed.
+/// Dropping the batch without committing aborts it.
+pub struct Batch<'a, T, const CAPACITY: usize> {
+    deque: &'a LockFreeDeque<T, CAPACITY>,
+    /// Buffer index of the first reserved slot
+    start: usize,
+    len: usize,
+    written: usize,
+    /// Whether the slots are reserved at the front, i.e. at decreasing buffer indices
+    front: bool,
 }
 
-impl<T, const CAPACITY: usize> LockFreeDeque<T, CAPACITY> {
-    const EMPTY_CELL: Slot<T> = Slot::new();
-
-    /// Create a new lock-free deque with compile-time capacity
-    pub const fn new() -> Self {
-        let buffer = [Self::EMPTY_CELL; CAPACITY];
-
-        Self {
-            buffer,
-            head: AtomicUsize::new(0),
-            tail: AtomicUsize::new(0),
+impl<'a, T, const CAPACITY: usize> Batch<'a, T, CAPACITY> {
+    /// Buffer index of the `i`-th reserved slot
+    fn position(&self, i: usize) -> usize {
+        if self.front {
+            (self.start + CAPACITY - i) % CAPACITY
+        } else {
+            (self.start + i) % CAPACITY
         }
     }
 
-    /// Push an item to the front of the deque
-    /// Returns Err(item) if the deque is full
-    pub fn push_front(&self, item: T) -> Result<(), T> {
-        loop {
-            let head = self.head.load(Ordering::Acquire);
-            let tail = self.tail.load(Ordering::Acquire);
-            let head_ = self.head.load(Ordering::Acquire);
-            if head_ != head {
-                continue;
-            }
-
-            // Calculate the new head position (moving backwards)
-            let new_head = if head == 0 { CAPACITY - 1 } else { head - 1 };
+    /// Write an item into the next reserved slot
+    /// Returns Err(item) if all reserved slots are already written
+    pub fn push(&mut self, item: T) -> Result<(), T> {
+        if self.written == self.len {
+            return Err(item);
+        }
+        let slot = &self.deque.buffer[self.position(self.written)];
+        // Safe because the slot is claimed by this batch in WRITING state
+        unsafe {
+            (*slot.data.get()).write(item);
+        }
+        self.written += 1;
+        Ok(())
+    }
 
-            // Check if queue is full
-            if new_head == tail {
-                return Err(item);
+    /// Publish all written items
+    ///
+    /// Reserved slots that were never written are cancelled and skipped by consumers.
+    ///
+    /// The slots are published one by one, not atomically, from the last one to the first: the slot of the first item,
+    /// which a consumer popping in FIFO order (from the other end, as with a `QueueView`) reaches first,
+    /// is published last. Such a consumer sees either none of the items, or all of them.
+    /// A consumer popping from the end the batch was reserved at reaches the last item first, and may take it
+    /// before the others are published: the unbounded pops then wait for the rest, the bounded ones may give up.
+    pub fn commit(mut self) {
+        for i in (0..self.len).rev() {
+            let state = if i < self.written {
+                SLOT_READY
+            } else {
+                SLOT_CANCELLED
+            };
+            let slot = &self.deque.buffer[self.position(i)];
+            if state == SLOT_READY {
+                slot.time(SLOT_READY);
             }
+            slot.state.store(state, Profile::RELEASE);
+            slot.record(match (state, self.front) {
+                (SLOT_CANCELLED, _) => SlotOp::Release,
+                (_, true) => SlotOp::PushFront,
+                (_, false) => SlotOp::PushBack,
+            });
+        }
+        // The slots left unwritten hold no item
+        self.deque
+            .count
+            .fetch_sub(self.len - self.written, Profile::RELEASE);
+        // Nothing left to abort on drop
+        self.len = 0;
+    }
 
-            // Check if the target slot is available
-            let slot = &self.buffer[new_head];
-
-            // Try to claim the slot for writing atomically
-            match slot.state.compare_exchange_weak(
-                SLOT_EMPTY,
-                SLOT_WRITING,
-                Ordering::Acquire,
-                Ordering::Relaxed,
-            ) {
-                Ok(_) => {
-                    // Successfully claimed slot, now try to update head
-                    match self.head.compare_exchange_weak(
-                        head,
-                        new_head,
-                        Ordering::Release,
-                        Ordering::Relaxed,
-                    ) {
-                        Ok(_) => {
-                            // Successfully reserved the slot, write the item
-                            unsafe {
-                                (*slot.data.get()).write(item);
-                            }
+    /// Cancel the batch, dropping the written items
+    ///
+    /// The reserved slots are skipped by consumers, so none of the items is ever observed.
+    pub fn abort(self) {
+        // Done in drop
+    }
+}
 
-                            // Mark slot as ready
-                            slot.state.store(SLOT_READY, Ordering::Release);
-                            return Ok(());
-                        }
-                        Err(_) => {
-                            // Failed to update head, release the slot and retry
-                            slot.state.store(SLOT_EMPTY, Ordering::Release);
-                            // Small backoff to reduce contention
-                            for _ in 0..5 {
-                                core::hint::spin_loop();
-                            }
-                            continue;
-                        }
-                    }
-                }
-                Err(current_state) => {
-                    // Slot is not empty
-                    if current_state == SLOT_WRITING || current_state == SLOT_READING {
-                        // Another thread is writing or reading, wait a bit
-                        for _ in 0..10 {
-                            core::hint::spin_loop();
-                        }
-                    }
-                    continue;
-                }
+impl<'a, T, const CAPACITY: usize> Drop for Batch<'a, T, CAPACITY> {
+    fn drop(&mut self) {
+        self.deque.count.fetch_sub(self.len, Profile::RELEASE);
+        for i in 0..self.len {
+            let slot = &self.deque.buffer[self.position(i)];
+            if i < self.written {
+                // Safe because the slot is claimed by this batch and the item is initialized
+                unsafe { (*slot.data.get()).assume_init_drop() };
             }
+            slot.state.store(SLOT_CANCELLED, Profile::RELEASE);
+            slot.record(SlotOp::Release);
         }
     }
+}
 
-    /// Push an item to the back of the deque
-    /// Returns Err(item) if the deque is full
-    pub fn push_back(&self, item: T) -> Result<(), T> {
-        loop {
-            let tail = self.tail.load(Ordering::Acquire);
-            let head = self.head.load(Ordering::Acquire);
-            let tail_ = self.tail.load(Ordering::Acquire);
-            if tail_ != tail {
-                continue;
-            }
+/// Consecutive slots reserved at the back of a deque for scatter-gather filling,
+/// returned by `LockFreeDeque::reserve_back`.
+///
+/// Unlike a `Batch`, whose items are written in order with `push`, each slot is accessible by index,
+/// so that e.g. a DMA device can be handed the addresses of all the slots and fill them in any order.
+/// The slots stay invisible to consumers until `publish`. Dropping the handle without publishing cancels it.
+#[cfg(feature = "deque-back")]
+pub struct ReservedSlots<'a, T, const CAPACITY: usize> {
+    batch: Batch<'a, T, CAPACITY>,
+}
 
-            // Calculate the new tail position
-            let new_tail = (tail + 1) % CAPACITY;
+#[cfg(feature = "deque-back")]
+impl<'a, T, const CAPACITY: usize> ReservedSlots<'a, T, CAPACITY> {
+    /// Get the number of reserved slots
+    pub fn len(&self) -> usize {
+        self.batch.len
+    }
 
-            // Check if queue is full
-            if new_tail == head {
-                return Err(item);
-            }
+    /// Check if no slot is reserved
+    pub fn is_empty(&self) -> bool {
+        self.batch.len == 0
+    }
 
-            // Check if the target slot is available
-            let slot = &self.buffer[tail];
+    /// Get the buffer index (`0..CAPACITY`) of the `i`-th reserved slot, see `LockFreeDeque::reserve_back`
+    ///
+    /// Panics if `i` is out of bounds.
+    pub fn buffer_index(&self, i: usize) -> usize {
+        assert!(i < self.batch.len, "ReservedSlots: index out of bounds");
+        self.batch.position(i)
+    }
 
-            // Try to claim the slot for writing atomically
-            match slot.state.compare_exchange_weak(
-                SLOT_EMPTY,
-                SLOT_WRITING,
-                Ordering::Acquire,
-                Ordering::Relaxed,
-            ) {
-                Ok(_) => {
-                    // Successfully claimed slot, now try to update tail
-                    match self.tail.compare_exchange_weak(
-                        tail,
-                        new_tail,
-                        Ordering::Release,
-                        Ordering::Relaxed,
-                    ) {
-                        Ok(_) => {
-                            // Successfully reserved the slot, write the item
-                            unsafe {
-                                (*slot.data.get()).write(item);
-                            }
+    /// Get mutable access to the `i`-th reserved slot, the item that will be popped `i`-th from the front
+    ///
+    /// Panics if `i` is out of bounds.
+    pub fn slot_mut(&mut self, i: usize) -> &mut MaybeUninit<T> {
+        let slot = &self.batch.deque.buffer[self.buffer_index(i)];
+        // Safe because the slot is claimed by this handle in WRITING state
+        unsafe { &mut *slot.data.get() }
+    }
 
-                            // Mark slot as ready
-                            slot.state.store(SLOT_READY, Ordering::Release);
-                            return Ok(());
-                        }
-                        Err(_) => {
-                            // Failed to update tail, release the slot and retry
-                            slot.state.store(SLOT_EMPTY, Ordering::Release);
-                            // Small backoff to reduce contention
-                            for _ in 0..5 {
-                                core::hint::spin_loop();
-                            }
-                            continue;
-                        }
-                    }
-                }
-                Err(current_state) => {
-                    // Slot is not empty
-                    if current_state == SLOT_WRITING || current_state == SLOT_READING {
-                        // Another thread is writing or reading, wait a bit
-                        for _ in 0..10 {
-                            core::hint::spin_loop();
-                        }
-                    }
-                    continue;
-                }
-            }
-        }
+    /// Get a pointer to the `i`-th reserved slot, e.g. to hand it to a device filling the slot
+    ///
+    /// The pointer is valid until the handle is published or cancelled. Panics if `i` is out of bounds.
+    pub fn as_mut_ptr(&mut self, i: usize) -> *mut T {
+        self.slot_mut(i).as_mut_ptr()
     }
 
-    /// Push a slot to the front of the deque, returning a guard to the slot for in-place construction
-    /// Drops the guard to finalize the slot
+    /// Publish all the reserved slots, see `Batch::commit` for the order
     ///
-    /// Returns Err(item) if the deque is full
-    pub fn push_slot_front(&self) -> Result<SlotGuard<'_, T>, ()> {
-        loop {
-            let head = self.head.load(Ordering::Acquire);
-            let tail = self.tail.load(Ordering::Acquire);
-            let head_ = self.head.load(Ordering::Acquire);
-            if head_ != head {
-                continue;
-            }
+    /// # Safety
+    ///
+    /// Every reserved slot must have been initialized, through `slot_mut` or `as_mut_ptr`.
+    pub unsafe fn publish(mut self) {
+        self.batch.written = self.batch.len;
+        let Self { batch } = self;
+        batch.commit();
+    }
 
-            // Calculate the new head position (moving backwards)
-            let new_head = if head == 0 { CAPACITY - 1 } else { head - 1 };
+    /// Roll the reservation back: the slots are skipped by consumers, like those of an aborted `Batch`
+    ///
+    /// Items already written into the slots are not dropped, as the handle doesn't know which slots were written.
+    pub fn cancel(self) {
+        // Done in the drop of the batch, with no written item
+    }
+}
 
-            // Check if queue is full
-            if new_head == tail {
-                return Err(());
-            }
+/// Error returned by the bounded-retry operations when they give up because of contention.
+///
+/// The deque was not observed to be empty or full, so retrying may succeed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Contended;
 
-            // Check if the target slot is available
-            let slot = &self.buffer[new_head];
+/// Why a push failed, giving back the item, see `LockFreeDeque::push_back_bounded`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PushError<T> {
+    /// The deque is full, retrying only succeeds once a consumer makes room
+    Full(T),
+    /// The retry budget ran out because of contention, the deque was not observed to be full
+    Contended(T),
+    /// The queue is closed: its id is no longer registered, so retrying never succeeds
+    ///
+    /// The deque itself has no closed state, this is only returned by the queue-level functions such as `api::try_push_bounded`.
+    Closed(T),
+    /// The deque is poisoned, see `LockFreeDeque::is_poisoned`
+    Poisoned(T),
+}
 
-            // Try to claim the slot for writing atomically
-            match slot.state.compare_exchange_weak(
-                SLOT_EMPTY,
-                SLOT_WRITING,
-                Ordering::Acquire,
-                Ordering::Relaxed,
-            ) {
-                Ok(_) => {
-                    // Successfully claimed slot, now try to update head
-                    match self.head.compare_exchange_weak(
-                        head,
-                        new_head,
-                        Ordering::Release,
-                        Ordering::Relaxed,
-                    ) {
-                        Ok(_) => {
-                            return Ok(SlotGuard { slot });
-                        }
-                        Err(_) => {
-                            // Failed to update head, release the slot and retry
-                            slot.state.store(SLOT_EMPTY, Ordering::Release);
-                            // Small backoff to reduce contention
-                            for _ in 0..5 {
-                                core::hint::spin_loop();
-                            }
-                            continue;
-                        }
-                    }
-                }
-                Err(current_state) => {
-                    // Slot is not empty
-                    if current_state == SLOT_WRITING || current_state == SLOT_READING {
-                        // Another thread is writing or reading, wait a bit
-                        for _ in 0..10 {
-                            core::hint::spin_loop();
-                        }
-                    }
-                    continue;
-                }
-            }
+impl<T> PushError<T> {
+    /// Get back the item that failed to be pushed
+    pub fn into_inner(self) -> T {
+        match self {
+            PushError::Full(item)
+            | PushError::Contended(item)
+            | PushError::Closed(item)
+            | PushError::Poisoned(item) => item,
         }
     }
 
-    /// Push a slot to the back of the deque, returning a guard to the slot for in-place construction
-    /// Drops the guard to finalize the slot
-    ///
-    /// Returns Err(item) if the deque is full
-    pub fn push_slot_back(&self) -> Result<SlotGuard<'_, T>, ()> {
-        loop {
-            let tail = self.tail.load(Ordering::Acquire);
-            let head = self.head.load(Ordering::Acquire);
-            let tail_ = self.tail.load(Ordering::Acquire);
-            if tail_ != tail {
-                continue;
-            }
+    /// Convert the item that failed to be pushed, keeping the reason
+    pub fn map<U>(self, f: impl FnOnce(T) -> U) -> PushError<U> {
+        match self {
+            PushError::Full(item) => PushError::Full(f(item)),
+            PushError::Contended(item) => PushError::Contended(f(item)),
+            PushError::Closed(item) => PushError::Closed(f(item)),
+            PushError::Poisoned(item) => PushError::Poisoned(f(item)),
+        }
+    }
+}
 
-            // Calculate the new tail position
-            let new_tail = (tail + 1) % CAPACITY;
+/// Error returned by `LockFreeDeque::check_poison` once the deque is poisoned, see `LockFreeDeque::is_poisoned`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Poisoned;
 
-            // Check if queue is full
-            if new_tail == head {
-                return Err(());
-            }
+/// Error returned by the blocking operations when their abort flag is set, giving back the item of a push
+///
+/// See `LockFreeDeque::push_back_blocking`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Aborted<T = ()>(pub T);
 
-            // Check if the target slot is available
-            let slot = &self.buffer[tail];
+/// The last `N` distinct keys seen by `LockFreeDeque::pop_front_dedup`, for dropping duplicate deliveries
+///
+/// A key identifies a logical message, typically `(sender, seq)` with a sequence number the producer keeps
+/// across its retries. The keys are kept in a fixed ring, so the window is `no_std` and never allocates:
+/// once `N` newer distinct keys have been seen, the oldest one is forgotten, and a duplicate of it arriving
+/// later is returned again. A larger window tolerates duplicates arriving further apart, at the cost of
+/// `16 * N` bytes and a linear scan of the window per popped item.
+/// A key is never reported as a duplicate unless it was seen before, so the window only ever lets duplicates
+/// through, it never drops a fresh message, as long as producers don't reuse keys.
+#[derive(Clone, Debug)]
+pub struct DedupWindow<const N: usize> {
+    keys: [(u64, u64); N],
+    /// Number of valid keys in `keys`, at most `N`
+    len: usize,
+    /// Index in `keys` where the next key is recorded, overwriting the oldest one once full
+    next: usize,
+}
 
-            // Try to claim the slot for writing atomically
-            match slot.state.compare_exchange_weak(
-                SLOT_EMPTY,
-                SLOT_WRITING,
-                Ordering::Acquire,
-                Ordering::Relaxed,
-            ) {
-                Ok(_) => {
-                    // Successfully claimed slot, now try to update tail
-                    match self.tail.compare_exchange_weak(
-                        tail,
-                        new_tail,
-                        Ordering::Release,
-                        Ordering::Relaxed,
-                    ) {
-                        Ok(_) => {
-                            return Ok(SlotGuard { slot });
-                        }
-                        Err(_) => {
-                            // Failed to update tail, release the slot and retry
-                            slot.state.store(SLOT_EMPTY, Ordering::Release);
-                            // Small backoff to reduce contention
-                            for _ in 0..5 {
-                                core::hint::spin_loop();
-                            }
-                            continue;
-                        }
-                    }
-                }
-                Err(current_state) => {
-                    // Slot is not empty
-                    if current_state == SLOT_WRITING || current_state == SLOT_READING {
-                        // Another thread is writing or reading, wait a bit
-                        for _ in 0..10 {
-                            core::hint::spin_loop();
-                        }
-                    }
-                    continue;
-                }
-            }
+impl<const N: usize> DedupWindow<N> {
+    /// Create an empty window
+    pub const fn new() -> Self {
+        Self {
+            keys: [(0, 0); N],
+            len: 0,
+            next: 0,
         }
     }
 
-    /// Pop an item from the front of the deque
-    /// Returns None if the deque is empty
-    pub fn pop_front(&self) -> Option<T> {
-        loop {
-            let head = self.head.load(Ordering::Acquire);
-            let tail = self.tail.load(Ordering::Acquire);
-            let head_ = self.head.load(Ordering::Acquire);
-            if head_ != head {
-                continue;
-            }
+    /// Check if `key` is in the window
+    pub fn contains(&self, key: (u64, u64)) -> bool {
+        self.keys[..self.len].contains(&key)
+    }
 
-            // Check if queue is empty
-            if head == tail {
-                return None;
-            }
+    /// Record `key` as seen
+    /// Returns false if it was already in the window, leaving the window unchanged
+    pub fn insert(&mut self, key: (u64, u64)) -> bool {
+        if N == 0 {
+            return true;
+        }
+        if self.contains(key) {
+            return false;
+        }
+        self.keys[self.next] = key;
+        self.next = (self.next + 1) % N;
+        self.len = (self.len + 1).min(N);
+        true
+    }
 
-            // Check if the slot has data ready
-            let slot = &self.buffer[head];
+    /// Forget all keys
+    pub fn clear(&mut self) {
+        self.len = 0;
+        self.next = 0;
+    }
+}
 
-            // Try to claim the slot for reading
-            match slot.state.compare_exchange_weak(
-                SLOT_READY,
-                SLOT_READING,
-                Ordering::Acquire,
-                Ordering::Relaxed,
-            ) {
-                Ok(_) => {
-                    // Successfully claimed slot for reading
-                    let new_head = (head + 1) % CAPACITY;
-
-                    // Try to update head
-                    match self.head.compare_exchange_weak(
-                        head,
-                        new_head,
-                        Ordering::Release,
-                        Ordering::Relaxed,
-                    ) {
-                        Ok(_) => {
-                            // Successfully updated head, read the item
-                            let item = unsafe { (*slot.data.get()).assume_init_read() };
-
-                            // Mark slot as empty
-                            slot.state.store(SLOT_EMPTY, Ordering::Release);
-                            return Some(item);
-                        }
-                        Err(_) => {
-                            // Failed to update head, restore slot state and retry
-                            slot.state.store(SLOT_READY, Ordering::Release);
-                            // Small backoff to reduce contention
-                            for _ in 0..5 {
-                                core::hint::spin_loop();
-                            }
-                            continue;
-                        }
-                    }
-                }
-                Err(current_state) => {
-                    if current_state == SLOT_WRITING || current_state == SLOT_READING {
-                        // Another thread is writing or reading, wait a bit
-                        for _ in 0..10 {
-                            core::hint::spin_loop();
-                        }
-                    }
-                    continue;
-                }
-            }
-        }
+impl<const N: usize> Default for DedupWindow<N> {
+    fn default() -> Self {
+        Self::new()
     }
+}
 
-    /// Pop an item from the back of the deque
-    /// Returns None if the deque is empty
-    pub fn pop_back(&self) -> Option<T> {
-        loop {
-            let tail = self.tail.load(Ordering::Acquire);
-            let head = self.head.load(Ordering::Acquire);
-            let tail_ = self.tail.load(Ordering::Acquire);
-            if tail_ != tail {
-                continue;
-            }
+/// What `LockFreeDeque::push_back_policy` does when the deque is full
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FullPolicy {
+    /// Give the item back, like `push_back`
+    #[default]
+    Fail,
+    /// Evict the item at the front (the oldest one when used as a FIFO with `pop_front`) to make room
+    Overwrite,
+    /// Retry with backoff until a consumer makes room
+    BlockSpin,
+}
 
-            // Check if queue is empty
-            if head == tail {
-                return None;
-            }
+/// Result of `LockFreeDeque::push_back_policy`
+#[derive(Debug, PartialEq, Eq)]
+pub enum PushOutcome<T> {
+    /// The item was pushed without evicting anything
+    Pushed,
+    /// The item was pushed after evicting this item from the front (`FullPolicy::Overwrite` only)
+    Evicted(T),
+    /// The deque was full, the item is given back (`FullPolicy::Fail` only)
+    Full(T),
+}
 
-            // Calculate the position of the last element
-            let last_pos = if tail == 0 { CAPACITY - 1 } else { tail - 1 };
+/// A handle fixing the end a deque is used from, returned by `LockFreeDeque::front_view` or `LockFreeDeque::back_view`
+///
+/// Both views are FIFO: items are pushed at the end the view is named after, and popped at the opposite one.
+///
+/// | view         | `push`       | `pop`       |
+/// |--------------|--------------|-------------|
+/// | `back_view`  | `push_back`  | `pop_front` |
+/// | `front_view` | `push_front` | `pop_back`  |
+///
+/// A producer and a consumer agreeing on the view can't mix up the ends, e.g. by pairing `push_back` with
+/// `pop_back`, which would make the queue LIFO. Handles of the two different views on the same deque
+/// shouldn't be mixed, as each one's `pop` takes from where the other's `push` puts.
+#[cfg(all(feature = "deque-front", feature = "deque-back"))]
+#[derive(Clone, Copy)]
+pub struct QueueView<'a, T, const CAPACITY: usize> {
+    deque: &'a LockFreeDeque<T, CAPACITY>,
+    /// Whether items are pushed at the front, and popped at the back
+    front: bool,
+}
 
-            // Check if the slot has data ready
-            let slot = &self.buffer[last_pos];
+#[cfg(all(feature = "deque-front", feature = "deque-back"))]
+impl<'a, T, const CAPACITY: usize> QueueView<'a, T, CAPACITY> {
+    /// Push an item at the end of the view, see the table in the type documentation
+    /// Returns Err(item) if the deque is full
+    pub fn push(&self, item: T) -> Result<(), T> {
+        if self.front {
+            self.deque.push_front(item)
+        } else {
+            self.deque.push_back(item)
+        }
+    }
 
-            // Try to claim the slot for reading
-            match slot.state.compare_exchange_weak(
-                SLOT_READY,
-                SLOT_READING,
-                Ordering::Acquire,
-                Ordering::Relaxed,
-            ) {
-                Ok(_) => {
-                    // Successfully claimed slot for reading
-
-                    // Try to update tail
-                    match self.tail.compare_exchange_weak(
-                        tail,
-                        last_pos,
-                        Ordering::Release,
-                        Ordering::Relaxed,
-                    ) {
-                        Ok(_) => {
-                            // Successfully updated tail, read the item
-                            let item = unsafe { (*slot.data.get()).assume_init_read() };
-
-                            // Mark slot as empty
-                            slot.state.store(SLOT_EMPTY, Ordering::Release);
-                            return Some(item);
-                        }
-                        Err(_) => {
-                            // Failed to update tail, restore slot state and retry
-                            slot.state.store(SLOT_READY, Ordering::Release);
-                            // Small backoff to reduce contention
-                            for _ in 0..5 {
-                                core::hint::spin_loop();
-                            }
-                            continue;
-                        }
-                    }
-                }
-                Err(current_state) => {
-                    if current_state == SLOT_WRITING || current_state == SLOT_READING {
-                        // Another thread is writing or reading, wait a bit
-                        for _ in 0..10 {
-                            core::hint::spin_loop();
-                        }
-                    }
-                    continue;
-                }
-            }
+    /// Pop the oldest item pushed through the view, see the table in the type documentation
+    /// Returns None if the deque is empty
+    pub fn pop(&self) -> Option<T> {
+        if self.front {
+            self.deque.pop_back()
+        } else {
+            self.deque.pop_front()
         }
     }
 
-    /// Get the current length of the deque (approximate in concurrent scenarios)
-    pub fn len(&self) -> usize {
-        let (head, tail) = loop {
-            let head = self.head.load(Ordering::Acquire);
-            let tail = self.tail.load(Ordering::Acquire);
-            let head_ = self.head.load(Ordering::Acquire);
-            if head_ == head {
-                break (head, tail);
-            }
-        };
+    /// Get the deque the view is on
+    pub fn deque(&self) -> &'a LockFreeDeque<T, CAPACITY> {
+        self.deque
+    }
+}
 
-        if tail >= head {
-            tail - head
-        } else {
-            CAPACITY - head + tail
+/// Backoff of `LockFreeDeque::pop_front_debug`, noting when the front slot is being written
+#[cfg(all(feature = "deque-front", feature = "debug-history"))]
+struct RaceObserver<'a, T, const CAPACITY: usize> {
+    deque: &'a LockFreeDeque<T, CAPACITY>,
+    raced: core::cell::Cell<bool>,
+}
+
+#[cfg(all(feature = "deque-front", feature = "debug-history"))]
+impl<T, const CAPACITY: usize> Backoff for RaceObserver<'_, T, CAPACITY> {
+    fn backoff(&self, spins: u32) {
+        let head = self.deque.indices().0 % CAPACITY;
+        if self.deque.buffer[head].state.load(Profile::ACQUIRE) == SLOT_WRITING {
+            self.raced.set(true);
         }
+        SpinBackoff.backoff(spins);
     }
+}
 
-    /// Check if the deque is empty (approximate in concurrent scenarios)
-    pub fn is_empty(&self) -> bool {
-        let (head, tail) = loop {
-            let head = self.head.load(Ordering::Acquire);
-            let tail = self.tail.load(Ordering::Acquire);
-            let head_ = self.head.load(Ordering::Acquire);
-            if head_ == head {
-                break (head, tail);
-            }
-        };
-        head == tail
+/// A guard giving access to the item at one end of a deque without removing it,
+/// returned by `LockFreeDeque::peek_front` or `LockFreeDeque::peek_back`.
+///
+/// The slot is held in PEEKING state, so that no pop takes the item meanwhile, and marked as ready again
+/// when the guard is dropped, leaving the item in the deque. Pops reaching the slot wait for the guard, so don't
+/// pop from that end on the thread holding it. Like a `SlotGuard`, the guard must not be forgotten:
+/// the slot would stay held forever, and pops reaching it would wait for it forever.
+pub struct PeekGuard<'a, T> {
+    slot: &'a Slot<T>,
+}
+
+impl<'a, T> Deref for PeekGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        // Safe because the slot is claimed in PEEKING state (or READING, for `peek_front_with`)
+        // and holds an initialized item
+        unsafe { (*self.slot.data.get()).assume_init_ref() }
     }
+}
 
-    /// Get the capacity of the deque
-    pub const fn capacity(&self) -> usize {
-        CAPACITY
+impl<'a, T> Drop for PeekGuard<'a, T> {
+    fn drop(&mut self) {
+        self.slot.state.store(SLOT_READY, Profile::RELEASE);
+        self.slot.record(SlotOp::Release);
     }
 }
 
-impl<T, const CAPACITY: usize> Default for LockFreeDeque<T, CAPACITY> {
-    fn default() -> Self {
-        Self::new()
+/// Marks a slot claimed for reading as empty when dropped, once a pop moved its item out or dropped it.
+///
+/// Used by the pop core, so that the slot is released even if the reader panics, see `pop_front_with`.
+struct ReadGuard<'a, T> {
+    slot: &'a Slot<T>,
+    /// The pop to record
+    op: SlotOp,
+}
+
+impl<'a, T> Drop for ReadGuard<'a, T> {
+    fn drop(&mut self) {
+        // Mark slot as empty
+        self.slot.time(SLOT_EMPTY);
+        self.slot.state.store(SLOT_EMPTY, Profile::RELEASE);
+        self.slot.record(self.op);
     }
 }
 
-impl<T, const CAPACITY: usize> Drop for LockFreeDeque<T, CAPACITY> {
+#[cfg(feature = "deque-front")]
+/// Drops the item behind the pointer when dropped, so that `pop_front_with` drops it even if the callback panics
+struct DropInPlace<T>(*mut T);
+
+#[cfg(feature = "deque-front")]
+impl<T> Drop for DropInPlace<T> {
     fn drop(&mut self) {
-        // Clean up any remaining items to prevent memory leaks
-        while self.pop_front().is_some() {}
+        // Safe because the pointer is to the initialized item of a slot claimed for reading, read by no one else
+        unsafe { self.0.drop_in_place() };
     }
 }
 
-// Safety: The deque can be sent between threads if T can be sent
-unsafe impl<T: Send, const CAPACITY: usize> Send for LockFreeDeque<T, CAPACITY> {}
-// Safety: The deque can be shared between threads if T can be sent
-unsafe impl<T: Send, const CAPACITY: usize> Sync for LockFreeDeque<T, CAPACITY> {}
+/// A lock-free deque implementation with fixed capacity, supporting multiple producers and multiple consumers.
+///
+/// Fairness: the deque is lock-free, not wait-free. Some operation always makes progress, but a single
+/// thread that keeps losing the head/tail CAS may retry indefinitely. There is no fairness mechanism;
+/// in practice the backoff after a lost CAS spreads operations roughly evenly among competing threads.
+///
+/// Head (the first element) and tail (one past the last element) are packed into a single word, `ends`,
+/// so that every operation moves its index with one CAS that also checks the other index: a push to one end
+/// can't fill the slot kept empty while a push to the other end fills the one before it.
+///
+/// Each index takes half of the word, so `CAPACITY` is limited to `2^(usize::BITS / 2)`: 65536 slots on
+/// 32-bit targets, more than any memory on 64-bit ones. `new` checks this with a compile-time assertion,
+/// so a larger deque fails to build instead of silently truncating its indices.
+pub struct LockFreeDeque<T, const CAPACITY: usize> {
+    buffer: [Slot<T>; CAPACITY],
+    /// Head in the upper half, tail in the lower half, see `pack`
+    ends: AtomicUsize,
+    /// Number of items in the deque, see `len_exact`
+    count: AtomicUsize,
+    /// Set once an operation finds the invariants broken, see `is_poisoned`
+    poisoned: AtomicBool,
+}
 
-#[cfg(test)]
-mod tests {
-    extern crate std;
+impl<T, const CAPACITY: usize> LockFreeDeque<T, CAPACITY> {
+    #[cfg(not(loom))]
+    const EMPTY_CELL: Slot<T> = Slot::new();
 
-    use super::*;
-    use core::sync::atomic::AtomicI32;
-    use std::{println, sync::Arc, thread, vec};
-    #[test]
-    fn test_basic_operations() {
-        let deque: LockFreeDeque<i32, 5> = LockFreeDeque::new();
+    const_fn! {
+        /// Create a new lock-free deque with compile-time capacity
+        pub const fn new() -> Self {
+            const {
+                assert!(
+                    CAPACITY <= 1 << END_BITS,
+                    "CAPACITY doesn't fit in half a usize"
+                )
+            };
+            #[cfg(not(loom))]
+            let buffer = [Self::EMPTY_CELL; CAPACITY];
+            #[cfg(loom)]
+            let buffer = core::array::from_fn(|_| Slot::new());
 
-        // Test push_back and pop_front
-        assert!(deque.push_back(1).is_ok());
-        assert!(deque.push_back(2).is_ok());
-        assert_eq!(deque.pop_front(), Some(1));
-        assert_eq!(deque.pop_front(), Some(2));
-        assert_eq!(deque.pop_front(), None);
+            Self {
+                buffer,
+                ends: AtomicUsize::new(0),
+                count: AtomicUsize::new(0),
+                poisoned: AtomicBool::new(false),
+            }
+        }
+    }
 
-        // Test push_front and pop_back
-        assert!(deque.push_front(3).is_ok());
-        assert!(deque.push_front(4).is_ok());
-        assert_eq!(deque.pop_back(), Some(3));
-        assert_eq!(deque.pop_back(), Some(4));
-        assert_eq!(deque.pop_back(), None);
+    /// Push an item to the front of the deque
+    /// Returns Err(item) if the deque is full
+    pub fn push_front(&self, item: T) -> Result<(), T> {
+        self.push_front_backoff(item, &SpinBackoff)
     }
 
-    #[test]
-    fn test_capacity_limit() {
-        let deque: LockFreeDeque<i32, 3> = LockFreeDeque::new();
+    /// Push an item to the front of the deque, returning the length of the deque right after the push
+    /// Returns Err(item) if the deque is full
+    ///
+    /// The length is computed from the indices the push was made against, so it is approximate under
+    /// concurrency, like `len`: concurrent pushes and pops may have changed it by the time it is returned.
+    pub fn push_front_len(&self, item: T) -> Result<usize, T> {
+        self.push_front_len_(item, usize::MAX, &SpinBackoff)
+            .map_err(PushError::into_inner)
+    }
 
-        assert!(deque.push_back(1).is_ok());
-        assert!(deque.push_back(2).is_ok());
-        assert!(deque.push_back(3).is_err()); // Should fail, queue is full
+    /// Push an item to the front of the deque, waiting with `backoff` between retries
+    /// Returns Err(item) if the deque is full
+    pub fn push_front_backoff(&self, item: T, backoff: &impl Backoff) -> Result<(), T> {
+        self.push_front_len_(item, usize::MAX, backoff)
+            .map(|_| ())
+            .map_err(PushError::into_inner)
     }
 
-    #[test]
-    fn test_concurrent_operations() {
-        let deque = Arc::new(LockFreeDeque::<i32, 100>::new());
-        let mut handles = vec![];
+    fn push_front_len_(
+        &self,
+        item: T,
+        max_retries: usize,
+        backoff: &impl Backoff,
+    ) -> Result<usize, PushError<T>> {
+        self.push_len_(true, item, max_retries, backoff)
+    }
 
-        // Spawn multiple producers
-        for i in 0..4 {
-            let deque_clone = Arc::clone(&deque);
-            let handle = thread::spawn(move || {
-                for j in 0..25 {
-                    let value = i * 25 + j;
-                    while deque_clone.push_back(value).is_err() {
-                        thread::yield_now();
-                    }
+    /// Push an item to the front (if `front`) or back of the deque, returning the length right after the push
+    fn push_len_(
+        &self,
+        front: bool,
+        item: T,
+        max_retries: usize,
+        backoff: &impl Backoff,
+    ) -> Result<usize, PushError<T>> {
+        match self.claim_push_(front, 1, max_retries, backoff) {
+            Ok((index, len)) => {
+                // Successfully reserved the slot, write the item
+                let slot = &self.buffer[index];
+                unsafe {
+                    (*slot.data.get()).write(item);
                 }
-            });
-            handles.push(handle);
+
+                // Mark slot as ready
+                slot.time(SLOT_READY);
+                slot.state.store(SLOT_READY, Profile::RELEASE);
+                slot.record(match front {
+                    true => SlotOp::PushFront,
+                    false => SlotOp::PushBack,
+                });
+                Ok(len)
+            }
+            Err(err) => Err(err.map(|()| item)),
         }
+    }
 
-        // Spawn multiple consumers
-        for _ in 0..2 {
-            let deque_clone = Arc::clone(&deque);
-            let handle = thread::spawn(move || {
-                let mut count = 0;
-                while count < 50 {
-                    if let Some(_) = deque_clone.pop_front() {
-                        count += 1;
-                    } else {
-                        thread::yield_now();
+    /// Claim `len` consecutive empty slots at the front (if `front`) or back of the deque for writing,
+    /// and move that end past them, giving up after `max_retries` retries due to contention
+    /// Returns the buffer index of the slot nearest to the other end (the one a single push claims),
+    /// and the length of the deque right after the move
+    ///
+    /// The claimed slots are in WRITING state, at decreasing buffer indices from the returned one if `front`,
+    /// else at increasing ones. This is the core of every push, `SlotGuard` and `Batch`.
+    fn claim_push_(
+        &self,
+        front: bool,
+        len: usize,
+        max_retries: usize,
+        backoff: &impl Backoff,
+    ) -> Result<(usize, usize), PushError<()>> {
+        let position = |start: usize, i: usize| {
+            if front {
+                (start + CAPACITY - i) % CAPACITY
+            } else {
+                (start + i) % CAPACITY
+            }
+        };
+        for _ in 0..=max_retries {
+            let (head, tail) = self.indices();
+
+            // An index outside the ring buffer means the deque is corrupted, see `is_poisoned`.
+            // Fail rather than index out of bounds below
+            if self.poisoned_at(head, tail) {
+                debug_assert!(!front || head < CAPACITY, "head {} out of bounds", head);
+                return Err(PushError::Poisoned(()));
+            }
+
+            // Check if there is enough free space (one slot is always kept empty)
+            let fits =
+                |head: usize, tail: usize| (tail + CAPACITY - head) % CAPACITY + len < CAPACITY;
+            if !fits(head, tail) {
+                return Err(PushError::Full(()));
+            }
+
+            // The first slot to claim, and the new position of the index (head moves backwards)
+            let (start, new) = if front {
+                (
+                    (head + CAPACITY - 1) % CAPACITY,
+                    (head + CAPACITY - len) % CAPACITY,
+                )
+            } else {
+                (tail, (tail + len) % CAPACITY)
+            };
+
+            // Try to claim the slots for writing atomically
+            let mut claimed = 0;
+            let mut busy = false;
+            while claimed < len {
+                let slot = &self.buffer[position(start, claimed)];
+                match slot.state.compare_exchange_weak(
+                    SLOT_EMPTY,
+                    SLOT_WRITING,
+                    Profile::ACQUIRE,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => {
+                        slot.record(SlotOp::Claim);
+                        slot.time(SLOT_WRITING);
+                        claimed += 1;
+                    }
+                    Err(current_state) => {
+                        // Another thread may be writing or reading the slot
+                        busy = current_state == SLOT_WRITING || current_state == SLOT_READING;
+                        break;
                     }
                 }
-            });
-            handles.push(handle);
-        }
+            }
 
-        // Wait for all threads to complete
-        for handle in handles {
-            handle.join().unwrap();
+            if claimed == len {
+                yield_hook(match front {
+                    true => SlotOp::PushFront,
+                    false => SlotOp::PushBack,
+                });
+                // Successfully claimed the slots, now try to move the index, if the other one didn't move
+                // meanwhile into the room needed
+                let moved = if front {
+                    self.move_index(true, head, new, |tail| fits(head, tail))
+                } else {
+                    self.move_index(false, tail, new, |head| fits(head, tail))
+                };
+                if let Ok(other) = moved {
+                    self.count.fetch_add(len, Profile::RELEASE);
+                    let len = if front {
+                        (other + CAPACITY - new) % CAPACITY
+                    } else {
+                        (new + CAPACITY - other) % CAPACITY
+                    };
+                    return Ok((start, len));
+                }
+            }
+
+            // Failed to claim the slots or to move the index, release the claimed slots and retry
+            for i in 0..claimed {
+                let slot = &self.buffer[position(start, i)];
+                slot.state.store(SLOT_EMPTY, Profile::RELEASE);
+                slot.record(SlotOp::Release);
+            }
+            if claimed == len {
+                // Small backoff to reduce contention
+                backoff.backoff(5);
+            } else if busy {
+                // Another thread is writing or reading, wait a bit
+                backoff.backoff(10);
+            }
         }
+        Err(PushError::Contended(()))
+    }
 
-        assert!(deque.is_empty());
+    #[cfg(feature = "deque-back")]
+    /// Push an item to the back of the deque
+    /// Returns Err(item) if the deque is full
+    pub fn push_back(&self, item: T) -> Result<(), T> {
+        self.push_back_backoff(item, &SpinBackoff)
     }
 
-    #[test]
-    fn test_mixed_operations() {
-        let deque: LockFreeDeque<i32, 6> = LockFreeDeque::new();
+    #[cfg(feature = "deque-back")]
+    /// Push an item to the back of the deque once admitted by `limit`, see `WriterLimit`
+    /// Returns Err(item) if the deque is full
+    pub fn push_back_limited(&self, item: T, limit: &WriterLimit) -> Result<(), T> {
+        let _permit = limit.admit();
+        self.push_back(item)
+    }
 
-        // Mix front and back operations
-        assert!(deque.push_front(1).is_ok());
-        assert!(deque.push_back(2).is_ok());
-        assert!(deque.push_front(0).is_ok());
-        assert!(deque.push_back(3).is_ok());
+    /// Push an item to the front of the deque, giving up after `max_retries` retries due to contention
+    /// Returns the reason of the failure along with the item, see `PushError`
+    pub fn push_front_bounded(&self, item: T, max_retries: usize) -> Result<(), PushError<T>> {
+        self.push_front_len_(item, max_retries, &SpinBackoff)
+            .map(|_| ())
+    }
 
-        // Should be: [0, 1, 2, 3]
-        assert_eq!(deque.pop_front(), Some(0));
-        assert_eq!(deque.pop_back(), Some(3));
-        assert_eq!(deque.pop_front(), Some(1));
-        assert_eq!(deque.pop_back(), Some(2));
-        assert!(deque.is_empty());
+    #[cfg(feature = "deque-back")]
+    /// Push an item to the back of the deque, returning the length of the deque right after the push
+    /// Returns Err(item) if the deque is full
+    ///
+    /// The length is approximate under concurrency, see `push_front_len`.
+    pub fn push_back_len(&self, item: T) -> Result<usize, T> {
+        self.push_back_len_(item, usize::MAX, &SpinBackoff)
+            .map_err(PushError::into_inner)
     }
 
-    #[test]
-    fn test_dequeue() {
-        let deque = LockFreeDeque::<usize, 16>::new();
-        for i in 0..4 {
-            let _ = deque.push_front(i);
-        }
-        for _ in 0..18 {
-            println!("{:?}", deque.pop_front());
-        }
+    #[cfg(feature = "deque-back")]
+    /// Push an item to the back of the deque, waiting with `backoff` between retries
+    /// Returns Err(item) if the deque is full
+    pub fn push_back_backoff(&self, item: T, backoff: &impl Backoff) -> Result<(), T> {
+        self.push_back_len_(item, usize::MAX, backoff)
+            .map(|_| ())
+            .map_err(PushError::into_inner)
+    }
 
-        // for _ in 0..5 {
-        //     println!("{:?}", deque.alloc_node());
-        // }
+    #[cfg(feature = "deque-back")]
+    /// Push an item to the back of the deque, giving up after `max_retries` retries due to contention
+    /// Returns the reason of the failure along with the item, see `PushError`
+    pub fn push_back_bounded(&self, item: T, max_retries: usize) -> Result<(), PushError<T>> {
+        self.push_back_len_(item, max_retries, &SpinBackoff)
+            .map(|_| ())
     }
 
-    #[test]
-    fn test_mpsc() {
-        let pad = 64usize;
+    #[cfg(feature = "deque-back")]
+    fn push_back_len_(
+        &self,
+        item: T,
+        max_retries: usize,
+        backoff: &impl Backoff,
+    ) -> Result<usize, PushError<T>> {
+        self.push_len_(false, item, max_retries, backoff)
+    }
 
-        let flag = Arc::new(AtomicI32::new(3));
-        let flag1 = flag.clone();
-        let flag2 = flag.clone();
-        let flag3 = flag.clone();
-        let p1 = Arc::new(LockFreeDeque::<usize, 256>::new());
-        let p2 = p1.clone();
-        let p3 = p1.clone();
-        let c = p1.clone();
+    /// Push an item to the front of the deque once admitted by `limit`, see `WriterLimit`
+    /// Returns Err(item) if the deque is full
+    pub fn push_front_limited(&self, item: T, limit: &WriterLimit) -> Result<(), T> {
+        let _permit = limit.admit();
+        self.push_front(item)
+    }
 
-        let t1 = thread::spawn(move || {
-            for i in 0..pad {
-                let _ = p1.push_back(i);
-            }
-            flag1.fetch_sub(1, Ordering::SeqCst);
-        });
-        let t2 = thread::spawn(move || {
-            for i in pad..(2 * pad) {
-                let _ = p2.push_back(i);
-            }
-            flag2.fetch_sub(1, Ordering::SeqCst);
-        });
-        let t3 = thread::spawn(move || {
-            for i in (2 * pad)..(3 * pad) {
-                let _ = p3.push_back(i);
-            }
-            flag3.fetch_sub(1, Ordering::SeqCst);
-        });
+    #[cfg(feature = "deque-slot")]
+    /// Push a slot to the front of the deque, returning a guard to the slot for in-place construction
+    /// Drops the guard to finalize the slot
+    ///
+    /// Returns Err(item) if the deque is full
+    pub fn push_slot_front(&self) -> Result<SlotGuard<'_, T>, ()> {
+        let (index, _) = self
+            .claim_push_(true, 1, usize::MAX, &SpinBackoff)
+            .map_err(|_| ())?;
+        let slot = &self.buffer[index];
+        // The guard publishes the slot on drop, record the push now
+        slot.record(SlotOp::PushFront);
+        Ok(SlotGuard::new(slot))
+    }
 
-        let mut sum = 0;
-        while flag.load(Ordering::SeqCst) != 0 || !c.is_empty() {
-            if let Some(num) = c.pop_front() {
-                sum += num;
-            }
-        }
+    #[cfg(all(feature = "deque-slot", feature = "deque-back"))]
+    /// Push a slot to the back of the deque, returning a guard to the slot for in-place construction
+    /// Drops the guard to finalize the slot
+    ///
+    /// Returns Err(item) if the deque is full
+    pub fn push_slot_back(&self) -> Result<SlotGuard<'_, T>, ()> {
+        self.push_slot_back_indexed().map(|(guard, _)| guard)
+    }
 
-        t1.join().unwrap();
-        t2.join().unwrap();
-        t3.join().unwrap();
-        assert_eq!(sum, (0..(3 * pad)).sum());
+    #[cfg(all(feature = "deque-slot", feature = "deque-back"))]
+    /// Same as `push_slot_back`, but also returns the buffer index of the claimed slot,
+    /// e.g. to program a DMA device with the address of that slot
+    ///
+    /// The index is into the `0..CAPACITY` ring buffer, and is only meaningful while the guard is held:
+    /// once the guard is dropped, the item may be popped and the slot reused.
+    pub fn push_slot_back_indexed(&self) -> Result<(SlotGuard<'_, T>, usize), ()> {
+        let (index, _) = self
+            .claim_push_(false, 1, usize::MAX, &SpinBackoff)
+            .map_err(|_| ())?;
+        let slot = &self.buffer[index];
+        // The guard publishes the slot on drop, record the push now
+        slot.record(SlotOp::PushBack);
+        Ok((SlotGuard::new(slot), index))
     }
 
-    #[test]
-    fn test_mpmc() {
-        let pad = 64usize;
+    #[cfg(feature = "deque-back")]
+    /// Predict the buffer index of the slot the next `push_back` (or `push_slot_back_indexed`) would claim
+    /// Returns None if the deque is full
+    ///
+    /// Advisory only, e.g. to pre-program a DMA descriptor before pushing: it is the current `tail`,
+    /// so a concurrent push or pop at either end may make the actual push land elsewhere, or fail.
+    /// Compare with the index returned by `push_slot_back_indexed` before relying on the prediction.
+    pub fn peek_back_index(&self) -> Option<usize> {
+        let (head, tail) = self.indices();
+        ((tail + 1) % CAPACITY != head).then_some(tail)
+    }
 
-        let flag = Arc::new(AtomicI32::new(3));
-        let flag_c = flag.clone();
-        let flag1 = flag.clone();
-        let flag2 = flag.clone();
-        let flag3 = flag.clone();
-
-        let p1 = Arc::new(LockFreeDeque::<usize, 256>::new());
-        let p2 = p1.clone();
-        let p3 = p1.clone();
-        let c1 = p1.clone();
-        let c2 = p1.clone();
-
-        let producer1 = thread::spawn(move || {
-            for i in 0..pad {
-                let _ = p1.push_back(i);
-            }
-            flag1.fetch_sub(1, Ordering::SeqCst);
-        });
-        let producer2 = thread::spawn(move || {
-            for i in pad..(2 * pad) {
-                let _ = p2.push_back(i);
+    #[cfg(all(feature = "deque-front", feature = "deque-back"))]
+    /// Push an item to the back of the deque, handling a full deque according to `policy`
+    ///
+    /// The policy is chosen per call rather than stored in the deque, so that the deque stays a plain
+    /// ring buffer: a caller wanting one policy per queue keeps it next to the deque, as `PerProcess`
+    /// does with `QueueConfig::overflow`.
+    ///
+    /// - `Fail` returns `PushOutcome::Full(item)`.
+    /// - `Overwrite` pops the front item and retries, returning the evicted item in `PushOutcome::Evicted`
+    ///   so that the caller can log or recycle it. If concurrent pushes keep refilling the deque, several
+    ///   items may be evicted before the push succeeds: only the last one is returned, the others are dropped.
+    /// - `BlockSpin` retries with `SpinBackoff` until a consumer pops. It never returns, i.e. spins forever,
+    ///   if nothing is ever popped, so use it only when a consumer is known to be running,
+    ///   or use `push_back_blocking` instead, which can be interrupted.
+    pub fn push_back_policy(&self, item: T, policy: FullPolicy) -> PushOutcome<T> {
+        let mut item = item;
+        let mut evicted = None;
+        loop {
+            match self.push_back(item) {
+                Ok(()) => {
+                    return match evicted {
+                        Some(evicted) => PushOutcome::Evicted(evicted),
+                        None => PushOutcome::Pushed,
+                    };
+                }
+                Err(rejected) => item = rejected,
             }
-            flag2.fetch_sub(1, Ordering::SeqCst);
-        });
-        let producer3 = thread::spawn(move || {
-            for i in (2 * pad)..(3 * pad) {
-                let _ = p3.push_back(i);
+            match policy {
+                FullPolicy::Fail => return PushOutcome::Full(item),
+                FullPolicy::Overwrite => {
+                    // The deque may have been drained meanwhile, then just retry
+                    if let Some(front) = self.pop_front() {
+                        evicted = Some(front);
+                    }
+                }
+                FullPolicy::BlockSpin => SpinBackoff.backoff(10),
             }
-            flag3.fetch_sub(1, Ordering::SeqCst);
-        });
+        }
+    }
 
-        let consumer = thread::spawn(move || {
-            let mut sum = 0;
-            while flag_c.load(Ordering::SeqCst) != 0 || !c2.is_empty() {
-                if let Some(num) = c2.pop_front() {
-                    sum += num;
+    #[cfg(all(feature = "deque-front", feature = "deque-back"))]
+    /// Move items from the front of `src` to the back of this deque, until `src` is empty or this deque is full
+    ///
+    /// Returns the number of items moved. The moved items keep their FIFO order: they are appended
+    /// in the order they would have been popped from `src`'s front.
+    ///
+    /// If this deque fills mid-merge, the item just popped is pushed back to the front of `src`,
+    /// so the remaining items stay in `src` in their original order. Items are moved one by one,
+    /// so concurrent operations on either deque can interleave with the merge: a concurrent `push_front`
+    /// to `src` may end up behind the item put back, and if `src` has meanwhile been filled up,
+    /// the merge retries pushing the item into either deque until one has room.
+    pub fn append_from<const SRC_CAPACITY: usize>(
+        &self,
+        src: &LockFreeDeque<T, SRC_CAPACITY>,
+    ) -> usize {
+        let mut moved = 0;
+        while let Some(item) = src.pop_front() {
+            let mut item = match self.push_back(item) {
+                Ok(()) => {
+                    moved += 1;
+                    continue;
                 }
+                Err(item) => item,
+            };
+            // This deque is full, give the item back to `src`
+            loop {
+                match src.push_front(item) {
+                    Ok(()) => return moved,
+                    Err(rejected) => item = rejected,
+                }
+                match self.push_back(item) {
+                    Ok(()) => {
+                        moved += 1;
+                        break;
+                    }
+                    Err(rejected) => item = rejected,
+                }
+                SpinBackoff.backoff(10);
             }
-            sum
-        });
+        }
+        moved
+    }
 
-        let mut sum = 0;
-        while flag.load(Ordering::SeqCst) != 0 || !c1.is_empty() {
-            if let Some(num) = c1.pop_front() {
-                sum += num;
+    #[cfg(all(feature = "deque-front", feature = "deque-back"))]
+    /// Get a FIFO view pushing at the front and popping at the back, see `QueueView`
+    pub fn front_view(&self) -> QueueView<'_, T, CAPACITY> {
+        QueueView {
+            deque: self,
+            front: true,
+        }
+    }
+
+    #[cfg(all(feature = "deque-front", feature = "deque-back"))]
+    /// Get a FIFO view pushing at the back and popping at the front, see `QueueView`
+    pub fn back_view(&self) -> QueueView<'_, T, CAPACITY> {
+        QueueView {
+            deque: self,
+            front: false,
+        }
+    }
+
+    #[cfg(feature = "deque-back")]
+    /// Push an item to the back of the deque, spinning with `SpinBackoff` while the deque is full
+    /// until a consumer makes room or `abort` is set
+    ///
+    /// `abort` is checked with an Acquire load after each failed attempt, so whatever the aborting thread
+    /// wrote before setting it (with a Release store or stronger) is visible once this returns `Aborted`.
+    /// The flag is owned by the caller, so one flag can interrupt every waiter on any number of deques,
+    /// e.g. during teardown. It is never reset here. Returns the item in `Aborted` if aborted.
+    pub fn push_back_blocking(&self, item: T, abort: &AtomicBool) -> Result<(), Aborted<T>> {
+        let mut item = item;
+        loop {
+            match self.push_back(item) {
+                Ok(()) => return Ok(()),
+                Err(rejected) => item = rejected,
+            }
+            if abort.load(Profile::ACQUIRE) {
+                return Err(Aborted(item));
             }
+            SpinBackoff.backoff(10);
         }
+    }
 
-        producer1.join().unwrap();
-        producer2.join().unwrap();
-        producer3.join().unwrap();
+    #[cfg(feature = "deque-back")]
+    /// Reserve `len` consecutive slots at the back of the deque for a transactional batch
+    ///
+    /// The slots are claimed and the tail is advanced at once, so the batch is guaranteed to fit:
+    /// returns Err(()) if there is not enough free space for `len` items right now.
+    /// Items written with `Batch::push` stay invisible to consumers (the slots are in WRITING state)
+    /// until `Batch::commit` publishes them all. Consumers reaching a reserved slot wait for the commit,
+    /// so they never observe a partial prefix of an aborted batch. The commit itself is not atomic,
+    /// but a consumer popping from the front sees the whole batch at once, see `Batch::commit`.
+    ///
+    /// `Batch::abort` (or dropping the batch) cancels the reservation instead.
+    /// Cancelled slots are silently discarded by later pops.
+    pub fn begin_batch(&self, len: usize) -> Result<Batch<'_, T, CAPACITY>, ()> {
+        self.begin_batch_(len, false)
+    }
 
-        let s = consumer.join().unwrap();
-        sum += s;
-        assert_eq!(sum, (0..(3 * pad)).sum());
+    #[cfg(feature = "deque-back")]
+    /// Reserve `k` consecutive slots at the back of the deque, to be filled in any order then published together
+    ///
+    /// Like `begin_batch`, the slots are claimed and the tail is advanced at once: returns Err(()) if there is
+    /// not enough free space for `k` more items right now, i.e. if `len() + k` would exceed `capacity()`.
+    /// The slots are contiguous in the ring, at buffer indices `tail, tail + 1, ...` modulo `CAPACITY`: the range
+    /// may wrap around the end of the buffer, and consecutive slots are `size_of` a slot apart rather than
+    /// `size_of::<T>()`, so address each one with `ReservedSlots::as_mut_ptr`.
+    /// Consumers reaching a reserved slot wait for it to be published or cancelled.
+    pub fn reserve_back(&self, k: usize) -> Result<ReservedSlots<'_, T, CAPACITY>, ()> {
+        self.begin_batch_(k, false)
+            .map(|batch| ReservedSlots { batch })
     }
 
-    #[test]
-    fn test_mpmc_rev() {
-        let pad = 64usize;
+    /// Reserve `len` consecutive slots at the front of the deque for a transactional batch
+    ///
+    /// Same as `begin_batch`, except that the items are ordered as if pushed one by one with `push_front`:
+    /// the first item pushed into the batch is the one nearest to the back.
+    pub fn begin_batch_front(&self, len: usize) -> Result<Batch<'_, T, CAPACITY>, ()> {
+        self.begin_batch_(len, true)
+    }
 
-        let flag = Arc::new(AtomicI32::new(3));
-        let flag_c = flag.clone();
-        let flag1 = flag.clone();
-        let flag2 = flag.clone();
-        let flag3 = flag.clone();
+    fn begin_batch_(&self, len: usize, front: bool) -> Result<Batch<'_, T, CAPACITY>, ()> {
+        let (start, _) = self
+            .claim_push_(front, len, usize::MAX, &SpinBackoff)
+            .map_err(|_| ())?;
+        Ok(Batch {
+            deque: self,
+            start,
+            len,
+            written: 0,
+            front,
+        })
+    }
 
-        let p1 = Arc::new(LockFreeDeque::<usize, 256>::new());
-        let p2 = p1.clone();
-        let p3 = p1.clone();
-        let c1 = p1.clone();
-        let c2 = p1.clone();
+    #[cfg(feature = "deque-front")]
+    /// Pop an item from the front of the deque
+    /// Returns None if the deque is empty
+    ///
+    /// A front slot in WRITING state (a push still writing it, a held `SlotGuard`, or an uncommitted `Batch`),
+    /// or held by a `PeekGuard`, is waited for: its item can't be skipped without breaking the order.
+    /// The slot may be held for arbitrarily long, so a consumer that must not block on a slow producer
+    /// uses `pop_front_bounded` or `try_pop_front` instead, which give up with `Err(Contended)`.
+    pub fn pop_front(&self) -> Option<T> {
+        self.pop_front_backoff(&SpinBackoff)
+    }
 
-        let producer1 = thread::spawn(move || {
-            for i in 0..pad {
-                let _ = p1.push_front(i);
+    #[cfg(feature = "deque-front")]
+    /// Pop an item from the front of the deque, waiting with `backoff` between retries
+    /// Returns None if the deque is empty
+    pub fn pop_front_backoff(&self, backoff: &impl Backoff) -> Option<T> {
+        loop {
+            if let Ok(item) = self.pop_(true, usize::MAX, usize::MAX, backoff) {
+                return item;
             }
-            flag1.fetch_sub(1, Ordering::SeqCst);
-        });
-        let producer2 = thread::spawn(move || {
-            for i in pad..(2 * pad) {
-                let _ = p2.push_front(i);
+        }
+    }
+
+    #[cfg(feature = "deque-front")]
+    /// Pop an item from the front of the deque, spinning with `SpinBackoff` while the deque is empty
+    /// until a producer pushes or `abort` is set
+    ///
+    /// `abort` is checked like in `push_back_blocking`.
+    pub fn pop_front_blocking(&self, abort: &AtomicBool) -> Result<T, Aborted> {
+        loop {
+            if let Some(item) = self.pop_front() {
+                return Ok(item);
             }
-            flag2.fetch_sub(1, Ordering::SeqCst);
-        });
-        let producer3 = thread::spawn(move || {
-            for i in (2 * pad)..(3 * pad) {
-                let _ = p3.push_front(i);
+            if abort.load(Profile::ACQUIRE) {
+                return Err(Aborted(()));
             }
-            flag3.fetch_sub(1, Ordering::SeqCst);
-        });
+            SpinBackoff.backoff(10);
+        }
+    }
 
-        let consumer = thread::spawn(move || {
-            let mut sum = 0;
-            while flag_c.load(Ordering::SeqCst) != 0 || !c2.is_empty() {
-                if let Some(num) = c2.pop_back() {
-                    sum += num;
-                }
+    #[cfg(feature = "deque-front")]
+    /// Pop an item from the front of the deque, along with the length remaining after the pop
+    /// Returns None if the deque is empty
+    ///
+    /// The remaining length is computed from the same index snapshot as the pop itself, so consumers can pace
+    /// their polling without a separate `len()` call racing with the pop. It is approximate: other threads
+    /// may push or pop concurrently, and items counted may still be being written.
+    pub fn pop_front_remaining(&self) -> Option<(T, usize)> {
+        loop {
+            // Safe because `read` is given the initialized item of a claimed slot, which is then marked empty
+            let res = self.pop_read_(
+                true,
+                usize::MAX,
+                usize::MAX,
+                &SpinBackoff,
+                |item, remaining| (unsafe { item.read() }, remaining),
+            );
+            if let Ok(res) = res {
+                return res;
             }
-            sum
-        });
+        }
+    }
 
-        let mut sum = 0;
-        while flag.load(Ordering::SeqCst) != 0 || !c1.is_empty() {
-            if let Some(num) = c1.pop_back() {
-                sum += num;
+    #[cfg(feature = "deque-front")]
+    /// Pop an item from the front of the deque directly into `out`
+    /// Returns false (leaving `out` untouched) if the deque is empty
+    ///
+    /// Avoids the `Option<T>` wrapper and the extra move of `pop_front`, e.g. when popping into a
+    /// pre-allocated buffer or an FFI out-pointer. A value already in `out` is overwritten without being dropped.
+    pub fn pop_front_into(&self, out: &mut MaybeUninit<T>) -> bool {
+        loop {
+            // Safe because the slot holds an initialized item, which is moved into `out`
+            let res = self.pop_read_(
+                true,
+                usize::MAX,
+                usize::MAX,
+                &SpinBackoff,
+                |item, _| unsafe { out.as_mut_ptr().copy_from_nonoverlapping(item, 1) },
+            );
+            if let Ok(res) = res {
+                return res.is_some();
             }
         }
+    }
 
-        producer1.join().unwrap();
-        producer2.join().unwrap();
-        producer3.join().unwrap();
+    #[cfg(feature = "deque-front")]
+    /// Pop items from the front of the deque into `out` until it is full or the deque is empty
+    /// Returns the number of items written, to `out[..count]`
+    ///
+    /// Each item is popped with `pop_front_into`, so a front slot still being written or read by another
+    /// thread is waited for rather than ending the drain. Unlike a `Batch`, which reserves all its slots
+    /// at once, this is not atomic: concurrent consumers may take items in between, and items pushed
+    /// meanwhile may be drained too. Values already in `out` are overwritten without being dropped.
+    pub fn drain_into(&self, out: &mut [MaybeUninit<T>]) -> usize {
+        let mut count = 0;
+        while count < out.len() && self.pop_front_into(&mut out[count]) {
+            count += 1;
+        }
+        count
+    }
 
-        let s = consumer.join().unwrap();
-        sum += s;
-        assert_eq!(sum, (0..(3 * pad)).sum());
+    #[cfg(feature = "deque-front")]
+    /// Pop the first item from the front of the deque whose key is not in `window`, recording its key
+    /// Returns None if the deque is empty, see `pop_front`
+    ///
+    /// `key` gives the key identifying the logical message of an item, e.g. `(sender, seq)`. Popped items whose
+    /// key was already seen are dropped, so that a message delivered more than once, e.g. because its producer
+    /// retried a push it believed had failed, is only returned once. See `DedupWindow` for how far apart
+    /// duplicates can be detected.
+    pub fn pop_front_dedup<const N: usize>(
+        &self,
+        window: &mut DedupWindow<N>,
+        mut key: impl FnMut(&T) -> (u64, u64),
+    ) -> Option<T> {
+        loop {
+            let item = self.pop_front()?;
+            if window.insert(key(&item)) {
+                return Some(item);
+            }
+        }
     }
 
-    // this test may take a long time to finish (≈ 1 minute)
-    // significantly longer than that means there is probably a deadlock
-    #[test]
-    fn test_mpmc_mix() {
-        let mut count = 10000;
-        while count > 0 {
-            count -= 1;
-            let pad = 750usize;
+    #[cfg(feature = "deque-front")]
+    /// Pop an item from the front of the deque, giving up after `max_retries` retries due to contention
+    /// Returns Ok(None) if the deque is empty, or Err(Contended) if the retry budget ran out
+    ///
+    /// Unlike `pop_front`, a consumer can tell a truly empty deque from one whose items are still
+    /// being inserted or removed by other threads. It also doesn't wait for a front slot held in WRITING state
+    /// (e.g. by a `SlotGuard` or an uncommitted `Batch`) or by a `PeekGuard`: it gives up with Err(Contended)
+    /// after `WRITE_PATIENCE` consecutive observations of it, whatever `max_retries`. Items behind that slot
+    /// stay in the deque, possibly even ones whose push has already returned, while the back end stays usable.
+    pub fn pop_front_bounded(&self, max_retries: usize) -> Result<Option<T>, Contended> {
+        self.pop_(true, max_retries, WRITE_PATIENCE, &SpinBackoff)
+    }
 
-            let flag = Arc::new(AtomicI32::new(4));
-            let flag_c = flag.clone();
-            let flag1 = flag.clone();
-            let flag2 = flag.clone();
-            let flag3 = flag.clone();
-            let flag4 = flag.clone();
+    #[cfg(feature = "deque-front")]
+    /// Pop an item from the front of the deque in a single optimistic attempt, for callers with their own
+    /// retry or backoff strategy
+    /// Returns Ok(None) if the deque is empty, or Err(Contended) as a hint to retry if the attempt lost a race
+    ///
+    /// One Acquire load of head and tail, one CAS to claim the slot and one to advance `head`, repeated only
+    /// if just the tail moved meanwhile. Unlike with `pop_front_bounded(0)`, the claim doesn't fail spuriously,
+    /// so a failure means that another thread got in the way.
+    pub fn try_pop_front(&self) -> Result<Option<T>, Contended> {
+        let (head, tail) = self.indices();
+        if head == tail || self.poisoned_at(head, tail) {
+            return Ok(None);
+        }
 
-            let p1 = Arc::new(LockFreeDeque::<usize, 4096>::new());
-            let p2 = p1.clone();
-            let p3 = p1.clone();
-            let p4 = p1.clone();
-            let c1 = p1.clone();
-            let c2 = p1.clone();
+        let slot = &self.buffer[head];
+        match slot.state.compare_exchange(
+            SLOT_READY,
+            SLOT_READING,
+            Profile::ACQUIRE,
+            Ordering::Relaxed,
+        ) {
+            Ok(_) => {}
+            Err(SLOT_CANCELLED) => {
+                // Left behind by an aborted batch, discard it so that the retry can make progress
+                self.discard_cancelled(slot, true, head, (head + 1) % CAPACITY);
+                return Err(Contended);
+            }
+            Err(_) => return Err(Contended),
+        }
+        slot.record(SlotOp::Claim);
+        yield_hook(SlotOp::PopFront);
 
-            let producer1 = thread::spawn(move || {
-                for i in 0..pad {
-                    if let Err(item) = p1.push_front(i) {
-                        println!("Failed to push front {}", item);
+        if self
+            .move_index(true, head, (head + 1) % CAPACITY, |_| true)
+            .is_err()
+        {
+            slot.state.store(SLOT_READY, Profile::RELEASE);
+            slot.record(SlotOp::Release);
+            return Err(Contended);
+        }
+        self.count.fetch_sub(1, Profile::RELEASE);
+        // Safe because the slot was claimed while ready, and `head` moved past it
+        let item = unsafe { (*slot.data.get()).assume_init_read() };
+        slot.time(SLOT_EMPTY);
+        slot.state.store(SLOT_EMPTY, Profile::RELEASE);
+        slot.record(SlotOp::PopFront);
+        Ok(Some(item))
+    }
+
+    #[cfg(all(feature = "deque-front", feature = "debug-history"))]
+    /// Pop an item from the front of the deque, also reporting whether a concurrent push was detected
+    ///
+    /// For debugging ordering anomalies such as missed wakeups: the flag is set if, while retrying,
+    /// the pop found the front slot still being written, or if it returned None but the deque was no longer
+    /// empty right afterwards. It is a heuristic: a push completing entirely between two loads goes
+    /// unnoticed, and a pop racing with a pop may also find the deque non-empty afterwards.
+    pub fn pop_front_debug(&self) -> (Option<T>, bool) {
+        let observer = RaceObserver {
+            deque: self,
+            raced: core::cell::Cell::new(false),
+        };
+        let item = loop {
+            if let Ok(item) = self.pop_(true, usize::MAX, usize::MAX, &observer) {
+                break item;
+            }
+        };
+        let raced = observer.raced.get() || (item.is_none() && !self.is_empty());
+        (item, raced)
+    }
+
+    /// Pop an item from the front (if `front`) or back of the deque
+    fn pop_(
+        &self,
+        front: bool,
+        max_retries: usize,
+        patience: usize,
+        backoff: &impl Backoff,
+    ) -> Result<Option<T>, Contended> {
+        // Safe because `read` is given the initialized item of a claimed slot, which is then marked empty
+        self.pop_read_(front, max_retries, patience, backoff, |item, _| unsafe {
+            item.read()
+        })
+    }
+
+    /// Pop an item from the front (if `front`) or back of the deque, moving it out of its slot with `read`
+    /// `read` takes ownership of the item behind the pointer: the slot is marked empty afterwards,
+    /// even if `read` panics. It also receives the length remaining after the pop, computed from the indices
+    /// seen by the pop. Gives up with Err(Contended) after `patience` consecutive observations of the slot
+    /// held in WRITING or PEEKING state, see `WRITE_PATIENCE`.
+    ///
+    /// This is the core of every pop but `try_pop_front`.
+    fn pop_read_<R>(
+        &self,
+        front: bool,
+        max_retries: usize,
+        patience: usize,
+        backoff: &impl Backoff,
+        read: impl FnOnce(*mut T, usize) -> R,
+    ) -> Result<Option<R>, Contended> {
+        let mut writing = 0;
+        for _ in 0..=max_retries {
+            let (head, tail) = self.indices();
+
+            // Check if queue is empty, or corrupted (see `is_poisoned`)
+            if head == tail || self.poisoned_at(head, tail) {
+                return Ok(None);
+            }
+
+            // The index to move, its new position, and the slot of the item
+            let (current, new) = if front {
+                (head, (head + 1) % CAPACITY)
+            } else {
+                (tail, if tail == 0 { CAPACITY - 1 } else { tail - 1 })
+            };
+            let slot = &self.buffer[if front { head } else { new }];
+
+            // Try to claim the slot for reading
+            match slot.state.compare_exchange_weak(
+                SLOT_READY,
+                SLOT_READING,
+                Profile::ACQUIRE,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => {
+                    slot.record(SlotOp::Claim);
+                    let op = match front {
+                        true => SlotOp::PopFront,
+                        false => SlotOp::PopBack,
+                    };
+                    yield_hook(op);
+                    // Successfully claimed slot for reading, try to move the index
+                    match self.move_index(front, current, new, |_| true) {
+                        Ok(other) => {
+                            // Successfully moved the index, read the item
+                            self.count.fetch_sub(1, Profile::RELEASE);
+                            let remaining = if front {
+                                (other + CAPACITY - new) % CAPACITY
+                            } else {
+                                (new + CAPACITY - other) % CAPACITY
+                            };
+                            // The guard marks the slot as empty once the item is read
+                            let _guard = ReadGuard { slot, op };
+                            return Ok(Some(read(slot.data.get().cast(), remaining)));
+                        }
+                        Err(_) => {
+                            // Failed to move the index, restore slot state and retry
+                            slot.state.store(SLOT_READY, Profile::RELEASE);
+                            slot.record(SlotOp::Release);
+                            // Small backoff to reduce contention
+                            backoff.backoff(5);
+                            continue;
+                        }
                     }
-                    // if let Err(item) = p1.push_back(i) {
-                    //     println!("Failed to push back {}", item);
-                    // }
                 }
-                flag1.fetch_sub(1, Ordering::SeqCst);
-            });
-            let producer2 = thread::spawn(move || {
-                for i in pad..(2 * pad) {
-                    // if let Err(item) = p2.push_front(i) {
-                    //     println!("Failed to push front {}", item);
-                    // }
-                    if let Err(item) = p2.push_back(i) {
-                        println!("Failed to push back {}", item);
+                Err(current_state) => {
+                    if current_state == SLOT_CANCELLED {
+                        // Left behind by an aborted batch, discard it and retry
+                        self.discard_cancelled(slot, front, current, new);
+                        continue;
                     }
-                }
-                flag2.fetch_sub(1, Ordering::SeqCst);
-            });
-            let producer3 = thread::spawn(move || {
-                for i in (2 * pad)..(3 * pad) {
-                    if let Ok(mut guard) = p3.push_slot_front() {
-                        guard.write(i);
+                    if current_state == SLOT_WRITING || current_state == SLOT_PEEKING {
+                        // The producer or a peek may hold the slot for long, see `pop_front_bounded`
+                        writing += 1;
+                        if writing >= patience {
+                            return Err(Contended);
+                        }
                     } else {
-                        println!("Failed to push front {}", i);
+                        writing = 0;
                     }
-                    // if let Ok(mut guard) = p3.push_slot_back() {
-                    //     guard.write(i);
-                    // } else {
-                    //     println!("Failed to push front {}", i);
-                    // }
-                }
-                flag3.fetch_sub(1, Ordering::SeqCst);
-            });
-            let producer4 = thread::spawn(move || {
-                for i in (3 * pad)..(4 * pad) {
-                    // if let Ok(mut guard) = p4.push_slot_front() {
-                    //     guard.write(i);
-                    // } else {
-                    //     println!("Failed to push front {}", i);
-                    // }
-                    if let Ok(mut guard) = p4.push_slot_back() {
-                        guard.write(i);
-                    } else {
-                        println!("Failed to push front {}", i);
+                    if current_state != SLOT_EMPTY {
+                        // Another thread is writing or reading, wait a bit
+                        backoff.backoff(10);
                     }
+                    continue;
                 }
-                flag4.fetch_sub(1, Ordering::SeqCst);
-            });
+            }
+        }
+        Err(Contended)
+    }
 
-            let consumer = thread::spawn(move || {
+    /// Pop an item from the back of the deque
+    /// Returns None if the deque is empty
+    ///
+    /// Like `pop_front`, waits for a back slot in WRITING state, see `pop_back_bounded` for giving up instead.
+    pub fn pop_back(&self) -> Option<T> {
+        self.pop_back_backoff(&SpinBackoff)
+    }
+
+    /// Pop an item from the back of the deque, waiting with `backoff` between retries
+    /// Returns None if the deque is empty
+    pub fn pop_back_backoff(&self, backoff: &impl Backoff) -> Option<T> {
+        loop {
+            if let Ok(item) = self.pop_(false, usize::MAX, usize::MAX, backoff) {
+                return item;
+            }
+        }
+    }
+
+    /// Pop an item from the back of the deque, along with the (approximate) length remaining after the pop
+    /// Returns None if the deque is empty
+    ///
+    /// See `pop_front_remaining`.
+    pub fn pop_back_remaining(&self) -> Option<(T, usize)> {
+        loop {
+            // Safe because `read` is given the initialized item of a claimed slot, which is then marked empty
+            let res = self.pop_read_(
+                false,
+                usize::MAX,
+                usize::MAX,
+                &SpinBackoff,
+                |item, remaining| (unsafe { item.read() }, remaining),
+            );
+            if let Ok(res) = res {
+                return res;
+            }
+        }
+    }
+
+    /// Pop an item from the back of the deque, giving up after `max_retries` retries due to contention
+    /// Returns Ok(None) if the deque is empty, or Err(Contended) if the retry budget ran out
+    ///
+    /// Like `pop_front_bounded`, also gives up on a back slot held in WRITING state.
+    pub fn pop_back_bounded(&self, max_retries: usize) -> Result<Option<T>, Contended> {
+        self.pop_(false, max_retries, WRITE_PATIENCE, &SpinBackoff)
+    }
+
+    #[cfg(feature = "deque-front")]
+    /// Pop an item from the front of the deque and consume it in place
+    /// Returns None if the deque is empty, otherwise the value returned by `f`
+    ///
+    /// `f` receives a reference to the item still stored in the buffer, so the item is never moved out.
+    /// While `f` runs, the item is already logically removed (head has advanced) but the slot stays in
+    /// SLOT_READING, so pushers targeting this slot will spin until `f` returns. Keep `f` short.
+    ///
+    /// `f` should not panic. If it does, the item is still dropped and the slot released during unwinding,
+    /// but the item is lost.
+    ///
+    /// `f` may call `push_back` on the same deque (e.g. to requeue the item). The held slot is the one just
+    /// before the new head, which `push_back` only targets when the deque is full, and then it returns Err
+    /// without touching the slot. `f` only ever accesses its own slot, so there is no aliasing either.
+    /// There is one exception: if other threads pop from the front while `f` runs and the deque is nearly full,
+    /// head can move further and `push_back` may target the held slot, spinning until `f` returns, i.e. forever.
+    /// In that setting, push after `pop_front_with` returns instead.
+    ///
+    /// `f` must not call `push_front` or `push_slot_front` on the same deque: they target the held slot unless
+    /// the deque is full, and spin forever.
+    pub fn pop_front_with<R>(&self, f: impl FnOnce(&T) -> R) -> Option<R> {
+        // Consume the item in place, the guard drops it even if `f` panics.
+        // Never gives up, with no limit on retries nor on the wait for a slot being written
+        self.pop_read_(true, usize::MAX, usize::MAX, &SpinBackoff, |item, _| {
+            let item = DropInPlace(item);
+            // Safe because the slot holds an initialized item, dropped once `f` returns
+            f(unsafe { &*item.0 })
+        })
+        .unwrap_or(None)
+    }
+
+    /// Rebuild the slot states from head and tail, e.g. after a producer or consumer crashed mid-operation
+    ///
+    /// - Slots between head and tail that are READY are kept. Other slots in this range
+    ///   (stuck in EMPTY, WRITING or READING) are cancelled, so consumers skip them.
+    /// - Slots outside the range are marked EMPTY.
+    /// - If head or tail is outside the ring buffer, the deque is reset to empty.
+    ///
+    /// Items in cancelled or emptied slots are not dropped, since their contents can't be trusted.
+    /// A poisoned deque stays poisoned, call `clear_poison` afterwards, see `is_poisoned`.
+    ///
+    /// # Safety
+    ///
+    /// The caller must have exclusive access to the deque: no other thread or process may operate on it
+    /// (or hold a `SlotGuard`/`Batch` of it) while it is being repaired, and the crashed party must never resume.
+    pub unsafe fn repair(&self) {
+        let (mut head, mut tail) = self.indices();
+        if head >= CAPACITY || tail >= CAPACITY {
+            head = 0;
+            tail = 0;
+            self.ends.store(pack(head, tail), Profile::RELEASE);
+        }
+
+        let mut count = 0;
+        let mut index = head;
+        while index != tail {
+            let state = &self.buffer[index].state;
+            if state.load(Profile::ACQUIRE) == SLOT_READY {
+                count += 1;
+            } else {
+                state.store(SLOT_CANCELLED, Profile::RELEASE);
+            }
+            index = (index + 1) % CAPACITY;
+        }
+        self.count.store(count, Profile::RELEASE);
+        while index != head {
+            self.buffer[index].state.store(SLOT_EMPTY, Profile::RELEASE);
+            index = (index + 1) % CAPACITY;
+        }
+    }
+
+    /// Run `f` on the item at the front of the deque without removing it
+    /// Returns None if the deque is empty
+    ///
+    /// Unlike `peek_front_relaxed`, this waits for a front slot being written or read, like a pop would.
+    /// While `f` runs, the slot is claimed for reading, so a concurrent pop of it waits meanwhile:
+    /// keep `f` short. If `f` panics, the slot is restored during unwinding, leaving the item in the deque.
+    pub fn peek_front_with<R>(&self, f: impl FnOnce(&T) -> R) -> Option<R> {
+        self.peek_with_(true, f)
+    }
+
+    /// Run `f` on the item at the back of the deque without removing it, see `peek_front_with`
+    /// Returns None if the deque is empty
+    pub fn peek_back_with<R>(&self, f: impl FnOnce(&T) -> R) -> Option<R> {
+        self.peek_with_(false, f)
+    }
+
+    fn peek_with_<R>(&self, front: bool, f: impl FnOnce(&T) -> R) -> Option<R> {
+        let slot = self.claim_peek(front, SLOT_READING, usize::MAX)?;
+        // The guard restores the slot like for a PEEKING claim, even if `f` panics
+        let guard = PeekGuard { slot };
+        Some(f(&guard))
+    }
+
+    /// Get a guard on the item at the front of the deque, giving access to it without removing it
+    /// Returns None if the deque is empty
+    ///
+    /// The slot is held in PEEKING state until the guard is dropped, which restores it: pops reaching it
+    /// meanwhile wait for the guard like for a front slot held in WRITING state, and the bounded pops give up
+    /// (see `pop_front_bounded`). Unlike a pop, this returns None rather than wait for a front slot being written
+    /// or peeked at, after `WRITE_PATIENCE` consecutive observations of it. It waits for one being read.
+    pub fn peek_front(&self) -> Option<PeekGuard<'_, T>> {
+        let slot = self.claim_peek(true, SLOT_PEEKING, WRITE_PATIENCE)?;
+        Some(PeekGuard { slot })
+    }
+
+    /// Get a guard on the item at the back of the deque, see `peek_front`
+    /// Returns None if the deque is empty
+    pub fn peek_back(&self) -> Option<PeekGuard<'_, T>> {
+        let slot = self.claim_peek(false, SLOT_PEEKING, WRITE_PATIENCE)?;
+        Some(PeekGuard { slot })
+    }
+
+    /// Claim the slot at the front (if `front`) or back of the deque in `state`, READING or PEEKING
+    /// Returns None if the deque is empty, or if the slot is held in WRITING or PEEKING state for `patience`
+    /// consecutive observations
+    fn claim_peek(&self, front: bool, state: u8, patience: usize) -> Option<&Slot<T>> {
+        let mut writing = 0;
+        loop {
+            let (head, tail) = self.indices();
+            if head == tail || self.poisoned_at(head, tail) {
+                return None;
+            }
+            let (current, new) = if front {
+                (head, (head + 1) % CAPACITY)
+            } else {
+                (tail, (tail + CAPACITY - 1) % CAPACITY)
+            };
+            let slot = &self.buffer[if front { head } else { new }];
+            match slot.state.compare_exchange_weak(
+                SLOT_READY,
+                state,
+                Profile::ACQUIRE,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => {
+                    slot.record(SlotOp::Claim);
+                    return Some(slot);
+                }
+                Err(SLOT_CANCELLED) => {
+                    // Left behind by an aborted batch, discard it like a pop would and retry
+                    self.discard_cancelled(slot, front, current, new);
+                }
+                Err(SLOT_WRITING | SLOT_PEEKING) => {
+                    // The producer or another peek may hold the slot for long, see `pop_front`
+                    writing += 1;
+                    if writing >= patience {
+                        return None;
+                    }
+                    SpinBackoff.backoff(10);
+                }
+                Err(SLOT_READING) => {
+                    // Another thread is reading, wait a bit
+                    writing = 0;
+                    SpinBackoff.backoff(10);
+                }
+                Err(_) => writing = 0,
+            }
+        }
+    }
+
+    /// Discard a slot cancelled by an aborted batch, which is at the end of the deque,
+    /// by moving that end (head if `front`, else tail) from `current` to `new`.
+    /// The caller retries its operation afterwards, whether this succeeds or not.
+    fn discard_cancelled(&self, slot: &Slot<T>, front: bool, current: usize, new: usize) {
+        if slot
+            .state
+            .compare_exchange(
+                SLOT_CANCELLED,
+                SLOT_READING,
+                Profile::ACQUIRE,
+                Ordering::Relaxed,
+            )
+            .is_ok()
+        {
+            slot.record(SlotOp::Claim);
+            if self.move_index(front, current, new, |_| true).is_ok() {
+                slot.state.store(SLOT_EMPTY, Profile::RELEASE);
+                slot.record(match front {
+                    true => SlotOp::PopFront,
+                    false => SlotOp::PopBack,
+                });
+            } else {
+                slot.state.store(SLOT_CANCELLED, Profile::RELEASE);
+                slot.record(SlotOp::Release);
+            }
+        }
+    }
+
+    /// Exchange the contents (items, head and tail) of this deque with `other`, e.g. to fail over
+    /// from an active queue to a standby one
+    ///
+    /// The buffers are inline arrays of the same CAPACITY, so there is no pointer to swap: the slots
+    /// are exchanged element by element (state and data), which takes O(CAPACITY) time whatever
+    /// the number of items. Swapping a deque with itself does nothing.
+    ///
+    /// # Safety
+    ///
+    /// The caller must have exclusive access to both deques: no other thread or process may operate on
+    /// either of them (or hold a `SlotGuard`/`Batch` of it) during the swap.
+    pub unsafe fn swap_contents(&self, other: &Self) {
+        if core::ptr::eq(self, other) {
+            return;
+        }
+        for (a, b) in self.buffer.iter().zip(other.buffer.iter()) {
+            let state = a
+                .state
+                .swap(b.state.load(Profile::ACQUIRE), Profile::ACQ_REL);
+            b.state.store(state, Profile::RELEASE);
+            #[cfg(feature = "debug-history")]
+            {
+                let last_op = a
+                    .last_op
+                    .swap(b.last_op.load(Ordering::Relaxed), Ordering::Relaxed);
+                b.last_op.store(last_op, Ordering::Relaxed);
+            }
+            // Safe because we have exclusive access to both slots, and they don't overlap
+            unsafe {
+                core::ptr::swap_nonoverlapping(a.data.get(), b.data.get(), 1);
+            }
+        }
+        let ends = self
+            .ends
+            .swap(other.ends.load(Profile::ACQUIRE), Profile::ACQ_REL);
+        other.ends.store(ends, Profile::RELEASE);
+        let count = self
+            .count
+            .swap(other.count.load(Profile::ACQUIRE), Profile::ACQ_REL);
+        other.count.store(count, Profile::RELEASE);
+    }
+
+    /// Pop and drop all items, returning the number of items removed
+    ///
+    /// Under concurrent producers this is best-effort: items pushed during the clear may or may not be removed.
+    pub fn clear(&self) -> usize {
+        let mut count = 0;
+        while self.pop_back().is_some() {
+            count += 1;
+        }
+        count
+    }
+
+    /// Remove all items without popping them one by one, returning the number of items removed
+    ///
+    /// Marks the slots between head and tail EMPTY, then moves head to tail. No item is read or dropped, so this
+    /// only compiles for item types that don't need dropping (`!needs_drop::<T>()`, e.g. `IPCItem`); use `clear`
+    /// for the others. Slots cancelled by an aborted batch or `retain` are reset as well, but not counted.
+    ///
+    /// # Safety
+    ///
+    /// The caller must have exclusive access to the deque, like for `repair`: no other thread or process may
+    /// operate on it (or hold a `SlotGuard`/`Batch` of it) meanwhile. A concurrent pop could read a slot
+    /// after it was reset, and a concurrent push could be lost or leave head and tail inconsistent.
+    pub unsafe fn fast_clear(&self) -> usize {
+        const {
+            assert!(
+                !core::mem::needs_drop::<T>(),
+                "fast_clear would leak the items, use clear"
+            )
+        };
+        let (head, tail) = self.raw_indices();
+        if head >= CAPACITY || tail >= CAPACITY {
+            // Corrupted, see `repair`
+            return 0;
+        }
+        let mut count = 0;
+        let mut index = head;
+        while index != tail {
+            let state = &self.buffer[index].state;
+            if state.swap(SLOT_EMPTY, Profile::ACQ_REL) == SLOT_READY {
+                count += 1;
+            }
+            index = (index + 1) % CAPACITY;
+        }
+        self.ends.store(pack(tail, tail), Profile::RELEASE);
+        self.count.store(0, Profile::RELEASE);
+        count
+    }
+
+    /// Get the current length of the deque (approximate in concurrent scenarios)
+    ///
+    /// This is the number of slots between head and tail, which includes slots still being written
+    /// and cancelled slots not discarded yet, see `len_exact` for the number of items.
+    ///
+    /// Never exceeds `capacity()`, even if head and tail are inconsistent.
+    /// Returns 0 once the deque is poisoned, see `is_poisoned`.
+    pub fn len(&self) -> usize {
+        let (head, tail) = self.indices();
+        if self.poisoned_at(head, tail) {
+            return 0;
+        }
+
+        let len = if tail >= head {
+            tail - head
+        } else {
+            (CAPACITY + tail).wrapping_sub(head)
+        };
+        // If head/tail are out of sync (e.g. an index beyond the buffer), the value above is garbage.
+        // Saturate it so that callers sizing buffers from it do not crash as well.
+        len.min(self.capacity())
+    }
+
+    /// Check if the deque is full (approximate in concurrent scenarios), i.e. if `len()` equals `capacity()`
+    pub fn is_full(&self) -> bool {
+        self.len() == self.capacity()
+    }
+
+    /// Get the number of items in the deque, from a dedicated counter
+    ///
+    /// The counter is incremented by a push right after its index move, and decremented by a pop right after
+    /// its own, so unlike `len` it doesn't count cancelled slots (batch slots left unwritten, items removed
+    /// by `retain`). It does count items still being written, from the index move of their push
+    /// (or batch, or `SlotGuard`) on. Once all pushes and pops have returned, it is exact.
+    /// Unlike `len`, it is not reset to 0 when the deque is poisoned.
+    pub fn len_exact(&self) -> usize {
+        self.count.load(Profile::ACQUIRE)
+    }
+
+    /// Count the items for which `pred` returns true, without removing them
+    ///
+    /// Under concurrency this is a racy best-effort count, see `for_each`. Keep `pred` short,
+    /// and don't let it panic.
+    pub fn count_where(&self, pred: impl Fn(&T) -> bool) -> usize {
+        let mut count = 0;
+        self.for_each(|item| {
+            if pred(item) {
+                count += 1;
+            }
+        });
+        count
+    }
+
+    /// Run `f` on each item from front to back, without removing them
+    ///
+    /// Scans the slots from head to tail. Each READY slot is claimed for reading while `f` inspects
+    /// its item, then restored, so the deque is left as it was; a concurrent pop of that slot waits meanwhile.
+    /// Under concurrency this is racy: items pushed or popped during the scan may or may not be visited,
+    /// and slots still being written are skipped. Keep `f` short, and don't let it panic: the slot being
+    /// inspected would stay claimed, blocking the deque.
+    pub fn for_each(&self, mut f: impl FnMut(&T)) {
+        let (head, tail) = self.raw_indices();
+        if head >= CAPACITY || tail >= CAPACITY {
+            // Corrupted, see `repair`
+            return;
+        }
+        let mut index = head;
+        while index != tail {
+            let slot = &self.buffer[index];
+            if slot
+                .state
+                .compare_exchange(
+                    SLOT_READY,
+                    SLOT_READING,
+                    Profile::ACQUIRE,
+                    Ordering::Relaxed,
+                )
+                .is_ok()
+            {
+                slot.record(SlotOp::Claim);
+                // Safe because the slot is claimed in READING state and holds an initialized item
+                f(unsafe { (*slot.data.get()).assume_init_ref() });
+                slot.state.store(SLOT_READY, Profile::RELEASE);
+                slot.record(SlotOp::Release);
+            }
+            index = (index + 1) % CAPACITY;
+        }
+    }
+
+    /// Remove the items for which `pred` returns false, keeping the others in place and in order
+    /// Returns the number of items removed
+    ///
+    /// This doesn't need exclusive access: like `count_where`, it scans the slots from head to tail,
+    /// claiming each READY slot for reading while `pred` inspects its item. A rejected item is dropped
+    /// and its slot marked CANCELLED, the tombstone also left by an aborted batch, which pops and peeks
+    /// discard once it reaches an end of the deque. So the remaining items don't move, but the tombstones
+    /// still count in `len` (and take up room for pushes) until then.
+    /// Under concurrency, items pushed or being written during the scan may be skipped.
+    /// Keep `pred` short, and don't let it panic, see `count_where`.
+    pub fn retain(&self, pred: impl Fn(&T) -> bool) -> usize {
+        let (head, tail) = self.raw_indices();
+        if head >= CAPACITY || tail >= CAPACITY {
+            // Corrupted, see `repair`
+            return 0;
+        }
+        let mut removed = 0;
+        let mut index = head;
+        while index != tail {
+            let slot = &self.buffer[index];
+            if slot
+                .state
+                .compare_exchange(
+                    SLOT_READY,
+                    SLOT_READING,
+                    Profile::ACQUIRE,
+                    Ordering::Relaxed,
+                )
+                .is_ok()
+            {
+                slot.record(SlotOp::Claim);
+                // Safe because the slot is claimed in READING state and holds an initialized item
+                let item = unsafe { &mut *slot.data.get() };
+                if pred(unsafe { item.assume_init_ref() }) {
+                    slot.state.store(SLOT_READY, Profile::RELEASE);
+                } else {
+                    unsafe { item.assume_init_drop() };
+                    slot.state.store(SLOT_CANCELLED, Profile::RELEASE);
+                    self.count.fetch_sub(1, Profile::RELEASE);
+                    removed += 1;
+                }
+                slot.record(SlotOp::Release);
+            }
+            index = (index + 1) % CAPACITY;
+        }
+        removed
+    }
+
+    /// Count the slots that look stuck in WRITING state, e.g. because their `SlotGuard` was forgotten
+    ///
+    /// Each slot is observed `threshold_passes` times, spinning with `SpinBackoff` in between,
+    /// and counted if it was in WRITING state every time. A healthy push keeps its slot in that state only
+    /// briefly, so a higher threshold means fewer false positives from pushes in progress (or held guards
+    /// and batches), at the cost of a slower scan. It can't tell a leaked guard from one still legitimately
+    /// held for a long time: the result is a hint for diagnostics, see `repair` for recovering.
+    pub fn scan_leaked_writing(&self, threshold_passes: usize) -> usize {
+        self.buffer
+            .iter()
+            .filter(|slot| {
+                (0..threshold_passes.max(1)).all(|pass| {
+                    if pass > 0 {
+                        SpinBackoff.backoff(10);
+                    }
+                    slot.state.load(Profile::ACQUIRE) == SLOT_WRITING
+                })
+            })
+            .count()
+    }
+
+    /// Check if the deque is empty (approximate in concurrent scenarios)
+    ///
+    /// Returns true once the deque is poisoned, like `len`.
+    pub fn is_empty(&self) -> bool {
+        let (head, tail) = self.indices();
+        head == tail || self.poisoned_at(head, tail)
+    }
+
+    /// Get a consistent `(head, tail)` snapshot, both read at once from `ends`
+    fn indices(&self) -> (usize, usize) {
+        unpack(self.ends.load(Profile::ACQUIRE))
+    }
+
+    /// Move head (if `front`) or tail from `current` to `new`, keeping the other index
+    /// Returns the other index the move was made against, or Err(()) if the index is no longer `current`,
+    /// or if `fits` rejects the other index
+    ///
+    /// The other index may move concurrently: the CAS is retried as long as this one is still `current`,
+    /// and `fits` is checked against each value of the other index, e.g. so that a push doesn't fill
+    /// the slot kept empty when a push at the other end moved its index meanwhile.
+    fn move_index(
+        &self,
+        front: bool,
+        current: usize,
+        new: usize,
+        fits: impl Fn(usize) -> bool,
+    ) -> Result<usize, ()> {
+        let mut ends = self.ends.load(Profile::ACQUIRE);
+        loop {
+            let (head, tail) = unpack(ends);
+            let (index, other) = if front { (head, tail) } else { (tail, head) };
+            if index != current || !fits(other) {
+                return Err(());
+            }
+            let moved = if front {
+                pack(new, tail)
+            } else {
+                pack(head, new)
+            };
+            match self
+                .ends
+                .compare_exchange_weak(ends, moved, Profile::ACQ_REL, Profile::ACQUIRE)
+            {
+                Ok(_) => return Ok(other),
+                Err(actual) => ends = actual,
+            }
+        }
+    }
+
+    /// Get the raw `(head, tail)` indices of the deque
+    ///
+    /// This is a racy snapshot: both indices are read at once, but may already be outdated.
+    /// Both are positions in the ring buffer (`0..CAPACITY`), head points to the first element
+    /// and tail points to one past the last element. An index outside this range means the deque is corrupted.
+    pub fn raw_indices(&self) -> (usize, usize) {
+        self.indices()
+    }
+
+    #[cfg(feature = "debug-history")]
+    /// Get the operation that last wrote the state of each slot, in buffer order (`0..CAPACITY`)
+    ///
+    /// `None` means the slot was never touched. Combined with `raw_indices`, this shows who left
+    /// each slot in its current state, e.g. a `Claim` never followed by a push or pop points at a stuck operation.
+    /// Like `raw_indices`, this is a racy snapshot. `repair` doesn't record anything, so the history from
+    /// before the repair is kept.
+    pub fn history(&self) -> impl Iterator<Item = Option<SlotOp>> + '_ {
+        self.buffer
+            .iter()
+            .map(|slot| SlotOp::from_u8(slot.last_op.load(Ordering::Relaxed)))
+    }
+
+    /// Get the capacity of the deque, i.e. the maximum number of items it can hold
+    ///
+    /// One slot of the buffer is always kept empty to tell a full deque from an empty one,
+    /// so this is `CAPACITY - 1`. `len`, `is_full` and the pushes all agree with it.
+    pub const fn capacity(&self) -> usize {
+        CAPACITY - 1
+    }
+
+    /// Get the number of slots in the ring buffer, `CAPACITY`, one more than `capacity()`
+    pub const fn raw_capacity(&self) -> usize {
+        CAPACITY
+    }
+
+    /// Check if the deque is poisoned, i.e. an operation found its invariants broken
+    ///
+    /// Currently detected: head or tail outside the ring buffer, e.g. after a crashed party or a stray write
+    /// to shared memory. Rather than carrying on with indices that can't be trusted, every push then fails
+    /// as if the deque were full, every pop and peek returns None, and `len` returns 0, without touching
+    /// the slots. `check_poison` tells these failures apart from a genuinely full or empty deque.
+    /// The deque stays poisoned until `clear_poison`.
+    pub fn is_poisoned(&self) -> bool {
+        self.poisoned.load(Profile::ACQUIRE)
+    }
+
+    /// Returns Err(Poisoned) if the deque is poisoned, see `is_poisoned`
+    pub fn check_poison(&self) -> Result<(), Poisoned> {
+        if self.is_poisoned() {
+            Err(Poisoned)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Clear the poison, so that operations run again, see `is_poisoned`
+    ///
+    /// # Safety
+    ///
+    /// The invariants must hold again, typically after `repair`, and with the same exclusive access it requires.
+    /// Otherwise the next operation may index out of the ring buffer, or return items that are not there.
+    pub unsafe fn clear_poison(&self) {
+        self.poisoned.store(false, Profile::RELEASE);
+    }
+
+    /// Check the indices an operation just read: returns true if the deque is poisoned,
+    /// poisoning it first if either index is outside the ring buffer
+    fn poisoned_at(&self, head: usize, tail: usize) -> bool {
+        if head >= CAPACITY || tail >= CAPACITY {
+            self.poisoned.store(true, Profile::RELEASE);
+            return true;
+        }
+        self.is_poisoned()
+    }
+
+    /// Get a pointer to the item storage of slot `index` of the ring buffer, used by tests to corrupt items in place
+    #[cfg(all(test, feature = "checksum"))]
+    pub(crate) fn slot_ptr(&self, index: usize) -> *mut T {
+        self.buffer[index].data.get().cast()
+    }
+
+    /// Overwrite head and tail directly, used by tests to inject inconsistent states
+    #[cfg(all(
+        test,
+        feature = "deque-front",
+        feature = "deque-back",
+        feature = "deque-slot"
+    ))]
+    fn set_raw_indices(&self, head: usize, tail: usize) {
+        self.ends.store(pack(head, tail), Profile::RELEASE);
+    }
+}
+
+/// Relaxed observers, for monitoring threads that only need a rough idea of the deque's state
+///
+/// Like the precise `len` and `is_empty`, they read head and tail in a single load, so they are wait-free too,
+/// but they skip the poison check and its store. Their result may be stale by the time it is returned,
+/// and disagree with what a pop would return right afterwards.
+impl<T, const CAPACITY: usize> LockFreeDeque<T, CAPACITY> {
+    /// Get the length of the deque from a single read of the indices, see the relaxed observers above
+    ///
+    /// Never exceeds `capacity()`.
+    pub fn len_relaxed(&self) -> usize {
+        let (head, tail) = self.raw_indices();
+        (tail + CAPACITY).wrapping_sub(head) % CAPACITY
+    }
+
+    /// Check if the deque is empty from a single read of the indices, see the relaxed observers above
+    pub fn is_empty_relaxed(&self) -> bool {
+        let (head, tail) = self.raw_indices();
+        head == tail
+    }
+
+    /// Clone the item at the front of the deque, without removing it, see the relaxed observers above
+    ///
+    /// Makes a single attempt: returns None if the deque is empty, or if the front slot is being written
+    /// or read by another thread. While cloning, the slot is claimed for reading, so a concurrent pop
+    /// of it waits meanwhile; don't let `clone` panic, the slot would stay claimed.
+    pub fn peek_front_relaxed(&self) -> Option<T>
+    where
+        T: Clone,
+    {
+        let slot = self.buffer.get(self.indices().0)?;
+        slot.state
+            .compare_exchange(
+                SLOT_READY,
+                SLOT_READING,
+                Profile::ACQUIRE,
+                Ordering::Relaxed,
+            )
+            .ok()?;
+        slot.record(SlotOp::Claim);
+        // Safe because the slot is claimed in READING state and holds an initialized item
+        let item = unsafe { (*slot.data.get()).assume_init_ref() }.clone();
+        slot.state.store(SLOT_READY, Profile::RELEASE);
+        slot.record(SlotOp::Release);
+        Some(item)
+    }
+}
+
+impl<T, const CAPACITY: usize> Default for LockFreeDeque<T, CAPACITY> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T, const CAPACITY: usize> Drop for LockFreeDeque<T, CAPACITY> {
+    fn drop(&mut self) {
+        // Clean up any remaining items to prevent memory leaks
+        while self.pop_back().is_some() {}
+    }
+}
+
+// Safety: The deque can be sent between threads if T can be sent
+unsafe impl<T: Send, const CAPACITY: usize> Send for LockFreeDeque<T, CAPACITY> {}
+// Safety: The deque can be shared between threads if T can be sent
+unsafe impl<T: Send, const CAPACITY: usize> Sync for LockFreeDeque<T, CAPACITY> {}
+
+// The tests exercise the whole API
+#[cfg(all(
+    test,
+    not(loom),
+    feature = "deque-front",
+    feature = "deque-back",
+    feature = "deque-slot"
+))]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use core::sync::atomic::{AtomicI32, AtomicUsize};
+    use std::{println, sync::Arc, thread, vec};
+    #[test]
+    fn test_basic_operations() {
+        let deque: LockFreeDeque<i32, 5> = LockFreeDeque::new();
+
+        // Test push_back and pop_front
+        assert!(deque.push_back(1).is_ok());
+        assert!(deque.push_back(2).is_ok());
+        assert_eq!(deque.pop_front(), Some(1));
+        assert_eq!(deque.pop_front(), Some(2));
+        assert_eq!(deque.pop_front(), None);
+
+        // Test push_front and pop_back
+        assert!(deque.push_front(3).is_ok());
+        assert!(deque.push_front(4).is_ok());
+        assert_eq!(deque.pop_back(), Some(3));
+        assert_eq!(deque.pop_back(), Some(4));
+        assert_eq!(deque.pop_back(), None);
+    }
+
+    #[test]
+    fn test_capacity_limit() {
+        let deque: LockFreeDeque<i32, 3> = LockFreeDeque::new();
+
+        assert!(deque.push_back(1).is_ok());
+        assert!(deque.push_back(2).is_ok());
+        assert!(deque.push_back(3).is_err()); // Should fail, queue is full
+    }
+
+    #[cfg(not(feature = "single-thread"))]
+    #[test]
+    fn test_concurrent_operations() {
+        let deque = Arc::new(LockFreeDeque::<i32, 100>::new());
+        let mut handles = vec![];
+
+        // Spawn multiple producers
+        for i in 0..4 {
+            let deque_clone = Arc::clone(&deque);
+            let handle = thread::spawn(move || {
+                for j in 0..25 {
+                    let value = i * 25 + j;
+                    while deque_clone.push_back(value).is_err() {
+                        thread::yield_now();
+                    }
+                }
+            });
+            handles.push(handle);
+        }
+
+        // Spawn multiple consumers
+        for _ in 0..2 {
+            let deque_clone = Arc::clone(&deque);
+            let handle = thread::spawn(move || {
+                let mut count = 0;
+                while count < 50 {
+                    if let Some(_) = deque_clone.pop_front() {
+                        count += 1;
+                    } else {
+                        thread::yield_now();
+                    }
+                }
+            });
+            handles.push(handle);
+        }
+
+        // Wait for all threads to complete
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        assert!(deque.is_empty());
+    }
+
+    #[test]
+    fn test_mixed_operations() {
+        let deque: LockFreeDeque<i32, 6> = LockFreeDeque::new();
+
+        // Mix front and back operations
+        assert!(deque.push_front(1).is_ok());
+        assert!(deque.push_back(2).is_ok());
+        assert!(deque.push_front(0).is_ok());
+        assert!(deque.push_back(3).is_ok());
+
+        // Should be: [0, 1, 2, 3]
+        assert_eq!(deque.pop_front(), Some(0));
+        assert_eq!(deque.pop_back(), Some(3));
+        assert_eq!(deque.pop_front(), Some(1));
+        assert_eq!(deque.pop_back(), Some(2));
+        assert!(deque.is_empty());
+    }
+
+    #[test]
+    fn test_dequeue() {
+        let deque = LockFreeDeque::<usize, 16>::new();
+        for i in 0..4 {
+            let _ = deque.push_front(i);
+        }
+        for _ in 0..18 {
+            println!("{:?}", deque.pop_front());
+        }
+
+        // for _ in 0..5 {
+        //     println!("{:?}", deque.alloc_node());
+        // }
+    }
+
+    #[cfg(not(feature = "single-thread"))]
+    #[test]
+    fn test_mpsc() {
+        let pad = 64usize;
+
+        let flag = Arc::new(AtomicI32::new(3));
+        let flag1 = flag.clone();
+        let flag2 = flag.clone();
+        let flag3 = flag.clone();
+        let p1 = Arc::new(LockFreeDeque::<usize, 256>::new());
+        let p2 = p1.clone();
+        let p3 = p1.clone();
+        let c = p1.clone();
+
+        let t1 = thread::spawn(move || {
+            for i in 0..pad {
+                let _ = p1.push_back(i);
+            }
+            flag1.fetch_sub(1, Ordering::SeqCst);
+        });
+        let t2 = thread::spawn(move || {
+            for i in pad..(2 * pad) {
+                let _ = p2.push_back(i);
+            }
+            flag2.fetch_sub(1, Ordering::SeqCst);
+        });
+        let t3 = thread::spawn(move || {
+            for i in (2 * pad)..(3 * pad) {
+                let _ = p3.push_back(i);
+            }
+            flag3.fetch_sub(1, Ordering::SeqCst);
+        });
+
+        let mut sum = 0;
+        while flag.load(Ordering::SeqCst) != 0 || !c.is_empty() {
+            if let Some(num) = c.pop_front() {
+                sum += num;
+            }
+        }
+
+        t1.join().unwrap();
+        t2.join().unwrap();
+        t3.join().unwrap();
+        assert_eq!(sum, (0..(3 * pad)).sum());
+    }
+
+    #[cfg(not(feature = "single-thread"))]
+    #[test]
+    fn test_mpmc() {
+        let pad = 64usize;
+
+        let flag = Arc::new(AtomicI32::new(3));
+        let flag_c = flag.clone();
+        let flag1 = flag.clone();
+        let flag2 = flag.clone();
+        let flag3 = flag.clone();
+
+        let p1 = Arc::new(LockFreeDeque::<usize, 256>::new());
+        let p2 = p1.clone();
+        let p3 = p1.clone();
+        let c1 = p1.clone();
+        let c2 = p1.clone();
+
+        let producer1 = thread::spawn(move || {
+            for i in 0..pad {
+                let _ = p1.push_back(i);
+            }
+            flag1.fetch_sub(1, Ordering::SeqCst);
+        });
+        let producer2 = thread::spawn(move || {
+            for i in pad..(2 * pad) {
+                let _ = p2.push_back(i);
+            }
+            flag2.fetch_sub(1, Ordering::SeqCst);
+        });
+        let producer3 = thread::spawn(move || {
+            for i in (2 * pad)..(3 * pad) {
+                let _ = p3.push_back(i);
+            }
+            flag3.fetch_sub(1, Ordering::SeqCst);
+        });
+
+        let consumer = thread::spawn(move || {
+            let mut sum = 0;
+            while flag_c.load(Ordering::SeqCst) != 0 || !c2.is_empty() {
+                if let Some(num) = c2.pop_front() {
+                    sum += num;
+                }
+            }
+            sum
+        });
+
+        let mut sum = 0;
+        while flag.load(Ordering::SeqCst) != 0 || !c1.is_empty() {
+            if let Some(num) = c1.pop_front() {
+                sum += num;
+            }
+        }
+
+        producer1.join().unwrap();
+        producer2.join().unwrap();
+        producer3.join().unwrap();
+
+        let s = consumer.join().unwrap();
+        sum += s;
+        assert_eq!(sum, (0..(3 * pad)).sum());
+    }
+
+    #[cfg(not(feature = "single-thread"))]
+    #[test]
+    fn test_mpmc_rev() {
+        let pad = 64usize;
+
+        let flag = Arc::new(AtomicI32::new(3));
+        let flag_c = flag.clone();
+        let flag1 = flag.clone();
+        let flag2 = flag.clone();
+        let flag3 = flag.clone();
+
+        let p1 = Arc::new(LockFreeDeque::<usize, 256>::new());
+        let p2 = p1.clone();
+        let p3 = p1.clone();
+        let c1 = p1.clone();
+        let c2 = p1.clone();
+
+        let producer1 = thread::spawn(move || {
+            for i in 0..pad {
+                let _ = p1.push_front(i);
+            }
+            flag1.fetch_sub(1, Ordering::SeqCst);
+        });
+        let producer2 = thread::spawn(move || {
+            for i in pad..(2 * pad) {
+                let _ = p2.push_front(i);
+            }
+            flag2.fetch_sub(1, Ordering::SeqCst);
+        });
+        let producer3 = thread::spawn(move || {
+            for i in (2 * pad)..(3 * pad) {
+                let _ = p3.push_front(i);
+            }
+            flag3.fetch_sub(1, Ordering::SeqCst);
+        });
+
+        let consumer = thread::spawn(move || {
+            let mut sum = 0;
+            while flag_c.load(Ordering::SeqCst) != 0 || !c2.is_empty() {
+                if let Some(num) = c2.pop_back() {
+                    sum += num;
+                }
+            }
+            sum
+        });
+
+        let mut sum = 0;
+        while flag.load(Ordering::SeqCst) != 0 || !c1.is_empty() {
+            if let Some(num) = c1.pop_back() {
+                sum += num;
+            }
+        }
+
+        producer1.join().unwrap();
+        producer2.join().unwrap();
+        producer3.join().unwrap();
+
+        let s = consumer.join().unwrap();
+        sum += s;
+        assert_eq!(sum, (0..(3 * pad)).sum());
+    }
+
+    // this test may take a long time to finish (≈ 1 minute)
+    // significantly longer than that means there is probably a deadlock
+    #[cfg(not(feature = "single-thread"))]
+    #[test]
+    fn test_mpmc_mix() {
+        let mut count = 10000;
+        while count > 0 {
+            count -= 1;
+            let pad = 750usize;
+
+            let flag = Arc::new(AtomicI32::new(4));
+            let flag_c = flag.clone();
+            let flag1 = flag.clone();
+            let flag2 = flag.clone();
+            let flag3 = flag.clone();
+            let flag4 = flag.clone();
+
+            let p1 = Arc::new(LockFreeDeque::<usize, 4096>::new());
+            let p2 = p1.clone();
+            let p3 = p1.clone();
+            let p4 = p1.clone();
+            let c1 = p1.clone();
+            let c2 = p1.clone();
+
+            let producer1 = thread::spawn(move || {
+                for i in 0..pad {
+                    if let Err(item) = p1.push_front(i) {
+                        println!("Failed to push front {}", item);
+                    }
+                    // if let Err(item) = p1.push_back(i) {
+                    //     println!("Failed to push back {}", item);
+                    // }
+                }
+                flag1.fetch_sub(1, Ordering::SeqCst);
+            });
+            let producer2 = thread::spawn(move || {
+                for i in pad..(2 * pad) {
+                    // if let Err(item) = p2.push_front(i) {
+                    //     println!("Failed to push front {}", item);
+                    // }
+                    if let Err(item) = p2.push_back(i) {
+                        println!("Failed to push back {}", item);
+                    }
+                }
+                flag2.fetch_sub(1, Ordering::SeqCst);
+            });
+            let producer3 = thread::spawn(move || {
+                for i in (2 * pad)..(3 * pad) {
+                    if let Ok(mut guard) = p3.push_slot_front() {
+                        guard.write(i);
+                    } else {
+                        println!("Failed to push front {}", i);
+                    }
+                    // if let Ok(mut guard) = p3.push_slot_back() {
+                    //     guard.write(i);
+                    // } else {
+                    //     println!("Failed to push front {}", i);
+                    // }
+                }
+                flag3.fetch_sub(1, Ordering::SeqCst);
+            });
+            let producer4 = thread::spawn(move || {
+                for i in (3 * pad)..(4 * pad) {
+                    // if let Ok(mut guard) = p4.push_slot_front() {
+                    //     guard.write(i);
+                    // } else {
+                    //     println!("Failed to push front {}", i);
+                    // }
+                    if let Ok(mut guard) = p4.push_slot_back() {
+                        guard.write(i);
+                    } else {
+                        println!("Failed to push front {}", i);
+                    }
+                }
+                flag4.fetch_sub(1, Ordering::SeqCst);
+            });
+
+            let consumer = thread::spawn(move || {
+                let mut sum = 0;
+                while flag_c.load(Ordering::SeqCst) != 0 || !c2.is_empty() {
+                    if let Some(num) = c2.pop_front() {
+                        // if let Some(num) = c2.pop_back() {
+                        sum += num;
+                    }
+                }
+                sum
+            });
+
+            let mut sum = 0;
+            while flag.load(Ordering::SeqCst) != 0 || !c1.is_empty() {
+                // if let Some(num) = c1.pop_front() {
+                if let Some(num) = c1.pop_back() {
+                    sum += num;
+                }
+            }
+
+            producer1.join().unwrap();
+            producer2.join().unwrap();
+            producer3.join().unwrap();
+            producer4.join().unwrap();
+
+            let s = consumer.join().unwrap();
+            sum += s;
+            assert_eq!(sum, (0..(4 * pad)).sum());
+        }
+    }
+
+    /// Deterministic repro of the race behind `test_mpmc_full_mix`
+    ///
+    /// With room for one more item, a push to each end targets a different free slot. Both read head and tail
+    /// before either moves them, so both pass the full check. With separate head and tail, the front push
+    /// moved head onto the slot before the tail, then the back push moved tail onto head, and the deque
+    /// looked empty while every slot was READY. Now the back push's move sees the new head and fails.
+    #[test]
+    fn test_full_race() {
+        use std::{boxed::Box, cell::Cell, rc::Rc};
+
+        static DEQUE: LockFreeDeque<i32, 4> = LockFreeDeque::new();
+        assert!(DEQUE.push_back(0).is_ok());
+        assert!(DEQUE.push_back(1).is_ok());
+
+        // The front push runs while the back push holds its claimed slot, before it moves the tail
+        let front_ok = Rc::new(Cell::new(false));
+        let front_ok_c = front_ok.clone();
+        set_yield_hook(Some(Box::new(move |op| {
+            if op == SlotOp::PushBack {
+                front_ok_c.set(DEQUE.push_front(-1).is_ok());
+            }
+        })));
+        let back_ok = DEQUE.push_back(2).is_ok();
+        set_yield_hook(None);
+
+        // Only one of them fits, and no item may be lost
+        assert!(front_ok.get() != back_ok, "both pushes succeeded");
+        assert_eq!(DEQUE.len(), DEQUE.capacity());
+        let mut popped = 0;
+        while DEQUE.pop_front().is_some() {
+            popped += 1;
+        }
+        assert_eq!(popped, DEQUE.capacity());
+    }
+
+    // this test may take a long time to finish (< 1 minute)
+    // longer than that means there is probably a deadlock
+    #[cfg(not(feature = "single-thread"))]
+    #[test]
+    fn test_mpmc_full_mix() {
+        let mut count = 10000;
+        while count > 0 {
+            count -= 1;
+            let pad = 1000usize;
+
+            let flag = Arc::new(AtomicI32::new(3));
+            let flag_c = flag.clone();
+            let flag1 = flag.clone();
+            let flag2 = flag.clone();
+            let flag3 = flag.clone();
+
+            let p1 = Arc::new(LockFreeDeque::<usize, 4096>::new());
+            let p2 = p1.clone();
+            let p3 = p1.clone();
+            let c1 = p1.clone();
+            let c2 = p1.clone();
+
+            // Fill the deque until it is full
+            for _ in 0..4095 {
+                if let Err(item) = p1.push_front(0) {
+                    println!("Failed to push front {}", item);
+                }
+            }
+
+            let producer1 = thread::spawn(move || {
+                for i in 0..pad {
+                    while p1.push_front(i).is_err() {}
+                    // while p1.push_back(i).is_err() {}
+                }
+                flag1.fetch_sub(1, Ordering::SeqCst);
+            });
+            let producer2 = thread::spawn(move || {
+                for i in pad..(2 * pad) {
+                    // while p2.push_front(i).is_err() {}
+                    while p2.push_back(i).is_err() {}
+                }
+                flag2.fetch_sub(1, Ordering::SeqCst);
+            });
+            let producer3 = thread::spawn(move || {
+                for i in (2 * pad)..(3 * pad) {
+                    while p3.push_front(i).is_err() {}
+                    // while p3.push_back(i).is_err() {}
+                }
+                flag3.fetch_sub(1, Ordering::SeqCst);
+            });
+
+            let consumer = thread::spawn(move || {
                 let mut sum = 0;
                 while flag_c.load(Ordering::SeqCst) != 0 || !c2.is_empty() {
                     if let Some(num) = c2.pop_front() {
@@ -914,150 +2677,1431 @@ mod tests {
                         sum += num;
                     }
                 }
-                sum
+                sum
+            });
+
+            let mut sum = 0;
+            while flag.load(Ordering::SeqCst) != 0 || !c1.is_empty() {
+                // if let Some(num) = c1.pop_front() {
+                if let Some(num) = c1.pop_back() {
+                    sum += num;
+                }
+            }
+
+            producer1.join().unwrap();
+            producer2.join().unwrap();
+            producer3.join().unwrap();
+
+            let s = consumer.join().unwrap();
+            sum += s;
+            assert_eq!(sum, (0..(3 * pad)).sum());
+        }
+    }
+
+    /// Keep the deque at or near full while pushing and popping at both ends, checking that every item
+    /// comes out exactly once and that the deque never holds more than its capacity
+    #[cfg(not(feature = "single-thread"))]
+    #[test]
+    fn test_mpmc_full_stress() {
+        const CAPACITY: usize = 64;
+        const PRODUCERS: usize = 4;
+        const ITEMS: usize = 20000;
+        const PREFILL: usize = CAPACITY - 1;
+        const TOTAL: usize = PREFILL + PRODUCERS * ITEMS;
+
+        let deque: LockFreeDeque<usize, CAPACITY> = LockFreeDeque::new();
+        for i in 0..PREFILL {
+            assert!(deque.push_back(i).is_ok());
+        }
+        assert_eq!(deque.len(), deque.capacity());
+        let popped = AtomicUsize::new(0);
+        let seen: vec::Vec<AtomicBool> = (0..TOTAL).map(|_| AtomicBool::new(false)).collect();
+
+        thread::scope(|s| {
+            for p in 0..PRODUCERS {
+                let deque = &deque;
+                s.spawn(move || {
+                    for i in 0..ITEMS {
+                        let mut item = PREFILL + p * ITEMS + i;
+                        loop {
+                            let pushed = match p % 2 {
+                                0 => deque.push_front(item),
+                                _ => deque.push_back(item),
+                            };
+                            match pushed {
+                                Ok(()) => break,
+                                Err(rejected) => item = rejected,
+                            }
+                            assert!(deque.len() <= deque.capacity());
+                            thread::yield_now();
+                        }
+                    }
+                });
+            }
+            for c in 0..2 {
+                let (deque, popped, seen) = (&deque, &popped, &seen);
+                s.spawn(move || {
+                    while popped.load(Ordering::Acquire) < TOTAL {
+                        let item = match c {
+                            0 => deque.pop_front(),
+                            _ => deque.pop_back(),
+                        };
+                        match item {
+                            Some(item) => {
+                                assert!(
+                                    !seen[item].swap(true, Ordering::AcqRel),
+                                    "{item} popped twice"
+                                );
+                                popped.fetch_add(1, Ordering::AcqRel);
+                            }
+                            None => thread::yield_now(),
+                        }
+                    }
+                });
+            }
+        });
+
+        assert!(seen.iter().all(|seen| seen.load(Ordering::Acquire)));
+        assert!(deque.is_empty());
+    }
+
+    #[cfg(not(feature = "single-thread"))]
+    #[test]
+    fn test_push_pop() {
+        const WORKERS_PER_QUEUE: usize = 16;
+        const DATA_PER_WORKER: usize = 128;
+
+        let mut handles = vec::Vec::new();
+        let queue = Arc::new(LockFreeDeque::<usize, 4097>::new());
+
+        for worker_id in 0..WORKERS_PER_QUEUE {
+            let queue_c = queue.clone();
+            // let data_num_c = data_num.clone();
+            let handle = std::thread::spawn(move || {
+                for i in 0..DATA_PER_WORKER {
+                    queue_c.push_front(i).expect(
+                        std::format!("Failed to push data in worker {}, iter {}", worker_id, i)
+                            .as_str(),
+                    );
+                    // data_num_c.fetch_add(1, Ordering::AcqRel);
+                }
+                for i in 0..DATA_PER_WORKER {
+                    // let data_num = data_num_c.fetch_sub(1, Ordering::AcqRel);
+                    // if data_num < 0 {
+                    //     println!("data_num < 0 in queue {}, worker {}", queue_id, worker_id);
+                    //     while data_num_c.load(Ordering::Acquire) < 0 {}
+                    // }
+                    queue_c.pop_back().expect(
+                        std::format!("Failed to pop data in worker {}, iter {}", worker_id, i)
+                            .as_str(),
+                    );
+                    // let data = pop(queue_id).expect(
+                    //     std::format!(
+                    //         "Failed to pop data in queue {}, worker {}",
+                    //         queue_id,
+                    //         worker_id
+                    //     )
+                    //     .as_str(),
+                    // );
+                    // assert!(data.msg_type == 0);
+                }
+            });
+            handles.push(handle);
+        }
+    }
+
+    #[test]
+    fn test_clear() {
+        let deque: LockFreeDeque<i32, 8> = LockFreeDeque::new();
+        assert_eq!(deque.clear(), 0);
+        for i in 0..5 {
+            assert!(deque.push_front(i).is_ok());
+        }
+        assert_eq!(deque.clear(), 5);
+        assert!(deque.is_empty());
+        assert!(deque.push_back(1).is_ok());
+        assert_eq!(deque.pop_front(), Some(1));
+    }
+
+    #[test]
+    fn test_raw_indices() {
+        let deque: LockFreeDeque<i32, 5> = LockFreeDeque::new();
+        assert_eq!(deque.raw_indices(), (0, 0));
+        assert!(deque.push_back(1).is_ok());
+        assert!(deque.push_front(0).is_ok());
+        assert_eq!(deque.raw_indices(), (4, 1));
+    }
+
+    #[test]
+    fn test_repair() {
+        let deque: LockFreeDeque<i32, 8> = LockFreeDeque::new();
+        for i in 0..4 {
+            assert!(deque.push_back(i).is_ok());
+        }
+        // A producer crashed in the middle of a push, and a slot inside the range got lost
+        deque.buffer[2].state.store(SLOT_EMPTY, Ordering::Release);
+        deque.buffer[4].state.store(SLOT_WRITING, Ordering::Release);
+        deque.buffer[6].state.store(SLOT_READY, Ordering::Release);
+
+        unsafe { deque.repair() };
+        assert_eq!(deque.pop_front(), Some(0));
+        assert_eq!(deque.pop_front(), Some(1));
+        assert_eq!(deque.pop_front(), Some(3));
+        assert_eq!(deque.pop_front(), None);
+
+        // All slots are usable again
+        for i in 0..7 {
+            assert!(deque.push_back(i).is_ok());
+        }
+        for i in 0..7 {
+            assert_eq!(deque.pop_front(), Some(i));
+        }
+
+        // Out-of-range indices reset the deque
+        deque.set_raw_indices(9, 3);
+        unsafe { deque.repair() };
+        assert!(deque.is_empty());
+        assert!(deque.push_back(1).is_ok());
+        assert_eq!(deque.pop_back(), Some(1));
+    }
+
+    #[test]
+    fn test_push_len() {
+        let deque: LockFreeDeque<i32, 5> = LockFreeDeque::new();
+        for i in 0..4 {
+            assert_eq!(deque.push_front_len(i), Ok(i as usize + 1));
+        }
+        assert_eq!(deque.push_front_len(4), Err(4));
+
+        // The indices wrap around as the front moves backwards
+        assert_eq!(deque.pop_back(), Some(0));
+        assert_eq!(deque.pop_back(), Some(1));
+        assert_eq!(deque.push_back_len(10), Ok(3));
+        assert_eq!(deque.push_front_len(11), Ok(4));
+        assert_eq!(deque.push_back_len(12), Err(12));
+    }
+
+    #[test]
+    fn test_drain_into() {
+        let deque: LockFreeDeque<i32, 16> = LockFreeDeque::new();
+        for i in 0..10 {
+            assert!(deque.push_back(i).is_ok());
+        }
+        let mut out = [MaybeUninit::uninit(); 20];
+        assert_eq!(deque.drain_into(&mut out), 10);
+        for (i, item) in out[..10].iter().enumerate() {
+            assert_eq!(unsafe { item.assume_init() }, i as i32);
+        }
+        assert!(deque.is_empty());
+
+        // Stops when the buffer is full
+        for i in 0..5 {
+            assert!(deque.push_back(i).is_ok());
+        }
+        assert_eq!(deque.drain_into(&mut out[..3]), 3);
+        assert_eq!(deque.pop_front(), Some(3));
+    }
+
+    #[test]
+    fn test_pop_front_dedup() {
+        let deque: LockFreeDeque<(u64, u64, i32), 32> = LockFreeDeque::new();
+        let mut window: DedupWindow<4> = DedupWindow::new();
+        // Two senders, each retrying some of its messages
+        let deliveries = [
+            (1, 0),
+            (2, 0),
+            (1, 0),
+            (1, 1),
+            (2, 1),
+            (2, 0),
+            (1, 1),
+            (1, 2),
+        ];
+        for (i, &(sender, seq)) in deliveries.iter().enumerate() {
+            assert!(deque.push_back((sender, seq, i as i32)).is_ok());
+        }
+        let mut popped = std::vec::Vec::new();
+        while let Some(item) = deque.pop_front_dedup(&mut window, |&(sender, seq, _)| (sender, seq))
+        {
+            popped.push(item);
+        }
+        // Each message once, its first delivery
+        assert_eq!(
+            popped,
+            [(1, 0, 0), (2, 0, 1), (1, 1, 3), (2, 1, 4), (1, 2, 7)]
+        );
+        assert!(deque.is_empty());
+
+        // A duplicate of a key pushed out of the window is not detected
+        assert!(window.contains((1, 1)));
+        assert!(!window.contains((1, 0)));
+        assert!(deque.push_back((1, 0, 8)).is_ok());
+        assert_eq!(
+            deque.pop_front_dedup(&mut window, |&(sender, seq, _)| (sender, seq)),
+            Some((1, 0, 8))
+        );
+
+        window.clear();
+        assert!(!window.contains((1, 1)));
+    }
+
+    #[test]
+    fn test_retain() {
+        let deque: LockFreeDeque<(u64, i32), 16> = LockFreeDeque::new();
+        for i in 0..12 {
+            assert!(deque.push_back((i as u64 % 4, i)).is_ok());
+        }
+        assert_eq!(deque.retain(|&(sender, _)| sender != 3), 3);
+        assert_eq!(deque.count_where(|&(sender, _)| sender == 3), 0);
+
+        // The others are kept in order, and the tombstones are skipped from both ends
+        assert!(deque.push_back((0, 12)).is_ok());
+        let mut popped = vec![];
+        while let Some((_, i)) = deque.pop_front() {
+            popped.push(i);
+        }
+        assert_eq!(popped, [0, 1, 2, 4, 5, 6, 8, 9, 10, 12]);
+        assert!(deque.is_empty());
+
+        assert!(deque.push_back((3, 0)).is_ok());
+        assert_eq!(deque.retain(|&(sender, _)| sender != 3), 1);
+        assert_eq!(deque.pop_back(), None);
+    }
+
+    #[test]
+    fn test_fast_clear() {
+        let deque: LockFreeDeque<u64, 128> = LockFreeDeque::new();
+        // Start away from slot 0, so that the items wrap around the end of the buffer
+        for i in 0..60 {
+            assert!(deque.push_back(i).is_ok());
+            assert_eq!(deque.pop_front(), Some(i));
+        }
+        for i in 0..100 {
+            assert!(deque.push_back(i).is_ok());
+        }
+        assert_eq!(unsafe { deque.fast_clear() }, 100);
+        assert!(deque.is_empty());
+        assert_eq!(deque.pop_front(), None);
+        assert_eq!(deque.pop_back(), None);
+
+        // Every slot is usable again
+        for i in 0..deque.capacity() as u64 {
+            assert!(deque.push_front(i).is_ok());
+        }
+        assert!(deque.is_full());
+        assert_eq!(deque.pop_back(), Some(0));
+        assert_eq!(unsafe { deque.fast_clear() }, deque.capacity() - 1);
+        assert_eq!(unsafe { deque.fast_clear() }, 0);
+    }
+
+    #[test]
+    fn test_pop_bounded() {
+        let deque: LockFreeDeque<i32, 5> = LockFreeDeque::new();
+        assert_eq!(deque.pop_front_bounded(10), Ok(None));
+        assert_eq!(deque.pop_back_bounded(10), Ok(None));
+
+        // The only item is still being inserted
+        let mut guard = deque.push_slot_back().unwrap();
+        assert_eq!(deque.pop_front_bounded(10), Err(Contended));
+        assert_eq!(deque.pop_back_bounded(10), Err(Contended));
+        guard.write(1);
+        drop(guard);
+
+        assert_eq!(deque.pop_front_bounded(10), Ok(Some(1)));
+        assert!(deque.push_front(2).is_ok());
+        assert_eq!(deque.pop_back_bounded(10), Ok(Some(2)));
+        assert_eq!(deque.pop_back_bounded(0), Ok(None));
+    }
+
+    #[test]
+    fn test_push_bounded() {
+        use std::{boxed::Box, cell::Cell, rc::Rc};
+
+        static DEQUE: LockFreeDeque<i32, 4> = LockFreeDeque::new();
+        assert_eq!(DEQUE.push_back_bounded(0, 10), Ok(()));
+        assert_eq!(DEQUE.push_front_bounded(1, 10), Ok(()));
+
+        // The back push runs while the outer one holds its claimed slot, before it moves the tail
+        let inner = Rc::new(Cell::new(None));
+        let inner_c = inner.clone();
+        set_yield_hook(Some(Box::new(move |op| {
+            if op == SlotOp::PushBack {
+                inner_c.set(Some(DEQUE.push_back_bounded(3, 3)));
+            }
+        })));
+        assert_eq!(DEQUE.push_back(2), Ok(()));
+        set_yield_hook(None);
+        assert_eq!(inner.get(), Some(Err(PushError::Contended(3))));
+
+        assert_eq!(DEQUE.push_back_bounded(3, 10), Err(PushError::Full(3)));
+        assert_eq!(DEQUE.push_front_bounded(3, 10), Err(PushError::Full(3)));
+        assert_eq!(DEQUE.pop_front(), Some(1));
+        assert_eq!(DEQUE.pop_front(), Some(0));
+        assert_eq!(DEQUE.pop_front(), Some(2));
+
+        // A tail beyond the buffer, see `test_poison`
+        DEQUE.set_raw_indices(0, 9);
+        assert_eq!(DEQUE.push_back_bounded(3, 10), Err(PushError::Poisoned(3)));
+        assert_eq!(PushError::Poisoned(3).map(|item| item * 2).into_inner(), 6);
+        DEQUE.set_raw_indices(0, 0);
+        unsafe { DEQUE.clear_poison() };
+    }
+
+    #[test]
+    fn test_try_pop_front() {
+        use std::{boxed::Box, cell::Cell, rc::Rc};
+
+        static DEQUE: LockFreeDeque<i32, 4> = LockFreeDeque::new();
+        assert_eq!(DEQUE.try_pop_front(), Ok(None));
+        for i in 0..3 {
+            assert!(DEQUE.push_back(i).is_ok());
+        }
+        assert_eq!(DEQUE.try_pop_front(), Ok(Some(0)));
+
+        // A pop holding the front slot makes the attempt fail instead of waiting for it
+        let inner = Rc::new(Cell::new(None));
+        let inner_c = inner.clone();
+        set_yield_hook(Some(Box::new(move |op| {
+            if op == SlotOp::PopFront {
+                inner_c.set(Some(DEQUE.try_pop_front()));
+            }
+        })));
+        assert_eq!(DEQUE.pop_front(), Some(1));
+        set_yield_hook(None);
+        assert_eq!(inner.get(), Some(Err(Contended)));
+
+        // So does a slot still being written
+        let mut guard = DEQUE.push_slot_front().unwrap();
+        assert_eq!(DEQUE.try_pop_front(), Err(Contended));
+        guard.write(3);
+        drop(guard);
+        assert_eq!(DEQUE.try_pop_front(), Ok(Some(3)));
+        assert_eq!(DEQUE.try_pop_front(), Ok(Some(2)));
+        assert_eq!(DEQUE.try_pop_front(), Ok(None));
+    }
+
+    #[cfg(not(feature = "single-thread"))]
+    #[test]
+    #[ignore]
+    fn bench_try_pop_front() {
+        use std::{println, time::Instant};
+
+        const ROUNDS: usize = 1 << 20;
+        let deque: LockFreeDeque<usize, 64> = LockFreeDeque::new();
+        let fill = || {
+            for i in 0..deque.capacity() {
+                assert!(deque.push_back(i).is_ok());
+            }
+        };
+
+        let start = Instant::now();
+        for _ in 0..ROUNDS / deque.capacity() {
+            fill();
+            while deque.pop_front().is_some() {}
+        }
+        let pop_front = start.elapsed();
+
+        let start = Instant::now();
+        for _ in 0..ROUNDS / deque.capacity() {
+            fill();
+            while let Ok(Some(_)) = deque.try_pop_front() {}
+        }
+        let try_pop_front = start.elapsed();
+
+        println!(
+            "pop_front: {:?}, try_pop_front: {:?}",
+            pop_front, try_pop_front
+        );
+    }
+
+    #[test]
+    fn test_pop_held_guard() {
+        let deque: LockFreeDeque<i32, 5> = LockFreeDeque::new();
+        assert!(deque.push_back(1).is_ok());
+        let mut guard = deque.push_slot_back().unwrap();
+        assert!(deque.push_back(3).is_ok());
+
+        // A bounded consumer reaching the held slot gives up on it instead of spinning
+        assert_eq!(deque.pop_front(), Some(1));
+        assert_eq!(deque.pop_front_bounded(usize::MAX), Err(Contended));
+        assert_eq!(deque.try_pop_front(), Err(Contended));
+        assert!(deque.peek_front().is_none());
+        // The other end is not blocked
+        assert_eq!(deque.pop_back(), Some(3));
+
+        guard.write(2);
+        drop(guard);
+        assert_eq!(deque.pop_front(), Some(2));
+        assert!(deque.is_empty());
+    }
+
+    #[cfg(not(feature = "single-thread"))]
+    #[test]
+    fn test_pop_held_guard_threads() {
+        use core::sync::atomic::AtomicBool;
+        use std::time::{Duration, Instant};
+
+        const HOLD: Duration = Duration::from_millis(50);
+        let deque: LockFreeDeque<u32, 8> = LockFreeDeque::new();
+        let held = AtomicBool::new(false);
+        std::thread::scope(|s| {
+            s.spawn(|| {
+                let first = deque.push_slot_back().unwrap();
+                let second = deque.push_slot_back().unwrap();
+                held.store(true, Ordering::Release);
+                // A slow producer, publishing each slot when its guard drops
+                for (mut guard, item) in [(first, 7), (second, 8)] {
+                    let start = Instant::now();
+                    while start.elapsed() < HOLD {
+                        std::thread::yield_now();
+                    }
+                    guard.write(item);
+                }
             });
+            while !held.load(Ordering::Acquire) {
+                std::thread::yield_now();
+            }
+            // Each bounded pop returns while the slot is held, so the consumer gets to poll repeatedly
+            let mut polls = 0;
+            let item = loop {
+                match deque.pop_front_bounded(usize::MAX) {
+                    Ok(Some(item)) => break item,
+                    Ok(None) => panic!("the held slot was reported empty"),
+                    Err(Contended) => polls += 1,
+                }
+                std::thread::yield_now();
+            };
+            assert_eq!(item, 7);
+            assert!(polls > 0);
+            // An unbounded pop waits for the slot instead, so None still means empty
+            assert_eq!(deque.pop_front(), Some(8));
+            assert_eq!(deque.pop_front(), None);
+        });
+    }
+
+    #[test]
+    fn test_capacity() {
+        let deque: LockFreeDeque<i32, 5> = LockFreeDeque::new();
+        assert_eq!(deque.capacity(), 4);
+        assert_eq!(deque.raw_capacity(), 5);
+        let mut pushed = 0;
+        while deque.push_back(pushed).is_ok() {
+            pushed += 1;
+            assert_eq!(deque.is_full(), pushed == 4);
+        }
+        assert_eq!(pushed, 4);
+        assert_eq!(deque.len(), deque.capacity());
+        assert_eq!(deque.len_relaxed(), deque.capacity());
+        assert!(deque.push_front(-1).is_err());
+        assert_eq!(deque.pop_back(), Some(3));
+        assert!(!deque.is_full());
+    }
+
+    #[test]
+    fn test_len_saturates() {
+        let deque: LockFreeDeque<i32, 5> = LockFreeDeque::new();
+        // An index beyond the buffer poisons the deque, see `test_poison`
+        deque.set_raw_indices(7, 1);
+        assert_eq!(deque.len(), 0);
+        assert!(deque.is_poisoned());
+        deque.set_raw_indices(0, 100);
+        assert_eq!(deque.len(), 0);
+        unsafe { deque.clear_poison() };
+        deque.set_raw_indices(4, 1);
+        assert_eq!(deque.len(), 2);
+        // restore a consistent state so that dropping the deque terminates
+        deque.set_raw_indices(0, 0);
+    }
+
+    #[cfg(debug_assertions)]
+    #[test]
+    #[should_panic(expected = "out of bounds")]
+    fn test_push_front_bad_head() {
+        // Not dropped: draining a deque with a corrupted head while unwinding would panic again
+        let deque = core::mem::ManuallyDrop::new(LockFreeDeque::<i32, 5>::new());
+        deque.set_raw_indices(7, 1);
+        let _ = deque.push_front(0);
+    }
+
+    #[cfg(not(debug_assertions))]
+    #[test]
+    fn test_push_front_bad_head() {
+        let deque: LockFreeDeque<i32, 5> = LockFreeDeque::new();
+        deque.set_raw_indices(7, 1);
+        assert_eq!(deque.push_front(0), Err(0));
+        deque.set_raw_indices(0, 0);
+    }
+
+    #[test]
+    fn test_poison() {
+        let deque: LockFreeDeque<i32, 5> = LockFreeDeque::new();
+        assert!(deque.push_back(1).is_ok());
+        assert_eq!(deque.check_poison(), Ok(()));
+
+        // A tail beyond the buffer, as a stray write to shared memory would leave it
+        deque.set_raw_indices(0, 9);
+        assert_eq!(deque.pop_back(), None);
+        assert!(deque.is_poisoned());
+        assert_eq!(deque.check_poison(), Err(Poisoned));
+
+        // Every operation now fails fast, even once the indices look valid again
+        deque.set_raw_indices(0, 1);
+        assert_eq!(deque.push_back(2), Err(2));
+        assert_eq!(deque.push_front(3), Err(3));
+        assert!(deque.begin_batch(1).is_err());
+        assert_eq!(deque.pop_front(), None);
+        assert_eq!(deque.pop_back(), None);
+        assert_eq!(deque.peek_front_with(|item| *item), None);
+        assert_eq!(deque.len(), 0);
+        assert!(deque.is_empty());
+
+        unsafe {
+            deque.repair();
+            deque.clear_poison();
+        }
+        assert_eq!(deque.pop_front(), Some(1));
+        assert!(deque.push_back(2).is_ok());
+        assert_eq!(deque.len(), 1);
+    }
+
+    #[test]
+    fn test_batch() {
+        let deque: LockFreeDeque<i32, 6> = LockFreeDeque::new();
+        assert!(deque.push_back(0).is_ok());
+
+        // Larger than the free space
+        assert!(deque.begin_batch(5).is_err());
+
+        let mut batch = deque.begin_batch(3).unwrap();
+        assert!(batch.push(1).is_ok());
+        assert!(batch.push(2).is_ok());
+        assert!(batch.push(3).is_ok());
+        assert_eq!(batch.push(4), Err(4));
+        // Only the item pushed before the batch is visible
+        assert_eq!(deque.pop_front(), Some(0));
+        batch.commit();
+
+        assert_eq!(deque.pop_front(), Some(1));
+        assert_eq!(deque.pop_front(), Some(2));
+        assert_eq!(deque.pop_front(), Some(3));
+        assert!(deque.is_empty());
+    }
+
+    #[test]
+    fn test_batch_front() {
+        let deque: LockFreeDeque<i32, 6> = LockFreeDeque::new();
+        assert!(deque.push_front(0).is_ok());
+        let mut batch = deque.begin_batch_front(3).unwrap();
+        assert!(batch.push(1).is_ok());
+        assert!(batch.push(2).is_ok());
+        assert!(batch.push(3).is_ok());
+        batch.commit();
+        assert!(deque.push_front(4).is_ok());
+
+        // Same order as pushing one by one with push_front
+        for i in 0..5 {
+            assert_eq!(deque.pop_back(), Some(i));
+        }
+    }
+
+    #[test]
+    fn test_batch_abort() {
+        let deque: LockFreeDeque<i32, 8> = LockFreeDeque::new();
+        assert!(deque.push_back(0).is_ok());
+        let mut batch = deque.begin_batch(3).unwrap();
+        assert!(batch.push(1).is_ok());
+        assert!(batch.push(2).is_ok());
+        batch.abort();
+        assert!(deque.push_back(4).is_ok());
+
+        // A partially written batch only publishes the written items
+        let mut batch = deque.begin_batch(2).unwrap();
+        assert!(batch.push(5).is_ok());
+        batch.commit();
+
+        assert_eq!(deque.pop_back(), Some(5));
+        assert_eq!(deque.pop_front(), Some(0));
+        assert_eq!(deque.pop_front(), Some(4));
+        assert_eq!(deque.pop_front(), None);
+        assert!(deque.is_empty());
+
+        // All slots are reusable
+        for i in 0..7 {
+            assert!(deque.push_front(i).is_ok());
+        }
+        assert!(deque.push_front(7).is_err());
+    }
+
+    #[cfg(not(feature = "single-thread"))]
+    #[test]
+    fn test_batch_visibility() {
+        use core::sync::atomic::AtomicBool;
+
+        const BATCH_LEN: usize = 8;
+        let deque = Arc::new(LockFreeDeque::<usize, 16>::new());
+        let committed = Arc::new(AtomicBool::new(false));
+
+        let consumer = {
+            let deque = deque.clone();
+            let committed = committed.clone();
+            thread::spawn(move || {
+                let first = loop {
+                    if let Some(item) = deque.pop_front() {
+                        break item;
+                    }
+                    thread::yield_now();
+                };
+                // No item may be observed before the whole batch is committed
+                assert!(committed.load(Ordering::Acquire));
+                assert_eq!(first, 0);
+                // Once the first item is visible, so are all the others
+                for i in 1..BATCH_LEN {
+                    assert_eq!(deque.try_pop_front(), Ok(Some(i)));
+                }
+            })
+        };
+
+        let mut batch = deque.begin_batch(BATCH_LEN).unwrap();
+        for i in 0..BATCH_LEN {
+            assert!(batch.push(i).is_ok());
+            thread::sleep(std::time::Duration::from_millis(1));
+        }
+        committed.store(true, Ordering::Release);
+        batch.commit();
+
+        consumer.join().unwrap();
+        assert!(deque.is_empty());
+    }
+
+    // Measures how evenly pops are spread between consumers competing for the head.
+    // No fairness is guaranteed, so only starvation (a consumer never succeeding) fails it.
+    // run with `cargo test bench_pop_fairness -- --ignored --nocapture`
+    #[cfg(not(feature = "single-thread"))]
+    #[test]
+    #[ignore]
+    fn bench_pop_fairness() {
+        use std::sync::Barrier;
+
+        const CONSUMERS: usize = 16;
+        const ITEMS: usize = 64 * 1024;
+
+        let deque = Arc::new(LockFreeDeque::<usize, 256>::new());
+        let popped = Arc::new(AtomicUsize::new(0));
+        let barrier = Arc::new(Barrier::new(CONSUMERS + 1));
+
+        let consumers: vec::Vec<_> = (0..CONSUMERS)
+            .map(|_| {
+                let deque = deque.clone();
+                let popped = popped.clone();
+                let barrier = barrier.clone();
+                thread::spawn(move || {
+                    barrier.wait();
+                    let mut count = 0usize;
+                    while popped.load(Ordering::Acquire) < ITEMS {
+                        if deque.pop_front().is_some() {
+                            popped.fetch_add(1, Ordering::AcqRel);
+                            count += 1;
+                        }
+                        // Process the item or wait for the next one
+                        thread::yield_now();
+                    }
+                    count
+                })
+            })
+            .collect();
+
+        // Feed the queue steadily
+        barrier.wait();
+        for i in 0..ITEMS {
+            while deque.push_back(i).is_err() {
+                thread::yield_now();
+            }
+        }
+
+        let counts: vec::Vec<usize> = consumers.into_iter().map(|h| h.join().unwrap()).collect();
+        assert_eq!(counts.iter().sum::<usize>(), ITEMS);
+        println!(
+            "pops per consumer: min {}, max {}",
+            counts.iter().min().unwrap(),
+            counts.iter().max().unwrap()
+        );
+        assert!(counts.iter().all(|&count| count > 0));
+    }
+
+    #[test]
+    fn test_pop_front_with() {
+        let deque: LockFreeDeque<i32, 5> = LockFreeDeque::new();
+        assert_eq!(deque.pop_front_with(|item| *item), None);
+
+        assert!(deque.push_back(1).is_ok());
+        assert!(deque.push_back(2).is_ok());
+        assert_eq!(deque.pop_front_with(|item| *item * 10), Some(10));
+        assert_eq!(deque.len(), 1);
+        assert_eq!(deque.pop_front(), Some(2));
+        assert!(deque.is_empty());
+    }
+
+    #[test]
+    fn test_pop_front_with_panic() {
+        let deque: LockFreeDeque<i32, 5> = LockFreeDeque::new();
+        assert!(deque.push_back(1).is_ok());
+        let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            deque.pop_front_with(|_| panic!("callback panicked"))
+        }));
+        assert!(res.is_err());
+
+        // The slot must be released after unwinding
+        assert!(deque.is_empty());
+
+        // A peek leaves the item in place
+        assert!(deque.push_back(0).is_ok());
+        let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            deque.peek_front_with(|_| panic!("callback panicked"))
+        }));
+        assert!(res.is_err());
+        assert_eq!(deque.peek_back_with(|item| *item), Some(0));
+        assert_eq!(deque.pop_front(), Some(0));
+
+        for i in 0..4 {
+            assert!(deque.push_back(i).is_ok());
+        }
+        for i in 0..4 {
+            assert_eq!(deque.pop_front(), Some(i));
+        }
+    }
+
+    #[test]
+    fn test_swap_contents() {
+        let active: LockFreeDeque<i32, 5> = LockFreeDeque::new();
+        let standby: LockFreeDeque<i32, 5> = LockFreeDeque::new();
+        for i in 0..3 {
+            assert!(active.push_back(i).is_ok());
+        }
+        // Offset the standby indices so that they differ from the active ones
+        for i in 0..3 {
+            assert!(standby.push_back(i).is_ok());
+            assert_eq!(standby.pop_front(), Some(i));
+        }
+        assert!(standby.push_back(10).is_ok());
+
+        unsafe { active.swap_contents(&standby) };
+        assert_eq!(active.len(), 1);
+        assert_eq!(standby.len(), 3);
+        assert_eq!(active.pop_front(), Some(10));
+        assert_eq!(active.pop_front(), None);
+        for i in 0..3 {
+            assert_eq!(standby.pop_front(), Some(i));
+        }
+        assert!(standby.is_empty());
+
+        unsafe { standby.swap_contents(&standby) };
+        assert!(standby.is_empty());
+    }
+
+    #[test]
+    fn test_pop_remaining() {
+        let deque: LockFreeDeque<i32, 4> = LockFreeDeque::new();
+        assert_eq!(deque.pop_front_remaining(), None);
+        // Wrap the indices around the buffer
+        for i in 0..3 {
+            assert!(deque.push_back(i).is_ok());
+            assert_eq!(deque.pop_front(), Some(i));
+        }
+        for i in 0..3 {
+            assert!(deque.push_back(i).is_ok());
+        }
+        assert_eq!(deque.pop_front_remaining(), Some((0, 2)));
+        assert_eq!(deque.pop_back_remaining(), Some((2, 1)));
+        assert_eq!(deque.pop_front_remaining(), Some((1, 0)));
+        assert_eq!(deque.pop_back_remaining(), None);
+    }
+
+    #[test]
+    fn test_pop_front_into() {
+        use crate::IPCItem;
+
+        let deque: LockFreeDeque<IPCItem, 4> = LockFreeDeque::new();
+        let item = IPCItem {
+            sender: 1,
+            msg_type: 2,
+            rep_type: 3,
+            data: [4; 8],
+        };
+        assert!(deque.push_back(item).is_ok());
 
-            let mut sum = 0;
-            while flag.load(Ordering::SeqCst) != 0 || !c1.is_empty() {
-                // if let Some(num) = c1.pop_front() {
-                if let Some(num) = c1.pop_back() {
-                    sum += num;
-                }
-            }
+        let mut out = MaybeUninit::<IPCItem>::uninit();
+        assert!(deque.pop_front_into(&mut out));
+        let out = unsafe { out.assume_init() };
+        assert_eq!(
+            (out.sender, out.msg_type, out.rep_type, out.data),
+            (1, 2, 3, [4; 8])
+        );
 
-            producer1.join().unwrap();
-            producer2.join().unwrap();
-            producer3.join().unwrap();
-            producer4.join().unwrap();
+        let mut out = MaybeUninit::<IPCItem>::uninit();
+        assert!(!deque.pop_front_into(&mut out));
+        assert!(deque.is_empty());
+    }
 
-            let s = consumer.join().unwrap();
-            sum += s;
-            assert_eq!(sum, (0..(4 * pad)).sum());
+    #[test]
+    fn test_push_slot_back_indexed() {
+        let deque: LockFreeDeque<i32, 4> = LockFreeDeque::new();
+        for i in 0..3 {
+            assert!(deque.push_back(i).is_ok());
+        }
+        assert_eq!(deque.pop_front(), Some(0));
+        assert_eq!(deque.pop_front(), Some(1));
+
+        // The tail is at index 3, the next slot wraps around to index 0
+        for expected in [3, 0] {
+            let (mut guard, index) = deque.push_slot_back_indexed().unwrap();
+            assert_eq!(index, expected);
+            guard.write(10 + index as i32);
+            drop(guard);
+            let data = unsafe { (*deque.buffer[index].data.get()).assume_init_ref() };
+            assert_eq!(*data, 10 + index as i32);
         }
+        assert!(deque.push_slot_back_indexed().is_err());
+        assert_eq!(deque.pop_front(), Some(2));
+        assert_eq!(deque.pop_front(), Some(13));
+        assert_eq!(deque.pop_front(), Some(10));
     }
 
-    // this test may take a long time to finish (< 1 minute)
-    // longer than that means there is probably a deadlock
-    //
-    // currently, this test will deadlock because of an unsolved bug.
     #[test]
-    fn test_mpmc_full_mix() {
-        let mut count = 10000;
-        while count > 0 {
-            count -= 1;
-            let pad = 1000usize;
+    fn test_peek_back_index() {
+        let deque: LockFreeDeque<i32, 4> = LockFreeDeque::new();
+        assert!(deque.push_back(0).is_ok());
+        assert_eq!(deque.pop_front(), Some(0));
 
-            let flag = Arc::new(AtomicI32::new(3));
-            let flag_c = flag.clone();
-            let flag1 = flag.clone();
-            let flag2 = flag.clone();
-            let flag3 = flag.clone();
+        // The prediction wraps around with the tail, and matches the slot each push claims
+        for i in 0..3 {
+            let predicted = deque.peek_back_index();
+            let (mut guard, index) = deque.push_slot_back_indexed().unwrap();
+            assert_eq!(predicted, Some(index));
+            guard.write(i);
+        }
+        assert_eq!(deque.peek_back_index(), None);
+        assert_eq!(deque.pop_front(), Some(0));
+        assert_eq!(deque.peek_back_index(), Some(0));
+    }
 
-            let p1 = Arc::new(LockFreeDeque::<usize, 4096>::new());
-            let p2 = p1.clone();
-            let p3 = p1.clone();
-            let c1 = p1.clone();
-            let c2 = p1.clone();
+    #[test]
+    fn test_pop_front_with_requeue() {
+        let deque: LockFreeDeque<i32, 5> = LockFreeDeque::new();
+        for i in 0..3 {
+            assert!(deque.push_back(i).is_ok());
+        }
+        for _ in 0..3 {
+            let res = deque.pop_front_with(|item| deque.push_back(*item + 10));
+            assert_eq!(res, Some(Ok(())));
+        }
+        for i in 0..3 {
+            assert_eq!(deque.pop_front(), Some(i + 10));
+        }
 
-            // Fill the deque until it is full
-            for _ in 0..4095 {
-                if let Err(item) = p1.push_front(0) {
-                    println!("Failed to push front {}", item);
-                }
+        // Requeue into a full deque: the popped item's slot is still held, so only one push fits
+        for i in 0..4 {
+            assert!(deque.push_back(i).is_ok());
+        }
+        let res = deque.pop_front_with(|item| (deque.push_back(*item), deque.push_back(-1)));
+        assert_eq!(res, Some((Ok(()), Err(-1))));
+        for i in [1, 2, 3, 0] {
+            assert_eq!(deque.pop_front(), Some(i));
+        }
+        assert!(deque.is_empty());
+    }
+
+    // run with `cargo test bench_pop_front_with -- --ignored --nocapture`
+    #[test]
+    #[ignore]
+    fn bench_pop_front_with() {
+        use crate::IPCItem;
+        use std::time::Instant;
+
+        const ROUNDS: usize = 1000;
+        let deque = LockFreeDeque::<IPCItem, 4097>::new();
+        let item = IPCItem {
+            sender: 1,
+            msg_type: 2,
+            rep_type: 3,
+            data: [4; 8],
+        };
+
+        let mut sum = 0u64;
+        let start = Instant::now();
+        for _ in 0..ROUNDS {
+            while deque.push_back(item).is_ok() {}
+            while let Some(item) = deque.pop_front() {
+                sum += item.msg_type;
             }
+        }
+        let by_value = start.elapsed();
 
-            let producer1 = thread::spawn(move || {
-                for i in 0..pad {
-                    while p1.push_front(i).is_err() {}
-                    // while p1.push_back(i).is_err() {}
-                }
-                flag1.fetch_sub(1, Ordering::SeqCst);
-            });
-            let producer2 = thread::spawn(move || {
-                for i in pad..(2 * pad) {
-                    // while p2.push_front(i).is_err() {}
-                    while p2.push_back(i).is_err() {}
-                }
-                flag2.fetch_sub(1, Ordering::SeqCst);
-            });
-            let producer3 = thread::spawn(move || {
-                for i in (2 * pad)..(3 * pad) {
-                    while p3.push_front(i).is_err() {}
-                    // while p3.push_back(i).is_err() {}
+        let start = Instant::now();
+        for _ in 0..ROUNDS {
+            while deque.push_back(item).is_ok() {}
+            while let Some(msg_type) = deque.pop_front_with(|item| item.msg_type) {
+                sum += msg_type;
+            }
+        }
+        let in_place = start.elapsed();
+
+        println!("pop_front: {:?}, pop_front_with: {:?}", by_value, in_place);
+        assert_eq!(sum, 2 * 2 * (ROUNDS * 4096) as u64);
+    }
+
+    #[cfg(feature = "debug-history")]
+    #[test]
+    fn test_history() {
+        use SlotOp::*;
+
+        let deque: LockFreeDeque<i32, 8> = LockFreeDeque::new();
+        assert!(deque.history().all(|op| op.is_none()));
+
+        assert!(deque.push_back(1).is_ok()); // slot 0
+        assert!(deque.push_front(2).is_ok()); // slot 7
+        assert!(deque.push_back(3).is_ok()); // slot 1
+        assert_eq!(deque.pop_front(), Some(2));
+        assert_eq!(deque.pop_back(), Some(3));
+        // Reserves slots 1 and 2
+        deque.begin_batch(2).unwrap().abort();
+        let mut guard = deque.push_slot_front().unwrap(); // slot 7
+        guard.write(4);
+        let history: std::vec::Vec<_> = deque.history().collect();
+        assert_eq!(
+            history,
+            [
+                Some(PushBack),
+                Some(Release),
+                Some(Release),
+                None,
+                None,
+                None,
+                None,
+                Some(PushFront)
+            ]
+        );
+        drop(guard);
+
+        // Popping from the back discards the cancelled slots first
+        assert_eq!(deque.pop_back(), Some(1));
+        let history: std::vec::Vec<_> = deque.history().take(3).collect();
+        assert_eq!(history, [Some(PopBack), Some(PopBack), Some(PopBack)]);
+    }
+
+    #[cfg(not(feature = "single-thread"))]
+    #[test]
+    fn test_len_under_churn() {
+        use core::sync::atomic::AtomicBool;
+
+        let deque: LockFreeDeque<usize, 8> = LockFreeDeque::new();
+        let done = AtomicBool::new(false);
+        thread::scope(|s| {
+            s.spawn(|| {
+                while !done.load(Ordering::Relaxed) {
+                    assert!(deque.len() <= deque.capacity());
                 }
-                flag3.fetch_sub(1, Ordering::SeqCst);
             });
+            let churners: std::vec::Vec<_> = (0..3)
+                .map(|_| {
+                    s.spawn(|| {
+                        for i in 0..20000 {
+                            let _ = deque.push_back(i);
+                            let _ = deque.push_front(i);
+                            deque.pop_front();
+                            deque.pop_back();
+                        }
+                    })
+                })
+                .collect();
+            for churner in churners {
+                churner.join().unwrap();
+            }
+            done.store(true, Ordering::Relaxed);
+        });
+    }
 
-            let consumer = thread::spawn(move || {
-                let mut sum = 0;
-                while flag_c.load(Ordering::SeqCst) != 0 || !c2.is_empty() {
-                    if let Some(num) = c2.pop_front() {
-                        // if let Some(num) = c2.pop_back() {
-                        sum += num;
+    #[cfg(not(feature = "single-thread"))]
+    #[test]
+    fn test_len_net_count() {
+        const PUSHES: usize = 2000;
+        const POPS: usize = 1500;
+
+        let deque: LockFreeDeque<usize, 4096> = LockFreeDeque::new();
+        thread::scope(|s| {
+            for producer in 0..2 {
+                let deque = &deque;
+                s.spawn(move || {
+                    for i in 0..PUSHES / 2 {
+                        if producer == 0 {
+                            assert!(deque.push_back(i).is_ok());
+                        } else {
+                            assert!(deque.push_front(i).is_ok());
+                        }
                     }
-                }
-                sum
-            });
+                });
+            }
+            for consumer in 0..2 {
+                let deque = &deque;
+                s.spawn(move || {
+                    let mut popped = 0;
+                    while popped < POPS / 2 {
+                        let item = if consumer == 0 {
+                            deque.pop_front()
+                        } else {
+                            deque.pop_back()
+                        };
+                        match item {
+                            Some(_) => popped += 1,
+                            None => thread::yield_now(),
+                        }
+                    }
+                });
+            }
+        });
+        assert_eq!(deque.len_exact(), PUSHES - POPS);
+        assert_eq!(deque.len(), PUSHES - POPS);
+    }
 
-            let mut sum = 0;
-            while flag.load(Ordering::SeqCst) != 0 || !c1.is_empty() {
-                // if let Some(num) = c1.pop_front() {
-                if let Some(num) = c1.pop_back() {
-                    sum += num;
-                }
+    #[test]
+    fn test_len_exact() {
+        let deque: LockFreeDeque<i32, 8> = LockFreeDeque::new();
+        assert_eq!(deque.len_exact(), 0);
+        assert!(deque.push_back(1).is_ok());
+        assert!(deque.push_front(0).is_ok());
+        assert_eq!(deque.len_exact(), 2);
+
+        // Unwritten batch slots and removed items leave tombstones counted by `len` only
+        let mut batch = deque.begin_batch(3).unwrap();
+        assert_eq!(deque.len_exact(), 5);
+        assert!(batch.push(2).is_ok());
+        batch.commit();
+        assert_eq!((deque.len(), deque.len_exact()), (5, 3));
+        deque.begin_batch(1).unwrap().abort();
+        assert_eq!((deque.len(), deque.len_exact()), (6, 3));
+        assert_eq!(deque.retain(|&item| item != 1), 1);
+        assert_eq!((deque.len(), deque.len_exact()), (6, 2));
+
+        // Discarding the tombstones doesn't change the count
+        assert_eq!(deque.pop_front(), Some(0));
+        assert_eq!(deque.pop_front(), Some(2));
+        assert_eq!(deque.pop_front(), None);
+        assert_eq!((deque.len(), deque.len_exact()), (0, 0));
+
+        let other: LockFreeDeque<i32, 8> = LockFreeDeque::new();
+        assert!(other.push_back(3).is_ok());
+        unsafe { deque.swap_contents(&other) };
+        assert_eq!((deque.len_exact(), other.len_exact()), (1, 0));
+        unsafe { deque.fast_clear() };
+        assert_eq!(deque.len_exact(), 0);
+    }
+
+    #[test]
+    fn test_views() {
+        let deque: LockFreeDeque<i32, 8> = LockFreeDeque::new();
+        for view in [deque.back_view(), deque.front_view()] {
+            for i in 0..7 {
+                assert!(view.push(i).is_ok());
+            }
+            assert_eq!(view.push(7), Err(7));
+            for i in 0..7 {
+                assert_eq!(view.pop(), Some(i));
             }
+            assert_eq!(view.pop(), None);
+        }
 
-            producer1.join().unwrap();
-            producer2.join().unwrap();
-            producer3.join().unwrap();
+        // Each view pushes at its own end
+        deque.back_view().push(1).unwrap();
+        deque.front_view().push(0).unwrap();
+        assert_eq!(deque.pop_front(), Some(0));
+        assert_eq!(deque.pop_back(), Some(1));
+        assert!(deque.back_view().deque().is_empty());
+    }
 
-            let s = consumer.join().unwrap();
-            sum += s;
-            assert_eq!(sum, (0..(3 * pad)).sum());
+    #[test]
+    fn test_append_from() {
+        let src: LockFreeDeque<i32, 8> = LockFreeDeque::new();
+        let dst: LockFreeDeque<i32, 4> = LockFreeDeque::new();
+        for i in 0..3 {
+            assert!(src.push_back(i).is_ok());
         }
+        assert!(dst.push_back(-1).is_ok());
+
+        // Only 2 of the 3 items fit
+        assert_eq!(dst.append_from(&src), 2);
+        assert_eq!(dst.pop_front(), Some(-1));
+        assert_eq!(dst.pop_front(), Some(0));
+        assert_eq!(dst.pop_front(), Some(1));
+        assert_eq!(dst.pop_front(), None);
+        assert_eq!(src.len(), 1);
+        assert_eq!(src.pop_front(), Some(2));
+
+        // Nothing to move
+        assert_eq!(dst.append_from(&src), 0);
     }
 
     #[test]
-    fn test_push_pop() {
-        const WORKERS_PER_QUEUE: usize = 16;
-        const DATA_PER_WORKER: usize = 128;
+    fn test_peek_with() {
+        let deque: LockFreeDeque<i32, 8> = LockFreeDeque::new();
+        assert_eq!(deque.peek_front_with(|x| *x), None);
+        assert_eq!(deque.peek_back_with(|x| *x), None);
+        for i in 0..3 {
+            assert!(deque.push_back(i).is_ok());
+        }
+        assert_eq!(deque.peek_front_with(|x| *x), Some(0));
+        assert_eq!(deque.peek_back_with(|x| *x), Some(2));
+        assert_eq!(deque.len(), 3);
 
-        let mut handles = vec::Vec::new();
-        let queue = Arc::new(LockFreeDeque::<usize, 4097>::new());
+        // Cancelled slots are discarded first
+        deque.begin_batch(2).unwrap().abort();
+        assert_eq!(deque.peek_back_with(|x| *x), Some(2));
+        assert_eq!(deque.len(), 3);
+    }
 
-        for worker_id in 0..WORKERS_PER_QUEUE {
-            let queue_c = queue.clone();
-            // let data_num_c = data_num.clone();
-            let handle = std::thread::spawn(move || {
-                for i in 0..DATA_PER_WORKER {
-                    queue_c.push_front(i).expect(
-                        std::format!("Failed to push data in worker {}, iter {}", worker_id, i)
-                            .as_str(),
-                    );
-                    // data_num_c.fetch_add(1, Ordering::AcqRel);
-                }
-                for i in 0..DATA_PER_WORKER {
-                    // let data_num = data_num_c.fetch_sub(1, Ordering::AcqRel);
-                    // if data_num < 0 {
-                    //     println!("data_num < 0 in queue {}, worker {}", queue_id, worker_id);
-                    //     while data_num_c.load(Ordering::Acquire) < 0 {}
-                    // }
-                    queue_c.pop_back().expect(
-                        std::format!("Failed to pop data in worker {}, iter {}", worker_id, i)
-                            .as_str(),
-                    );
-                    // let data = pop(queue_id).expect(
-                    //     std::format!(
-                    //         "Failed to pop data in queue {}, worker {}",
-                    //         queue_id,
-                    //         worker_id
-                    //     )
-                    //     .as_str(),
-                    // );
-                    // assert!(data.msg_type == 0);
-                }
+    #[cfg(not(feature = "single-thread"))]
+    #[test]
+    fn test_peek_guard() {
+        let deque: LockFreeDeque<i32, 8> = LockFreeDeque::new();
+        assert!(deque.peek_front().is_none());
+        assert!(deque.peek_back().is_none());
+        for i in 0..3 {
+            assert!(deque.push_back(i).is_ok());
+        }
+
+        // A pop on another thread can't take the peeked item until the guard is dropped
+        let guard = deque.peek_front().unwrap();
+        assert_eq!(*guard, 0);
+        thread::scope(|s| {
+            let popper = s.spawn(|| deque.pop_front());
+            thread::sleep(std::time::Duration::from_millis(50));
+            assert!(!popper.is_finished());
+            // The bounded pop and another peek give up instead of waiting
+            assert_eq!(deque.pop_front_bounded(4), Err(Contended));
+            assert!(deque.peek_front().is_none());
+            // The other end is still available
+            assert_eq!(*deque.peek_back().unwrap(), 2);
+            drop(guard);
+            assert_eq!(popper.join().unwrap(), Some(0));
+        });
+        assert_eq!(deque.len(), 2);
+
+        // A slot being written is not waited for
+        let deque: LockFreeDeque<i32, 8> = LockFreeDeque::new();
+        let mut guard = deque.push_slot_back().unwrap();
+        assert!(deque.peek_back().is_none());
+        guard.write(1);
+        drop(guard);
+        assert_eq!(*deque.peek_back().unwrap(), 1);
+    }
+
+    #[test]
+    fn test_relaxed() {
+        let deque: LockFreeDeque<i32, 4> = LockFreeDeque::new();
+        assert!(deque.is_empty_relaxed());
+        assert_eq!(deque.len_relaxed(), 0);
+        assert_eq!(deque.peek_front_relaxed(), None);
+
+        assert!(deque.push_back(1).is_ok());
+        assert!(deque.push_front(0).is_ok());
+        assert!(deque.push_back(2).is_ok());
+        assert!(!deque.is_empty_relaxed());
+        assert_eq!(deque.len_relaxed(), 3);
+        assert_eq!(deque.peek_front_relaxed(), Some(0));
+        // Peeking leaves the item in place
+        assert_eq!(deque.pop_front(), Some(0));
+        assert_eq!(deque.peek_front_relaxed(), Some(1));
+
+        // A slot being written is not waited for
+        let guard = deque.push_slot_front().unwrap();
+        assert_eq!(deque.len_relaxed(), 3);
+        assert_eq!(deque.peek_front_relaxed(), None);
+        drop(guard);
+    }
+
+    #[cfg(all(feature = "debug-history", not(feature = "single-thread")))]
+    #[test]
+    fn test_pop_front_debug() {
+        let deque: LockFreeDeque<i32, 8> = LockFreeDeque::new();
+        assert_eq!(deque.pop_front_debug(), (None, false));
+        assert!(deque.push_back(1).is_ok());
+        assert_eq!(deque.pop_front_debug(), (Some(1), false));
+
+        // A reserved batch keeps the front slot in WRITING state until committed
+        let mut batch = deque.begin_batch(1).unwrap();
+        thread::scope(|s| {
+            let popper = s.spawn(|| deque.pop_front_debug());
+            thread::sleep(std::time::Duration::from_millis(50));
+            batch.push(2).unwrap();
+            batch.commit();
+            assert_eq!(popper.join().unwrap(), (Some(2), true));
+        });
+    }
+
+    #[test]
+    fn test_push_back_policy() {
+        let deque: LockFreeDeque<i32, 4> = LockFreeDeque::new();
+        for i in 0..3 {
+            assert_eq!(
+                deque.push_back_policy(i, FullPolicy::Fail),
+                PushOutcome::Pushed
+            );
+        }
+
+        // Fail
+        assert_eq!(
+            deque.push_back_policy(3, FullPolicy::Fail),
+            PushOutcome::Full(3)
+        );
+        assert_eq!(deque.len(), 3);
+
+        // Overwrite evicts the front
+        assert_eq!(
+            deque.push_back_policy(3, FullPolicy::Overwrite),
+            PushOutcome::Evicted(0)
+        );
+        assert_eq!(
+            deque.push_back_policy(4, FullPolicy::Overwrite),
+            PushOutcome::Evicted(1)
+        );
+        assert_eq!(deque.len(), 3);
+
+        // BlockSpin waits for a consumer
+        #[cfg(feature = "single-thread")]
+        assert_eq!(deque.pop_front(), Some(2));
+        #[cfg(feature = "single-thread")]
+        assert_eq!(
+            deque.push_back_policy(5, FullPolicy::BlockSpin),
+            PushOutcome::Pushed
+        );
+        #[cfg(not(feature = "single-thread"))]
+        std::thread::scope(|s| {
+            s.spawn(|| {
+                std::thread::sleep(std::time::Duration::from_millis(10));
+                assert_eq!(deque.pop_front(), Some(2));
             });
-            handles.push(handle);
+            assert_eq!(
+                deque.push_back_policy(5, FullPolicy::BlockSpin),
+                PushOutcome::Pushed
+            );
+        });
+        for i in 3..6 {
+            assert_eq!(deque.pop_front(), Some(i));
+        }
+        assert!(deque.is_empty());
+    }
+
+    #[cfg(not(feature = "single-thread"))]
+    #[test]
+    fn test_blocking_abort() {
+        let deque: LockFreeDeque<i32, 2> = LockFreeDeque::new();
+        let abort = AtomicBool::new(false);
+        assert_eq!(deque.push_back_blocking(0, &abort), Ok(()));
+        thread::scope(|s| {
+            s.spawn(|| {
+                thread::sleep(std::time::Duration::from_millis(10));
+                abort.store(true, Ordering::Release);
+            });
+            // Blocked on the full deque until aborted
+            assert_eq!(deque.push_back_blocking(1, &abort), Err(Aborted(1)));
+        });
+
+        abort.store(false, Ordering::Relaxed);
+        assert_eq!(deque.pop_front_blocking(&abort), Ok(0));
+        thread::scope(|s| {
+            s.spawn(|| {
+                thread::sleep(std::time::Duration::from_millis(10));
+                abort.store(true, Ordering::Release);
+            });
+            // Blocked on the empty deque until aborted
+            assert_eq!(deque.pop_front_blocking(&abort), Err(Aborted(())));
+        });
+    }
+
+    #[test]
+    fn test_scan_leaked_writing() {
+        #[cfg(all(feature = "std", debug_assertions))]
+        let outstanding = SlotGuard::<i32>::outstanding();
+        let deque: LockFreeDeque<i32, 8> = LockFreeDeque::new();
+        assert_eq!(deque.scan_leaked_writing(3), 0);
+
+        let mut guard = deque.push_slot_back().unwrap();
+        guard.write(1);
+        drop(guard);
+        let mut guard = deque.push_slot_back().unwrap();
+        guard.write(2);
+        #[cfg(all(feature = "std", debug_assertions))]
+        assert_eq!(SlotGuard::<i32>::outstanding(), outstanding + 1);
+        core::mem::forget(guard);
+        assert_eq!(deque.scan_leaked_writing(3), 1);
+        #[cfg(all(feature = "std", debug_assertions))]
+        assert_eq!(SlotGuard::<i32>::outstanding(), outstanding + 1);
+
+        // Recover the slot so that dropping the deque terminates
+        assert_eq!(deque.pop_front(), Some(1));
+        deque.buffer[1].state.store(SLOT_READY, Ordering::Release);
+        assert_eq!(deque.scan_leaked_writing(3), 0);
+        assert_eq!(deque.pop_front(), Some(2));
+    }
+
+    #[test]
+    fn test_reserve_back() {
+        let deque: LockFreeDeque<i32, 6> = LockFreeDeque::new();
+        // Make the reservation wrap around the end of the buffer
+        for i in 0..4 {
+            assert!(deque.push_back(i).is_ok());
+            assert_eq!(deque.pop_front(), Some(i));
+        }
+        assert!(deque.push_back(-1).is_ok());
+        assert!(deque.reserve_back(5).is_err());
+
+        let mut slots = deque.reserve_back(4).unwrap();
+        assert_eq!(slots.len(), 4);
+        assert_eq!(
+            (0..4)
+                .map(|i| slots.buffer_index(i))
+                .collect::<std::vec::Vec<_>>(),
+            [5, 0, 1, 2]
+        );
+        assert!(deque.is_full());
+        // Fill in any order
+        for i in [2, 0, 3, 1] {
+            unsafe { slots.as_mut_ptr(i).write(i as i32 * 10) };
+        }
+        // Not visible before publishing
+        assert_eq!(deque.pop_front(), Some(-1));
+        assert_eq!(deque.pop_front_bounded(10), Err(Contended));
+        unsafe { slots.publish() };
+        for i in 0..4 {
+            assert_eq!(deque.pop_front(), Some(i * 10));
+        }
+
+        // Cancelled slots are skipped
+        let mut slots = deque.reserve_back(2).unwrap();
+        slots.slot_mut(0).write(1);
+        slots.cancel();
+        assert!(deque.push_back(2).is_ok());
+        assert_eq!(deque.pop_front(), Some(2));
+        assert!(deque.is_empty());
+    }
+
+    #[test]
+    fn test_count_where() {
+        let deque: LockFreeDeque<i32, 8> = LockFreeDeque::new();
+        assert_eq!(deque.count_where(|_| true), 0);
+        // Wrap around the buffer
+        for i in 0..6 {
+            assert!(deque.push_back(i).is_ok());
+            assert_eq!(deque.pop_front(), Some(i));
         }
+        for i in 0..5 {
+            assert!(deque.push_back(i).is_ok());
+        }
+        assert!(deque.push_front(10).is_ok());
+
+        assert_eq!(deque.count_where(|&item| item % 2 == 0), 4);
+        assert_eq!(deque.count_where(|&item| item > 100), 0);
+        // Nothing was consumed
+        assert_eq!(deque.len(), 6);
+        assert_eq!(deque.pop_front(), Some(10));
+        assert_eq!(deque.count_where(|_| true), 5);
     }
 }
@@ -0,0 +1,134 @@
+//! A queue that never fails a push, for std-based tests and userspace tools.
+//!
+//! Requires the `std` feature: it is not `no_std`, and not meant for the vDSO or kernel path.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+use crate::LockFreeDeque;
+
+/// A FIFO queue wrapping a `LockFreeDeque`, spilling into a heap-backed `VecDeque` when the ring is full
+///
+/// While nothing is spilled, `push` and `pop` only use the lock-free ring. Once the ring is full,
+/// pushed items go to the spill queue behind a mutex, and so do all later pushes until it is empty again,
+/// so that they can't overtake the spilled ones. Pops move spilled items back into the ring as space frees.
+pub struct GrowableQueue<T, const CAPACITY: usize> {
+    ring: LockFreeDeque<T, CAPACITY>,
+    spill: Mutex<VecDeque<T>>,
+    /// Number of items in `spill`, read without the lock on the fast path
+    spilled: AtomicUsize,
+}
+
+impl<T, const CAPACITY: usize> GrowableQueue<T, CAPACITY> {
+    /// Create an empty queue
+    pub const fn new() -> Self {
+        Self {
+            ring: LockFreeDeque::new(),
+            spill: Mutex::new(VecDeque::new()),
+            spilled: AtomicUsize::new(0),
+        }
+    }
+
+    /// Push an item to the back of the queue, spilling it to the heap if the ring is full
+    pub fn push(&self, item: T) {
+        let item = if self.spilled.load(Ordering::Acquire) == 0 {
+            match self.ring.push_back(item) {
+                Ok(()) => return,
+                Err(item) => item,
+            }
+        } else {
+            item
+        };
+        let mut spill = self.spill.lock().unwrap();
+        // The spill queue may have been drained meanwhile, then the ring may have room again
+        let item = if spill.is_empty() {
+            match self.ring.push_back(item) {
+                Ok(()) => return,
+                Err(item) => item,
+            }
+        } else {
+            item
+        };
+        spill.push_back(item);
+        self.spilled.store(spill.len(), Ordering::Release);
+    }
+
+    /// Pop the item at the front of the queue
+    /// Returns None if the queue is empty
+    pub fn pop(&self) -> Option<T> {
+        if let Some(item) = self.ring.pop_front() {
+            self.refill();
+            return Some(item);
+        }
+        if self.spilled.load(Ordering::Acquire) == 0 {
+            return None;
+        }
+        self.refill();
+        self.ring.pop_front()
+    }
+
+    /// Get the number of items in the queue (approximate in concurrent scenarios)
+    pub fn len(&self) -> usize {
+        self.ring.len() + self.spilled.load(Ordering::Acquire)
+    }
+
+    /// Check if the queue is empty (approximate in concurrent scenarios)
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Get the number of items currently spilled to the heap
+    pub fn spilled(&self) -> usize {
+        self.spilled.load(Ordering::Acquire)
+    }
+
+    /// Move spilled items back into the ring, as many as fit
+    fn refill(&self) {
+        if self.spilled.load(Ordering::Acquire) == 0 {
+            return;
+        }
+        let mut spill = self.spill.lock().unwrap();
+        while let Some(item) = spill.pop_front() {
+            if let Err(item) = self.ring.push_back(item) {
+                spill.push_front(item);
+                break;
+            }
+        }
+        self.spilled.store(spill.len(), Ordering::Release);
+    }
+}
+
+impl<T, const CAPACITY: usize> Default for GrowableQueue<T, CAPACITY> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::GrowableQueue;
+
+    #[test]
+    fn test_growable() {
+        let queue: GrowableQueue<i32, 4> = GrowableQueue::new();
+        assert_eq!(queue.pop(), None);
+        for i in 0..10 {
+            queue.push(i);
+        }
+        assert_eq!(queue.len(), 10);
+        assert_eq!(queue.spilled(), 7);
+
+        // Interleave pushes while the spill queue is being drained
+        for i in 0..5 {
+            assert_eq!(queue.pop(), Some(i));
+        }
+        queue.push(10);
+        for i in 5..11 {
+            assert_eq!(queue.pop(), Some(i));
+        }
+        assert_eq!(queue.pop(), None);
+        assert!(queue.is_empty());
+        assert_eq!(queue.spilled(), 0);
+    }
+}
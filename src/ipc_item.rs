@@ -17,3 +17,244 @@ pub struct IPCItem {
     /// 消息数据
     pub data: [u64; 8],
 }
+
+/// `IPCItem`序列化后的字节数
+pub const IPC_ITEM_BYTES: usize = 11 * 8;
+
+impl IPCItem {
+    /// 按`sender`、`msg_type`、`rep_type`、`data`的顺序，将各字段以小端序序列化，与本机字节序无关。
+    ///
+    /// 队列本身按本机字节序存储消息。字节序不同的处理器（例如异构SoC）通过共享内存或其他通道交换消息时，
+    /// 应使用本函数与`from_le_bytes`（或大端序版本）显式转换。
+    pub fn to_le_bytes(self) -> [u8; IPC_ITEM_BYTES] {
+        self.to_bytes(u64::to_le_bytes)
+    }
+
+    /// 从`to_le_bytes`的结果反序列化
+    pub fn from_le_bytes(bytes: [u8; IPC_ITEM_BYTES]) -> Self {
+        Self::from_bytes(bytes, u64::from_le_bytes)
+    }
+
+    /// 按`sender`、`msg_type`、`rep_type`、`data`的顺序，将各字段以大端序序列化，与本机字节序无关。
+    pub fn to_be_bytes(self) -> [u8; IPC_ITEM_BYTES] {
+        self.to_bytes(u64::to_be_bytes)
+    }
+
+    /// 从`to_be_bytes`的结果反序列化
+    pub fn from_be_bytes(bytes: [u8; IPC_ITEM_BYTES]) -> Self {
+        Self::from_bytes(bytes, u64::from_be_bytes)
+    }
+
+    /// 以本机字节序直接读取消息的内存表示，不复制，与逐字以`u64::to_ne_bytes`序列化的结果相同。
+    ///
+    /// 例如用于计算校验和，或复制到只在同一主机上读取的缓冲区。跨主机交换消息时应使用`to_le_bytes`等函数。
+    ///
+    /// 依赖`IPCItem`没有填充（见本文件末尾的常量断言）：填充字节的值未定义，若存在填充，读取它们是未定义行为。
+    pub fn as_bytes(&self) -> &[u8; IPC_ITEM_BYTES] {
+        // Safe because `IPCItem` is `#[repr(C)]` with exactly `IPC_ITEM_BYTES` bytes and no padding,
+        // so every byte is initialized, and `u8` has no alignment requirement
+        unsafe { &*(self as *const Self as *const [u8; IPC_ITEM_BYTES]) }
+    }
+
+    /// 以`f`逐字序列化
+    fn to_bytes(self, f: fn(u64) -> [u8; 8]) -> [u8; IPC_ITEM_BYTES] {
+        let mut bytes = [0; IPC_ITEM_BYTES];
+        let words = [self.sender, self.msg_type, self.rep_type]
+            .into_iter()
+            .chain(self.data);
+        for (chunk, word) in bytes.chunks_exact_mut(8).zip(words) {
+            chunk.copy_from_slice(&f(word));
+        }
+        bytes
+    }
+
+    /// 以`f`逐字反序列化
+    fn from_bytes(bytes: [u8; IPC_ITEM_BYTES], f: fn([u8; 8]) -> u64) -> Self {
+        let mut words = bytes
+            .chunks_exact(8)
+            .map(|chunk| f(chunk.try_into().unwrap()));
+        let mut next = || words.next().unwrap();
+        Self {
+            sender: next(),
+            msg_type: next(),
+            rep_type: next(),
+            data: core::array::from_fn(|_| next()),
+        }
+    }
+}
+
+/// 所有队列共享的全局序号计数器，每条推入的消息从中分配`QueuedItem::global_seq`，见`pop_with_global_seq`
+///
+/// 计数器是本crate的静态变量，因此只对同一地址空间中的推入者是全局的。所有推入者都对它做`fetch_add`，
+/// 多核并发推入时它的缓存行在核之间来回传递，即使各自推入不同的队列，也会相互拖慢，
+/// 这是它放在`global-seq` feature之后的原因。
+#[cfg(feature = "global-seq")]
+static GLOBAL_SEQ: core::sync::atomic::AtomicU64 = core::sync::atomic::AtomicU64::new(0);
+
+/// 队列中实际存储的消息，附带推入时分配的序号
+///
+/// 布局：`item`（88字节）、`seq`（8字节），启用`global-seq` feature时其后为`global_seq`（8字节），
+/// 启用`checksum` feature时最后为`checksum`（8字节）。
+/// 启用与否的各种构建布局不同，`ABI_VERSION`随之不同，不能共享同一队列。
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct QueuedItem {
+    /// 消息本身
+    pub(crate) item: IPCItem,
+    /// 推入时从队列的序号计数器（`PerProcess::seq`）分配的序号
+    pub(crate) seq: u64,
+    /// 推入时从全局计数器（`GLOBAL_SEQ`）分配的序号，在所有队列之间单调递增
+    #[cfg(feature = "global-seq")]
+    pub(crate) global_seq: u64,
+    /// `item`与`seq`的校验和，推入时计算，`pop_checked`弹出时校验，见`checksum`
+    #[cfg(feature = "checksum")]
+    checksum: u64,
+}
+
+impl QueuedItem {
+    /// 以推入时分配的序号包装一条消息，启用`checksum` feature时同时计算校验和，启用`global-seq` feature时同时分配全局序号
+    pub(crate) fn new(item: IPCItem, seq: u64) -> Self {
+        Self {
+            item,
+            seq,
+            // A single atomic has a total modification order, so Relaxed is enough for stamps to increase
+            // along every happens-before chain of pushes
+            #[cfg(feature = "global-seq")]
+            global_seq: GLOBAL_SEQ.fetch_add(1, core::sync::atomic::Ordering::Relaxed),
+            #[cfg(feature = "checksum")]
+            checksum: checksum(&item, seq),
+        }
+    }
+
+    /// 检查消息在队列中是否被破坏，即校验和是否与内容一致
+    #[cfg(feature = "checksum")]
+    pub(crate) fn is_intact(&self) -> bool {
+        self.checksum == checksum(&self.item, self.seq)
+    }
+}
+
+/// 消息各字与序号的校验和：第i个字循环左移i位后异或
+///
+/// 移位使交换两个字的破坏也能被检测到。只用于发现程序错误导致的破坏，不能防范恶意篡改。
+#[cfg(feature = "checksum")]
+fn checksum(item: &IPCItem, seq: u64) -> u64 {
+    [item.sender, item.msg_type, item.rep_type, seq]
+        .into_iter()
+        .chain(item.data)
+        .enumerate()
+        .fold(0, |acc, (i, word)| acc ^ word.rotate_left(i as u32))
+}
+
+/// `pop_checked`等弹出的消息校验和不一致：消息在队列中被破坏，其中为破坏后的内容
+///
+/// 该消息已从队列中移除。需要启用`checksum` feature。
+#[cfg(feature = "checksum")]
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct Corrupted(pub IPCItem);
+
+/// `IPCItem`在`extern "C"`接口（如`deque_push`、`deque_pop`）中按值传递，其布局是C ABI的一部分：
+/// `#[repr(C)]`，且为11个`u64`，没有填充。
+///
+/// 若`IPCItem`今后改为泛型（如负载长度可变），FFI接口仍须使用满足该断言的具体类型。
+///
+/// 没有填充同时是`as_bytes`的安全前提：其字节视图中的每个字节都必须已初始化。
+/// 因此大小须恰为各字段大小之和，今后即使调整字段顺序或插入更小的字段（如`u32`），也不能悄悄引入填充。
+const _: () = {
+    use core::mem::size_of;
+    assert!(
+        size_of::<IPCItem>()
+            == size_of::<u64>() + size_of::<u64>() + size_of::<u64>() + size_of::<[u64; 8]>()
+    );
+    assert!(size_of::<IPCItem>() == 11 * size_of::<u64>());
+    assert!(size_of::<IPCItem>() == IPC_ITEM_BYTES);
+    assert!(core::mem::align_of::<IPCItem>() == core::mem::align_of::<u64>());
+};
+
+#[cfg(test)]
+mod tests {
+    use super::{IPC_ITEM_BYTES, IPCItem};
+    use core::mem::{offset_of, size_of};
+
+    fn sample() -> IPCItem {
+        IPCItem {
+            sender: 0x0102_0304_0506_0708,
+            msg_type: 1,
+            rep_type: u64::MAX - 1,
+            data: core::array::from_fn(|i| (i as u64) << 56 | 0xab),
+        }
+    }
+
+    fn words(item: &IPCItem) -> [u64; 11] {
+        let mut words = [
+            item.sender,
+            item.msg_type,
+            item.rep_type,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+        ];
+        words[3..].copy_from_slice(&item.data);
+        words
+    }
+
+    #[test]
+    fn test_byte_order() {
+        let item = sample();
+        let le = item.to_le_bytes();
+        let be = item.to_be_bytes();
+        // The least significant byte of `sender` comes first in little-endian, last in big-endian
+        assert_eq!(le[..8], [8, 7, 6, 5, 4, 3, 2, 1]);
+        assert_eq!(be[..8], [1, 2, 3, 4, 5, 6, 7, 8]);
+        // Each word of one is the byte-swapped word of the other, whatever the host byte order
+        for (le, be) in le.chunks_exact(8).zip(be.chunks_exact(8)) {
+            let mut swapped = [0; 8];
+            swapped.copy_from_slice(le);
+            swapped.reverse();
+            assert_eq!(swapped, be);
+        }
+
+        assert_eq!(words(&IPCItem::from_le_bytes(le)), words(&item));
+        assert_eq!(words(&IPCItem::from_be_bytes(be)), words(&item));
+
+        // Reading bytes written by a host of the other byte order yields byte-swapped words
+        let mut swapped = [0; IPC_ITEM_BYTES];
+        for (dst, src) in swapped.chunks_exact_mut(8).zip(le.chunks_exact(8)) {
+            dst.copy_from_slice(src);
+            dst.reverse();
+        }
+        let misread = IPCItem::from_le_bytes(swapped);
+        assert_eq!(words(&misread).map(u64::swap_bytes), words(&item));
+    }
+
+    #[test]
+    fn test_as_bytes() {
+        let item = sample();
+        let bytes = item.as_bytes();
+        for (chunk, word) in bytes.chunks_exact(8).zip(words(&item)) {
+            assert_eq!(chunk, word.to_ne_bytes());
+        }
+        if cfg!(target_endian = "little") {
+            assert_eq!(*bytes, item.to_le_bytes());
+        } else {
+            assert_eq!(*bytes, item.to_be_bytes());
+        }
+    }
+
+    #[test]
+    fn test_ffi_layout() {
+        // The FFI functions take and return exactly `IPCItem`
+        let _push: extern "C" fn(usize, IPCItem) -> Result<(), IPCItem> = crate::deque_push;
+        let _pop: extern "C" fn(usize) -> Option<IPCItem> = crate::deque_pop;
+
+        assert_eq!(size_of::<IPCItem>(), 88);
+        assert_eq!(offset_of!(IPCItem, sender), 0);
+        assert_eq!(offset_of!(IPCItem, msg_type), 8);
+        assert_eq!(offset_of!(IPCItem, rep_type), 16);
+        assert_eq!(offset_of!(IPCItem, data), 24);
+    }
+}
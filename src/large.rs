@@ -0,0 +1,243 @@
+//! 长消息：超出单个`IPCItem`负载（8个字）的消息，拆分为多个连续的分片传递。
+//!
+//! 每个分片是一个`IPCItem`：
+//!
+//! - `sender`：整条消息的总字数
+//! - `msg_type`：除最后一个分片外，最高位（`MSG_CONTINUED`）置位，表示后面还有分片
+//! - `rep_type`：分片序号，从0开始
+//! - `data`：消息中的对应8个字，最后一个分片不足的部分填0
+//!
+//! 各分片的序号（见`pop_with_seq`）连续。
+
+use core::sync::atomic::Ordering;
+
+use crate::{Discipline, IPCItem, PerProcess, QueuedItem, config::PUSH_FRONT};
+
+/// 长消息分片的`msg_type`最高位，表示该分片之后还有同一条消息的分片
+pub const MSG_CONTINUED: u64 = 1 << 63;
+
+/// 每个分片携带的字数
+const WORDS_PER_ITEM: usize = 8;
+
+impl PerProcess {
+    /// 推入一条长消息。
+    ///
+    /// 所有分片在`deque_push`推入的一端一次性预留连续的槽位（`LockFreeDeque::begin_batch_front`或`begin_batch`），写入后再发布，
+    /// 因此即使有其他生产者并发推入，分片也保持连续。发布从最后一个分片开始（见`Batch::commit`），
+    /// 消费者看到首个分片时，其余分片都已可见。
+    ///
+    /// 队列空间不足、或队列不是先进先出时失败。不受`OverflowPolicy`影响。
+    pub(crate) fn push_large(&self, data: &[u64]) -> Result<(), ()> {
+        if self.config.discipline != Discipline::Fifo {
+            return Err(());
+        }
+        let count = data.len().div_ceil(WORDS_PER_ITEM).max(1);
+        let mut batch = match PUSH_FRONT {
+            true => self.deque.begin_batch_front(count)?,
+            // `push-back-default` requires `deque-back`
+            #[cfg(feature = "deque-back")]
+            false => self.deque.begin_batch(count)?,
+            #[cfg(not(feature = "deque-back"))]
+            false => return Err(()),
+        };
+        let seq = self.seq.fetch_add(count as u64, Ordering::Relaxed);
+        for index in 0..count {
+            let chunk = data
+                .get(index * WORDS_PER_ITEM..)
+                .unwrap_or_default()
+                .iter()
+                .take(WORDS_PER_ITEM);
+            let mut item = IPCItem {
+                sender: data.len() as u64,
+                msg_type: if index + 1 < count { MSG_CONTINUED } else { 0 },
+                rep_type: index as u64,
+                data: [0; WORDS_PER_ITEM],
+            };
+            item.data
+                .iter_mut()
+                .zip(chunk)
+                .for_each(|(dst, src)| *dst = *src);
+            // The batch has exactly `count` slots
+            let _ = batch.push(QueuedItem::new(item, seq.wrapping_add(index as u64)));
+        }
+        batch.commit();
+        Ok(())
+    }
+
+    /// 弹出一条长消息写入`out`，返回消息的总字数。队列为空时返回`Ok(None)`。
+    ///
+    /// `out`长度不足时，超出的部分被丢弃，此时返回值大于`out.len()`。
+    /// 长消息只适用于单消费者：多个消费者并发弹出时，同一条消息的分片可能被不同消费者取走，
+    /// 此时返回`LargeError`，见其各个变体。长消息不经过优先通道与溢出队列，因此只从`deque`弹出。
+    ///
+    /// 首个分片可见时，其余分片已全部发布（见`Batch::commit`）。后续分片尚未出现（队列为空）时继续等待，
+    /// 直到最后一个分片到达，或队列中出现了其他消息。
+    pub(crate) fn pop_large(&self, out: &mut [u64]) -> Result<Option<usize>, LargeError> {
+        let Some(first) = self.pop_(!PUSH_FRONT) else {
+            return Ok(None);
+        };
+        let mut item = first.item;
+        if item.rep_type != 0 {
+            return Err(LargeError::Orphan);
+        }
+        let total = item.sender as usize;
+        let mut offset = 0;
+        loop {
+            let len = WORDS_PER_ITEM.min(total.saturating_sub(offset));
+            if let Some(dst) = out.get_mut(offset..) {
+                dst.iter_mut()
+                    .zip(&item.data[..len])
+                    .for_each(|(dst, src)| *dst = *src);
+            }
+            offset += len;
+            if item.msg_type & MSG_CONTINUED == 0 {
+                return Ok(Some(total));
+            }
+            let index = item.rep_type + 1;
+            // Only take the next item if it continues this message, otherwise leave it for the next pop
+            let continues = |next: &QueuedItem| {
+                next.item.rep_type == index && next.item.sender as usize == total
+            };
+            item = loop {
+                let next = match PUSH_FRONT {
+                    true => self.deque.peek_back_with(continues),
+                    false => self.deque.peek_front_with(continues),
+                };
+                match next {
+                    Some(true) => {
+                        if let Some(next) = self.pop_(!PUSH_FRONT) {
+                            break next.item;
+                        }
+                    }
+                    Some(false) => {
+                        return Err(LargeError::Truncated { received: offset });
+                    }
+                    // Not arrived yet, or taken by another consumer: wait for the next item to tell
+                    None => {}
+                }
+                core::hint::spin_loop();
+            };
+        }
+    }
+}
+
+/// `pop_large`未能弹出完整的长消息，FFI对应的返回值见`POP_LARGE_ORPHAN`与`POP_LARGE_TRUNCATED`
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum LargeError {
+    /// 弹出的消息不是长消息的首个分片（`rep_type`不为0）：所属消息的开头已被其他消费者取走。
+    ///
+    /// 该分片已从队列中移除并被丢弃。
+    Orphan,
+    /// 长消息在中途中断：下一条消息不是其后续分片，剩余的分片已被其他消费者取走。
+    ///
+    /// 下一条消息留在队列中。
+    Truncated {
+        /// 已收到并写入`out`的字数
+        received: usize,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::{LargeError, MSG_CONTINUED};
+    use crate::{IPCItem, PerProcess};
+
+    fn fragment(total: u64, index: u64, last: bool) -> IPCItem {
+        IPCItem {
+            sender: total,
+            msg_type: if last { 0 } else { MSG_CONTINUED },
+            rep_type: index,
+            data: [index; 8],
+        }
+    }
+
+    #[test]
+    fn test_large_message() {
+        let queue = PerProcess::default();
+        let small = IPCItem {
+            sender: 1,
+            msg_type: 2,
+            rep_type: 3,
+            data: [4; 8],
+        };
+        let data: [u64; 20] = core::array::from_fn(|i| i as u64 * 3);
+
+        assert!(queue.push(small).is_ok());
+        assert!(queue.push_large(&data).is_ok());
+        assert!(queue.push(small).is_ok());
+        // 1 + 3 fragments + 1
+        assert_eq!(queue.deque.len(), 5);
+
+        assert_eq!(queue.pop().unwrap().msg_type, 2);
+        let mut out = [0u64; 24];
+        assert!(matches!(queue.pop_large(&mut out), Ok(Some(20))));
+        assert_eq!(out[..20], data);
+        assert_eq!(out[20..], [0; 4]);
+        assert_eq!(queue.pop().unwrap().msg_type, 2);
+        assert!(queue.pop().is_none());
+
+        // Too small output buffer
+        assert!(queue.push_large(&data).is_ok());
+        let mut out = [0u64; 10];
+        assert!(matches!(queue.pop_large(&mut out), Ok(Some(20))));
+        assert_eq!(out, data[..10]);
+        assert!(matches!(queue.pop_large(&mut out), Ok(None)));
+
+        // Empty message
+        assert!(queue.push_large(&[]).is_ok());
+        let item = queue.pop().unwrap();
+        assert_eq!((item.sender, item.msg_type & MSG_CONTINUED), (0, 0));
+    }
+
+    #[test]
+    fn test_large_broken() {
+        let queue = PerProcess::default();
+        let mut out = [0u64; 24];
+
+        // The first fragment was taken by another consumer
+        let data = [7u64; 20];
+        assert!(queue.push_large(&data).is_ok());
+        assert_eq!(queue.pop().unwrap().rep_type, 0);
+        for _ in 0..2 {
+            assert!(matches!(queue.pop_large(&mut out), Err(LargeError::Orphan)));
+        }
+        assert!(matches!(queue.pop_large(&mut out), Ok(None)));
+
+        // The remaining fragments were taken, and another message follows
+        assert!(queue.push(fragment(20, 0, false)).is_ok());
+        assert!(queue.push(fragment(3, 0, true)).is_ok());
+        assert!(matches!(
+            queue.pop_large(&mut out),
+            Err(LargeError::Truncated { received: 8 })
+        ));
+        assert_eq!(out[..8], [0; 8]);
+        // The following message is left in the queue
+        assert!(matches!(queue.pop_large(&mut out), Ok(Some(3))));
+        assert!(queue.pop().is_none());
+    }
+
+    #[cfg(not(feature = "single-thread"))]
+    #[test]
+    fn test_large_wait() {
+        let queue = PerProcess::default();
+        // Only the first fragment has arrived: wait for the rest instead of returning a partial message
+        assert!(queue.push(fragment(20, 0, false)).is_ok());
+        std::thread::scope(|s| {
+            let consumer = s.spawn(|| {
+                let mut out = [0u64; 20];
+                let res = queue.pop_large(&mut out);
+                (res, out)
+            });
+            std::thread::sleep(std::time::Duration::from_millis(50));
+            assert!(!consumer.is_finished());
+            assert!(queue.push(fragment(20, 1, false)).is_ok());
+            assert!(queue.push(fragment(20, 2, true)).is_ok());
+            let (res, out) = consumer.join().unwrap();
+            assert!(matches!(res, Ok(Some(20))));
+            assert_eq!(out[8..16], [1; 8]);
+            assert_eq!(out[16..], [2; 4]);
+        });
+    }
+}
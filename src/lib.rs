@@ -4,39 +4,134 @@
 #![no_std]
 #![deny(missing_docs)]
 
-use core::sync::atomic::{AtomicU64, AtomicUsize};
+#[cfg(any(feature = "std", test))]
+extern crate std;
+
+#[cfg(all(feature = "static-array", not(feature = "vdso")))]
+use core::cell::UnsafeCell;
+use core::sync::atomic::{AtomicPtr, AtomicU8, AtomicU64, AtomicUsize};
 #[cfg(not(feature = "vdso"))]
-use core::{mem::MaybeUninit, ptr::NonNull, sync::atomic::AtomicPtr};
+use core::{mem::MaybeUninit, ptr::NonNull, sync::atomic::Ordering};
 
 #[cfg(not(feature = "vdso"))]
 use lazyinit::LazyInit;
 
 use crate::slot_array::SlotArray;
 
+mod abi;
+pub use abi::{ABI_VERSION, VqueueConstants, vqueue_abi_version, vqueue_constants};
 mod api;
 pub use api::*;
+mod backoff;
+pub use backoff::{Backoff, CycleBudgetBackoff, SpinBackoff, WriterLimit};
+mod channel;
+pub use channel::{Receiver, Sender, channel};
+mod config;
+pub use config::{
+    Discipline, NO_MONITOR, NO_OVERFLOW_QUEUE, NonemptyHook, OverflowPolicy, QueueConfig, WakeToken,
+};
 mod deque;
-pub use deque::{LockFreeDeque, SlotGuard};
+#[cfg(all(feature = "deque-front", feature = "deque-back"))]
+pub use deque::QueueView;
+#[cfg(feature = "deque-back")]
+pub use deque::ReservedSlots;
+#[cfg(feature = "deque-slot")]
+pub use deque::SlotGuard;
+#[cfg(feature = "debug-history")]
+pub use deque::SlotOp;
+pub use deque::{
+    Aborted, Batch, Contended, DedupWindow, FullPolicy, LockFreeDeque, PeekGuard, Poisoned,
+    PushError, PushOutcome, WRITE_PATIENCE,
+};
+#[cfg(all(feature = "std", feature = "deque-front", feature = "deque-back"))]
+mod growable;
+#[cfg(all(feature = "std", feature = "deque-front", feature = "deque-back"))]
+pub use growable::GrowableQueue;
 mod ipc_item;
-pub use ipc_item::IPCItem;
+#[cfg(feature = "checksum")]
+pub use ipc_item::Corrupted;
+use ipc_item::QueuedItem;
+pub use ipc_item::{IPC_ITEM_BYTES, IPCItem};
+mod large;
+use large::LargeError;
+pub use large::MSG_CONTINUED;
+#[cfg(feature = "metrics")]
+mod metrics;
+mod mpmc;
+pub use mpmc::MpmcQueue;
 mod slot_array;
-pub use slot_array::SlotRef;
+pub use slot_array::{ID_INDEX_BITS, SendSlotRef, SlotRef};
+#[cfg(all(feature = "deque-front", feature = "deque-back"))]
+mod stress;
+#[cfg(all(feature = "deque-front", feature = "deque-back"))]
+pub use stress::{StressTally, stress_mpmc};
+mod spsc;
+pub use spsc::{
+    SpscConsumer, SpscProducer, SpscRing, StatelessConsumer, StatelessDeque, StatelessProducer,
+};
+mod sync;
+pub use sync::{OrderingProfile, Profile, Relaxed, Strong};
+#[cfg(feature = "trace")]
+mod trace;
+#[cfg(feature = "trace")]
+pub use trace::{TraceCallback, TraceEvent, TraceOp};
+#[cfg(feature = "timing")]
+mod timing;
+#[cfg(feature = "timing")]
+pub use timing::{
+    DWELL_BUCKETS, DwellHistogram, reset_dwell_histogram, set_dwell_clock, state_dwell_histogram,
+};
 
 vdso_helper::use_mut_cfg! {}
 /// 队列占用的空间，为队列长度加1，以区分满和空的情况
 pub const QUEUE_CAPACITY: usize = QUEUE_LEN + 1;
+/// 优先通道占用的空间，为优先通道的队列长度加1
+pub const PRIORITY_QUEUE_CAPACITY: usize = PRIORITY_QUEUE_LEN + 1;
 
 /// 每个进程的IPC数据结构
 #[derive(Default)]
 pub struct PerProcess {
     /// IPC消息的接收队列
-    deque: LockFreeDeque<IPCItem, QUEUE_CAPACITY>,
+    deque: LockFreeDeque<QueuedItem, QUEUE_CAPACITY>,
+    /// 优先通道：其中的消息总是先于`deque`中的消息弹出，见`push_priority`
+    priority: LockFreeDeque<QueuedItem, PRIORITY_QUEUE_CAPACITY>,
+    /// 下一条推入消息的序号，用于消费者检测重复处理的消息
+    seq: AtomicU64,
     /// 进程id，用于通知机制
     pid: AtomicUsize,
     /// 从msg_type（调度器协程id）到ntf_id（通知源id，例如信号编号）的映射
     ///
     /// 若登记的msg_type为USIZE_MAX，则查找时视为任何msg_type均对应到这一项
     map: SlotArray<(usize, usize), ARRAY_LEN>,
+    /// 注册时指定的队列配置
+    config: QueueConfig,
+    /// 消费者发布的滞后程度，供生产者参考是否减速，见`set_lag_level`
+    lag_level: AtomicU8,
+    /// 以`register_queue_named`注册时，名字的哈希值；0表示未命名，`NAME_RESERVED`位表示仍在预留中，见`register_queue_named`
+    name: AtomicU64,
+    /// 队列由空变为非空时调用的`NonemptyHook`，为空指针时表示未设置，见`set_nonempty_hook`
+    nonempty_hook: AtomicPtr<()>,
+    /// 调用`nonempty_hook`时传入的队列id
+    nonempty_hook_id: AtomicUsize,
+    /// 消费者登记的`WakeToken`的函数指针，为空指针时表示未登记，见`SlotRef::set_waker`
+    waker: AtomicPtr<()>,
+    /// 消费者登记的`WakeToken`的数据指针
+    waker_data: AtomicPtr<()>,
+    /// 保护`waker`与`waker_data`的序列锁版本号，修改期间为奇数
+    waker_version: AtomicUsize,
+    /// 队列的指标计数器
+    #[cfg(feature = "metrics")]
+    metrics: metrics::Metrics,
+}
+
+impl PerProcess {
+    /// 使用给定的配置创建
+    fn with_config(config: QueueConfig) -> Self {
+        Self {
+            config,
+            ..Default::default()
+        }
+    }
 }
 
 // 存放于vDSO中的全局数据结构，包含每个进程的IPC数据结构数组
@@ -50,22 +145,158 @@ vdso_helper::vvar_data! {
 static QUEUE_ARRAY_ADDR: LazyInit<usize> = LazyInit::new();
 
 #[cfg(not(feature = "vdso"))]
-pub const QUEUE_ARRAY_SIZE: usize = core::mem::size_of::<SlotArray<PerProcess, ARRAY_LEN>>();
+/// 队列数组所在区域占用的字节数（包括区域开头的`QUEUE_ARRAY_MAGIC`），供`set_queue_array_addr`的调用者分配空间
+pub const QUEUE_ARRAY_SIZE: usize = core::mem::size_of::<QueueRegion>();
+
+#[cfg(not(feature = "vdso"))]
+/// 队列数组区域初始化完成的标志，见`QueueRegion`
+pub const QUEUE_ARRAY_MAGIC: u64 = 0x7671_7565_7565_0001;
+
+/// `set_queue_array_addr`系列函数所用区域的布局：开头是一个标志字，之后是队列数组。
+///
+/// 多个进程映射同一块区域时，初始化与映射之间需要同步：初始化方（`set_queue_array_addr_and_init`）先将标志字清零，
+/// 再写入队列数组，最后以Release存入`QUEUE_ARRAY_MAGIC`；映射方（`set_queue_array_addr`）以Acquire自旋读取标志字，
+/// 直到读到`QUEUE_ARRAY_MAGIC`才开始使用数组，因此一定能看到初始化方写入的完整内容，即使它先于初始化方映射了区域。
+///
+/// 先清零是为了让映射方不会把上一次运行留在持久共享内存中的标志字当作本次初始化已完成。
+/// 区域未初始化时标志字是任意内容，恰好等于`QUEUE_ARRAY_MAGIC`的概率可以忽略。
+#[cfg(not(feature = "vdso"))]
+#[repr(C)]
+struct QueueRegion {
+    magic: AtomicU64,
+    array: SlotArray<PerProcess, ARRAY_LEN>,
+}
+
+#[cfg(not(feature = "vdso"))]
+/// 在`region`处初始化队列数组，并以`QUEUE_ARRAY_MAGIC`发布，见`QueueRegion`
+///
+/// # Safety
+///
+/// `region`须对齐，且指向可写的`QUEUE_ARRAY_SIZE`字节，初始化期间不能有其他进程初始化或使用同一区域。
+unsafe fn init_queue_region(region: *mut QueueRegion) {
+    // Any bit pattern is a valid `AtomicU64`, so the word can be accessed before it is initialized
+    let magic = unsafe { &*(&raw const (*region).magic) };
+    magic.store(0, Ordering::Relaxed);
+    unsafe { (&raw mut (*region).array).write(SlotArray::new()) };
+    magic.store(QUEUE_ARRAY_MAGIC, Ordering::Release);
+}
+
+#[cfg(not(feature = "vdso"))]
+/// 等待`region`处的队列数组由初始化方发布，见`QueueRegion`
+///
+/// # Safety
+///
+/// `region`须对齐，且指向可读的`QUEUE_ARRAY_SIZE`字节。
+unsafe fn wait_queue_region(region: *const QueueRegion) {
+    let magic = unsafe { &*(&raw const (*region).magic) };
+    while magic.load(Ordering::Acquire) != QUEUE_ARRAY_MAGIC {
+        core::hint::spin_loop();
+    }
+}
 
 /// Set the address of the queue array.
 ///
+/// Waits until the region is published by `set_queue_array_addr_and_init` (possibly from another process
+/// mapping the same memory), spinning on the magic word at its start, see `QUEUE_ARRAY_MAGIC`.
+/// A process mapping the region may thus start before the initializer is done; it spins until some process
+/// initializes the region.
+///
 /// # Safety
 ///
-/// The address must refer to a `SlotArray<PerProcess, ARRAY_LEN>` that is already initialized,
-/// and be valid for the lifetime of the program.
+/// The address must refer to a region of `QUEUE_ARRAY_SIZE` bytes that is, or will be, initialized
+/// by `set_queue_array_addr_and_init`, and be valid for the lifetime of the program.
 ///
 /// Before calling other functions, `set_queue_array_addr` or `set_queue_array_addr_and_init`
 /// must be called once and only once.
+///
+/// Panics if the address is not aligned for the array.
 #[cfg(not(feature = "vdso"))]
 pub unsafe fn set_queue_array_addr(addr: NonNull<()>) {
+    check_queue_array_region(addr, None);
+    unsafe { wait_queue_region(addr.as_ptr().cast()) };
     QUEUE_ARRAY_ADDR.init_once(addr.as_ptr() as usize);
 }
 
+/// Check that a region can hold the queue array: the address must be aligned for the array,
+/// and the length, if known, at least `QUEUE_ARRAY_SIZE`. Panics with a message naming the problem otherwise.
+///
+/// Misaligned atomics are undefined behavior, so this is checked even in release builds.
+#[cfg(not(feature = "vdso"))]
+fn check_queue_array_region(addr: NonNull<()>, len: Option<usize>) {
+    let align = core::mem::align_of::<QueueRegion>();
+    assert!(
+        addr.cast::<QueueRegion>().as_ptr().is_aligned(),
+        "queue array address {:p} is not aligned to {} bytes",
+        addr,
+        align
+    );
+    if let Some(len) = len {
+        assert!(
+            len >= QUEUE_ARRAY_SIZE,
+            "queue array region of {} bytes is smaller than QUEUE_ARRAY_SIZE ({} bytes)",
+            len,
+            QUEUE_ARRAY_SIZE
+        );
+    }
+}
+
+/// Problem found by `validate_queue_array` in a queue array region
+#[cfg(not(feature = "vdso"))]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ValidationError {
+    /// The address is not aligned for the array
+    Misaligned,
+    /// The magic word at the start of the region is not `QUEUE_ARRAY_MAGIC`: the region was never initialized
+    /// by `set_queue_array_addr_and_init`, or the address doesn't point at its start
+    NotInitialized,
+    /// The state byte of the slot at `index` is not one of the valid states
+    BadSlotState {
+        /// Index of the slot
+        index: usize,
+        /// The state byte found
+        state: u8,
+    },
+    /// The reference count of the slot at `index` doesn't match its state: a free slot must have none,
+    /// a slot in use at least one
+    BadRefCount {
+        /// Index of the slot
+        index: usize,
+        /// The state byte found
+        state: u8,
+        /// The reference count found
+        rc: u8,
+    },
+}
+
+/// Sanity-check the queue array region at the given address before trusting it, for example before
+/// `set_queue_array_addr`, to catch a wrongly mapped or uninitialized region early with a clear error
+/// instead of undefined behavior later.
+///
+/// Checks that the address is aligned, that the magic word is `QUEUE_ARRAY_MAGIC`, and that every slot
+/// of the array has a valid state byte with a reference count consistent with it, stopping at the first problem.
+///
+/// The checks are heuristic: they read only the slot headers, never the queues in the slots, which may still
+/// be corrupted, and a region passing them is not proven valid. A region that is in use may transiently fail
+/// the reference count check (a slot is published before its count is set), so this is meant for bring-up,
+/// before processes start registering.
+///
+/// # Safety
+///
+/// The address must point to `QUEUE_ARRAY_SIZE` readable and initialized bytes. Any content is fine.
+#[cfg(not(feature = "vdso"))]
+pub unsafe fn validate_queue_array(addr: NonNull<()>) -> Result<(), ValidationError> {
+    let region = addr.cast::<QueueRegion>();
+    if !region.as_ptr().is_aligned() {
+        return Err(ValidationError::Misaligned);
+    }
+    // Safe because every field of the region is made of atomics and `MaybeUninit`s, valid for any bit pattern
+    let region = unsafe { region.as_ref() };
+    if region.magic.load(Ordering::Acquire) != QUEUE_ARRAY_MAGIC {
+        return Err(ValidationError::NotInitialized);
+    }
+    region.array.validate()
+}
+
 #[cfg(not(feature = "vdso"))]
 /// Initialize the queue array at the given address.
 ///
@@ -75,12 +306,68 @@ pub unsafe fn set_queue_array_addr(addr: NonNull<()>) {
 ///
 /// Before calling other functions, `set_queue_array_addr` or `set_queue_array_addr_and_init`
 /// must be called once and only once.
+///
+/// Panics if the address is not aligned for the array, see `set_queue_array_region_and_init`
+/// to also check the size of the region.
+///
+/// The magic word at the start of the region is written last, with Release ordering, so that processes
+/// waiting in `set_queue_array_addr` see the whole array, see `QUEUE_ARRAY_MAGIC`.
 pub unsafe fn set_queue_array_addr_and_init(addr: NonNull<()>) {
+    check_queue_array_region(addr, None);
     QUEUE_ARRAY_ADDR.init_once(addr.as_ptr() as usize);
-    unsafe {
-        ((*QUEUE_ARRAY_ADDR.get().unwrap()) as *mut () as *mut SlotArray<PerProcess, ARRAY_LEN>)
-            .write(SlotArray::new())
-    };
+    unsafe { init_queue_region(addr.as_ptr().cast()) };
+}
+
+/// `static-array`下队列数组的存储空间，其静态变量`QUEUE_ARRAY`由build.rs生成，以便带上指定的`#[link_section]`
+#[cfg(all(feature = "static-array", not(feature = "vdso")))]
+struct StaticQueueArray(UnsafeCell<MaybeUninit<QueueRegion>>);
+
+// 只通过`get_queue_array`得到共享引用，初始化前不会访问
+#[cfg(all(feature = "static-array", not(feature = "vdso")))]
+unsafe impl Sync for StaticQueueArray {}
+
+#[cfg(all(feature = "static-array", not(feature = "vdso")))]
+include!(concat!(env!("OUT_DIR"), "/static_array.rs"));
+
+#[cfg(all(feature = "static-array", not(feature = "vdso")))]
+/// 初始化crate内的静态队列数组并使用它，代替`set_queue_array_addr_and_init`，无需另行提供地址。
+///
+/// 数组所在的段在构建时由环境变量`VQUEUE_ARRAY_SECTION`指定，未设置时为`.bss.vqueue_array`（并入`.bss`）。
+/// 若指定`.noinit`等不由加载器清零的段，数组的内容在此处才被初始化。
+///
+/// 与`set_queue_array_addr`系列函数一样，在调用其他函数前必须调用且只能调用一次（第二次调用会panic）。
+/// 数组位于多方共享的内存段、且已由另一方初始化时，改用`attach_static_queue_array`。
+pub fn init_static_queue_array() {
+    // Safe because the static lives for the whole program, and `init_once` panics before a second write
+    unsafe { set_queue_array_addr_and_init(NonNull::from(&QUEUE_ARRAY.0).cast()) }
+}
+
+#[cfg(all(feature = "static-array", not(feature = "vdso")))]
+/// 使用crate内的静态队列数组，但不初始化其内容，代替`set_queue_array_addr`。
+///
+/// # Safety
+///
+/// 数组必须已经初始化，例如位于共享内存段，且已由另一方调用了`init_static_queue_array`。
+///
+/// 在调用其他函数前必须调用且只能调用一次。
+pub unsafe fn attach_static_queue_array() {
+    unsafe { set_queue_array_addr(NonNull::from(&QUEUE_ARRAY.0).cast()) }
+}
+
+#[cfg(not(feature = "vdso"))]
+/// Initialize the queue array in the region of `len` bytes at the given address.
+///
+/// Same as `set_queue_array_addr_and_init`, but also panics if `len` is less than `QUEUE_ARRAY_SIZE`,
+/// so that a region computed by the integrator is validated before anything is written to it.
+///
+/// # Safety
+///
+/// The region must be valid for the lifetime of the program.
+///
+/// Before calling other functions, one of the `set_queue_array_*` functions must be called once and only once.
+pub unsafe fn set_queue_array_region_and_init(addr: NonNull<()>, len: usize) {
+    check_queue_array_region(addr, Some(len));
+    unsafe { set_queue_array_addr_and_init(addr) };
 }
 
 pub(crate) fn get_queue_array() -> &'static SlotArray<PerProcess, ARRAY_LEN> {
@@ -93,10 +380,10 @@ pub(crate) fn get_queue_array() -> &'static SlotArray<PerProcess, ARRAY_LEN> {
     #[cfg(not(feature = "vdso"))]
     {
         unsafe {
-            &*((*QUEUE_ARRAY_ADDR.get().expect(
+            &(*((*QUEUE_ARRAY_ADDR.get().expect(
                 "QUEUE_ARRAY_ADDR is not initialized. Please call `set_queue_array_addr` or `set_queue_array_addr_and_init` first.",
-            )) as *const ()
-                as *const SlotArray<PerProcess, ARRAY_LEN>)
+            )) as *const QueueRegion))
+                .array
         }
     }
 }
@@ -118,3 +405,137 @@ mod test_mut_cfg {
         // println!("FLOAT_TEST: {}", FLOAT_TEST);
     }
 }
+
+#[cfg(all(test, feature = "static-array", not(feature = "vdso")))]
+mod test_static_array {
+    extern crate std;
+
+    #[test]
+    fn test_static_array() {
+        // The array is built on the stack before being written into the static
+        std::thread::Builder::new()
+            .stack_size(2 * super::QUEUE_ARRAY_SIZE + (16 << 20))
+            .spawn(|| {
+                super::init_static_queue_array();
+                assert_eq!(super::get_queue_array() as *const _, unsafe {
+                    &raw const (*super::QUEUE_ARRAY.0.get().cast::<super::QueueRegion>()).array
+                });
+                let queue = crate::register_process().unwrap();
+                let item = crate::IPCItem {
+                    sender: 1,
+                    msg_type: 2,
+                    rep_type: 3,
+                    data: [4; 8],
+                };
+                assert!(queue.push(item).is_ok());
+                assert_eq!(queue.pop().map(|item| item.msg_type), Some(2));
+            })
+            .unwrap()
+            .join()
+            .unwrap();
+    }
+}
+
+#[cfg(all(test, not(feature = "vdso")))]
+mod test_queue_array_region {
+    use super::{ARRAY_LEN, PerProcess, QUEUE_ARRAY_SIZE, QueueRegion, check_queue_array_region};
+    use core::ptr::NonNull;
+
+    fn aligned() -> NonNull<()> {
+        NonNull::<QueueRegion>::dangling().cast()
+    }
+
+    #[test]
+    fn test_aligned() {
+        check_queue_array_region(aligned(), None);
+        check_queue_array_region(aligned(), Some(QUEUE_ARRAY_SIZE));
+    }
+
+    #[test]
+    fn test_late_init() {
+        use super::{QueueRegion, init_queue_region, wait_queue_region};
+        use std::alloc::{Layout, alloc, dealloc};
+
+        let layout = Layout::new::<QueueRegion>();
+        let region = unsafe { alloc(layout) } as *mut QueueRegion;
+        assert!(!region.is_null());
+        // Garbage, as in freshly mapped memory left over by a previous run
+        unsafe { region.cast::<u8>().write_bytes(0xa5, layout.size()) };
+        let addr = region as usize;
+        std::thread::scope(|s| {
+            // The mapper starts first, and must wait for the initializer
+            let mapper = std::thread::Builder::new()
+                .stack_size(16 << 20)
+                .spawn_scoped(s, move || {
+                    let region = addr as *const QueueRegion;
+                    unsafe { wait_queue_region(region) };
+                    let array = unsafe { &(*region).array };
+                    assert!((0..ARRAY_LEN).all(|i| array.try_ref(i).is_none()));
+                    array.push(PerProcess::default()).is_ok()
+                })
+                .unwrap();
+            std::thread::Builder::new()
+                .stack_size(2 * QUEUE_ARRAY_SIZE + (16 << 20))
+                .spawn_scoped(s, move || unsafe {
+                    init_queue_region(addr as *mut QueueRegion)
+                })
+                .unwrap();
+            assert!(mapper.join().unwrap());
+        });
+        unsafe { dealloc(region.cast(), layout) };
+    }
+
+    #[test]
+    fn test_validate() {
+        use super::{QUEUE_ARRAY_MAGIC, ValidationError, init_queue_region, validate_queue_array};
+        use core::sync::atomic::Ordering;
+        use std::alloc::{Layout, alloc, dealloc};
+
+        let layout = Layout::new::<QueueRegion>();
+        let region = unsafe { alloc(layout) } as *mut QueueRegion;
+        let addr = NonNull::new(region).unwrap().cast();
+        let region_addr = region as usize;
+        unsafe { region.cast::<u8>().write_bytes(0xa5, layout.size()) };
+        assert_eq!(
+            unsafe { validate_queue_array(addr) },
+            Err(ValidationError::NotInitialized)
+        );
+
+        // Garbage behind a magic word, as a region mapped at the wrong offset could look
+        unsafe { (*region).magic.store(QUEUE_ARRAY_MAGIC, Ordering::Relaxed) };
+        assert_eq!(
+            unsafe { validate_queue_array(addr) },
+            Err(ValidationError::BadSlotState {
+                index: 0,
+                state: 0xa5
+            })
+        );
+        let misaligned = unsafe { addr.cast::<u8>().add(1) }.cast();
+        assert_eq!(
+            unsafe { validate_queue_array(misaligned) },
+            Err(ValidationError::Misaligned)
+        );
+
+        std::thread::Builder::new()
+            .stack_size(2 * QUEUE_ARRAY_SIZE + (16 << 20))
+            .spawn(move || unsafe { init_queue_region(region_addr as *mut QueueRegion) })
+            .unwrap()
+            .join()
+            .unwrap();
+        assert_eq!(unsafe { validate_queue_array(addr) }, Ok(()));
+        unsafe { dealloc(region.cast(), layout) };
+    }
+
+    #[test]
+    #[should_panic(expected = "is not aligned")]
+    fn test_misaligned() {
+        let misaligned = unsafe { aligned().cast::<u8>().add(1) }.cast();
+        check_queue_array_region(misaligned, None);
+    }
+
+    #[test]
+    #[should_panic(expected = "smaller than QUEUE_ARRAY_SIZE")]
+    fn test_region_too_small() {
+        check_queue_array_region(aligned(), Some(QUEUE_ARRAY_SIZE - 1));
+    }
+}
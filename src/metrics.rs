@@ -0,0 +1,170 @@
+//! 队列压力（pressure）指标，供调度器据此做路由决策。
+//!
+//! 压力是一个0-255的字节，由两部分相加得到：
+//! - 占用部分：`len * 192 / capacity`，范围0-192，反映队列的填充程度；
+//! - 竞争部分：衰减后的重试计数，截断到63，反映最近推入/弹出时的竞争程度。
+//!
+//! 重试计数只在注册时指定了`QueueConfig::metrics`的队列上统计，每次推入/弹出失败一次CAS或遇到正在读写的槽位时加1。
+//! 为使其反映“最近”的竞争，每进行`DECAY_PERIOD`次操作就将计数减半，开销只有一次原子加法。
+//!
+//! 此外还统计成功推入的消息总数，供调用者估计消息速率（见`PerProcess::rate_since`）。
+//! crate本身没有时钟，因此只维护单调递增的计数，时间由调用者提供。
+
+use core::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+
+use crate::{Backoff, PerProcess, SpinBackoff};
+
+/// 每进行这么多次操作，重试计数减半
+const DECAY_PERIOD: u32 = 64;
+/// 占用部分的最大值
+const LEN_WEIGHT: usize = 192;
+/// 竞争部分的最大值
+const RETRY_MAX: u32 = 255 - LEN_WEIGHT as u32;
+
+/// 一个队列的指标计数器
+#[derive(Default)]
+pub(crate) struct Metrics {
+    /// 衰减后的重试计数
+    retries: AtomicU32,
+    /// 推入/弹出操作计数，用于决定何时衰减
+    ops: AtomicU32,
+    /// 成功推入的消息总数，只增不减（回绕）
+    pushes: AtomicU64,
+}
+
+impl Metrics {
+    /// 记录一次推入/弹出操作，每`DECAY_PERIOD`次将重试计数减半
+    pub(crate) fn record_op(&self) {
+        if self.ops.fetch_add(1, Ordering::Relaxed) % DECAY_PERIOD == DECAY_PERIOD - 1 {
+            let _ = self
+                .retries
+                .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |retries| {
+                    Some(retries / 2)
+                });
+        }
+    }
+}
+
+impl Metrics {
+    /// 记录一次成功的推入
+    pub(crate) fn record_push(&self) {
+        self.pushes.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// 作为退避策略传给队列操作，以统计重试次数
+impl Backoff for Metrics {
+    fn backoff(&self, spins: u32) {
+        self.retries.fetch_add(1, Ordering::Relaxed);
+        SpinBackoff.backoff(spins);
+    }
+}
+
+impl PerProcess {
+    /// 估计队列的压力，见模块文档。
+    ///
+    /// 未启用`QueueConfig::metrics`的队列只有占用部分。
+    pub(crate) fn pressure(&self) -> u8 {
+        let len_part = self.deque.len() * LEN_WEIGHT / self.deque.capacity();
+        let retry_part = self.metrics.retries.load(Ordering::Relaxed).min(RETRY_MAX);
+        (len_part as u32 + retry_part) as u8
+    }
+
+    /// 成功推入该队列的消息总数，只在注册时指定了`QueueConfig::metrics`的队列上统计，否则总是0。
+    ///
+    /// 用作`rate_since`的快照：记下此值与当时的时间，之后以二者调用`rate_since`。
+    pub fn push_count(&self) -> u64 {
+        self.metrics.pushes.load(Ordering::Relaxed)
+    }
+
+    /// 估计自快照以来的推入速率：每个时间单位（tick）推入的消息数，向下取整。
+    ///
+    /// `last_count`为之前某一时刻`push_count`的返回值，`elapsed_ticks`为自那一时刻经过的时间，单位由调用者决定
+    /// （如周期数、毫秒）。计数回绕也能正确计算差值。`elapsed_ticks`为0时返回0。
+    pub fn rate_since(&self, last_count: u64, elapsed_ticks: u64) -> u64 {
+        self.push_count()
+            .wrapping_sub(last_count)
+            .checked_div(elapsed_ticks)
+            .unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use super::{DECAY_PERIOD, RETRY_MAX};
+    use crate::{Backoff, IPCItem, PerProcess, QUEUE_LEN, QueueConfig};
+
+    const ITEM: IPCItem = IPCItem {
+        sender: 0,
+        msg_type: 0,
+        rep_type: 0,
+        data: [0; 8],
+    };
+
+    #[test]
+    fn test_pressure() {
+        // `PerProcess` is too large for the default test thread stack
+        std::thread::Builder::new()
+            .stack_size(16 << 20)
+            .spawn(|| {
+                let queue = PerProcess::with_config(QueueConfig {
+                    metrics: true,
+                    ..Default::default()
+                });
+                assert_eq!(queue.pressure(), 0);
+
+                for _ in 0..QUEUE_LEN / 2 {
+                    assert!(queue.push(ITEM).is_ok());
+                }
+                let half = queue.pressure();
+                assert!((95..=96).contains(&half));
+
+                for _ in 0..1000 {
+                    queue.metrics.backoff(0);
+                }
+                assert_eq!(queue.pressure() as u32, half as u32 + RETRY_MAX);
+
+                // 持续操作后重试计数衰减到0
+                for _ in 0..DECAY_PERIOD * 16 {
+                    assert!(queue.pop().is_some());
+                    assert!(queue.push(ITEM).is_ok());
+                }
+                assert_eq!(queue.pressure(), half);
+            })
+            .unwrap()
+            .join()
+            .unwrap();
+    }
+
+    #[test]
+    fn test_rate_since() {
+        // `PerProcess` is too large for the default test thread stack
+        std::thread::Builder::new()
+            .stack_size(16 << 20)
+            .spawn(|| {
+                let queue = PerProcess::with_config(QueueConfig {
+                    metrics: true,
+                    ..Default::default()
+                });
+                let snapshot = queue.push_count();
+                for _ in 0..100 {
+                    assert!(queue.push(ITEM).is_ok());
+                }
+                // Pops don't count
+                assert!(queue.pop().is_some());
+                assert_eq!(queue.push_count(), snapshot + 100);
+                assert_eq!(queue.rate_since(snapshot, 20), 5);
+                assert_eq!(queue.rate_since(snapshot, 30), 3);
+                assert_eq!(queue.rate_since(snapshot, 0), 0);
+
+                // Not counted without `QueueConfig::metrics`
+                let queue = PerProcess::default();
+                assert!(queue.push(ITEM).is_ok());
+                assert_eq!(queue.push_count(), 0);
+            })
+            .unwrap()
+            .join()
+            .unwrap();
+    }
+}
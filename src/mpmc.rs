@@ -0,0 +1,258 @@
+//! A bounded multi-producer multi-consumer FIFO queue, after Dmitry Vyukov's sequence-number ring.
+//!
+//! `LockFreeDeque` supports both ends, at the cost of a claim-then-move-index protocol that is known to
+//! break down when the deque is full with producers at both ends. When only FIFO is needed, this queue
+//! is a simpler alternative with a well-known correctness argument: each slot carries a sequence number
+//! telling which lap of the ring it is ready for, so a producer or consumer only ever moves its own
+//! position, with a single CAS, after checking that the slot at that position is ready for it.
+//! A full or empty queue is detected from the sequence number alone, never from comparing the two positions,
+//! so a full queue is just another state: pushes fail until a pop frees a slot.
+//! Positions are moved with a CAS rather than `fetch_add`, so that a push to a full queue (or a pop from an empty one)
+//! fails without consuming a position.
+//!
+//! For slot `i` at lap `k` (position `pos = k * CAPACITY + i`), the sequence number is:
+//! - `pos`: empty, ready for the producer at `pos`
+//! - `pos + 1`: written, ready for the consumer at `pos`
+//! - `pos + CAPACITY`: read, i.e. empty and ready for the producer of the next lap
+
+use core::cell::UnsafeCell;
+use core::mem::MaybeUninit;
+use core::sync::atomic::Ordering;
+
+use crate::spsc::CacheAligned;
+use crate::sync::AtomicUsize;
+
+struct Cell<T> {
+    sequence: AtomicUsize,
+    data: UnsafeCell<MaybeUninit<T>>,
+}
+
+/// A fixed-capacity FIFO queue for any number of producers and consumers, see the module documentation
+///
+/// `CAPACITY` must be a power of two, so that positions keep mapping to the same slots when they wrap around.
+/// Unlike `LockFreeDeque`, all `CAPACITY` slots can hold an item.
+pub struct MpmcQueue<T, const CAPACITY: usize> {
+    buffer: [Cell<T>; CAPACITY],
+    /// Position of the next slot to push to
+    enqueue_pos: CacheAligned<AtomicUsize>,
+    /// Position of the next slot to pop from
+    dequeue_pos: CacheAligned<AtomicUsize>,
+}
+
+impl<T, const CAPACITY: usize> MpmcQueue<T, CAPACITY> {
+    /// Create an empty queue with compile-time capacity
+    pub fn new() -> Self {
+        const {
+            assert!(
+                CAPACITY.is_power_of_two(),
+                "MpmcQueue: CAPACITY must be a power of two"
+            )
+        };
+        Self {
+            buffer: core::array::from_fn(|i| Cell {
+                sequence: AtomicUsize::new(i),
+                data: UnsafeCell::new(MaybeUninit::uninit()),
+            }),
+            enqueue_pos: CacheAligned(AtomicUsize::new(0)),
+            dequeue_pos: CacheAligned(AtomicUsize::new(0)),
+        }
+    }
+
+    /// Get the capacity of the queue, `CAPACITY`
+    pub const fn capacity(&self) -> usize {
+        CAPACITY
+    }
+
+    /// Get the number of items in the queue (approximate in concurrent scenarios)
+    ///
+    /// Counts the slots claimed by producers, including ones still being written.
+    pub fn len(&self) -> usize {
+        let dequeue = self.dequeue_pos.0.load(Ordering::Acquire);
+        let enqueue = self.enqueue_pos.0.load(Ordering::Acquire);
+        enqueue.wrapping_sub(dequeue).min(CAPACITY)
+    }
+
+    /// Check if the queue is empty (approximate in concurrent scenarios)
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Push an item to the back of the queue
+    /// Returns Err(item) if the queue is full
+    pub fn enqueue(&self, item: T) -> Result<(), T> {
+        let mut pos = self.enqueue_pos.0.load(Ordering::Relaxed);
+        let cell = loop {
+            let cell = &self.buffer[pos % CAPACITY];
+            let sequence = cell.sequence.load(Ordering::Acquire);
+            match (sequence as isize).wrapping_sub(pos as isize) {
+                // Empty for this lap, try to claim it
+                0 => match self.enqueue_pos.0.compare_exchange_weak(
+                    pos,
+                    pos.wrapping_add(1),
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => break cell,
+                    Err(actual) => pos = actual,
+                },
+                // Still holds the item of the previous lap: full
+                diff if diff < 0 => return Err(item),
+                // Another producer claimed it, catch up
+                _ => pos = self.enqueue_pos.0.load(Ordering::Relaxed),
+            }
+        };
+        // Safe because the CAS gave this producer the slot, which no consumer reads before the store below
+        unsafe { (*cell.data.get()).write(item) };
+        cell.sequence.store(pos.wrapping_add(1), Ordering::Release);
+        Ok(())
+    }
+
+    /// Pop the item at the front of the queue
+    /// Returns None if the queue is empty
+    pub fn dequeue(&self) -> Option<T> {
+        let mut pos = self.dequeue_pos.0.load(Ordering::Relaxed);
+        let cell = loop {
+            let cell = &self.buffer[pos % CAPACITY];
+            let sequence = cell.sequence.load(Ordering::Acquire);
+            match (sequence as isize).wrapping_sub(pos.wrapping_add(1) as isize) {
+                // Written for this lap, try to claim it
+                0 => match self.dequeue_pos.0.compare_exchange_weak(
+                    pos,
+                    pos.wrapping_add(1),
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => break cell,
+                    Err(actual) => pos = actual,
+                },
+                // Not written yet: empty
+                diff if diff < 0 => return None,
+                // Another consumer claimed it, catch up
+                _ => pos = self.dequeue_pos.0.load(Ordering::Relaxed),
+            }
+        };
+        // Safe because the CAS gave this consumer the slot, which the producer published before
+        let item = unsafe { (*cell.data.get()).assume_init_read() };
+        cell.sequence
+            .store(pos.wrapping_add(CAPACITY), Ordering::Release);
+        Some(item)
+    }
+}
+
+impl<T, const CAPACITY: usize> Default for MpmcQueue<T, CAPACITY> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T, const CAPACITY: usize> Drop for MpmcQueue<T, CAPACITY> {
+    fn drop(&mut self) {
+        while self.dequeue().is_some() {}
+    }
+}
+
+// Safety: each item is accessed by the one producer, then the one consumer, that claimed its slot
+unsafe impl<T: Send, const CAPACITY: usize> Send for MpmcQueue<T, CAPACITY> {}
+unsafe impl<T: Send, const CAPACITY: usize> Sync for MpmcQueue<T, CAPACITY> {}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use super::MpmcQueue;
+
+    #[test]
+    fn test_wraparound() {
+        let queue: MpmcQueue<u32, 4> = MpmcQueue::new();
+        assert_eq!(queue.dequeue(), None);
+        let mut next = 0;
+        let mut expected = 0;
+        for round in 0..20 {
+            let batch = 3 + round % 2;
+            for _ in 0..batch {
+                assert!(queue.enqueue(next).is_ok());
+                next += 1;
+            }
+            if batch == 4 {
+                assert_eq!(queue.enqueue(next), Err(next));
+            }
+            assert_eq!(queue.len(), batch as usize);
+            for _ in 0..batch {
+                assert_eq!(queue.dequeue(), Some(expected));
+                expected += 1;
+            }
+            assert!(queue.is_empty());
+        }
+    }
+
+    #[test]
+    fn test_drop() {
+        use std::rc::Rc;
+
+        let item = Rc::new(());
+        let queue: MpmcQueue<Rc<()>, 4> = MpmcQueue::new();
+        for _ in 0..3 {
+            assert!(queue.enqueue(item.clone()).is_ok());
+        }
+        drop(queue.dequeue());
+        assert_eq!(Rc::strong_count(&item), 3);
+        drop(queue);
+        assert_eq!(Rc::strong_count(&item), 1);
+    }
+
+    /// Producers and consumers hammering a queue small enough to be full most of the time,
+    /// the case where `LockFreeDeque` may livelock (see `deque::tests::test_full_race`)
+    #[cfg(not(feature = "single-thread"))]
+    #[test]
+    fn test_mpmc_full() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::vec::Vec;
+
+        const PRODUCERS: usize = 4;
+        const CONSUMERS: usize = 4;
+        const ITEMS: usize = 20000;
+
+        let queue: MpmcQueue<(usize, usize), 4> = MpmcQueue::new();
+        let popped = AtomicUsize::new(0);
+        let sums: Vec<usize> = std::thread::scope(|s| {
+            for p in 0..PRODUCERS {
+                let queue = &queue;
+                s.spawn(move || {
+                    for i in 0..ITEMS {
+                        let mut item = (p, i);
+                        while let Err(rejected) = queue.enqueue(item) {
+                            item = rejected;
+                            std::thread::yield_now();
+                        }
+                    }
+                });
+            }
+            let consumers: Vec<_> = (0..CONSUMERS)
+                .map(|_| {
+                    s.spawn(|| {
+                        // Items of one producer are popped in the order it pushed them
+                        let mut last = [None; PRODUCERS];
+                        let mut sum = 0;
+                        while popped.load(Ordering::Relaxed) < PRODUCERS * ITEMS {
+                            match queue.dequeue() {
+                                Some((p, i)) => {
+                                    assert!(last[p].is_none_or(|last| last < i));
+                                    last[p] = Some(i);
+                                    sum += i;
+                                    popped.fetch_add(1, Ordering::Relaxed);
+                                }
+                                None => std::thread::yield_now(),
+                            }
+                        }
+                        sum
+                    })
+                })
+                .collect();
+            consumers.into_iter().map(|c| c.join().unwrap()).collect()
+        });
+        assert_eq!(
+            sums.iter().sum::<usize>(),
+            PRODUCERS * ITEMS * (ITEMS - 1) / 2
+        );
+        assert!(queue.is_empty());
+    }
+}
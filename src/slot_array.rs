@@ -2,15 +2,66 @@ use core::{
     cell::UnsafeCell,
     mem::{ManuallyDrop, MaybeUninit},
     ops::Deref,
-    sync::atomic::{AtomicU8, Ordering},
+    ptr::NonNull,
+    sync::atomic::{AtomicU8, AtomicUsize, Ordering},
 };
 
+use crate::sync::{OrderingProfile, Profile};
 use crate::{
     ARRAY_LEN, PerProcess, QUEUE_CAPACITY, deque::LockFreeDeque, get_queue_array, ipc_item::IPCItem,
 };
+#[cfg(feature = "std")]
+use crate::{IPC_ITEM_BYTES, QueuedItem};
+#[cfg(feature = "std")]
+use core::sync::atomic::AtomicU64;
 
 pub struct SlotArray<T, const N: usize> {
     slots: [Slot<T>; N],
+    /// Index to start the next `push_` scan from, the slot after the last successful push.
+    ///
+    /// Only a hint: a stale value just makes the scan start elsewhere.
+    hint: AtomicUsize,
+}
+
+#[cfg(feature = "std")]
+std::thread_local! {
+    /// Per-thread `push_` scan hint, used instead of the shared `SlotArray::hint`
+    /// to avoid bouncing its cache line between threads registering at the same time.
+    ///
+    /// Shared by all arrays, so it may point anywhere in a given array: it is only a hint.
+    static SCAN_HINT: core::cell::Cell<Option<usize>> = const { core::cell::Cell::new(None) };
+}
+
+/// Number of low bits of an id (see `SlotArray::id`) holding the slot index, so an array holds at most
+/// `1 << ID_INDEX_BITS` (65536) slots, and the global queue array (`ARRAY_LEN`) one less, checked at compile time.
+/// The remaining high bits (48 on 64-bit targets, 16 on 32-bit ones) hold the slot epoch,
+/// which wraps around after that many frees of the same slot.
+pub const ID_INDEX_BITS: u32 = 16;
+const ID_INDEX_MASK: usize = (1 << ID_INDEX_BITS) - 1;
+
+// Bit budget of an id, from the low bits:
+// - index: `ID_INDEX_BITS` bits. `ARRAY_LEN` must even be strictly less than `1 << ID_INDEX_BITS`, so that
+//   the all-ones index is never valid and `usize::MAX` (`NO_MONITOR`, `NO_OVERFLOW_QUEUE`) is never a real id.
+// - epoch: all the remaining high bits. It wraps around, so it doesn't constrain `ARRAY_LEN`.
+// A feature packing more metadata into ids must take its bits from the epoch and check them here.
+const _: () = {
+    assert!(ID_INDEX_BITS < usize::BITS);
+    assert!(
+        ARRAY_LEN < 1 << ID_INDEX_BITS,
+        "ARRAY_LEN doesn't fit in the index bits of an id, see `ID_INDEX_BITS`"
+    );
+};
+
+/// Number of passes over the array `push` makes before giving up on contention, see `SlotArray::push_passes`
+pub(crate) const PUSH_PASSES: usize = 4;
+
+/// Why `SlotArray::push_passes` failed
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum PushFailure {
+    /// A whole pass found every slot in use
+    Full,
+    /// Every pass found slots being claimed or freed by other threads, but none free
+    Contended,
 }
 
 const SLOT_EMPTY: u8 = 0;
@@ -20,59 +71,120 @@ const SLOT_PENDING: u8 = 2;
 struct Slot<T> {
     state: AtomicU8,
     rc: AtomicU8,
+    /// Incremented each time the slot is freed, so that ids of previous occupants can be told apart
+    epoch: AtomicUsize,
     value: UnsafeCell<MaybeUninit<T>>,
 }
 
 // low-level operations
 impl<T, const N: usize> SlotArray<T, N> {
-    /// Attempts to push a value into the slot array.
+    /// Attempts to push a value into the slot array, see `push_passes_`.
     /// Returns the index of the slot if successful, or an error if the array is full.
     fn push_(&self, value: T) -> Result<usize, ()> {
-        for i in 0..N {
-            let Slot {
-                state,
-                rc,
-                value: prev_value,
-            } = &self.slots[i];
-            if let Ok(prev) = state.compare_exchange(
+        self.push_passes_(value, PUSH_PASSES).map_err(|_| ())
+    }
+
+    /// Attempts to push a value into the slot array, scanning at most `max_scan` slots starting from the hint.
+    fn push_scan(&self, value: T, max_scan: usize) -> Result<usize, ()> {
+        self.push_from(value, self.scan_start(), max_scan)
+    }
+
+    /// Attempts to push a value into the slot array, making at most `max_passes` (at least one) passes over
+    /// the whole array starting from the hint.
+    ///
+    /// A pass that finds no free slot but sees one being claimed or freed by another thread (`SLOT_PENDING`)
+    /// is retried, since the slot may be free by the next pass; after `max_passes` such passes, this gives up
+    /// with `PushFailure::Contended`. A pass that only sees slots in use fails with `PushFailure::Full` at once.
+    fn push_passes_(&self, value: T, max_passes: usize) -> Result<usize, PushFailure> {
+        let start = self.scan_start();
+        for _ in 0..max_passes.max(1) {
+            match self.claim_from(start, N) {
+                Ok(i) => return Ok(self.fill(i, value)),
+                Err(false) => return Err(PushFailure::Full),
+                Err(true) => core::hint::spin_loop(),
+            }
+        }
+        Err(PushFailure::Contended)
+    }
+
+    /// Attempts to push a value into the slot array, scanning at most `max_scan` slots starting from `start`.
+    fn push_from(&self, value: T, start: usize, max_scan: usize) -> Result<usize, ()> {
+        let i = self.claim_from(start, max_scan).map_err(|_| ())?;
+        Ok(self.fill(i, value))
+    }
+
+    /// Index to start a push scan from: the per-thread hint if set, else the shared one
+    fn scan_start(&self) -> usize {
+        #[cfg(feature = "std")]
+        if let Some(start) = SCAN_HINT.get() {
+            return start;
+        }
+        self.hint.load(Ordering::Relaxed)
+    }
+
+    /// Claims a free slot by moving it to `SLOT_PENDING`, scanning at most `max_scan` slots starting from `start`.
+    /// Returns its index, or whether a slot in `SLOT_PENDING` (being claimed or freed by another thread) was seen.
+    fn claim_from(&self, start: usize, max_scan: usize) -> Result<usize, bool> {
+        let mut saw_pending = false;
+        for k in 0..max_scan.min(N) {
+            let i = (start + k) % N;
+            match self.slots[i].state.compare_exchange(
                 SLOT_EMPTY,
                 SLOT_PENDING,
-                Ordering::AcqRel,
-                Ordering::Acquire,
+                Profile::ACQ_REL,
+                Profile::ACQUIRE,
             ) {
-                assert_eq!(prev, SLOT_EMPTY);
-                // Safe using `get` because we have exclusive access to this slot by setting state to SLOT_PENDING
-                // Safe using `write` because we are initializing the slot
-                unsafe {
-                    (&mut *prev_value.get()).write(value);
+                Ok(prev) => {
+                    assert_eq!(prev, SLOT_EMPTY);
+                    return Ok(i);
                 }
-                let prev = state.swap(SLOT_READY, Ordering::AcqRel);
-                assert_eq!(prev, SLOT_PENDING);
-                let prev_rc = rc.fetch_add(1, Ordering::AcqRel);
-                assert_eq!(prev_rc, 0);
-                return Ok(i);
+                Err(SLOT_PENDING) => saw_pending = true,
+                Err(_) => {}
             }
         }
-        Err(())
+        Err(saw_pending)
     }
 
-    pub(crate) fn get(&self, index: usize) -> Option<&T> {
+    /// Initializes the slot at `index` claimed by `claim_from` with `value`, and publishes it.
+    /// Returns `index`.
+    fn fill(&self, index: usize, value: T) -> usize {
         let Slot {
             state,
-            rc: _,
-            value,
+            rc,
+            value: prev_value,
+            ..
         } = &self.slots[index];
-        if state.load(Ordering::Acquire) == SLOT_READY {
-            let res = Some(unsafe { (&*value.get()).assume_init_ref() });
-            if state.load(Ordering::Acquire) == SLOT_READY {
-                res
-            } else {
-                // state changed, return None
-                None
-            }
-        } else {
-            None
+        // Safe using `get` because we have exclusive access to this slot by setting state to SLOT_PENDING
+        // Safe using `write` because we are initializing the slot
+        unsafe {
+            (&mut *prev_value.get()).write(value);
         }
+        let prev = state.swap(SLOT_READY, Profile::ACQ_REL);
+        assert_eq!(prev, SLOT_PENDING);
+        let prev_rc = rc.fetch_add(1, Profile::ACQ_REL);
+        assert_eq!(prev_rc, 0);
+        self.hint.store((index + 1) % N, Ordering::Relaxed);
+        #[cfg(feature = "std")]
+        SCAN_HINT.set(Some((index + 1) % N));
+        index
+    }
+
+    /// Gets the value in the slot at `index`, if the slot is in use, guarded by a new `SlotRef`.
+    ///
+    /// The returned `SlotRef` holds a reference count of its own (see `try_ref`), so the value stays alive,
+    /// and the slot isn't reused, until it is dropped. Returns None if the slot is not in use.
+    pub(crate) fn get(&self, index: usize) -> Option<SlotRef<'_, T, N>> {
+        self.try_ref(index)
+    }
+
+    /// Gets a reference to the value in the slot at `index`, without any check.
+    ///
+    /// # Safety
+    ///
+    /// The slot must be in use, and stay so for the lifetime of the reference, e.g. because the caller
+    /// holds a `SlotRef` to it for that long.
+    unsafe fn get_unchecked(&self, index: usize) -> &T {
+        unsafe { (&*self.slots[index].value.get()).assume_init_ref() }
     }
 
     /// Deletes a value from the slot array at the given index.
@@ -86,26 +198,153 @@ impl<T, const N: usize> SlotArray<T, N> {
     /// - the state at that index is currently in the `SLOT_PENDING` state.
     /// - the caller has exclusive access to the slot (`rc == 0` because `rc` is already decreased in `SlotRef::drop`).
     unsafe fn delete(&self, index: usize) {
-        let Slot { state, rc, value } = &self.slots[index];
-        let prev = state.swap(SLOT_EMPTY, Ordering::AcqRel);
+        let Slot {
+            state,
+            rc,
+            epoch,
+            value,
+        } = &self.slots[index];
+        epoch.fetch_add(1, Profile::ACQ_REL);
+        let prev = state.swap(SLOT_EMPTY, Profile::ACQ_REL);
         assert_eq!(prev, SLOT_PENDING);
         // Safe because we have exclusive access to this slot by setting state to SLOT_PENDING
         unsafe {
             (&mut *value.get()).assume_init_drop();
         }
-        let rc = rc.load(Ordering::Acquire);
+        let rc = rc.load(Profile::ACQUIRE);
         assert_eq!(rc, 0);
     }
 
+    /// Gets a new `SlotRef` to the slot at `index`, if the slot is in use.
+    ///
+    /// Unlike `SlotRef::from_id`, this is safe to call with any index: it returns None if the index is
+    /// out of bounds or the slot is empty or being freed. On success the reference count is incremented,
+    /// so the slot stays alive until the returned `SlotRef` is dropped.
+    ///
+    /// Also returns None if the reference count is saturated (`u8::MAX`), unless the `saturating-rc`
+    /// feature is enabled, in which case the slot is leaked and a `SlotRef` to it is always valid.
+    pub(crate) fn try_ref(&self, index: usize) -> Option<SlotRef<'_, T, N>> {
+        let Slot { state, rc, .. } = self.slots.get(index)?;
+        let mut current = rc.load(Profile::ACQUIRE);
+        loop {
+            // rc == 0: the slot is empty, being initialized or being freed
+            if current == 0 || state.load(Profile::ACQUIRE) != SLOT_READY {
+                return None;
+            }
+            if current == u8::MAX {
+                #[cfg(feature = "saturating-rc")]
+                return Some(SlotRef { array: self, index });
+                #[cfg(not(feature = "saturating-rc"))]
+                return None;
+            }
+            match rc.compare_exchange_weak(current, current + 1, Profile::ACQ_REL, Profile::ACQUIRE)
+            {
+                Ok(_) => return Some(SlotRef { array: self, index }),
+                Err(actual) => current = actual,
+            }
+        }
+    }
+
+    /// Encodes the index of a slot and its current epoch into an id:
+    /// the index in the low `ID_INDEX_BITS` bits, and the epoch in the remaining high bits.
+    ///
+    /// The first occupant of a slot has epoch 0, so its id equals the index.
+    pub(crate) fn id(&self, index: usize) -> usize {
+        let epoch = self.slots[index].epoch.load(Profile::ACQUIRE);
+        // High bits of the epoch are shifted out: it wraps around
+        (epoch << ID_INDEX_BITS) | index
+    }
+
+    /// Decodes an id from `id`, returning the slot index if it is in bounds and the epoch still matches,
+    /// i.e. the slot has not been freed since the id was issued.
+    pub(crate) fn index_of(&self, id: usize) -> Option<usize> {
+        let index = id & ID_INDEX_MASK;
+        (index < N && self.id(index) == id).then_some(index)
+    }
+
+    /// Gets a new `SlotRef` from an id, like `try_ref`, but also returns None if the epoch in the id
+    /// no longer matches (the slot was freed and possibly reused by another value).
+    pub(crate) fn try_ref_id(&self, id: usize) -> Option<SlotRef<'_, T, N>> {
+        let slot_ref = self.try_ref(self.index_of(id)?)?;
+        // The slot may have been freed and reused between the check and the increment;
+        // now that we hold a reference, the epoch can no longer change
+        (self.id(slot_ref.index) == id).then_some(slot_ref)
+    }
+
+    /// Checks that every slot has a valid state, with a reference count consistent with it, see `validate_queue_array`
+    #[cfg(not(feature = "vdso"))]
+    pub(crate) fn validate(&self) -> Result<(), crate::ValidationError> {
+        for (index, slot) in self.slots.iter().enumerate() {
+            let state = slot.state.load(Profile::ACQUIRE);
+            let rc = slot.rc.load(Profile::ACQUIRE);
+            let consistent = match state {
+                SLOT_EMPTY => rc == 0,
+                SLOT_READY => rc >= 1,
+                // Claimed or being freed, the count is in flux
+                SLOT_PENDING => true,
+                _ => return Err(crate::ValidationError::BadSlotState { index, state }),
+            };
+            if !consistent {
+                return Err(crate::ValidationError::BadRefCount { index, state, rc });
+            }
+        }
+        Ok(())
+    }
+
+    /// Drops one reference to the slot of a live id, taken over from a `SlotRef` forgotten with `mem::forget`,
+    /// freeing the slot if it was the last one, like `SlotRef::from_id` followed by `drop`.
+    ///
+    /// # Safety
+    ///
+    /// A reference to the slot must have been forgotten for this call, and not be dropped otherwise.
+    ///
+    /// Panics if the id is out of bounds or stale, which can't happen when the above holds.
+    pub(crate) unsafe fn unpin_id(&self, id: usize) {
+        let index = self
+            .index_of(id)
+            .expect("SlotArray::unpin_id: stale id, the slot has been freed");
+        drop(SlotRef { array: self, index });
+    }
+
+    /// Gets a new `SlotRef` from an id that is known to be live, for the duration of one operation.
+    ///
+    /// Like `try_ref_id`, the reference count is incremented, so the slot can't be freed while the
+    /// returned `SlotRef` is held, even if every other holder drops theirs meanwhile: the final drop
+    /// then happens when the returned `SlotRef` is dropped (reclamation is deferred to the last reader).
+    /// If the count is saturated by concurrent holders, waits for one of them to drop.
+    ///
+    /// Panics if the id is out of bounds or stale, like `SlotRef::from_id`.
+    pub(crate) fn pin_id(&self, id: usize) -> SlotRef<'_, T, N> {
+        loop {
+            if let Some(slot_ref) = self.try_ref_id(id) {
+                return slot_ref;
+            }
+            assert!(
+                id & ID_INDEX_MASK < N,
+                "SlotArray::pin_id: id out of bounds"
+            );
+            let index = self
+                .index_of(id)
+                .expect("SlotArray::pin_id: stale id, the slot has been freed");
+            // Still the same occupant and referenced, so the count was saturated
+            assert_ne!(
+                self.slots[index].rc.load(Profile::ACQUIRE),
+                0,
+                "SlotArray::pin_id: the slot is not in use"
+            );
+            core::hint::spin_loop();
+        }
+    }
+
     /// 释放一个引用计数恰好为1的槽位
     ///
     /// 仅用于特定用途
     pub(crate) unsafe fn drop_slot(&self, index: usize) {
         let Slot { state, rc, .. } = &self.slots[index];
-        let prev_rc = rc.fetch_sub(1, Ordering::AcqRel);
+        let prev_rc = rc.fetch_sub(1, Profile::ACQ_REL);
         assert!(prev_rc == 1);
 
-        let prev_state = state.swap(SLOT_PENDING, Ordering::Release);
+        let prev_state = state.swap(SLOT_PENDING, Profile::RELEASE);
         assert_eq!(prev_state, SLOT_READY);
 
         unsafe {
@@ -114,13 +353,253 @@ impl<T, const N: usize> SlotArray<T, N> {
     }
 }
 
+impl<T, const N: usize> SlotArray<T, N> {
+    /// Drops the values in all slots in use and marks them empty, returning the number of values dropped.
+    ///
+    /// The global queue array is written in place into the vvar region (or a caller-supplied address)
+    /// and is never dropped, so the destructors of the live values (e.g. `LockFreeDeque`s draining
+    /// their items) never run automatically. Call this manually for a clean teardown.
+    ///
+    /// # Safety
+    ///
+    /// No `SlotRef` (or id from `SlotRef::into_id`) to this array may be used after this call,
+    /// and no other thread may operate on the array concurrently.
+    pub unsafe fn drop_in_place_all(&self) -> usize {
+        let mut count = 0;
+        for Slot {
+            state,
+            rc,
+            epoch,
+            value,
+        } in self.slots.iter()
+        {
+            if state
+                .compare_exchange(SLOT_READY, SLOT_PENDING, Profile::ACQ_REL, Profile::ACQUIRE)
+                .is_ok()
+            {
+                rc.store(0, Profile::RELEASE);
+                epoch.fetch_add(1, Profile::ACQ_REL);
+                // Safe because the slot is initialized and we have exclusive access by setting state to SLOT_PENDING
+                unsafe {
+                    (&mut *value.get()).assume_init_drop();
+                }
+                state.store(SLOT_EMPTY, Profile::RELEASE);
+                count += 1;
+            }
+        }
+        count
+    }
+
+    /// Moves the values in use to the lowest free slots, so that live slots are contiguous from index 0.
+    ///
+    /// After long uptime with churning push/delete, live slots end up scattered across high indices,
+    /// which hurts the locality of scans over the array. For every value moved, `remap(old_id, new_id)`
+    /// is called with its ids (see `id`) before and after the move. Reference counts move along with the values,
+    /// and the vacated slot gets a new epoch, so the old id is rejected afterwards.
+    ///
+    /// # Safety
+    ///
+    /// `SlotRef`s (and ids from `SlotRef::into_id`) embed the slot index, so every holder must cooperate:
+    /// it must update its id in `remap`, and not use the old one afterwards.
+    /// This can only be called during a quiescent window, with no other thread operating on the array
+    /// or dereferencing a `SlotRef` to it.
+    pub unsafe fn defrag(&self, mut remap: impl FnMut(usize, usize)) {
+        let mut low = 0;
+        let mut high = N;
+        loop {
+            while low < high && self.slots[low].state.load(Profile::ACQUIRE) != SLOT_EMPTY {
+                low += 1;
+            }
+            while low < high && self.slots[high - 1].state.load(Profile::ACQUIRE) != SLOT_READY {
+                high -= 1;
+            }
+            if low + 1 >= high {
+                break;
+            }
+            high -= 1;
+            let (old_id, new_id) = (self.id(high), self.id(low));
+            let (from, to) = (&self.slots[high], &self.slots[low]);
+            to.state.store(SLOT_PENDING, Profile::RELEASE);
+            from.state.store(SLOT_PENDING, Profile::RELEASE);
+            // Safe because we have exclusive access to both slots, and the value is moved out of `from` exactly once
+            unsafe {
+                (&mut *to.value.get()).write((&*from.value.get()).assume_init_read());
+            }
+            to.rc
+                .store(from.rc.swap(0, Profile::ACQ_REL), Profile::RELEASE);
+            from.epoch.fetch_add(1, Profile::ACQ_REL);
+            from.state.store(SLOT_EMPTY, Profile::RELEASE);
+            to.state.store(SLOT_READY, Profile::RELEASE);
+            remap(old_id, new_id);
+            low += 1;
+        }
+        self.hint.store(low % N, Ordering::Relaxed);
+    }
+}
+
+impl<T, const N: usize> SlotArray<T, N> {
+    /// Returns the address and length in bytes of the storage of the value at `index`, whether or not the slot
+    /// is in use, so that an integrator can bind that memory to a NUMA node (e.g. with `mbind`/`move_pages`).
+    ///
+    /// The array is one contiguous region: `slots` is a `[Slot<T>; N]`, so slot `index` starts at
+    /// `&self.slots as usize + index * size_of::<Slot<T>>()`, and its value at `offset_of!(Slot<T>, value)`
+    /// from there, spanning `size_of::<T>()` bytes. The per-slot `state` and `rc` are not included.
+    /// The range is generally not page-aligned: pages at its ends may be shared with neighbouring slots.
+    ///
+    /// Panics if `index` is out of bounds.
+    #[cfg(feature = "std")]
+    pub(crate) fn value_range(&self, index: usize) -> (usize, usize) {
+        let start = &self.slots as *const [Slot<T>; N] as usize
+            + index * size_of::<Slot<T>>()
+            + core::mem::offset_of!(Slot<T>, value);
+        debug_assert_eq!(start, self.slots[index].value.get() as usize);
+        (start, size_of::<T>())
+    }
+}
+
+impl<T, const N: usize> SlotArray<T, N> {
+    /// Puts `value` back into the slot of `id`, with the epoch encoded in `id`, as it was when the id was issued.
+    ///
+    /// The slot gets a reference count of 1, owned by the id: like an id from `SlotRef::into_id`,
+    /// it must eventually be converted back and dropped to free the slot.
+    /// Returns Err(value) if the index is out of bounds or the slot is in use.
+    #[cfg(feature = "std")]
+    fn restore(&self, id: usize, value: T) -> Result<(), T> {
+        let Some(Slot {
+            state,
+            rc,
+            epoch,
+            value: slot_value,
+        }) = self.slots.get(id & ID_INDEX_MASK)
+        else {
+            return Err(value);
+        };
+        if state
+            .compare_exchange(SLOT_EMPTY, SLOT_PENDING, Profile::ACQ_REL, Profile::ACQUIRE)
+            .is_err()
+        {
+            return Err(value);
+        }
+        // Safe because we have exclusive access to this slot by setting state to SLOT_PENDING
+        unsafe { (&mut *slot_value.get()).write(value) };
+        epoch.store(id >> ID_INDEX_BITS, Profile::RELEASE);
+        let prev = state.swap(SLOT_READY, Profile::ACQ_REL);
+        assert_eq!(prev, SLOT_PENDING);
+        let prev_rc = rc.fetch_add(1, Profile::ACQ_REL);
+        assert_eq!(prev_rc, 0);
+        Ok(())
+    }
+}
+
+/// Snapshots of the queues, for checkpoint/restore
+///
+/// The format is a sequence of little-endian `u64`s, with items as `IPCItem::to_le_bytes`:
+///
+/// - the number of queues, then for each live queue:
+/// - its id (index and epoch, see `ID_INDEX_BITS`) and its next sequence number
+/// - the number of items in its `deque`, then each item from front to back, as its sequence number and its bytes
+/// - the same for its priority lane
+///
+/// Only the items are saved: the `QueueConfig`, the pid, the `msg_type` map, hooks, names and metrics
+/// are not, and a restored queue gets the defaults. Both functions require a quiescent array: no queue
+/// may be registered, freed, pushed to or popped from meanwhile, otherwise the snapshot is inconsistent.
+#[cfg(feature = "std")]
+impl<const N: usize> SlotArray<PerProcess, N> {
+    /// Writes a snapshot of all live queues to `w`, see the format above
+    pub fn serialize(&self, w: &mut impl std::io::Write) -> std::io::Result<()> {
+        let queues: std::vec::Vec<_> = (0..N).filter_map(|i| self.try_ref(i)).collect();
+        w.write_all(&(queues.len() as u64).to_le_bytes())?;
+        for queue in &queues {
+            w.write_all(&(queue.id() as u64).to_le_bytes())?;
+            w.write_all(&queue.seq.load(Profile::ACQUIRE).to_le_bytes())?;
+            write_items(w, &queue.deque)?;
+            write_items(w, &queue.priority)?;
+        }
+        Ok(())
+    }
+
+    /// Restores the queues of a snapshot written by `serialize`, returning the number of queues restored
+    ///
+    /// Each queue goes back into the slot of its id, with the same id, so that ids held by processes stay valid.
+    /// As with `into_id`, the id owns one reference to the queue. Fails with `InvalidData` if a slot is
+    /// already in use or a lane has more items than it can hold; queues restored before the error are kept.
+    pub fn deserialize_into(&self, r: &mut impl std::io::Read) -> std::io::Result<usize> {
+        let count = read_u64(r)?;
+        for _ in 0..count {
+            let id = read_u64(r)? as usize;
+            let queue = PerProcess {
+                seq: AtomicU64::new(read_u64(r)?),
+                ..Default::default()
+            };
+            read_items(r, &queue.deque)?;
+            read_items(r, &queue.priority)?;
+            self.restore(id, queue)
+                .map_err(|_| invalid_data("slot of a restored queue in use or out of bounds"))?;
+        }
+        Ok(count as usize)
+    }
+}
+
+#[cfg(feature = "std")]
+fn write_items<const C: usize>(
+    w: &mut impl std::io::Write,
+    deque: &LockFreeDeque<QueuedItem, C>,
+) -> std::io::Result<()> {
+    let mut items = std::vec::Vec::new();
+    deque.for_each(|queued| items.push((queued.seq, queued.item)));
+    w.write_all(&(items.len() as u64).to_le_bytes())?;
+    for (seq, item) in items {
+        w.write_all(&seq.to_le_bytes())?;
+        w.write_all(&item.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+#[cfg(feature = "std")]
+fn read_items<const C: usize>(
+    r: &mut impl std::io::Read,
+    deque: &LockFreeDeque<QueuedItem, C>,
+) -> std::io::Result<()> {
+    let len = read_u64(r)? as usize;
+    if len > deque.capacity() {
+        return Err(invalid_data("more items than the queue can hold"));
+    }
+    let mut items = std::vec::Vec::with_capacity(len);
+    for _ in 0..len {
+        let seq = read_u64(r)?;
+        let mut bytes = [0; IPC_ITEM_BYTES];
+        r.read_exact(&mut bytes)?;
+        items.push(QueuedItem::new(IPCItem::from_le_bytes(bytes), seq));
+    }
+    // Pushed to the front from the back, so that the items take the same slots from head to tail
+    // whatever the pop discipline, and without requiring `deque-back`
+    for item in items.into_iter().rev() {
+        assert!(deque.push_front(item).is_ok());
+    }
+    Ok(())
+}
+
+#[cfg(feature = "std")]
+fn read_u64(r: &mut impl std::io::Read) -> std::io::Result<u64> {
+    let mut bytes = [0; 8];
+    r.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+#[cfg(feature = "std")]
+fn invalid_data(msg: &'static str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, msg)
+}
+
 impl<T, const N: usize> Default for SlotArray<T, N> {
     fn default() -> Self {
         Self::new()
     }
 }
 
-unsafe impl<T, const N: usize> Sync for SlotArray<T, N> where T: Sync {}
+// A shared array hands out `&T` to every thread, and the value is dropped by whichever thread drops the last
+// `SlotRef`, so sharing it needs `T: Send` as well as `T: Sync`, like `Arc`
+unsafe impl<T, const N: usize> Sync for SlotArray<T, N> where T: Send + Sync {}
 unsafe impl<T, const N: usize> Send for SlotArray<T, N> where T: Send {}
 
 /// 对应于`SlotArray`中一个槽位的引用，包含了槽位所在的数组和槽位的索引。
@@ -143,7 +622,12 @@ impl<'a, T, const N: usize> core::fmt::Debug for SlotRef<'a, T, N> {
 impl<'a, T, const N: usize> SlotRef<'a, T, N> {
     /// 调试用接口
     pub fn rc(&self) -> u8 {
-        self.array.slots[self.index].rc.load(Ordering::Acquire)
+        self.array.slots[self.index].rc.load(Profile::ACQUIRE)
+    }
+
+    /// 槽位的id，编码了索引与epoch，见`SlotArray::id`
+    pub(crate) fn id(&self) -> usize {
+        self.array.id(self.index)
     }
 }
 
@@ -154,8 +638,11 @@ impl<'a, T, const N: usize> SlotRef<'a, T, N> {
 /// (Similar to `Arc::into_raw` and `Arc::from_raw`)
 impl SlotRef<'static, PerProcess, ARRAY_LEN> {
     /// Converts a `SlotRef` into a usize ID.
+    ///
+    /// The ID encodes the slot index and the slot epoch (see `ID_INDEX_BITS`),
+    /// so that it is rejected once the slot is freed, even if it is later reused by another process.
     pub fn into_id(self) -> usize {
-        let id = self.index;
+        let id = self.id();
         core::mem::forget(self);
         // let _ = ManuallyDrop::new(self);
         id
@@ -168,22 +655,42 @@ impl SlotRef<'static, PerProcess, ARRAY_LEN> {
     /// The caller must ensure that the id is get from `SlotRef::into_id`.
     ///
     /// one id can only be converted back to one `SlotRef`.
+    ///
+    /// Panics if the id is out of bounds or stale (the slot has been freed since the id was issued).
     pub(crate) unsafe fn from_id(id: usize) -> Self {
-        assert!(id < ARRAY_LEN, "SlotRef::from_id: id out of bounds");
         let array = get_queue_array();
-        let Slot {
-            state,
-            rc,
-            value: _,
-        } = &array.slots[id];
-        assert_eq!(state.load(Ordering::Acquire), SLOT_READY);
-        assert!(rc.load(Ordering::Acquire) >= 1);
-        Self { array, index: id }
+        assert!(
+            id & ID_INDEX_MASK < ARRAY_LEN,
+            "SlotRef::from_id: id out of bounds"
+        );
+        let index = array
+            .index_of(id)
+            .expect("SlotRef::from_id: stale id, the slot has been freed");
+        let Slot { state, rc, .. } = &array.slots[index];
+        assert_eq!(state.load(Profile::ACQUIRE), SLOT_READY);
+        assert!(rc.load(Profile::ACQUIRE) >= 1);
+        Self { array, index }
     }
 
-    // pub fn id(&self) -> usize {
-    //     self.index
-    // }
+    /// Checked version of `from_id`, which gets a new `SlotRef` instead of taking over the one
+    /// forgotten by `into_id`.
+    ///
+    /// Returns None if the id is out of bounds, the slot is not in use, or the id is stale
+    /// (its epoch no longer matches because the slot was freed, even if it was reused since),
+    /// otherwise the reference count is incremented and the returned `SlotRef` can be dropped normally.
+    pub(crate) fn try_from_id(id: usize) -> Option<Self> {
+        get_queue_array().try_ref_id(id)
+    }
+
+    /// Gets a counted `SlotRef` from a live id for the duration of one FFI operation, see `SlotArray::pin_id`.
+    ///
+    /// The returned `SlotRef` holds a reference count of its own, so unregistering the queue meanwhile
+    /// only decrements the count, and the slot is freed once the returned `SlotRef` is dropped.
+    ///
+    /// Panics if the id is out of bounds or stale.
+    pub(crate) fn pin(id: usize) -> Self {
+        get_queue_array().pin_id(id)
+    }
 
     // /// error code:
     // /// - 1: id out of bounds
@@ -198,36 +705,82 @@ impl SlotRef<'static, PerProcess, ARRAY_LEN> {
     //         .compare_exchange(
     //             SLOT_READY,
     //             SLOT_PENDING,
-    //             Ordering::AcqRel,
-    //             Ordering::Acquire,
+    //             Profile::ACQ_REL,
+    //             Profile::ACQUIRE,
     //         )
     //         .is_err()
     //     {
     //         return Err(2); // slot not ready
     //     }
-    //     rc.fetch_add(1, Ordering::AcqRel);
+    //     rc.fetch_add(1, Profile::ACQ_REL);
     //     // with the above fetch_add, rc must be >= 1.
     //     // so we can restore the state to SLOT_READY and return the SlotRef safely.
-    //     let old_state = state.swap(SLOT_READY, Ordering::AcqRel);
+    //     let old_state = state.swap(SLOT_READY, Profile::ACQ_REL);
     //     assert_eq!(old_state, SLOT_PENDING);
     //     Ok(Self { array, index: id })
     // }
 }
 
-unsafe impl<T: Sync, const N: usize> Send for SlotRef<'_, T, N> {}
+/// `SlotRef`的线程安全条件，与`Arc<T>`相同：
+///
+/// - 发送或共享`SlotRef`后，其他线程可以通过它取得`&T`，因此要求`T: Sync`；
+/// - 最后一个被丢弃的`SlotRef`在丢弃它的线程中析构槽位中的值，因此还要求`T: Send`，
+///   否则`T`可能在创建它的线程之外被析构（例如`MutexGuard`）。
+///
+/// 引用计数的修改都是原子操作，`clone`与`drop`可以在任意线程进行。至于数组须比`SlotRef`活得更久，由生命周期`'a`保证，
+/// 不需要额外的限制：`std::thread::spawn`要求闭包为`'static`，因此只有全局数组的`SlotRef`（即`SendSlotRef`）
+/// 能被发送到这样的线程；引用栈上数组的`SlotRef`只能发送到作用域线程（`std::thread::scope`），而作用域结束前数组一直被借用。
+///
+/// ```compile_fail
+/// fn assert_send<T: Send>() {}
+/// // `MutexGuard` is `Sync` but must be dropped on the thread that locked the mutex
+/// assert_send::<vqueue::SlotRef<'static, std::sync::MutexGuard<'static, ()>, 4>>();
+/// ```
+///
+/// ```compile_fail
+/// fn assert_sync<T: Sync>() {}
+/// assert_sync::<vqueue::SlotRef<'static, std::sync::MutexGuard<'static, ()>, 4>>();
+/// ```
+unsafe impl<T: Send + Sync, const N: usize> Send for SlotRef<'_, T, N> {}
+unsafe impl<T: Send + Sync, const N: usize> Sync for SlotRef<'_, T, N> {}
+
+/// 全局队列数组（`get_queue_array`）中队列的`SlotRef`，是唯一能被发送到`std::thread::spawn`的线程的`SlotRef`。
+///
+/// 全局数组在整个程序运行期间有效，因此其`SlotRef`的生命周期为`'static`：
+///
+/// ```no_run
+/// fn consume_elsewhere(queue: vqueue::SendSlotRef) {
+///     std::thread::spawn(move || drop(queue));
+/// }
+/// ```
+///
+/// 引用其他数组（例如栈上的数组）的`SlotRef`借用该数组，生命周期短于`'static`，不能发送到这样的线程：
+///
+/// ```compile_fail
+/// fn consume_elsewhere<'a>(queue: vqueue::SlotRef<'a, vqueue::PerProcess, 4>) {
+///     std::thread::spawn(move || drop(queue));
+/// }
+/// ```
+pub type SendSlotRef = SlotRef<'static, PerProcess, ARRAY_LEN>;
 
 // -------- high-level operations --------
 
 impl<T, const N: usize> SlotArray<T, N> {
     pub const fn new() -> Self {
+        assert!(
+            N <= 1 << ID_INDEX_BITS,
+            "SlotArray: too many slots to encode in an id"
+        );
         Self {
             slots: [const {
                 Slot {
                     state: AtomicU8::new(SLOT_EMPTY),
                     rc: AtomicU8::new(0),
+                    epoch: AtomicUsize::new(0),
                     value: UnsafeCell::new(MaybeUninit::uninit()),
                 }
             }; N],
+            hint: AtomicUsize::new(0),
         }
     }
 }
@@ -240,27 +793,104 @@ impl<'a, T, const N: usize> SlotArray<T, N> {
     }
 }
 
+impl<'a, T, const N: usize> SlotArray<T, N> {
+    /// Pushes a value into the slot array like `push`, but scans at most `max_scan` slots,
+    /// so that the work done is bounded even when the array is (nearly) full.
+    ///
+    /// May fail even though free slots exist beyond the scan window.
+    pub fn push_bounded(&'a self, value: T, max_scan: usize) -> Result<SlotRef<'a, T, N>, ()> {
+        let index = self.push_scan(value, max_scan)?;
+        Ok(SlotRef { array: self, index })
+    }
+}
+
+impl<'a, T, const N: usize> SlotArray<T, N> {
+    /// Pushes a value into the slot array like `push`, but makes up to `max_passes` passes over the array
+    /// instead of `PUSH_PASSES`, bounding the time spent when many threads push at once.
+    ///
+    /// Fails with `PushFailure::Contended` once the passes ran out while slots were still changing hands,
+    /// so that the caller can back off and retry, or with `PushFailure::Full` when the array is full.
+    pub(crate) fn push_passes(
+        &'a self,
+        value: T,
+        max_passes: usize,
+    ) -> Result<SlotRef<'a, T, N>, PushFailure> {
+        let index = self.push_passes_(value, max_passes)?;
+        Ok(SlotRef { array: self, index })
+    }
+}
+
+impl<'a, T, const N: usize> SlotArray<T, N> {
+    /// Pushes a value into the first free slot at or after `start` (wrapping around), and returns a `SlotRef` to it.
+    ///
+    /// Unlike `push`, the scan doesn't start from the hint, so the slot chosen only depends on `start`
+    /// and on the slots in use, as needed for linear probing.
+    pub fn push_probing(&'a self, value: T, start: usize) -> Result<SlotRef<'a, T, N>, ()> {
+        let index = self.push_from(value, start % N, N)?;
+        Ok(SlotRef { array: self, index })
+    }
+
+    /// Returns a `SlotRef` to the first slot in use at or after `start` (wrapping around) whose value matches `pred`.
+    ///
+    /// Free slots don't end the scan: they may be holes left by values freed after a later one was pushed
+    /// past them, so every slot is visited when nothing matches.
+    pub fn find_probing(
+        &'a self,
+        start: usize,
+        pred: impl Fn(&T) -> bool,
+    ) -> Option<SlotRef<'a, T, N>> {
+        (0..N)
+            .filter_map(|k| self.try_ref((start + k) % N))
+            .find(|slot_ref| pred(slot_ref.get()))
+    }
+}
+
 impl<'a, T, const N: usize> SlotRef<'a, T, N> {
     /// get a reference to a slot in the array
     /// safe because the SlotRef guarantees that the slot is valid
-    pub fn get(&self) -> &'a T {
-        self.array.get(self.index).unwrap()
+    ///
+    /// The reference borrows the `SlotRef`, not the array, so it can't outlive the reference count
+    /// that keeps the slot alive: dropping the `SlotRef` (possibly the final drop, which frees the value)
+    /// while the reference is still in use, e.g. across a suspension point, doesn't compile.
+    pub fn get(&self) -> &T {
+        // Safe because the reference count held by `self` keeps the slot in use for the borrow
+        unsafe { self.array.get_unchecked(self.index) }
+    }
+
+    /// Get a raw pointer to the value in the slot, for callers caching it instead of looking the slot up again
+    ///
+    /// The value never moves while the slot is in use, so the pointer stays the same for every `SlotRef`
+    /// to the slot. But it is only valid while this `SlotRef` (or a clone keeping the reference count up)
+    /// is alive: once every reference is dropped, the slot is freed, and it may be reused for another value.
+    /// Unlike with `get`, the borrow checker can't enforce this.
+    pub fn as_ptr(&self) -> NonNull<T> {
+        NonNull::from(self.get())
     }
 }
 
 impl<'a, T, const N: usize> Deref for SlotRef<'a, T, N> {
     type Target = T;
 
-    fn deref(&self) -> &'a Self::Target {
+    fn deref(&self) -> &Self::Target {
         self.get()
     }
 }
 
+/// With the `saturating-rc` feature, the reference count saturates at `u8::MAX` instead of wrapping:
+/// once saturated, `clone` and `drop` no longer change it, so the slot is intentionally leaked
+/// (never freed) rather than risking a premature free. This suits broadcast-heavy workloads
+/// that clone a few `SlotRef`s more than 255 times and don't need to reclaim those slots.
 impl<'a, T, const N: usize> Clone for SlotRef<'a, T, N> {
     fn clone(&self) -> Self {
-        let prev_rc = self.array.slots[self.index]
-            .rc
-            .fetch_add(1, Ordering::AcqRel);
+        let rc = &self.array.slots[self.index].rc;
+        #[cfg(not(feature = "saturating-rc"))]
+        let prev_rc = rc.fetch_add(1, Profile::ACQ_REL);
+        #[cfg(feature = "saturating-rc")]
+        let prev_rc = rc
+            .fetch_update(Profile::ACQ_REL, Profile::ACQUIRE, |rc| {
+                Some(rc.saturating_add(1))
+            })
+            .unwrap();
         assert!(prev_rc >= 1);
         Self {
             array: self.array,
@@ -271,13 +901,20 @@ impl<'a, T, const N: usize> Clone for SlotRef<'a, T, N> {
 
 impl<'a, T, const N: usize> Drop for SlotRef<'a, T, N> {
     fn drop(&mut self) {
-        let prev_rc = self.array.slots[self.index]
-            .rc
-            .fetch_sub(1, Ordering::AcqRel);
+        let rc = &self.array.slots[self.index].rc;
+        #[cfg(not(feature = "saturating-rc"))]
+        let prev_rc = rc.fetch_sub(1, Profile::ACQ_REL);
+        #[cfg(feature = "saturating-rc")]
+        let Ok(prev_rc) = rc.fetch_update(Profile::ACQ_REL, Profile::ACQUIRE, |rc| {
+            // A saturated slot is leaked, never decremented
+            (rc != u8::MAX).then(|| rc - 1)
+        }) else {
+            return;
+        };
         if prev_rc == 1 {
             let prev_state = self.array.slots[self.index]
                 .state
-                .swap(SLOT_PENDING, Ordering::Release);
+                .swap(SLOT_PENDING, Profile::RELEASE);
             assert_eq!(prev_state, SLOT_READY);
             // Safe because the caller has exclusive access to the slot
             unsafe {
@@ -292,6 +929,184 @@ mod tests {
     extern crate std;
     use super::{SlotArray, SlotRef};
 
+    #[test]
+    fn test_push_passes() {
+        use super::{PushFailure, SLOT_PENDING, SLOT_READY};
+        use core::sync::atomic::Ordering;
+
+        let array: SlotArray<u64, 2> = SlotArray::new();
+        let first = array.push(0).unwrap();
+        let second = array.push(1).unwrap();
+        assert_eq!(array.push_passes(2, 3).err(), Some(PushFailure::Full));
+
+        // A slot caught in the middle of being freed
+        array.slots[second.index]
+            .state
+            .store(SLOT_PENDING, Ordering::Release);
+        assert_eq!(array.push_passes(2, 3).err(), Some(PushFailure::Contended));
+        assert_eq!(array.push_passes(2, 0).err(), Some(PushFailure::Contended));
+        array.slots[second.index]
+            .state
+            .store(SLOT_READY, Ordering::Release);
+
+        drop(first);
+        assert_eq!(*array.push_passes(2, 3).unwrap(), 2);
+    }
+
+    #[cfg(not(feature = "single-thread"))]
+    #[test]
+    fn test_push_passes_threads() {
+        use super::PushFailure;
+        use core::sync::atomic::{AtomicUsize, Ordering};
+
+        const THREADS: usize = 16;
+        let array: SlotArray<usize, 4> = SlotArray::new();
+        let registered = AtomicUsize::new(0);
+        std::thread::scope(|s| {
+            for t in 0..THREADS {
+                let (array, registered) = (&array, &registered);
+                s.spawn(move || {
+                    for _ in 0..200 {
+                        match array.push_passes(t, 2) {
+                            Ok(slot_ref) => {
+                                assert_eq!(*slot_ref, t);
+                                registered.fetch_add(1, Ordering::Relaxed);
+                                std::thread::yield_now();
+                            }
+                            Err(PushFailure::Full | PushFailure::Contended) => {
+                                std::thread::yield_now()
+                            }
+                        }
+                    }
+                });
+            }
+        });
+        assert!(registered.load(Ordering::Relaxed) > 0);
+        // Every slot was freed again
+        let refs: std::vec::Vec<_> = (0..4).map(|value| array.push(value).unwrap()).collect();
+        assert!(array.push(4).is_err());
+        drop(refs);
+    }
+
+    #[test]
+    fn test_send() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<SlotRef<'static, crate::PerProcess, { crate::ARRAY_LEN }>>();
+
+        // A `SlotRef` into a stack array can only be sent to a scoped thread, which ends before the array does
+        let array: SlotArray<u64, 2> = SlotArray::new();
+        let slot_ref = array.push(1).unwrap();
+        let clone = slot_ref.clone();
+        std::thread::scope(|s| {
+            s.spawn(move || drop(clone));
+        });
+        assert_eq!(slot_ref.rc(), 1);
+    }
+
+    #[test]
+    fn test_init_in_place() {
+        use super::{SLOT_EMPTY, Slot};
+        use core::sync::atomic::Ordering;
+        use std::alloc::{Layout, alloc, dealloc};
+        use std::vec::Vec;
+
+        const N: usize = 64;
+        type Array = SlotArray<u64, N>;
+        let layout = Layout::new::<Array>();
+        // Like `set_queue_array_addr_and_init`: the memory holds garbage until the raw write
+        let ptr = unsafe { alloc(layout) } as *mut Array;
+        assert!(!ptr.is_null());
+        unsafe {
+            ptr.cast::<u8>().write_bytes(0xa5, layout.size());
+            ptr.write(SlotArray::new());
+        }
+        let array = unsafe { &*ptr };
+
+        for Slot {
+            state, rc, epoch, ..
+        } in &array.slots
+        {
+            assert_eq!(state.load(Ordering::Acquire), SLOT_EMPTY);
+            assert_eq!(rc.load(Ordering::Acquire), 0);
+            assert_eq!(epoch.load(Ordering::Acquire), 0);
+        }
+        assert_eq!(array.hint.load(Ordering::Relaxed), 0);
+
+        // Every slot can be registered once, then the array is full
+        let refs: Vec<_> = (0..N as u64).map(|i| array.push(i).unwrap()).collect();
+        let mut ids: Vec<_> = refs.iter().map(|slot_ref| slot_ref.id()).collect();
+        ids.sort();
+        assert!(ids.into_iter().eq(0..N));
+        for (i, slot_ref) in refs.iter().enumerate() {
+            assert_eq!(**slot_ref, i as u64);
+        }
+        assert!(array.push(N as u64).is_err());
+
+        drop(refs);
+        unsafe { dealloc(ptr.cast(), layout) };
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_serialize() {
+        use crate::{IPCItem, PerProcess};
+        use core::sync::atomic::Ordering;
+        use std::vec::Vec;
+
+        static ARRAY: SlotArray<PerProcess, 4> = SlotArray::new();
+        static RESTORED: SlotArray<PerProcess, 4> = SlotArray::new();
+        fn item(i: u64) -> IPCItem {
+            IPCItem {
+                sender: i,
+                msg_type: i + 1,
+                rep_type: i + 2,
+                data: [i; 8],
+            }
+        }
+
+        // `PerProcess` is too large for the default test thread stack
+        std::thread::Builder::new()
+            .stack_size(16 << 20)
+            .spawn(|| {
+                // A freed slot, so that the id of the queue reusing it has a non-zero epoch
+                drop(ARRAY.push(PerProcess::default()).unwrap());
+                let a = ARRAY.push(PerProcess::default()).unwrap();
+                let b = ARRAY.push(PerProcess::default()).unwrap();
+                for i in 0..5 {
+                    a.push(item(i)).unwrap();
+                }
+                assert!(a.pop().is_some());
+                a.push_priority(item(10)).unwrap();
+
+                let mut bytes = Vec::new();
+                ARRAY.serialize(&mut bytes).unwrap();
+                assert_eq!(RESTORED.deserialize_into(&mut bytes.as_slice()).unwrap(), 2);
+                // Restoring again fails, the slots are in use
+                assert!(RESTORED.deserialize_into(&mut bytes.as_slice()).is_err());
+
+                for original in [a, b] {
+                    let restored = RESTORED.try_ref_id(original.id()).unwrap();
+                    assert_eq!(
+                        restored.seq.load(Ordering::Acquire),
+                        original.seq.load(Ordering::Acquire)
+                    );
+                    loop {
+                        let (expected, popped) = (original.pop(), restored.pop());
+                        assert_eq!(
+                            expected.map(|item| item.sender),
+                            popped.map(|item| item.sender)
+                        );
+                        if expected.is_none() {
+                            break;
+                        }
+                    }
+                }
+            })
+            .unwrap()
+            .join()
+            .unwrap();
+    }
+
     #[test]
     fn test_sequential() {
         let array: SlotArray<usize, 4> = SlotArray::new();
@@ -319,6 +1134,363 @@ mod tests {
         assert_eq!(*slot6, 60);
     }
 
+    #[test]
+    fn test_as_ptr() {
+        let array: SlotArray<usize, 4> = SlotArray::new();
+        let slot = array.push(10).unwrap();
+        let ptr = slot.as_ptr();
+        assert_eq!(unsafe { *ptr.as_ref() }, 10);
+        assert_eq!(slot.clone().as_ptr(), ptr);
+        assert_eq!(array.try_ref(slot.index).unwrap().as_ptr(), ptr);
+        assert_ne!(array.push(20).unwrap().as_ptr(), ptr);
+    }
+
+    #[test]
+    fn test_push_after_hint() {
+        let array: SlotArray<usize, 4> = SlotArray::new();
+        let slot0 = array.push(0).unwrap();
+        let slot1 = array.push(1).unwrap();
+        assert_eq!((slot0.index, slot1.index), (0, 1));
+        drop(slot0);
+        // The scan starts after the last pushed slot, and wraps around
+        let slot2 = array.push(2).unwrap();
+        let slot3 = array.push(3).unwrap();
+        let slot4 = array.push(4).unwrap();
+        assert_eq!((slot2.index, slot3.index, slot4.index), (2, 3, 0));
+        assert!(array.push(5).is_err());
+    }
+
+    #[test]
+    fn test_push_bounded() {
+        use std::vec::Vec;
+
+        let array: SlotArray<usize, 8> = SlotArray::new();
+        let mut slots: Vec<_> = (0..8).map(|i| array.push(i).unwrap()).collect();
+        // Only slot 6 is free, and the next scan starts from slot 0
+        drop(slots.remove(6));
+        assert!(array.push_bounded(8, 4).is_err());
+        assert!(array.push_bounded(8, 6).is_err());
+        let slot = array.push_bounded(8, 7).unwrap();
+        assert_eq!(slot.index, 6);
+        assert!(array.push_bounded(9, usize::MAX).is_err());
+    }
+
+    // run with `cargo test bench_register --features std -- --ignored --nocapture`
+    #[cfg(feature = "std")]
+    #[test]
+    #[ignore]
+    fn bench_register() {
+        use core::sync::atomic::Ordering;
+        use std::{println, thread, time::Instant, vec::Vec};
+
+        const THREADS: usize = 32;
+        const ROUNDS: usize = 10000;
+        static BENCH_ARRAY: SlotArray<usize, 64> = SlotArray::new();
+
+        let run = |thread_local: bool| {
+            let start = Instant::now();
+            let handles: Vec<_> = (0..THREADS)
+                .map(|_| {
+                    thread::spawn(move || {
+                        for i in 0..ROUNDS {
+                            let index = if thread_local {
+                                BENCH_ARRAY.push_(i)
+                            } else {
+                                BENCH_ARRAY.push_from(
+                                    i,
+                                    BENCH_ARRAY.hint.load(Ordering::Relaxed),
+                                    64,
+                                )
+                            }
+                            .unwrap();
+                            drop(SlotRef {
+                                array: &BENCH_ARRAY,
+                                index,
+                            });
+                        }
+                    })
+                })
+                .collect();
+            handles.into_iter().for_each(|h| h.join().unwrap());
+            start.elapsed()
+        };
+
+        let shared = run(false);
+        let local = run(true);
+        println!("shared hint: {:?}, thread-local hint: {:?}", shared, local);
+    }
+
+    #[test]
+    fn test_try_ref() {
+        let array: SlotArray<usize, 4> = SlotArray::new();
+        let slot = array.push(10).unwrap();
+        assert!(array.try_ref(1).is_none());
+        assert!(array.try_ref(4).is_none());
+
+        let slot_clone = array.try_ref(slot.index).unwrap();
+        assert_eq!(*slot_clone, 10);
+        assert_eq!(slot.rc(), 2);
+        drop(slot);
+        assert_eq!(slot_clone.rc(), 1);
+        let index = slot_clone.index;
+        drop(slot_clone);
+        assert!(array.try_ref(index).is_none());
+    }
+
+    #[test]
+    fn test_drop_in_place_all() {
+        use core::sync::atomic::{AtomicUsize, Ordering};
+
+        static DROPPED: AtomicUsize = AtomicUsize::new(0);
+        struct DropCounter;
+        impl Drop for DropCounter {
+            fn drop(&mut self) {
+                DROPPED.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+
+        let array: SlotArray<DropCounter, 4> = SlotArray::new();
+        for _ in 0..3 {
+            core::mem::forget(array.push(DropCounter).unwrap());
+        }
+        assert_eq!(DROPPED.load(Ordering::Relaxed), 0);
+        assert_eq!(unsafe { array.drop_in_place_all() }, 3);
+        assert_eq!(DROPPED.load(Ordering::Relaxed), 3);
+
+        // All slots are empty again
+        let slots: std::vec::Vec<_> = (0..4).map(|_| array.push(DropCounter).unwrap()).collect();
+        drop(slots);
+        assert_eq!(DROPPED.load(Ordering::Relaxed), 7);
+    }
+
+    #[cfg(feature = "saturating-rc")]
+    #[test]
+    fn test_saturating_rc() {
+        use core::sync::atomic::{AtomicUsize, Ordering};
+
+        static DROPPED: AtomicUsize = AtomicUsize::new(0);
+        struct DropCounter;
+        impl Drop for DropCounter {
+            fn drop(&mut self) {
+                DROPPED.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+
+        let array: SlotArray<DropCounter, 2> = SlotArray::new();
+        let slot = array.push(DropCounter).unwrap();
+        let clones: std::vec::Vec<_> = (0..300).map(|_| slot.clone()).collect();
+        assert_eq!(slot.rc(), u8::MAX);
+        assert!(array.try_ref(slot.index).is_some());
+
+        // Once saturated, the slot is leaked: dropping every reference never frees it
+        let index = slot.index;
+        drop(clones);
+        drop(slot);
+        assert_eq!(DROPPED.load(Ordering::Relaxed), 0);
+        assert_eq!(array.try_ref(index).unwrap().rc(), u8::MAX);
+
+        // Slots that never saturate are still freed
+        drop(array.push(DropCounter).unwrap());
+        assert_eq!(DROPPED.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn test_defrag() {
+        use std::vec::Vec;
+
+        let array: SlotArray<usize, 8> = SlotArray::new();
+        let leak = |slot: SlotRef<'_, usize, 8>| {
+            let id = slot.id();
+            core::mem::forget(slot);
+            id
+        };
+        let mut ids: Vec<usize> = (0..8).map(|i| leak(array.push(i * 10).unwrap())).collect();
+        // Free every slot except 1, 4, 6 and 7
+        for index in [0, 2, 3, 5] {
+            drop(SlotRef {
+                array: &array,
+                index,
+            });
+        }
+        ids.retain(|&id| [1, 4, 6, 7].contains(&id));
+        // A second reference to slot 7 moves along with it
+        let mut extra = leak(array.try_ref(7).unwrap());
+        let old_extra = extra;
+
+        unsafe {
+            array.defrag(|old, new| {
+                for id in ids.iter_mut().chain(core::iter::once(&mut extra)) {
+                    if *id == old {
+                        *id = new;
+                    }
+                }
+            })
+        };
+        assert!(array.index_of(old_extra).is_none());
+        let mut indices: Vec<usize> = ids.iter().map(|&id| array.index_of(id).unwrap()).collect();
+        indices.sort();
+        assert_eq!(indices, [0, 1, 2, 3]);
+        let extra = array.index_of(extra).unwrap();
+        assert_eq!(extra, 0);
+
+        let refs: Vec<_> = indices
+            .iter()
+            .map(|&index| SlotRef {
+                array: &array,
+                index,
+            })
+            .collect();
+        let values: Vec<_> = refs.iter().map(|slot| **slot).collect();
+        assert_eq!(values, [70, 10, 60, 40]);
+        assert_eq!(refs[0].rc(), 2);
+        drop(SlotRef {
+            array: &array,
+            index: extra,
+        });
+        assert_eq!(refs[0].rc(), 1);
+
+        // Freed slots are reusable from the low end
+        let slot = array.push(80).unwrap();
+        assert_eq!(slot.index, 4);
+    }
+
+    #[test]
+    fn test_stale_id() {
+        let array: SlotArray<usize, 2> = SlotArray::new();
+        let slot = array.push(10).unwrap();
+        let old_id = slot.id();
+        // The first occupant's id is its index
+        assert_eq!(old_id, slot.index);
+        assert_eq!(*array.try_ref_id(old_id).unwrap(), 10);
+        drop(slot);
+
+        // Re-register into the same slot: the old id must not reach the new value
+        let _first = array.push(0).unwrap();
+        let slot = array.push(20).unwrap();
+        assert_eq!(slot.index, old_id);
+        assert_ne!(slot.id(), old_id);
+        assert_eq!(slot.id() >> super::ID_INDEX_BITS, 1);
+        assert!(array.index_of(old_id).is_none());
+        assert!(array.try_ref_id(old_id).is_none());
+        assert_eq!(*array.try_ref_id(slot.id()).unwrap(), 20);
+    }
+
+    #[test]
+    fn test_sentinel_id() {
+        let array: SlotArray<u8, { crate::ARRAY_LEN }> = SlotArray::new();
+        assert!(array.index_of(crate::NO_MONITOR).is_none());
+        assert!(array.index_of(crate::NO_OVERFLOW_QUEUE).is_none());
+    }
+
+    #[test]
+    fn test_pin_id() {
+        use core::sync::atomic::{AtomicBool, Ordering};
+
+        static DROPPED: AtomicBool = AtomicBool::new(false);
+        struct Tracked(usize);
+        impl Drop for Tracked {
+            fn drop(&mut self) {
+                DROPPED.store(true, Ordering::Release);
+            }
+        }
+
+        let array: SlotArray<Tracked, 2> = SlotArray::new();
+        let owner = array.push(Tracked(10)).unwrap();
+        let id = owner.id();
+        let pinned = array.pin_id(id);
+        assert_eq!(pinned.rc(), 2);
+
+        // The owner unregisters while an operation holds the pinned reference:
+        // the value stays alive until the operation finishes
+        drop(owner);
+        assert!(!DROPPED.load(Ordering::Acquire));
+        assert_eq!(pinned.0, 10);
+        drop(pinned);
+        assert!(DROPPED.load(Ordering::Acquire));
+        assert!(array.try_ref_id(id).is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "stale id")]
+    fn test_pin_stale_id() {
+        let array: SlotArray<usize, 2> = SlotArray::new();
+        let id = array.push(10).unwrap().id();
+        array.pin_id(id);
+    }
+
+    #[test]
+    #[should_panic(expected = "not in use")]
+    fn test_pin_unused_id() {
+        let array: SlotArray<usize, 2> = SlotArray::new();
+        array.pin_id(1);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_value_range() {
+        let array: SlotArray<[u64; 5], 4> = SlotArray::new();
+        let slot = array.push([1; 5]).unwrap();
+        let (start, len) = array.value_range(slot.index);
+        assert_eq!(start, slot.get() as *const [u64; 5] as usize);
+        assert_eq!(len, 40);
+
+        let (next, _) = array.value_range(slot.index + 1);
+        assert_eq!(next - start, size_of::<super::Slot<[u64; 5]>>());
+    }
+
+    #[cfg(not(feature = "single-thread"))]
+    #[test]
+    fn test_get_vs_drop() {
+        use std::sync::atomic::{AtomicBool, Ordering};
+
+        const MAGIC: u64 = 0x5eed_f00d;
+        const ROUNDS: u64 = 20000;
+
+        /// Poisoned when dropped, so a read through a dangling reference shows up as a wrong `check`
+        struct Value {
+            n: u64,
+            check: u64,
+        }
+        impl Drop for Value {
+            fn drop(&mut self) {
+                self.check = 0;
+            }
+        }
+
+        // One slot per reader, each holding at most one guard, plus one for the writer, so pushes can't fail
+        let array: SlotArray<Value, 4> = SlotArray::new();
+        let done = AtomicBool::new(false);
+        std::thread::scope(|s| {
+            for _ in 0..3 {
+                s.spawn(|| {
+                    while !done.load(Ordering::Acquire) {
+                        for i in 0..4 {
+                            if let Some(value) = array.get(i) {
+                                // The guard keeps the value alive even if the owner drops its reference now
+                                std::thread::yield_now();
+                                assert_eq!(value.check, value.n ^ MAGIC);
+                            }
+                        }
+                    }
+                });
+            }
+            // Push and final-drop over and over, so the slots keep being freed and reused for new values
+            for n in 0..ROUNDS {
+                let slot_ref = array
+                    .push(Value {
+                        n,
+                        check: n ^ MAGIC,
+                    })
+                    .unwrap();
+                if n % 64 == 0 {
+                    std::thread::yield_now();
+                }
+                drop(slot_ref);
+            }
+            done.store(true, Ordering::Release);
+        });
+    }
+
     const THREAD_NUM: usize = 16;
     const DATA_PER_THREAD: usize = 1000;
     const TOTAL_DATA: usize = (THREAD_NUM + 1) * DATA_PER_THREAD;
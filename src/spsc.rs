@@ -0,0 +1,682 @@
+//! A single-producer single-consumer ring buffer, for one-to-one channels.
+//!
+//! `LockFreeDeque` keeps a state byte per slot so that several producers and consumers can each claim a slot
+//! before moving an index. With exactly one producer and one consumer, the indices alone are enough:
+//! the producer writes the slot, then publishes it with a Release store of `tail`; the consumer reads it
+//! after an Acquire load of `tail`, then hands it back with a Release store of `head`. No CAS and no
+//! per-slot state are needed, and `head` and `tail` live on separate cache lines so that the two sides
+//! don't false-share.
+//!
+//! The buffer thus holds only the items: compared to a `LockFreeDeque` of the same capacity, each slot is
+//! smaller by its state byte, and often by the padding after it (8 bytes per slot for `u64` items).
+//! For deep queues with one producer and one consumer, this is the compact variant. The price is that
+//! it only works for a single producer and a single consumer, or with external synchronization on each side.
+//! It also supports no in-place construction like `LockFreeDeque::push_slot_back`: a slot published
+//! before its item is written needs a WRITING state to keep the consumer off it.
+//!
+//! `StatelessDeque` is the same idea for a deque: one producer pushing to either end, one consumer popping
+//! from either end. As both sides then move both indices, head and tail are packed into one word like in
+//! `LockFreeDeque`, and each operation publishes or hands back its slot with a CAS of that word. The producer
+//! only ever grows the range between head and tail, and the consumer only shrinks it, so a slot inside the
+//! range seen by the consumer stays put until its CAS, and a slot outside the range seen by the producer
+//! is free: still no per-slot state is needed.
+
+use core::cell::UnsafeCell;
+use core::mem::MaybeUninit;
+use core::sync::atomic::Ordering;
+
+use crate::deque::{END_BITS, pack, unpack};
+use crate::sync::{AtomicUsize, const_fn};
+
+/// Aligns its content to a cache line, so that it doesn't share one with neighbouring fields
+#[repr(align(64))]
+pub(crate) struct CacheAligned<T>(pub(crate) T);
+
+/// A fixed-capacity ring buffer for one producer and one consumer, see the module documentation
+///
+/// `split` gives a safe producer and consumer handle. When the ring lives in shared memory, where
+/// handles can't be passed around, each side calls the unsafe `push` or `pop` directly instead.
+pub struct SpscRing<T, const CAPACITY: usize> {
+    /// Index of the next item to pop, only written by the consumer
+    head: CacheAligned<AtomicUsize>,
+    /// Index of the next slot to push to, only written by the producer
+    tail: CacheAligned<AtomicUsize>,
+    buffer: [UnsafeCell<MaybeUninit<T>>; CAPACITY],
+}
+
+impl<T, const CAPACITY: usize> SpscRing<T, CAPACITY> {
+    const_fn! {
+        /// Create an empty ring with compile-time capacity
+        pub const fn new() -> Self {
+            Self {
+                head: CacheAligned(AtomicUsize::new(0)),
+                tail: CacheAligned(AtomicUsize::new(0)),
+                buffer: [const { UnsafeCell::new(MaybeUninit::uninit()) }; CAPACITY],
+            }
+        }
+    }
+
+    /// Get the capacity of the ring, `CAPACITY - 1`: like `LockFreeDeque`, one slot is always kept empty
+    pub const fn capacity(&self) -> usize {
+        CAPACITY - 1
+    }
+
+    /// Get the number of items in the ring (approximate while the other side is operating)
+    pub fn len(&self) -> usize {
+        let head = self.head.0.load(Ordering::Acquire);
+        let tail = self.tail.0.load(Ordering::Acquire);
+        (tail + CAPACITY - head) % CAPACITY
+    }
+
+    /// Check if the ring is empty (approximate while the other side is operating)
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Push an item to the ring
+    /// Returns Err(item) if the ring is full
+    ///
+    /// # Safety
+    ///
+    /// Only one thread (or process) may push to the ring: no other `push` may run concurrently,
+    /// and no `SpscProducer` of it may exist.
+    pub unsafe fn push(&self, item: T) -> Result<(), T> {
+        // Only this side writes `tail`
+        let tail = self.tail.0.load(Ordering::Relaxed);
+        let next = (tail + 1) % CAPACITY;
+        if next == self.head.0.load(Ordering::Acquire) {
+            return Err(item);
+        }
+        // Safe because the slot is outside `head..tail`, so the consumer doesn't touch it until published
+        unsafe { (*self.buffer[tail].get()).write(item) };
+        self.tail.0.store(next, Ordering::Release);
+        Ok(())
+    }
+
+    /// Pop the oldest item from the ring
+    /// Returns None if the ring is empty
+    ///
+    /// # Safety
+    ///
+    /// Only one thread (or process) may pop from the ring: no other `pop` may run concurrently,
+    /// and no `SpscConsumer` of it may exist.
+    pub unsafe fn pop(&self) -> Option<T> {
+        // Only this side writes `head`
+        let head = self.head.0.load(Ordering::Relaxed);
+        if head == self.tail.0.load(Ordering::Acquire) {
+            return None;
+        }
+        // Safe because the slot was published by the producer's Release store of `tail`,
+        // and it won't reuse it before the Release store of `head` below
+        let item = unsafe { (*self.buffer[head].get()).assume_init_read() };
+        self.head.0.store((head + 1) % CAPACITY, Ordering::Release);
+        Some(item)
+    }
+
+    /// Split the ring into its producer and consumer handles, which can be sent to different threads
+    pub fn split(&mut self) -> (SpscProducer<'_, T, CAPACITY>, SpscConsumer<'_, T, CAPACITY>) {
+        let ring = &*self;
+        (SpscProducer { ring }, SpscConsumer { ring })
+    }
+}
+
+impl<T, const CAPACITY: usize> Default for SpscRing<T, CAPACITY> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T, const CAPACITY: usize> Drop for SpscRing<T, CAPACITY> {
+    fn drop(&mut self) {
+        // Safe because `&mut self` excludes any other producer or consumer
+        while unsafe { self.pop() }.is_some() {}
+    }
+}
+
+// Safety: the producer and the consumer never access the same slot at the same time, see the module documentation
+unsafe impl<T: Send, const CAPACITY: usize> Send for SpscRing<T, CAPACITY> {}
+unsafe impl<T: Send, const CAPACITY: usize> Sync for SpscRing<T, CAPACITY> {}
+
+/// The producer side of a split `SpscRing`
+pub struct SpscProducer<'a, T, const CAPACITY: usize> {
+    ring: &'a SpscRing<T, CAPACITY>,
+}
+
+impl<T, const CAPACITY: usize> SpscProducer<'_, T, CAPACITY> {
+    /// Push an item to the ring
+    /// Returns Err(item) if the ring is full
+    pub fn push(&mut self, item: T) -> Result<(), T> {
+        // Safe because this handle is the only producer, and `&mut self` serializes its pushes
+        unsafe { self.ring.push(item) }
+    }
+
+    /// Get the number of items in the ring, see `SpscRing::len`
+    pub fn len(&self) -> usize {
+        self.ring.len()
+    }
+
+    /// Check if the ring is empty, see `SpscRing::is_empty`
+    pub fn is_empty(&self) -> bool {
+        self.ring.is_empty()
+    }
+}
+
+/// The consumer side of a split `SpscRing`
+pub struct SpscConsumer<'a, T, const CAPACITY: usize> {
+    ring: &'a SpscRing<T, CAPACITY>,
+}
+
+impl<T, const CAPACITY: usize> SpscConsumer<'_, T, CAPACITY> {
+    /// Pop the oldest item from the ring
+    /// Returns None if the ring is empty
+    pub fn pop(&mut self) -> Option<T> {
+        // Safe because this handle is the only consumer, and `&mut self` serializes its pops
+        unsafe { self.ring.pop() }
+    }
+
+    /// Get the number of items in the ring, see `SpscRing::len`
+    pub fn len(&self) -> usize {
+        self.ring.len()
+    }
+
+    /// Check if the ring is empty, see `SpscRing::is_empty`
+    pub fn is_empty(&self) -> bool {
+        self.ring.is_empty()
+    }
+}
+
+/// A fixed-capacity deque for one producer and one consumer, each using either end, with no per-slot state
+///
+/// See the module documentation. Like `SpscRing`, it only works for a single producer and a single consumer,
+/// or with external synchronization on each side, and it supports no in-place construction
+/// (`LockFreeDeque::push_slot_back` needs the WRITING state). It takes `CAPACITY` bytes less than a
+/// `LockFreeDeque` of the same capacity, and more with the padding after each state byte.
+/// `CAPACITY` is limited to `2^(usize::BITS / 2)`, like for `LockFreeDeque`.
+///
+/// `split` gives a safe producer and consumer handle, or each side calls the unsafe methods directly.
+pub struct StatelessDeque<T, const CAPACITY: usize> {
+    /// Head and tail packed into one word, like `LockFreeDeque::ends`
+    ends: AtomicUsize,
+    buffer: [UnsafeCell<MaybeUninit<T>>; CAPACITY],
+}
+
+impl<T, const CAPACITY: usize> StatelessDeque<T, CAPACITY> {
+    const_fn! {
+        /// Create an empty deque with compile-time capacity
+        pub const fn new() -> Self {
+            const {
+                assert!(
+                    CAPACITY <= 1 << END_BITS,
+                    "CAPACITY doesn't fit in half a usize"
+                )
+            };
+            Self {
+                ends: AtomicUsize::new(0),
+                buffer: [const { UnsafeCell::new(MaybeUninit::uninit()) }; CAPACITY],
+            }
+        }
+    }
+
+    /// Get the capacity of the deque, `CAPACITY - 1`: like `LockFreeDeque`, one slot is always kept empty
+    pub const fn capacity(&self) -> usize {
+        CAPACITY - 1
+    }
+
+    /// Get the number of items in the deque (approximate while the other side is operating)
+    pub fn len(&self) -> usize {
+        let (head, tail) = unpack(self.ends.load(Ordering::Acquire));
+        (tail + CAPACITY - head) % CAPACITY
+    }
+
+    /// Check if the deque is empty (approximate while the other side is operating)
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Push an item to the front of the deque
+    /// Returns Err(item) if the deque is full
+    ///
+    /// # Safety
+    ///
+    /// Only one thread (or process) may push to the deque, at either end: no other push may run concurrently,
+    /// and no `StatelessProducer` of it may exist.
+    pub unsafe fn push_front(&self, item: T) -> Result<(), T> {
+        unsafe { self.push_(true, item) }
+    }
+
+    /// Push an item to the back of the deque, see `push_front`
+    /// Returns Err(item) if the deque is full
+    ///
+    /// # Safety
+    ///
+    /// Same as `push_front`.
+    pub unsafe fn push_back(&self, item: T) -> Result<(), T> {
+        unsafe { self.push_(false, item) }
+    }
+
+    /// Pop an item from the front of the deque
+    /// Returns None if the deque is empty
+    ///
+    /// # Safety
+    ///
+    /// Only one thread (or process) may pop from the deque, at either end: no other pop may run concurrently,
+    /// and no `StatelessConsumer` of it may exist.
+    pub unsafe fn pop_front(&self) -> Option<T> {
+        unsafe { self.pop_(true) }
+    }
+
+    /// Pop an item from the back of the deque, see `pop_front`
+    /// Returns None if the deque is empty
+    ///
+    /// # Safety
+    ///
+    /// Same as `pop_front`.
+    pub unsafe fn pop_back(&self) -> Option<T> {
+        unsafe { self.pop_(false) }
+    }
+
+    /// Push an item to the front (if `front`) or back of the deque
+    ///
+    /// The item is written into the slot first, then published by moving the index with a CAS. The CAS fails
+    /// only if the consumer moved an index meanwhile: the item is then taken back and the push retried.
+    unsafe fn push_(&self, front: bool, mut item: T) -> Result<(), T> {
+        let mut ends = self.ends.load(Ordering::Acquire);
+        loop {
+            let (head, tail) = unpack(ends);
+            if (tail + 1) % CAPACITY == head {
+                return Err(item);
+            }
+            // The slot to write, and the new ends
+            let (index, new) = if front {
+                let head = (head + CAPACITY - 1) % CAPACITY;
+                (head, pack(head, tail))
+            } else {
+                (tail, pack(head, (tail + 1) % CAPACITY))
+            };
+            let slot = self.buffer[index].get();
+            // Safe because the slot is outside head..tail, and the consumer only shrinks that range
+            unsafe { (*slot).write(item) };
+            match self
+                .ends
+                .compare_exchange_weak(ends, new, Ordering::AcqRel, Ordering::Acquire)
+            {
+                Ok(_) => return Ok(()),
+                Err(actual) => {
+                    // Safe because the slot was not published, so it still holds the item just written
+                    item = unsafe { (*slot).assume_init_read() };
+                    ends = actual;
+                }
+            }
+        }
+    }
+
+    /// Pop an item from the front (if `front`) or back of the deque
+    ///
+    /// The item is copied out of the slot first, then the slot is handed back by moving the index with a CAS:
+    /// afterwards, the producer may reuse it. The copy is only kept if the CAS succeeds.
+    unsafe fn pop_(&self, front: bool) -> Option<T> {
+        let mut ends = self.ends.load(Ordering::Acquire);
+        loop {
+            let (head, tail) = unpack(ends);
+            if head == tail {
+                return None;
+            }
+            // The slot to read, and the new ends
+            let (index, new) = if front {
+                (head, pack((head + 1) % CAPACITY, tail))
+            } else {
+                let tail = (tail + CAPACITY - 1) % CAPACITY;
+                (tail, pack(head, tail))
+            };
+            // Safe because the slot is inside head..tail, and the producer only grows that range
+            let item = unsafe { self.buffer[index].get().read() };
+            match self
+                .ends
+                .compare_exchange_weak(ends, new, Ordering::AcqRel, Ordering::Acquire)
+            {
+                // Safe because the slot held a published item, now ours
+                Ok(_) => return Some(unsafe { item.assume_init() }),
+                Err(actual) => ends = actual,
+            }
+        }
+    }
+
+    /// Split the deque into its producer and consumer handles, which can be sent to different threads
+    pub fn split(
+        &mut self,
+    ) -> (
+        StatelessProducer<'_, T, CAPACITY>,
+        StatelessConsumer<'_, T, CAPACITY>,
+    ) {
+        let deque = &*self;
+        (StatelessProducer { deque }, StatelessConsumer { deque })
+    }
+}
+
+impl<T, const CAPACITY: usize> Default for StatelessDeque<T, CAPACITY> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T, const CAPACITY: usize> Drop for StatelessDeque<T, CAPACITY> {
+    fn drop(&mut self) {
+        // Safe because `&mut self` excludes any other producer or consumer
+        while unsafe { self.pop_front() }.is_some() {}
+    }
+}
+
+// Safety: the producer and the consumer never access the same slot at the same time, see the module documentation
+unsafe impl<T: Send, const CAPACITY: usize> Send for StatelessDeque<T, CAPACITY> {}
+unsafe impl<T: Send, const CAPACITY: usize> Sync for StatelessDeque<T, CAPACITY> {}
+
+/// The producer side of a split `StatelessDeque`
+pub struct StatelessProducer<'a, T, const CAPACITY: usize> {
+    deque: &'a StatelessDeque<T, CAPACITY>,
+}
+
+impl<T, const CAPACITY: usize> StatelessProducer<'_, T, CAPACITY> {
+    /// Push an item to the front of the deque
+    /// Returns Err(item) if the deque is full
+    pub fn push_front(&mut self, item: T) -> Result<(), T> {
+        // Safe because this handle is the only producer, and `&mut self` serializes its pushes
+        unsafe { self.deque.push_front(item) }
+    }
+
+    /// Push an item to the back of the deque
+    /// Returns Err(item) if the deque is full
+    pub fn push_back(&mut self, item: T) -> Result<(), T> {
+        // Safe because this handle is the only producer, and `&mut self` serializes its pushes
+        unsafe { self.deque.push_back(item) }
+    }
+
+    /// Get the number of items in the deque, see `StatelessDeque::len`
+    pub fn len(&self) -> usize {
+        self.deque.len()
+    }
+
+    /// Check if the deque is empty, see `StatelessDeque::is_empty`
+    pub fn is_empty(&self) -> bool {
+        self.deque.is_empty()
+    }
+}
+
+/// The consumer side of a split `StatelessDeque`
+pub struct StatelessConsumer<'a, T, const CAPACITY: usize> {
+    deque: &'a StatelessDeque<T, CAPACITY>,
+}
+
+impl<T, const CAPACITY: usize> StatelessConsumer<'_, T, CAPACITY> {
+    /// Pop an item from the front of the deque
+    /// Returns None if the deque is empty
+    pub fn pop_front(&mut self) -> Option<T> {
+        // Safe because this handle is the only consumer, and `&mut self` serializes its pops
+        unsafe { self.deque.pop_front() }
+    }
+
+    /// Pop an item from the back of the deque
+    /// Returns None if the deque is empty
+    pub fn pop_back(&mut self) -> Option<T> {
+        // Safe because this handle is the only consumer, and `&mut self` serializes its pops
+        unsafe { self.deque.pop_back() }
+    }
+
+    /// Get the number of items in the deque, see `StatelessDeque::len`
+    pub fn len(&self) -> usize {
+        self.deque.len()
+    }
+
+    /// Check if the deque is empty, see `StatelessDeque::is_empty`
+    pub fn is_empty(&self) -> bool {
+        self.deque.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use super::{SpscRing, StatelessDeque};
+
+    #[test]
+    fn test_wraparound() {
+        let mut ring: SpscRing<u32, 4> = SpscRing::new();
+        let (mut producer, mut consumer) = ring.split();
+        assert_eq!(consumer.pop(), None);
+
+        // Batches of 3 and 2 items start at every offset of the 4-slot buffer
+        let mut next = 0;
+        let mut expected = 0;
+        for round in 0..20 {
+            let batch = 2 + round % 2;
+            for _ in 0..batch {
+                assert!(producer.push(next).is_ok());
+                next += 1;
+            }
+            if batch == 3 {
+                assert_eq!(producer.push(next), Err(next));
+            }
+            assert_eq!(consumer.len(), batch as usize);
+            for _ in 0..batch {
+                assert_eq!(consumer.pop(), Some(expected));
+                expected += 1;
+            }
+            assert!(consumer.is_empty());
+        }
+    }
+
+    #[test]
+    fn test_size() {
+        use core::mem::size_of;
+
+        const CAPACITY: usize = 4096;
+        // Only the items, plus a cache line for each index
+        assert_eq!(
+            size_of::<SpscRing<u64, CAPACITY>>(),
+            CAPACITY * size_of::<u64>() + 2 * 64
+        );
+        // Only the items, plus the packed indices
+        assert_eq!(
+            size_of::<StatelessDeque<u64, CAPACITY>>(),
+            CAPACITY * size_of::<u64>() + size_of::<usize>()
+        );
+        assert_eq!(
+            size_of::<StatelessDeque<u8, CAPACITY>>(),
+            CAPACITY + size_of::<usize>()
+        );
+        // A state byte per slot, padded to the alignment of the item, plus the packed indices, the item count
+        // and the poison flag padded to a word. The debug features add more per-slot fields
+        #[cfg(not(any(feature = "debug-history", feature = "timing")))]
+        {
+            use crate::LockFreeDeque;
+
+            assert_eq!(
+                size_of::<LockFreeDeque<u64, CAPACITY>>(),
+                size_of::<StatelessDeque<u64, CAPACITY>>()
+                    + CAPACITY * size_of::<u64>()
+                    + 2 * size_of::<usize>()
+            );
+            assert_eq!(
+                size_of::<LockFreeDeque<u8, CAPACITY>>(),
+                size_of::<StatelessDeque<u8, CAPACITY>>() + CAPACITY + 2 * size_of::<usize>()
+            );
+        }
+    }
+
+    #[test]
+    fn test_stateless_ends() {
+        let mut deque: StatelessDeque<u32, 4> = StatelessDeque::new();
+        assert_eq!(deque.capacity(), 3);
+        let (mut producer, mut consumer) = deque.split();
+        assert_eq!(consumer.pop_front(), None);
+        assert_eq!(consumer.pop_back(), None);
+
+        // Rounds start at every offset of the 4-slot buffer, both ends moving both ways
+        for round in 0..8 {
+            assert!(producer.push_back(round + 1).is_ok());
+            assert!(producer.push_front(round).is_ok());
+            assert!(producer.push_back(round + 2).is_ok());
+            assert_eq!(producer.push_front(0), Err(0));
+            assert_eq!(producer.len(), 3);
+            assert_eq!(consumer.pop_back(), Some(round + 2));
+            assert_eq!(consumer.pop_front(), Some(round));
+            if round % 2 == 0 {
+                assert_eq!(consumer.pop_front(), Some(round + 1));
+            } else {
+                assert_eq!(consumer.pop_back(), Some(round + 1));
+            }
+            assert!(consumer.is_empty());
+        }
+    }
+
+    #[test]
+    fn test_drop() {
+        use std::rc::Rc;
+
+        let item = Rc::new(());
+        let ring: SpscRing<Rc<()>, 4> = SpscRing::new();
+        for _ in 0..3 {
+            assert!(unsafe { ring.push(item.clone()) }.is_ok());
+        }
+        drop(unsafe { ring.pop() });
+        assert_eq!(Rc::strong_count(&item), 3);
+        drop(ring);
+        assert_eq!(Rc::strong_count(&item), 1);
+
+        let deque: StatelessDeque<Rc<()>, 4> = StatelessDeque::new();
+        assert!(unsafe { deque.push_front(item.clone()) }.is_ok());
+        assert!(unsafe { deque.push_back(item.clone()) }.is_ok());
+        assert_eq!(Rc::strong_count(&item), 3);
+        drop(deque);
+        assert_eq!(Rc::strong_count(&item), 1);
+    }
+
+    #[cfg(not(feature = "single-thread"))]
+    #[test]
+    fn test_threads() {
+        const ITEMS: u64 = 100000;
+        let mut ring: SpscRing<u64, 16> = SpscRing::new();
+        let (mut producer, mut consumer) = ring.split();
+        std::thread::scope(|s| {
+            s.spawn(move || {
+                for i in 0..ITEMS {
+                    let mut item = i;
+                    while let Err(rejected) = producer.push(item) {
+                        item = rejected;
+                        std::thread::yield_now();
+                    }
+                }
+            });
+            let mut expected = 0;
+            while expected < ITEMS {
+                match consumer.pop() {
+                    Some(item) => {
+                        assert_eq!(item, expected);
+                        expected += 1;
+                    }
+                    None => std::thread::yield_now(),
+                }
+            }
+        });
+        assert!(ring.is_empty());
+    }
+
+    #[cfg(not(feature = "single-thread"))]
+    #[test]
+    fn test_stateless_threads() {
+        const ITEMS: u64 = 100000;
+        let mut deque: StatelessDeque<u64, 16> = StatelessDeque::new();
+        let (mut producer, mut consumer) = deque.split();
+        std::thread::scope(|s| {
+            s.spawn(move || {
+                for i in 0..ITEMS {
+                    let mut item = i;
+                    loop {
+                        let res = match i % 2 {
+                            0 => producer.push_back(item),
+                            _ => producer.push_front(item),
+                        };
+                        match res {
+                            Ok(()) => break,
+                            Err(rejected) => item = rejected,
+                        }
+                        std::thread::yield_now();
+                    }
+                }
+            });
+            // Every item arrives exactly once, whatever the ends
+            let mut seen = std::vec![false; ITEMS as usize];
+            let mut received = 0;
+            while received < ITEMS {
+                let item = match received % 3 {
+                    0 => consumer.pop_back(),
+                    _ => consumer.pop_front(),
+                };
+                match item {
+                    Some(item) => {
+                        assert!(!core::mem::replace(&mut seen[item as usize], true));
+                        received += 1;
+                    }
+                    None => std::thread::yield_now(),
+                }
+            }
+        });
+        assert!(deque.is_empty());
+    }
+
+    #[cfg(all(
+        not(feature = "single-thread"),
+        feature = "deque-front",
+        feature = "deque-back"
+    ))]
+    #[test]
+    #[ignore]
+    fn bench_spsc() {
+        use crate::LockFreeDeque;
+        use std::{println, time::Instant};
+
+        const ITEMS: u64 = 1 << 22;
+
+        let mut ring: SpscRing<u64, 1024> = SpscRing::new();
+        let (mut producer, mut consumer) = ring.split();
+        let start = Instant::now();
+        std::thread::scope(|s| {
+            s.spawn(move || {
+                for i in 0..ITEMS {
+                    while producer.push(i).is_err() {
+                        std::thread::yield_now();
+                    }
+                }
+            });
+            let mut received = 0;
+            while received < ITEMS {
+                match consumer.pop() {
+                    Some(_) => received += 1,
+                    None => std::thread::yield_now(),
+                }
+            }
+        });
+        let spsc = start.elapsed();
+
+        let deque: LockFreeDeque<u64, 1024> = LockFreeDeque::new();
+        let start = Instant::now();
+        std::thread::scope(|s| {
+            s.spawn(|| {
+                for i in 0..ITEMS {
+                    while deque.push_back(i).is_err() {
+                        std::thread::yield_now();
+                    }
+                }
+            });
+            let mut received = 0;
+            while received < ITEMS {
+                match deque.pop_front() {
+                    Some(_) => received += 1,
+                    None => std::thread::yield_now(),
+                }
+            }
+        });
+        let mpmc = start.elapsed();
+
+        println!("SpscRing: {:?}, LockFreeDeque: {:?}", spsc, mpmc);
+    }
+}
@@ -0,0 +1,169 @@
+//! A portable stress routine for `LockFreeDeque`, usable without `std`.
+//!
+//! The std tests spawn threads and share the deque with `Arc`, which a bare-metal test runner can't do.
+//! Here the caller provides the concurrency instead: it runs `stress_mpmc` concurrently on each
+//! thread, core or interrupt context it has, all sharing one deque and one `StressTally`,
+//! then calls `StressTally::verify` once they have all returned.
+//!
+//! Each call pushes distinct values and pops whatever it finds, choosing every operation from a seeded
+//! pseudo-random sequence, so a failing run can be replayed with the same seeds. The tally records
+//! a fingerprint of the pushed and of the popped multisets; `verify` drains the deque and checks that
+//! they are equal, i.e. that no item was lost, duplicated or corrupted.
+
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use crate::LockFreeDeque;
+
+/// Number of low bits of a pushed value holding the per-call counter, the worker id is above them
+const COUNTER_BITS: u32 = 40;
+
+/// Order-independent fingerprint of a multiset of values
+#[derive(Default)]
+struct Fingerprint {
+    count: AtomicU64,
+    sum: AtomicU64,
+    mixed_sum: AtomicU64,
+    mixed_xor: AtomicU64,
+}
+
+impl Fingerprint {
+    const fn new() -> Self {
+        Self {
+            count: AtomicU64::new(0),
+            sum: AtomicU64::new(0),
+            mixed_sum: AtomicU64::new(0),
+            mixed_xor: AtomicU64::new(0),
+        }
+    }
+
+    fn add(&self, value: u64) {
+        let mixed = mix(value);
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum.fetch_add(value, Ordering::Relaxed);
+        self.mixed_sum.fetch_add(mixed, Ordering::Relaxed);
+        self.mixed_xor.fetch_xor(mixed, Ordering::Relaxed);
+    }
+
+    fn load(&self) -> [u64; 4] {
+        [&self.count, &self.sum, &self.mixed_sum, &self.mixed_xor]
+            .map(|x| x.load(Ordering::Acquire))
+    }
+}
+
+/// Shared bookkeeping of a stress run, see the module documentation
+#[derive(Default)]
+pub struct StressTally {
+    pushed: Fingerprint,
+    popped: Fingerprint,
+}
+
+impl StressTally {
+    /// Create an empty tally, e.g. in a `static`
+    pub const fn new() -> Self {
+        Self {
+            pushed: Fingerprint::new(),
+            popped: Fingerprint::new(),
+        }
+    }
+
+    /// Drain `deque` and check that everything pushed by `stress_mpmc` has been popped exactly once
+    ///
+    /// Call it once every `stress_mpmc` call of the run has returned, with nothing else using the deque.
+    /// Returns the number of items pushed, or None if the multisets differ.
+    pub fn verify<const CAPACITY: usize>(
+        &self,
+        deque: &LockFreeDeque<u64, CAPACITY>,
+    ) -> Option<u64> {
+        while let Some(value) = deque.pop_back() {
+            self.popped.add(value);
+        }
+        let pushed = self.pushed.load();
+        (pushed == self.popped.load()).then_some(pushed[0])
+    }
+}
+
+/// Run `ops` pseudo-random operations on `deque` as worker `worker`, recording them in `tally`
+///
+/// Operations are push_front, push_back, pop_front and pop_back with equal probability, chosen from `seed`.
+/// Pushed values are unique across a run as long as every concurrent call uses a distinct `worker`
+/// (below `1 << 24`) and `ops` is below `1 << 40`. Pushes that find the deque full are skipped.
+/// See the module documentation for how to drive a run.
+pub fn stress_mpmc<const CAPACITY: usize>(
+    deque: &LockFreeDeque<u64, CAPACITY>,
+    tally: &StressTally,
+    worker: u32,
+    seed: u64,
+    ops: usize,
+) {
+    let mut state = seed;
+    let mut counter = 0u64;
+    for _ in 0..ops {
+        state = mix(state);
+        let popped = match state >> 62 {
+            0 | 1 => {
+                let value = ((worker as u64) << COUNTER_BITS) | counter;
+                counter += 1;
+                let res = match state >> 62 {
+                    0 => deque.push_front(value),
+                    _ => deque.push_back(value),
+                };
+                if res.is_ok() {
+                    tally.pushed.add(value);
+                }
+                None
+            }
+            2 => deque.pop_front(),
+            _ => deque.pop_back(),
+        };
+        if let Some(value) = popped {
+            tally.popped.add(value);
+        }
+    }
+}
+
+/// SplitMix64 finalizer, used both as the operation sequence and to spread values in the fingerprint
+const fn mix(x: u64) -> u64 {
+    let x = x.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    let x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    let x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+#[cfg(all(test, not(loom)))]
+mod tests {
+    extern crate std;
+    use super::{StressTally, stress_mpmc};
+    use crate::LockFreeDeque;
+
+    #[test]
+    fn test_stress_single() {
+        let deque: LockFreeDeque<u64, 8> = LockFreeDeque::new();
+        let tally = StressTally::new();
+        stress_mpmc(&deque, &tally, 0, 1, 1000);
+        let pushed = tally.verify(&deque).unwrap();
+        assert!(pushed > 0);
+
+        // A lost item is detected
+        let tally = StressTally::new();
+        stress_mpmc(&deque, &tally, 0, 2, 1000);
+        while deque.len() < 3 {
+            let _ = deque.push_back(u64::MAX);
+            tally.pushed.add(u64::MAX);
+        }
+        let _ = deque.pop_front();
+        assert!(tally.verify(&deque).is_none());
+    }
+
+    #[cfg(not(feature = "single-thread"))]
+    #[test]
+    fn test_stress_threads() {
+        static DEQUE: LockFreeDeque<u64, 16> = LockFreeDeque::new();
+        static TALLY: StressTally = StressTally::new();
+        std::thread::scope(|s| {
+            for worker in 0..4 {
+                s.spawn(move || stress_mpmc(&DEQUE, &TALLY, worker, worker as u64 + 7, 20000));
+            }
+        });
+        assert!(TALLY.verify(&DEQUE).is_some());
+    }
+}
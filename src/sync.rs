@@ -0,0 +1,306 @@
+//! Atomic types used by `LockFreeDeque`.
+//!
+//! With the `single-thread` feature, they are replaced by non-atomic `Cell`-based equivalents
+//! with the same interface. Compare-exchange then never fails spuriously and never races,
+//! so every push/pop runs straight through its loop once, compiled to plain loads and stores.
+//!
+//! Safety: with `single-thread`, a deque must never be accessed concurrently, e.g. only on a
+//! uniprocessor with interrupts disabled during queue operations. Not suitable for the shared vDSO setup.
+//!
+//! Memory orderings: every ordering of `LockFreeDeque` and `SlotArray` is taken from `Profile`, the
+//! `OrderingProfile` selected crate-wide, apart from the Relaxed ones of statistics and debugging fields.
+//! The profile names each ordering by direction; with the default `Strong` profile, each is chosen for what
+//! it synchronizes.
+//!
+//! - A deque slot's `state` guards its data. The CAS claiming a slot is `ACQUIRE`, and the store publishing
+//!   an item (READY) or handing the slot back (EMPTY) is `RELEASE`. The writes of an item thus happen before
+//!   its reads, and the reads of the previous item happen before the next writes to the slot.
+//! - The head/tail CAS moving an index is `RELEASE`, and the loads of the indices are `ACQUIRE`, so that a slot
+//!   claimed through an index is seen in the state its last mover left it in.
+//! - A `SlotArray` reference count is updated with `ACQ_REL`, so that the last `SlotRef` dropping a queue sees
+//!   every use of it by the others, and the epoch bumps invalidating stale ids are `ACQ_REL` too.
+//! - Counters only read as statistics (metrics, tallies in tests) are Relaxed in every profile.
+//!
+//! The `relaxed-orderings` feature selects the experimental `Relaxed` profile instead, to measure what the
+//! orderings of `Strong` cost. Weakening them makes the accesses to the slot data a data race, which is
+//! undefined behavior even on targets whose hardware is strongly ordered: never use it outside benchmarks.
+//! To measure the cost of the atomics themselves on one core, `single-thread` drops the read-modify-writes too.
+//!
+//! Under `cfg(loom)`, the atomics of this module are loom's, so that the loom tests below explore the
+//! interleavings of `LockFreeDeque` and the weak memory effects allowed by the profile. `SlotArray` keeps
+//! the atomics of `core`, which loom doesn't model. Loom's atomics can't be created in constant context,
+//! so the constructors of the types using them are not `const` then, see `const_fn`.
+
+use core::sync::atomic::Ordering;
+
+/// The memory orderings used by `LockFreeDeque` and `SlotArray`, named by direction, see the module documentation
+///
+/// `Profile` is the one selected for the whole crate.
+pub trait OrderingProfile {
+    /// Loads, and the CASes claiming what they load
+    const ACQUIRE: Ordering;
+    /// Stores, and the CASes publishing what was written before them
+    const RELEASE: Ordering;
+    /// Read-modify-writes doing both, such as reference count updates
+    const ACQ_REL: Ordering;
+}
+
+/// The default profile: Acquire, Release and AcqRel
+///
+/// An item written before being published is seen whole by the pop claiming it, a slot handed back
+/// is only written again once its previous item was read, and the last `SlotRef` of a slot sees every
+/// use of it. This is what the loom tests check.
+pub struct Strong;
+
+impl OrderingProfile for Strong {
+    const ACQUIRE: Ordering = Ordering::Acquire;
+    const RELEASE: Ordering = Ordering::Release;
+    const ACQ_REL: Ordering = Ordering::AcqRel;
+}
+
+/// Experimental profile for benchmarking, selected by the `relaxed-orderings` feature: every ordering is Relaxed
+///
+/// It only keeps the atomicity of each access: a pop may see a slot as READY before the item in it, and
+/// a push may overwrite an item still being read. This is undefined behavior, see the module documentation.
+pub struct Relaxed;
+
+impl OrderingProfile for Relaxed {
+    const ACQUIRE: Ordering = Ordering::Relaxed;
+    const RELEASE: Ordering = Ordering::Relaxed;
+    const ACQ_REL: Ordering = Ordering::Relaxed;
+}
+
+/// The profile selected for the crate, `Strong` unless the `relaxed-orderings` feature is enabled
+#[cfg(not(feature = "relaxed-orderings"))]
+pub type Profile = Strong;
+
+/// The profile selected for the crate, `Strong` unless the `relaxed-orderings` feature is enabled
+#[cfg(feature = "relaxed-orderings")]
+pub type Profile = Relaxed;
+
+#[cfg(all(not(feature = "single-thread"), not(loom)))]
+pub(crate) use core::sync::atomic::{AtomicU8, AtomicUsize};
+
+#[cfg(all(not(feature = "single-thread"), loom))]
+pub(crate) use loom::sync::atomic::{AtomicU8, AtomicUsize};
+
+/// Hint for spin-wait loops; under loom, it lets the model run the other threads
+#[cfg(not(loom))]
+pub(crate) use core::hint::spin_loop;
+
+#[cfg(loom)]
+pub(crate) use loom::hint::spin_loop;
+
+/// Defines a `const fn`, except under `cfg(loom)`, where the atomics can't be created in constant context
+macro_rules! const_fn {
+    ($(#[$attr:meta])* $vis:vis const fn $($rest:tt)*) => {
+        $(#[$attr])*
+        #[cfg(not(loom))]
+        $vis const fn $($rest)*
+
+        $(#[$attr])*
+        #[cfg(loom)]
+        $vis fn $($rest)*
+    };
+}
+pub(crate) use const_fn;
+
+#[cfg(feature = "single-thread")]
+pub(crate) use single_thread::{AtomicU8, AtomicUsize};
+
+#[cfg(feature = "single-thread")]
+mod single_thread {
+    use core::cell::Cell;
+    use core::sync::atomic::Ordering;
+
+    macro_rules! cell_atomic {
+        ($name:ident, $ty:ty) => {
+            /// Non-atomic replacement of the atomic type of the same name
+            #[derive(Debug, Default)]
+            #[repr(transparent)]
+            pub(crate) struct $name(Cell<$ty>);
+
+            #[allow(dead_code)]
+            impl $name {
+                pub(crate) const fn new(value: $ty) -> Self {
+                    Self(Cell::new(value))
+                }
+
+                #[inline]
+                pub(crate) fn load(&self, _: Ordering) -> $ty {
+                    self.0.get()
+                }
+
+                #[inline]
+                pub(crate) fn store(&self, value: $ty, _: Ordering) {
+                    self.0.set(value)
+                }
+
+                #[inline]
+                pub(crate) fn swap(&self, value: $ty, _: Ordering) -> $ty {
+                    self.0.replace(value)
+                }
+
+                #[inline]
+                pub(crate) fn compare_exchange(
+                    &self,
+                    current: $ty,
+                    new: $ty,
+                    _: Ordering,
+                    _: Ordering,
+                ) -> Result<$ty, $ty> {
+                    let value = self.0.get();
+                    if value == current {
+                        self.0.set(new);
+                        Ok(value)
+                    } else {
+                        Err(value)
+                    }
+                }
+
+                #[inline]
+                pub(crate) fn compare_exchange_weak(
+                    &self,
+                    current: $ty,
+                    new: $ty,
+                    success: Ordering,
+                    failure: Ordering,
+                ) -> Result<$ty, $ty> {
+                    self.compare_exchange(current, new, success, failure)
+                }
+
+                #[inline]
+                pub(crate) fn fetch_add(&self, value: $ty, _: Ordering) -> $ty {
+                    let prev = self.0.get();
+                    self.0.set(prev.wrapping_add(value));
+                    prev
+                }
+
+                #[inline]
+                pub(crate) fn fetch_sub(&self, value: $ty, _: Ordering) -> $ty {
+                    let prev = self.0.get();
+                    self.0.set(prev.wrapping_sub(value));
+                    prev
+                }
+            }
+        };
+    }
+
+    cell_atomic!(AtomicU8, u8);
+    cell_atomic!(AtomicUsize, usize);
+
+    #[cfg(test)]
+    mod tests {
+        use crate::LockFreeDeque;
+
+        #[cfg(all(feature = "deque-front", feature = "deque-back"))]
+        #[test]
+        fn test_single_thread_deque() {
+            let deque: LockFreeDeque<i32, 4> = LockFreeDeque::new();
+            assert!(deque.push_back(1).is_ok());
+            assert!(deque.push_back(2).is_ok());
+            assert!(deque.push_front(0).is_ok());
+            assert_eq!(deque.push_front(-1), Err(-1));
+            assert_eq!(deque.len(), 3);
+            assert_eq!(deque.pop_front(), Some(0));
+            assert_eq!(deque.pop_back(), Some(2));
+            assert_eq!(deque.pop_back(), Some(1));
+            assert_eq!(deque.pop_back(), None);
+            assert!(deque.is_empty());
+        }
+    }
+}
+
+// Run with `RUSTFLAGS="--cfg loom" cargo test --release loom_`
+#[cfg(all(test, loom, feature = "deque-front", feature = "deque-back"))]
+mod loom_tests {
+    use loom::sync::Arc;
+    use loom::thread;
+
+    use crate::LockFreeDeque;
+
+    #[test]
+    fn loom_push_pop() {
+        loom::model(|| {
+            let deque = Arc::new(LockFreeDeque::<usize, 4>::new());
+            let producer = {
+                let deque = deque.clone();
+                thread::spawn(move || {
+                    assert!(deque.push_back(1).is_ok());
+                    assert!(deque.push_back(2).is_ok());
+                })
+            };
+            // Items are seen in order, and only once published
+            let first = deque.pop_front();
+            if let Some(first) = first {
+                assert_eq!(first, 1);
+            }
+            producer.join().unwrap();
+            match first {
+                Some(_) => assert_eq!(deque.pop_front(), Some(2)),
+                None => {
+                    assert_eq!(deque.pop_front(), Some(1));
+                    assert_eq!(deque.pop_front(), Some(2));
+                }
+            }
+            assert_eq!(deque.len_exact(), 0);
+        });
+    }
+
+    #[test]
+    fn loom_full_race() {
+        loom::model(|| {
+            // Room for one item, pushed to from both ends at once
+            let deque = Arc::new(LockFreeDeque::<usize, 2>::new());
+            let front = {
+                let deque = deque.clone();
+                thread::spawn(move || deque.push_front(1).is_ok())
+            };
+            let back = deque.push_back(2).is_ok();
+            let front = front.join().unwrap();
+            assert!(front != back);
+            assert_eq!(deque.len(), 1);
+            assert_eq!(deque.pop_front(), Some(if front { 1 } else { 2 }));
+        });
+    }
+
+    #[test]
+    fn loom_pop_race() {
+        loom::model(|| {
+            let deque = Arc::new(LockFreeDeque::<usize, 4>::new());
+            assert!(deque.push_back(1).is_ok());
+            let back = {
+                let deque = deque.clone();
+                thread::spawn(move || deque.pop_back())
+            };
+            let front = deque.pop_front();
+            let back = back.join().unwrap();
+            // The item is taken exactly once
+            assert_eq!(front.or(back), Some(1));
+            assert!(front.is_none() || back.is_none());
+            assert!(deque.is_empty());
+        });
+    }
+
+    #[test]
+    fn loom_batch() {
+        loom::model(|| {
+            let deque = Arc::new(LockFreeDeque::<usize, 4>::new());
+            let producer = {
+                let deque = deque.clone();
+                thread::spawn(move || {
+                    let mut batch = deque.begin_batch(2).unwrap();
+                    assert!(batch.push(1).is_ok());
+                    assert!(batch.push(2).is_ok());
+                    batch.commit();
+                })
+            };
+            // Popping in FIFO order, the batch is seen whole or not at all
+            if let Some(first) = deque.pop_front() {
+                assert_eq!(first, 1);
+                assert_eq!(deque.pop_front(), Some(2));
+            }
+            producer.join().unwrap();
+        });
+    }
+}
@@ -0,0 +1,178 @@
+//! 槽位在各状态停留时间（dwell time）的统计，用于诊断活锁与调整退避策略。
+//!
+//! 启用`timing` feature并以`set_dwell_clock`设置时钟后，双端队列的每个槽位在状态转换时记录时间戳，并把两段停留时间计入直方图：
+//! - WRITING：从生产者占用槽位到发布消息（推入、`SlotGuard`释放或批次提交）；
+//! - READY：从发布消息到消费者取走它（弹出完成）。
+//!
+//! crate本身没有时钟，因此时间由调用者提供的函数给出，单位（周期数、纳秒等）由调用者决定。
+//! 直方图是本进程内所有双端队列共享的全局变量，见`state_dwell_histogram`。
+//!
+//! 开销：默认关闭。启用后每个槽位多占8字节（因此启用与否的构建不能共享同一队列）；
+//! 未设置时钟时每次状态转换多一次原子读，设置后每次状态转换还要调用一次时钟、写一次时间戳，并对直方图做一次原子加法，
+//! 多核并发时直方图的缓存行会在核之间来回传递，测得的停留时间本身也会因此变长。
+
+use core::sync::atomic::{AtomicPtr, AtomicU64, Ordering};
+
+/// 直方图的桶数
+///
+/// 第0个桶统计停留时间为0的次数，第i个桶（`1 <= i < DWELL_BUCKETS - 1`）统计停留时间在`[2^(i-1), 2^i)`之间的次数，
+/// 最后一个桶统计所有更长的停留时间。
+pub const DWELL_BUCKETS: usize = 32;
+
+/// 槽位时间戳的特殊值，表示槽位当前没有待统计的停留时间（从未被占用，或其中的消息已被取走）
+pub(crate) const NO_STAMP: u64 = u64::MAX;
+
+/// `state_dwell_histogram`返回的直方图快照，各桶的含义见`DWELL_BUCKETS`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DwellHistogram {
+    /// 槽位在WRITING状态停留时间的分布
+    pub writing: [u64; DWELL_BUCKETS],
+    /// 槽位在READY状态停留时间的分布
+    pub ready: [u64; DWELL_BUCKETS],
+}
+
+/// 当前设置的时钟，为空指针时表示未设置，不做任何统计
+///
+/// 函数指针只在当前进程的地址空间内有效，因此该变量不放在共享的vvar中。
+static CLOCK: AtomicPtr<()> = AtomicPtr::new(core::ptr::null_mut());
+
+/// WRITING状态停留时间的直方图
+static WRITING: [AtomicU64; DWELL_BUCKETS] = [const { AtomicU64::new(0) }; DWELL_BUCKETS];
+/// READY状态停留时间的直方图
+static READY: [AtomicU64; DWELL_BUCKETS] = [const { AtomicU64::new(0) }; DWELL_BUCKETS];
+
+/// 设置统计停留时间所用的时钟，传入`None`则停止统计。
+///
+/// 时钟应单调不减。在设置时钟之前就已被占用或发布的槽位没有时间戳，或者时间戳来自之前的时钟，
+/// 因此设置或更换时钟后最初的若干个样本可能缺失或不准确。
+pub fn set_dwell_clock(clock: Option<fn() -> u64>) {
+    let ptr = match clock {
+        Some(clock) => clock as *mut (),
+        None => core::ptr::null_mut(),
+    };
+    CLOCK.store(ptr, Ordering::Release);
+}
+
+/// 返回各状态停留时间直方图的快照。
+///
+/// 各桶分别读取，因此与并发的统计相比不是原子的快照。
+pub fn state_dwell_histogram() -> DwellHistogram {
+    let load = |buckets: &[AtomicU64; DWELL_BUCKETS]| {
+        core::array::from_fn(|i| buckets[i].load(Ordering::Relaxed))
+    };
+    DwellHistogram {
+        writing: load(&WRITING),
+        ready: load(&READY),
+    }
+}
+
+/// 清空直方图
+pub fn reset_dwell_histogram() {
+    for bucket in WRITING.iter().chain(&READY) {
+        bucket.store(0, Ordering::Relaxed);
+    }
+}
+
+/// 若已设置时钟，则返回当前时间
+#[inline]
+pub(crate) fn now() -> Option<u64> {
+    let ptr = CLOCK.load(Ordering::Acquire);
+    if ptr.is_null() {
+        return None;
+    }
+    // Safe because the pointer is only ever set from a `fn() -> u64` in `set_dwell_clock`
+    let clock: fn() -> u64 = unsafe { core::mem::transmute(ptr) };
+    Some(clock())
+}
+
+/// 将从`since`到`now`的停留时间计入WRITING（`writing`为true时）或READY状态的直方图，`since`为`NO_STAMP`时忽略
+pub(crate) fn record_dwell(writing: bool, since: u64, now: u64) {
+    if since == NO_STAMP {
+        return;
+    }
+    let buckets = if writing { &WRITING } else { &READY };
+    buckets[bucket(now.saturating_sub(since))].fetch_add(1, Ordering::Relaxed);
+}
+
+/// 停留时间所在的桶，见`DWELL_BUCKETS`
+fn bucket(dwell: u64) -> usize {
+    ((u64::BITS - dwell.leading_zeros()) as usize).min(DWELL_BUCKETS - 1)
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::{bucket, reset_dwell_histogram, set_dwell_clock, state_dwell_histogram};
+
+    #[test]
+    fn test_bucket() {
+        assert_eq!(bucket(0), 0);
+        assert_eq!(bucket(1), 1);
+        assert_eq!(bucket(2), 2);
+        assert_eq!(bucket(3), 2);
+        assert_eq!(bucket(4), 3);
+        assert_eq!(bucket(1 << 29), 30);
+        assert_eq!(bucket(1 << 30), 31);
+        assert_eq!(bucket(u64::MAX - 1), 31);
+    }
+
+    #[cfg(all(
+        feature = "deque-front",
+        feature = "deque-back",
+        feature = "deque-slot"
+    ))]
+    #[test]
+    fn test_dwell_time() {
+        use crate::LockFreeDeque;
+        use core::cell::Cell;
+
+        std::thread_local! {
+            static NOW: Cell<u64> = const { Cell::new(0) };
+        }
+        // Other tests running meanwhile read 0, so their dwell times all fall into bucket 0
+        fn clock() -> u64 {
+            NOW.with(Cell::get)
+        }
+        let advance = |ticks| NOW.with(|now| now.set(now.get() + ticks));
+
+        let deque: LockFreeDeque<i32, 8> = LockFreeDeque::new();
+        NOW.with(|now| now.set(100));
+        reset_dwell_histogram();
+        set_dwell_clock(Some(clock));
+
+        // Written for 5 ticks through a guard, ready for 15
+        let mut guard = deque.push_slot_back().unwrap();
+        guard.write(1);
+        advance(5);
+        drop(guard);
+        advance(15);
+        assert_eq!(deque.pop_front(), Some(1));
+
+        // A batch is written for 10 ticks, then ready for 1000, peeks don't count
+        let mut batch = deque.begin_batch(2).unwrap();
+        batch.push(2).unwrap();
+        batch.push(3).unwrap();
+        advance(10);
+        batch.commit();
+        advance(500);
+        assert_eq!(deque.peek_front_with(|&item| item), Some(2));
+        advance(500);
+        assert_eq!(deque.pop_front(), Some(2));
+        assert_eq!(deque.pop_back(), Some(3));
+
+        // Nothing is recorded without a clock
+        set_dwell_clock(None);
+        assert!(deque.push_back(4).is_ok());
+        advance(100);
+        assert_eq!(deque.pop_front(), Some(4));
+
+        let histogram = state_dwell_histogram();
+        let mut writing = [0; super::DWELL_BUCKETS];
+        (writing[3], writing[4]) = (1, 2);
+        let mut ready = [0; super::DWELL_BUCKETS];
+        (ready[4], ready[10]) = (1, 2);
+        assert_eq!(histogram.writing[1..], writing[1..]);
+        assert_eq!(histogram.ready[1..], ready[1..]);
+    }
+}
@@ -0,0 +1,105 @@
+//! 队列操作的跟踪（trace）机制，用于调试跨特权级的IPC。
+//!
+//! 注册回调函数后，`register_process`、`deque_push`、`deque_pop`每次调用都会以一个`TraceEvent`调用该回调。
+//! 未注册回调时，快速路径上只有一次原子读，不会发生函数调用。
+
+use core::sync::atomic::{AtomicPtr, Ordering};
+
+/// 被跟踪的操作类型
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TraceOp {
+    /// `register_process`
+    Register = 0,
+    /// `deque_push`
+    Push = 1,
+    /// `deque_pop`
+    Pop = 2,
+}
+
+/// 一次队列操作的跟踪记录
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TraceEvent {
+    /// 操作类型
+    pub op: TraceOp,
+    /// 操作的队列id。`Register`失败时为`usize::MAX`
+    pub queue_id: usize,
+    /// 操作是否成功（`Push`满、`Pop`空、`Register`数组满时为false）
+    pub success: bool,
+}
+
+/// 跟踪回调函数的类型
+pub type TraceCallback = extern "C" fn(event: TraceEvent);
+
+/// 当前注册的跟踪回调，为空指针时表示未注册
+///
+/// 函数指针只在当前进程的地址空间内有效，因此该变量不放在共享的vvar中。
+static TRACE_CALLBACK: AtomicPtr<()> = AtomicPtr::new(core::ptr::null_mut());
+
+/// 设置跟踪回调，传入`None`则取消跟踪。
+pub(crate) fn set_callback(callback: Option<TraceCallback>) {
+    let ptr = match callback {
+        Some(callback) => callback as *mut (),
+        None => core::ptr::null_mut(),
+    };
+    TRACE_CALLBACK.store(ptr, Ordering::Release);
+}
+
+/// 若已注册回调，则以给定的事件调用它。
+#[inline]
+pub(crate) fn emit(op: TraceOp, queue_id: usize, success: bool) {
+    let ptr = TRACE_CALLBACK.load(Ordering::Acquire);
+    if ptr.is_null() {
+        return;
+    }
+    // Safe because the pointer is only ever set from a `TraceCallback` in `set_callback`
+    let callback: TraceCallback = unsafe { core::mem::transmute(ptr) };
+    callback(TraceEvent {
+        op,
+        queue_id,
+        success,
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::{TraceEvent, TraceOp, emit, set_callback};
+    use std::{sync::Mutex, vec::Vec};
+
+    static EVENTS: Mutex<Vec<TraceEvent>> = Mutex::new(Vec::new());
+
+    extern "C" fn record(event: TraceEvent) {
+        EVENTS.lock().unwrap().push(event);
+    }
+
+    #[test]
+    fn test_trace_events() {
+        // no callback, nothing recorded
+        emit(TraceOp::Push, 0, true);
+
+        set_callback(Some(record));
+        emit(TraceOp::Register, 3, true);
+        emit(TraceOp::Push, 3, true);
+        emit(TraceOp::Pop, 3, true);
+        emit(TraceOp::Pop, 3, false);
+        set_callback(None);
+        emit(TraceOp::Push, 3, true);
+
+        let events = EVENTS.lock().unwrap();
+        let expected = [
+            (TraceOp::Register, true),
+            (TraceOp::Push, true),
+            (TraceOp::Pop, true),
+            (TraceOp::Pop, false),
+        ];
+        assert_eq!(events.len(), expected.len());
+        for (event, (op, success)) in events.iter().zip(expected) {
+            assert_eq!(event.op, op);
+            assert_eq!(event.queue_id, 3);
+            assert_eq!(event.success, success);
+        }
+    }
+}
@@ -0,0 +1,61 @@
+//! End-to-end checks of the API through the mapped `.so`.
+//!
+//! Every call below goes through the vtable set up by `init_vdso_vtable` in `map_vdso`, and the queues live in
+//! the mapped vvar region, so relocation or vtable regressions show up as failed assertions here
+//! rather than only as a crash (or nothing at all) in the throughput test.
+
+use libvqueue::*;
+
+const ITEMS: u64 = 64;
+
+fn item(i: u64) -> IPCItem {
+    IPCItem {
+        sender: 1,
+        msg_type: i,
+        rep_type: i + 1,
+        data: [i * 10; 8],
+    }
+}
+
+/// Run the checks on a queue of its own, so it can run after other tests as long as a slot is free
+pub fn run() {
+    let slot_ref = crate::api::register_process().expect("Failed to register queue");
+    let id = slot_ref.into_id();
+
+    // Items come back in order and unchanged
+    assert!(deque_pop(id).is_none(), "new queue is not empty");
+    for i in 0..ITEMS {
+        deque_push(id, item(i)).expect("Failed to push");
+    }
+    for i in 0..ITEMS {
+        let popped = deque_pop(id).expect("Failed to pop");
+        let expected = item(i);
+        assert_eq!(
+            (popped.sender, popped.msg_type, popped.rep_type, popped.data),
+            (
+                expected.sender,
+                expected.msg_type,
+                expected.rep_type,
+                expected.data
+            ),
+            "item {} popped out of order or corrupted",
+            i
+        );
+    }
+    assert!(
+        deque_pop(id).is_none(),
+        "queue not empty after popping everything"
+    );
+
+    // The id round-trips through the FFI boundary
+    let slot_ref = slotref_from_id(id);
+    assert_eq!(slot_ref.into_id(), id, "id changed across from_id/into_id");
+    assert_eq!(try_push(id, item(0)), TRY_PUSH_OK);
+    assert_eq!(deque_pop(id).map(|popped| popped.msg_type), Some(0));
+
+    // Once the last reference is dropped, the id is rejected
+    drop(slotref_from_id(id));
+    assert_eq!(try_push(id, item(0)), TRY_PUSH_NOT_REGISTERED);
+
+    log::info!("End-to-end checks passed");
+}
@@ -9,6 +9,7 @@ use std::{
 use crate::map::map_vdso;
 use libvqueue::*;
 
+mod e2e;
 mod map;
 
 const QUEUE_NUM: usize = 16;
@@ -71,6 +72,10 @@ fn main() {
 
     handles.into_iter().for_each(|h| h.join().unwrap());
 
+    // After the throughput test, which expects the first queues to get ids 0..QUEUE_NUM
+    assert!(QUEUE_NUM < ARRAY_LEN);
+    e2e::run();
+
     println!("Test passed!");
     drop(map);
 }